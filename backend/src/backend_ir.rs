@@ -2,6 +2,8 @@ use crate::insts::AsmInstruction;
 
 type Data = Vec<u8>;
 
+pub const INIT_SYMBOL: &str = "__init_globals";
+
 pub type AsmBasicBlock = Vec<AsmInstruction>;
 
 pub struct AsmFunction {
@@ -11,7 +13,12 @@ pub struct AsmFunction {
 
 pub struct AsmProgram {
     pub data: Vec<(String, Data)>,
-    pub start: AsmBasicBlock,
+    // globals which are never written
+    pub rodata: Vec<(String, Data)>,
+    // unit with the main function
+    pub entry: bool,
+    // initialization of the globals, `_start` calls it before main
+    pub init: AsmFunction,
     pub text: Vec<AsmFunction>,
 }
 
@@ -19,7 +26,12 @@ impl Default for AsmProgram {
     fn default() -> Self {
         Self {
             data: vec![],
-            start: vec![],
+            rodata: vec![],
+            entry: false,
+            init: AsmFunction {
+                name: INIT_SYMBOL.to_string(),
+                blocks: vec![],
+            },
             text: vec![],
         }
     }
@@ -1,5 +1,7 @@
 use std::fmt::Display;
 
+use middleend::syscall::{Syscall, Target};
+
 use crate::{backend_ir::AsmBasicBlock, insts::AsmInstruction, AsmFunction, AsmProgram};
 
 impl Display for AsmInstruction {
@@ -21,7 +23,7 @@ impl Display for AsmInstruction {
             AsmInstruction::Lh(x, y, z) => write!(f, "lh {}, {}, {}", x, y, z),
             AsmInstruction::Lw(x, y, z) => write!(f, "lw {}, {}, {}", x, y, z),
             AsmInstruction::Ld(x, y, offset) => write!(f, "ld {}, {}({})", x, offset, y),
-            AsmInstruction::Lbu(x, y, z) => write!(f, "lbu {}, {}, {}", x, y, z),
+            AsmInstruction::Lbu(x, y, offset) => write!(f, "lbu {}, {}({})", x, offset, y),
             AsmInstruction::Lhu(x, y, z) => write!(f, "lhu {}, {}, {}", x, y, z),
             AsmInstruction::Sb(x, y, offset) => write!(f, "sb {}, {}({})", x, offset, y),
             AsmInstruction::Sh(x, y, z) => write!(f, "sh {}, {}, {}", x, y, z),
@@ -46,6 +48,9 @@ impl Display for AsmInstruction {
             AsmInstruction::Or(x, y, z) => write!(f, "or {}, {}, {}", x, y, z),
             AsmInstruction::And(x, y, z) => write!(f, "and {}, {}, {}", x, y, z),
             AsmInstruction::Sra(x, y, z) => write!(f, "sra {}, {}, {}", x, y, z),
+            AsmInstruction::Div(x, y, z) => write!(f, "div {}, {}, {}", x, y, z),
+            AsmInstruction::Rem(x, y, z) => write!(f, "rem {}, {}, {}", x, y, z),
+            AsmInstruction::La(rd, symbol) => write!(f, "la {}, {}", rd, symbol),
             AsmInstruction::Call(imm, _) => write!(f, "call {}", imm),
             AsmInstruction::Ret => write!(f, "ret"),
             AsmInstruction::Mul(x, y, z) => write!(f, "mul {}, {}, {}", x, y, z),
@@ -54,25 +59,88 @@ impl Display for AsmInstruction {
     }
 }
 
+/// Emits one compilation unit, only the unit with the main
+/// function gets the `_start` entry, other units are linked to it
 pub fn emit_assembly(program: AsmProgram) -> String {
-    let mut lines = vec![".global _start".to_string(), "_start:".to_string()];
+    let mut lines = vec![".text".to_string()];
+
+    if program.entry {
+        lines.push(".global _start".to_string());
+        lines.push("_start:".to_string());
 
-    // appending any prologue
-    lines.append(&mut emit_basicblock(program.start));
+        // every unit puts its init function into the init array,
+        // the linker provides the bounds of the array
+        lines.push("    la s1, __init_array_start".to_string());
+        lines.push("    la s2, __init_array_end".to_string());
+        lines.push(".Linit:".to_string());
+        lines.push("    bgeu s1, s2, .Lmain".to_string());
+        lines.push("    ld t0, 0(s1)".to_string());
+        lines.push("    jalr ra, t0, 0".to_string());
+        lines.push("    addi s1, s1, 8".to_string());
+        lines.push("    j .Linit".to_string());
+        lines.push(".Lmain:".to_string());
 
-    lines.push("    call main".to_string());
+        // argc is on the top of the initial stack, followed by the argv
+        // and envp arrays, both are terminated by the null pointer
+        lines.push("    ld a0, 0(sp)".to_string());
+        lines.push("    addi a1, sp, 8".to_string());
+        lines.push("    slli a2, a0, 3".to_string());
+        lines.push("    add a2, a1, a2".to_string());
+        lines.push("    addi a2, a2, 8".to_string());
+        lines.push("    call main".to_string());
+
+        // result of main is the exit code
+        let exit = Syscall::Exit.number(Target::Riscv64Linux);
+        lines.push(format!("    addi a7, zero, {exit}"));
+        lines.push("    ecall".to_string());
+    }
 
-    // epilogue
-    lines.push("    addi a7, zero, 93".to_string());
-    lines.push("    ecall".to_string());
+    // the init function is local, so every unit can have its own
+    let init = program.init.name.clone();
+    lines.append(&mut emit_body(program.init));
+    lines.push(".section .init_array".to_string());
+    lines.push(".align 3".to_string());
+    lines.push(format!("    .dword {init}"));
+    lines.push(".text".to_string());
 
     // main logic
     lines.append(&mut program.text.into_iter().flat_map(emit_function).collect());
 
+    if !program.data.is_empty() {
+        lines.push(".data".to_string());
+        lines.append(&mut program.data.into_iter().flat_map(emit_data).collect());
+    }
+
+    if !program.rodata.is_empty() {
+        lines.push(".section .rodata".to_string());
+        lines.append(&mut program.rodata.into_iter().flat_map(emit_data).collect());
+    }
+
     lines.join("\n")
 }
 
+fn emit_data((name, data): (String, Vec<u8>)) -> Vec<String> {
+    let mut result = vec![
+        format!(".global {}", name),
+        ".align 3".to_string(),
+        name + ":",
+    ];
+    if data.iter().all(|x| *x == 0) {
+        result.push(format!("    .zero {}", data.len()));
+    } else {
+        let bytes: Vec<String> = data.iter().map(|x| x.to_string()).collect();
+        result.push(format!("    .byte {}", bytes.join(", ")));
+    }
+    result
+}
+
 fn emit_function(function: AsmFunction) -> Vec<String> {
+    let mut result = vec![format!(".global {}", function.name)];
+    result.append(&mut emit_body(function));
+    result
+}
+
+fn emit_body(function: AsmFunction) -> Vec<String> {
     let mut result = vec![function.name + ":"];
     let mut code = function
         .blocks
@@ -2,6 +2,7 @@ use std::collections::HashSet;
 
 use middleend::{
     analysis::{dataflow::DataFlowAnalysis, live::LiveRegisterAnalysis},
+    inst::{InstructionType, RegRegs, SymRegs},
     ir::{Function, InstStore},
 };
 
@@ -13,10 +14,15 @@ use crate::{
     AsmFunction,
 };
 
+/// size of the area where the variadic function saves the argument registers
+const VA_SAVE_SIZE: usize = 64;
+
 pub struct AsmFunctionBuilder<'a> {
     pub name: String,
     stacksize: usize,
     pub actual_bb: usize,
+    // the argument registers are saved next to the stack arguments
+    pub variadic: bool,
     blocks: Vec<AsmBasicBlock>,
     liveness: Vec<Vec<HashSet<middleend::ir::Register>>>,
 
@@ -31,8 +37,9 @@ impl<'a> AsmFunctionBuilder<'a> {
         Self {
             liveness: lifeanalysis.analyze(store),
             name,
-            stacksize: 0,
+            stacksize: AsmFunctionBuilder::outgoing_args_size(ir_function, store),
             actual_bb: 0,
+            variadic: false,
             blocks: vec![],
 
             freetemp: vec![29, 30, 31],
@@ -41,6 +48,40 @@ impl<'a> AsmFunctionBuilder<'a> {
         }
     }
 
+    /// arguments after the eighth are passed on the bottom
+    /// of the caller's frame
+    fn outgoing_args_size(function: &Function, store: &InstStore) -> usize {
+        let most_args = function
+            .blocks
+            .iter()
+            .flat_map(|bb| bb.iter())
+            .map(|inst| match &store.get(*inst).data {
+                InstructionType::Call(RegRegs(_, args))
+                | InstructionType::CallDirect(SymRegs(_, args)) => args.len(),
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0);
+        8 * most_args.saturating_sub(8)
+    }
+
+    /// places relative to the entry stack pointer get the final offset
+    fn patch_frame(block: &mut AsmBasicBlock, stacksize: usize) {
+        for inst in block.iter_mut() {
+            match inst {
+                AsmInstruction::Addi(_, rs, offset)
+                | AsmInstruction::Ld(_, rs, offset)
+                | AsmInstruction::Sd(_, rs, offset)
+                    if *rs == Rd::Frame =>
+                {
+                    *rs = Rd::Sp;
+                    *offset += stacksize as i64;
+                }
+                _ => (),
+            }
+        }
+    }
+
     fn add_epilogue(block: AsmBasicBlock, stacksize: usize) -> AsmBasicBlock {
         let mut block = block;
         match block.last() {
@@ -184,7 +225,7 @@ impl<'a> AsmFunctionBuilder<'a> {
         match &mut inst {
             AsmInstruction::Lui(_, _) => todo!(),
             AsmInstruction::Auipc(_, _) => todo!(),
-            AsmInstruction::Jal(rd, _, _) => *rd = write_regs[0],
+            AsmInstruction::Jal(rd, _, _) | AsmInstruction::La(rd, _) => *rd = write_regs[0],
             AsmInstruction::Jalr(rd, rs, _) => {
                 *rd = write_regs[0];
                 *rs = load_regs[0];
@@ -221,7 +262,9 @@ impl<'a> AsmFunctionBuilder<'a> {
             | AsmInstruction::Xor(rd, rs1, rs2)
             | AsmInstruction::Or(rd, rs1, rs2)
             | AsmInstruction::And(rd, rs1, rs2)
-            | AsmInstruction::Sra(rd, rs1, rs2) => {
+            | AsmInstruction::Sra(rd, rs1, rs2)
+            | AsmInstruction::Div(rd, rs1, rs2)
+            | AsmInstruction::Rem(rd, rs1, rs2) => {
                 *rd = write_regs[0];
                 *rs1 = load_regs[0];
                 *rs2 = load_regs[1];
@@ -374,7 +417,14 @@ impl<'a> AsmFunctionBuilder<'a> {
                 block
             })
             .collect();
-        let stacksize = stacksize + biggest_addition as usize;
+        let mut stacksize = stacksize + biggest_addition as usize;
+        // the save area is on the top of the frame, so all
+        // variadic arguments are one after another in memory
+        if self.variadic {
+            stacksize += VA_SAVE_SIZE;
+        }
+        // the psABI keeps the stack pointer 16 bytes aligned
+        let stacksize = stacksize.next_multiple_of(16);
 
         // epilogues
         let mut blocks: Vec<AsmBasicBlock> = blocks
@@ -383,10 +433,17 @@ impl<'a> AsmFunctionBuilder<'a> {
             .collect();
 
         // prolog
-        blocks
-            .first_mut()
-            .expect("Totally empty function")
-            .insert(0, AsmInstruction::Addi(Rd::Sp, Rd::Sp, -(stacksize as i64)));
+        let first = blocks.first_mut().expect("Totally empty function");
+        first.insert(0, AsmInstruction::Addi(Rd::Sp, Rd::Sp, -(stacksize as i64)));
+        if self.variadic {
+            for i in 0..8 {
+                let offset = (stacksize - VA_SAVE_SIZE + 8 * i) as i64;
+                first.insert(1 + i, AsmInstruction::Sd(Rd::ArgReg(i as u8), Rd::Sp, offset));
+            }
+        }
+        for block in blocks.iter_mut() {
+            AsmFunctionBuilder::patch_frame(block, stacksize);
+        }
 
         AsmFunctionBuilder::peepholer_run(&peepholer, &mut blocks);
 
@@ -1,6 +1,6 @@
 use middleend::{
     inst::{
-        ImmC, ImmI, ImmIRegs, Reg, RegReg, RegRegImm, SymRegs, TerminatorBranch, TerminatorJump,
+        ImmC, ImmI, ImmIRegs, ImmS, Reg, RegReg, RegRegImm, SymRegs, TerminatorBranch, TerminatorJump,
         TerminatorReg,
     },
     ir::{InstStore, Instruction},
@@ -23,9 +23,12 @@ pub fn basic_instruction_selection(
         &middleend::inst::InstructionType::Ldc(ImmC(imm)) => {
             builder.add_instruction(AsmInstruction::Addi(Ir(reg), Zero, imm as u8 as i64));
         }
-        &middleend::inst::InstructionType::Ld(Reg(rs1)) => {
-            builder.add_instruction(AsmInstruction::Ld(Ir(reg), Ir(rs1), 0));
-        }
+        &middleend::inst::InstructionType::Ld(Reg(rs1)) => match inst.reg_type {
+            middleend::ir::RegType::Char => {
+                builder.add_instruction(AsmInstruction::Lbu(Ir(reg), Ir(rs1), 0))
+            }
+            _ => builder.add_instruction(AsmInstruction::Ld(Ir(reg), Ir(rs1), 0)),
+        },
         &middleend::inst::InstructionType::St(RegReg(rs1, rs2)) => {
             match store.get(rs2).reg_type {
                 middleend::ir::RegType::Void => panic!(),
@@ -39,7 +42,9 @@ pub fn basic_instruction_selection(
             builder.release_temp();
         }
         &middleend::inst::InstructionType::Alloca(ImmI(_)) => (),
-        &middleend::inst::InstructionType::Allocg(_) => todo!(),
+        middleend::inst::InstructionType::Ldg(ImmS(name)) => {
+            builder.add_instruction(AsmInstruction::La(Ir(reg), name.clone()));
+        }
         &middleend::inst::InstructionType::Mov(Reg(rs1)) => {
             builder.add_instruction(AsmInstruction::Addi(Ir(reg), Ir(rs1), 0))
         }
@@ -65,16 +70,37 @@ pub fn basic_instruction_selection(
         &middleend::inst::InstructionType::Mul(RegReg(rs1, rs2)) => {
             builder.add_instruction(AsmInstruction::Mul(Ir(reg), Ir(rs1), Ir(rs2)));
         }
-        middleend::inst::InstructionType::Div(_) => todo!(),
-        middleend::inst::InstructionType::Mod(_) => todo!(),
-        middleend::inst::InstructionType::Shr(_) => todo!(),
-        middleend::inst::InstructionType::Shl(_) => todo!(),
-        middleend::inst::InstructionType::And(_) => todo!(),
-        middleend::inst::InstructionType::Or(_) => todo!(),
-        middleend::inst::InstructionType::Xor(_) => todo!(),
+        &middleend::inst::InstructionType::Div(RegReg(rs1, rs2)) => {
+            builder.add_instruction(AsmInstruction::Div(Ir(reg), Ir(rs1), Ir(rs2)));
+        }
+        &middleend::inst::InstructionType::Mod(RegReg(rs1, rs2)) => {
+            builder.add_instruction(AsmInstruction::Rem(Ir(reg), Ir(rs1), Ir(rs2)));
+        }
+        &middleend::inst::InstructionType::Shr(RegReg(rs1, rs2)) => {
+            builder.add_instruction(AsmInstruction::Sra(Ir(reg), Ir(rs1), Ir(rs2)));
+        }
+        &middleend::inst::InstructionType::Shl(RegReg(rs1, rs2)) => {
+            builder.add_instruction(AsmInstruction::Sll(Ir(reg), Ir(rs1), Ir(rs2)));
+        }
+        &middleend::inst::InstructionType::And(RegReg(rs1, rs2)) => {
+            builder.add_instruction(AsmInstruction::And(Ir(reg), Ir(rs1), Ir(rs2)));
+        }
+        &middleend::inst::InstructionType::Or(RegReg(rs1, rs2)) => {
+            builder.add_instruction(AsmInstruction::Or(Ir(reg), Ir(rs1), Ir(rs2)));
+        }
+        &middleend::inst::InstructionType::Xor(RegReg(rs1, rs2)) => {
+            builder.add_instruction(AsmInstruction::Xor(Ir(reg), Ir(rs1), Ir(rs2)));
+        }
         &middleend::inst::InstructionType::Neg(Reg(rs1)) => {
             builder.add_instruction(AsmInstruction::Sltiu(Ir(inst.id), Ir(rs1), 1));
         }
+        // the chars are kept zero extended in the registers
+        &middleend::inst::InstructionType::Conv(Reg(rs1)) => match inst.reg_type {
+            middleend::ir::RegType::Char => {
+                builder.add_instruction(AsmInstruction::Andi(Ir(reg), Ir(rs1), 0xff))
+            }
+            _ => builder.add_instruction(AsmInstruction::Addi(Ir(reg), Ir(rs1), 0)),
+        },
         &middleend::inst::InstructionType::Le(RegReg(rs1, rs2)) => {
             builder.add_instruction(AsmInstruction::Addi(Arch(31), Ir(rs2), 1));
             builder.add_instruction(AsmInstruction::Slt(Ir(reg), Ir(rs1), Arch(31)));
@@ -85,7 +111,10 @@ pub fn basic_instruction_selection(
         &middleend::inst::InstructionType::Gt(RegReg(rs1, rs2)) => {
             builder.add_instruction(AsmInstruction::Slt(Ir(reg), Ir(rs2), Ir(rs1)));
         }
-        &middleend::inst::InstructionType::Ge(_) => todo!(),
+        &middleend::inst::InstructionType::Ge(RegReg(rs1, rs2)) => {
+            builder.add_instruction(AsmInstruction::Slt(Ir(reg), Ir(rs1), Ir(rs2)));
+            builder.add_instruction(AsmInstruction::Xori(Ir(reg), Ir(reg), 1));
+        }
         &middleend::inst::InstructionType::Eql(RegReg(rs1, rs2)) => {
             builder.add_instruction(AsmInstruction::Sub(Ir(inst.id), Ir(rs1), Ir(rs2)));
             // seqz rd, rs => sltiu rd, rs, 1
@@ -94,24 +123,32 @@ pub fn basic_instruction_selection(
         }
         &middleend::inst::InstructionType::Call(_) => todo!(),
         middleend::inst::InstructionType::CallDirect(SymRegs(sym, regs)) => {
-            if regs.len() >= 8 {
-                todo!();
+            // the rest of the arguments goes to the bottom of the frame
+            for (i, arg) in regs.iter().enumerate().skip(8) {
+                builder.add_instruction(AsmInstruction::Sd(Ir(*arg), Sp, 8 * (i as i64 - 8)));
             }
-            // TODO implement working solution for stack passed arguments
-            for i in 0..regs.len() {
-                builder.add_instruction(AsmInstruction::Addi(ArgReg(i as u8), Ir(regs[i]), 0));
+            for (i, arg) in regs.iter().enumerate().take(8) {
+                builder.add_instruction(AsmInstruction::Addi(ArgReg(i as u8), Ir(*arg), 0));
             }
             let offset = builder.force_store(Ra);
             builder.add_instruction(AsmInstruction::Call(sym.clone(), place));
             builder.add_instruction(AsmInstruction::Ld(Ra, Sp, offset));
             builder.add_instruction(AsmInstruction::Addi(Ir(reg), ArgReg(0), 0));
         }
-        &middleend::inst::InstructionType::Arg(ImmI(imm)) => {
-            // TODO implement working solution for stack passed arguments
+        &middleend::inst::InstructionType::Arg(ImmI(imm)) if imm < 8 => {
             builder.add_instruction(AsmInstruction::Addi(Ir(reg), ArgReg(imm as u8), 0));
         }
+        &middleend::inst::InstructionType::Arg(ImmI(imm)) => {
+            builder.add_instruction(AsmInstruction::Ld(Ir(reg), Frame, 8 * (imm - 8)));
+        }
+        &middleend::inst::InstructionType::VaStart(ImmI(imm)) => {
+            // the argument registers are saved right under the stack arguments
+            builder.variadic = true;
+            builder.add_instruction(AsmInstruction::Addi(Ir(reg), Frame, 8 * imm - 64));
+        }
         &middleend::inst::InstructionType::Ret(_) => builder.add_instruction(AsmInstruction::Ret),
-        &middleend::inst::InstructionType::Exit(_) => (),
+        // the global block is called from `_start` like a function
+        &middleend::inst::InstructionType::Exit(_) => builder.add_instruction(AsmInstruction::Ret),
         &middleend::inst::InstructionType::Retr(TerminatorReg(reg)) => {
             builder.add_instruction(AsmInstruction::Addi(ArgReg(0), Ir(reg), 0));
             builder.add_instruction(AsmInstruction::Ret);
@@ -136,8 +173,13 @@ pub fn basic_instruction_selection(
             ));
             builder.release_temp();
         }
-        middleend::inst::InstructionType::Print(_) => todo!(),
-        middleend::inst::InstructionType::Phi(_) => todo!(),
+        // `asm_compile` replaces them with the calls of the runtime
+        middleend::inst::InstructionType::Print(_) => unreachable!(),
+        // `asm_compile` translates the program out of ssa first
+        middleend::inst::InstructionType::Phi(_) => unreachable!(),
+        &middleend::inst::InstructionType::Copy(RegReg(rd, rs1)) => {
+            builder.add_instruction(AsmInstruction::Addi(Ir(rd), Ir(rs1), 0))
+        }
         middleend::inst::InstructionType::SysCall(ImmIRegs(num, regs)) => {
             if regs.len() >= 7 {
                 todo!();
@@ -147,7 +189,10 @@ pub fn basic_instruction_selection(
             }
 
             builder.add_instruction(AsmInstruction::Addi(ArgReg(7), Zero, *num));
-            builder.add_instruction(AsmInstruction::Ecall)
+            builder.add_instruction(AsmInstruction::Ecall);
+            if inst.reg_type != middleend::ir::RegType::Void {
+                builder.add_instruction(AsmInstruction::Addi(Ir(reg), ArgReg(0), 0));
+            }
         }
     }
 }
@@ -8,6 +8,9 @@ pub enum Rd {
     Zero,
     Sp,
     Ra,
+    // stack pointer at the entry of the function, it is replaced
+    // by sp and the size of the frame when the frame is known
+    Frame,
 
     // Real architectural registers
     Arch(usize),
@@ -21,6 +24,7 @@ impl Display for Rd {
             Rd::Zero => write!(f, "zero"),
             Rd::Sp => write!(f, "sp"),
             Rd::Ra => write!(f, "ra"),
+            Rd::Frame => write!(f, "frame"),
             Rd::Arch(number) => write!(f, "x{}", number),
         }
     }
@@ -77,8 +81,11 @@ pub enum AsmInstruction {
     Or(Rd, Rd, Rd),
     And(Rd, Rd, Rd),
     Sra(Rd, Rd, Rd),
+    Div(Rd, Rd, Rd),
+    Rem(Rd, Rd, Rd),
 
     // pseudo instructions
+    La(Rd, String),
     Call(String, middleend::ir::InstUUID),
     Ret,
 
@@ -136,6 +143,8 @@ impl AsmInstruction {
             &AsmInstruction::Or(_, rs1, rs2) => vec![rs1, rs2],
             &AsmInstruction::And(_, rs1, rs2) => vec![rs1, rs2],
             &AsmInstruction::Sra(_, rs1, rs2) => vec![rs1, rs2],
+            &AsmInstruction::Div(_, rs1, rs2) => vec![rs1, rs2],
+            &AsmInstruction::Rem(_, rs1, rs2) => vec![rs1, rs2],
             _ => vec![],
         }
     }
@@ -145,6 +154,7 @@ impl AsmInstruction {
             &AsmInstruction::Lui(_, _) => todo!(),
             &AsmInstruction::Auipc(_, _) => todo!(),
             &AsmInstruction::Jal(rd, _, _) => Some(rd),
+            &AsmInstruction::La(rd, _) => Some(rd),
             &AsmInstruction::Jalr(rd, _, _) => Some(rd),
             &AsmInstruction::Lb(rd, _, _) => Some(rd),
             &AsmInstruction::Lh(rd, _, _) => Some(rd),
@@ -172,6 +182,8 @@ impl AsmInstruction {
             &AsmInstruction::Or(rd, _, _) => Some(rd),
             &AsmInstruction::And(rd, _, _) => Some(rd),
             &AsmInstruction::Sra(rd, _, _) => Some(rd),
+            &AsmInstruction::Div(rd, _, _) => Some(rd),
+            &AsmInstruction::Rem(rd, _, _) => Some(rd),
             _ => None,
         }
     }
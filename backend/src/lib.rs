@@ -6,14 +6,25 @@ mod insts;
 mod peepholer;
 mod register_alloc;
 
-use backend_ir::{AsmFunction, AsmProgram};
+use backend_ir::{AsmFunction, AsmProgram, INIT_SYMBOL};
 use fn_builder::AsmFunctionBuilder;
 use inst_selection::basic_instruction_selection;
-use middleend::ir::{BasicBlock, Function, InstStore, IrProgram};
+use middleend::{
+    destruct_ssa,
+    inst::{InstructionType, Reg, SymRegs},
+    ir::{BasicBlock, Function, InstStore, IrProgram, RegType},
+    link::print_routine,
+};
 use peepholer::{MockDatabase, PeepHoler};
 
 pub fn asm_compile(ir_program: IrProgram) -> AsmProgram {
-    let mut start = asm_func(ir_program.glob, &ir_program.store);
+    let mut ir_program = ir_program;
+    destruct_ssa(&mut ir_program);
+    lower_prints(&mut ir_program);
+    let mut glob = ir_program.glob;
+    glob.name = INIT_SYMBOL.to_string();
+    let init = asm_func(glob, &ir_program.store);
+    let entry = ir_program.funcs.contains_key("main");
 
     let text: Vec<AsmFunction> = ir_program
         .funcs
@@ -21,10 +32,36 @@ pub fn asm_compile(ir_program: IrProgram) -> AsmProgram {
         .map(|x| asm_func(x.1, &ir_program.store))
         .collect();
 
+    let (rodata, data): (Vec<_>, Vec<_>) =
+        ir_program.globals.into_iter().partition(|x| x.read_only);
+
     AsmProgram {
-        start: std::mem::take(&mut start.blocks[0]),
+        init,
         text,
-        data: vec![],
+        data: data.into_iter().map(|x| (x.name, x.data)).collect(),
+        rodata: rodata.into_iter().map(|x| (x.name, x.data)).collect(),
+        entry,
+    }
+}
+
+/// the print instructions call the runtime, which the
+/// linker added to the program together with them
+fn lower_prints(ir_program: &mut IrProgram) {
+    let funcs = ir_program.funcs.values().chain([&ir_program.glob]);
+    let prints: Vec<_> = funcs
+        .flat_map(|func| func.blocks.iter().flat_map(|bb| bb.iter()))
+        .filter_map(|inst| match ir_program.store.get(*inst).data {
+            InstructionType::Print(Reg(reg)) => Some((*inst, reg)),
+            _ => None,
+        })
+        .collect();
+    for (inst, reg) in prints {
+        let routine = print_routine(ir_program.store.get(reg).reg_type);
+        ir_program.store.replace_inst(
+            inst,
+            InstructionType::CallDirect(SymRegs(routine, vec![reg])),
+            RegType::Void,
+        );
     }
 }
 
@@ -51,3 +88,52 @@ fn asm_basicblock(
         basic_instruction_selection(store.get(*x), *x, builder, store)
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use middleend::ir_parse::parse_ir;
+
+    use super::*;
+    use crate::emit::emit_assembly;
+
+    #[test]
+    fn print_calls_runtime() {
+        let text = "global:
+function global(0) : void {
+BB0:
+exit
+}
+function main(0) : int {
+BB0:
+%0 : int = ldi 42
+print %0
+%1 : char = ldc 'a'
+print %1
+retr %0
+}";
+        let asm = emit_assembly(asm_compile(parse_ir(text).unwrap()));
+        assert!(asm.contains("call __print_int"));
+        assert!(asm.contains("call putchar"));
+    }
+
+    #[test]
+    fn every_unit_registers_init() {
+        // the unit without main has no `_start`, but its globals are
+        // initialized through the init array as well
+        let text = "global:
+function global(0) : void {
+BB0:
+calldirect __init_globals.lib []
+exit
+}
+function __init_globals.lib(0) : void {
+BB0:
+ret
+}";
+        let asm = emit_assembly(asm_compile(parse_ir(text).unwrap()));
+        assert!(!asm.contains("_start"));
+        assert!(asm.contains("call __init_globals.lib"));
+        assert!(asm.contains(".section .init_array\n.align 3\n    .dword __init_globals\n"));
+        assert!(!asm.contains(".global __init_globals\n"));
+    }
+}
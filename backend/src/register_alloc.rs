@@ -2,7 +2,10 @@
 #[allow(dead_code)]
 use std::collections::{HashMap, HashSet};
 
-use middleend::ir::{BasicBlock, InstStore, InstUUID};
+use middleend::{
+    analysis::loops::LoopForest,
+    ir::{InstStore, InstUUID},
+};
 
 use crate::insts::Rd;
 
@@ -95,6 +98,11 @@ pub struct LinearAllocator<'a> {
     used_ir: HashSet<Rd>,
     stacksize: i64,
     store: &'a InstStore,
+    // uses weighted by the depth of the loops, the cheaper register
+    // goes to the stack when there is no free one
+    costs: HashMap<middleend::ir::Register, usize>,
+    // ir registers in the machine registers
+    holders: HashMap<usize, middleend::ir::Register>,
 }
 
 impl<'a> LinearAllocator<'a> {
@@ -119,66 +127,127 @@ impl<'a> LinearAllocator<'a> {
             used_ir,
             stacksize,
             store,
+            costs: HashMap::new(),
+            holders: HashMap::new(),
         };
+        res.costs = res.spill_costs(function);
         res.allocate(function);
         res
     }
 
+    /// every definition and use costs 10 to the power of the loop depth
+    fn spill_costs(
+        &self,
+        fun: &middleend::ir::Function,
+    ) -> HashMap<middleend::ir::Register, usize> {
+        let loops = LoopForest::new(fun, self.store);
+        let mut costs: HashMap<middleend::ir::Register, usize> = HashMap::new();
+        for (bb_index, block) in fun.blocks.iter().enumerate() {
+            let weight = 10usize.saturating_pow(loops.depth(bb_index) as u32);
+            for inst_id in block.iter() {
+                let regs = self.store.get(*inst_id).data.get_regs();
+                for reg in regs.into_iter().chain([*inst_id]) {
+                    let cost = costs.entry(reg).or_default();
+                    *cost = cost.saturating_add(weight);
+                }
+            }
+        }
+        costs
+    }
+
+    /// Places where the registers start and stop being used in the order
+    /// of the blocks. Out of ssa the copies write the registers of the
+    /// phi nodes in the predecessors, so the register starts at its first
+    /// write or at the first place where it is live.
+    fn intervals(
+        &self,
+        fun: &middleend::ir::Function,
+    ) -> (
+        HashMap<Place, Vec<middleend::ir::Register>>,
+        HashMap<middleend::ir::Register, Place>,
+    ) {
+        let mut first: HashMap<middleend::ir::Register, Place> = HashMap::new();
+        let mut last: HashMap<middleend::ir::Register, Place> = HashMap::new();
+        for (bb_index, block) in fun.blocks.iter().enumerate() {
+            for (inst_index, inst_id) in block.iter().enumerate() {
+                let place = (bb_index, inst_index);
+                let mut regs = vec![*inst_id];
+                if let middleend::inst::InstructionType::Copy(middleend::inst::RegReg(to, _)) =
+                    self.store.get(*inst_id).data
+                {
+                    regs.push(to);
+                }
+                regs.extend(self.liveness[bb_index][inst_index].iter().copied());
+                for reg in regs {
+                    first.entry(reg).or_insert(place);
+                    last.insert(reg, place);
+                }
+            }
+        }
+        let mut starts: HashMap<Place, Vec<middleend::ir::Register>> = HashMap::new();
+        for (reg, place) in first {
+            starts.entry(place).or_default().push(reg);
+        }
+        for regs in starts.values_mut() {
+            regs.sort_by_key(|x| x.val());
+        }
+        (starts, last)
+    }
+
     fn allocate(&mut self, fun: &middleend::ir::Function) {
+        let (starts, ends) = self.intervals(fun);
         for (bb_index, block) in fun.blocks.iter().enumerate() {
             for inst_index in 0..block.len() {
                 let inst_id = block[inst_index];
-                if self.used_ir.contains(&Rd::Ir(inst_id)) {
-                    match &self.store.get(inst_id).data {
+                let place = (bb_index, inst_index);
+                for reg in starts.get(&place).into_iter().flatten() {
+                    if !self.used_ir.contains(&Rd::Ir(*reg)) {
+                        continue;
+                    }
+                    match &self.store.get(*reg).data {
                         middleend::inst::InstructionType::Alloca(middleend::inst::ImmI(size)) => {
                             self.registers
-                                .insert(inst_id, ValueCell::Value(self.stacksize));
+                                .insert(*reg, ValueCell::Value(self.stacksize));
                             self.stacksize += size;
                         }
-                        _ => self.allocate_reg(inst_id, (bb_index, inst_index), &fun.blocks),
+                        _ => self.allocate_reg(*reg, ends[reg]),
                     }
                 }
                 self.used.insert(inst_id.val(), self.used_register.clone());
-                self.release((bb_index, inst_index));
+                self.release(place);
             }
         }
     }
 
-    fn allocate_reg(
-        &mut self,
-        reg: middleend::ir::Register,
-        place: Place,
-        blocks: &Vec<BasicBlock>,
-    ) {
-        if self.freeowned.len() <= 0 {
-            let offset = ValueCell::StackOffset(self.stacksize);
-            self.stacksize += 8;
-            self.registers.insert(reg, offset);
-        } else {
-            let reg_name = self.freeowned.pop().unwrap();
-            self.used_register.push(reg_name);
-            let register = ValueCell::Register(reg_name);
-            self.registers.insert(reg, register);
-            self.create_release(reg, place, blocks);
-        }
-    }
-
-    fn create_release(
-        &mut self,
-        reg: middleend::ir::Register,
-        place: Place,
-        blocks: &Vec<BasicBlock>,
-    ) {
-        let (bb_start, inst_start) = place;
-        let mut place = place;
-        for bb_index in bb_start..blocks.len() {
-            for inst_index in inst_start..blocks[bb_index].len() {
-                if self.liveness[bb_index][inst_index].contains(&reg) {
-                    place = (bb_index, inst_index);
+    fn allocate_reg(&mut self, reg: middleend::ir::Register, end: Place) {
+        let reg_name = match self.freeowned.pop() {
+            Some(reg_name) => {
+                self.used_register.push(reg_name);
+                reg_name
+            }
+            None => {
+                // the register used less in the loops goes to the stack
+                let cost =
+                    |reg: &middleend::ir::Register| self.costs.get(reg).copied().unwrap_or(0);
+                let cheapest = self.holders.iter().min_by_key(|(_, x)| (cost(x), x.val()));
+                let (reg_name, spilled) = match cheapest {
+                    Some((reg_name, spilled)) if cost(spilled) < cost(&reg) => {
+                        (*reg_name, *spilled)
+                    }
+                    _ => (0, reg),
+                };
+                let offset = ValueCell::StackOffset(self.stacksize);
+                self.stacksize += 8;
+                self.registers.insert(spilled, offset);
+                if spilled == reg {
+                    return;
                 }
+                reg_name
             }
-        }
-        let (bb_index, inst_index) = place;
+        };
+        self.registers.insert(reg, ValueCell::Register(reg_name));
+        self.holders.insert(reg_name, reg);
+        let (bb_index, inst_index) = end;
         self.release[bb_index][inst_index].push(reg);
     }
 
@@ -193,6 +262,7 @@ impl<'a> LinearAllocator<'a> {
                             break;
                         }
                     }
+                    self.holders.remove(&reg);
                     self.freeowned.push(reg)
                 }
                 _ => (),
@@ -218,3 +288,55 @@ impl RegAllocator for LinearAllocator<'_> {
 #[allow(dead_code)]
 /// This one is a big boy
 pub struct ColoringAllocator;
+
+#[cfg(test)]
+mod tests {
+    use middleend::{
+        analysis::{dataflow::DataFlowAnalysis, live::LiveRegisterAnalysis},
+        ir_parse::parse_ir,
+    };
+
+    use super::*;
+
+    #[test]
+    fn loop_uses_keep_register() {
+        // %0 to %3 are used once after the loop, %4 in every iteration
+        let text = "function f(0) : int {
+BB0:
+%0 : int = ldi 1
+%1 : int = ldi 2
+%2 : int = ldi 3
+%3 : int = ldi 4
+%4 : int = ldi 5
+%5 : int = alloca 8
+store [%5] %4
+jmp BB1
+BB1:
+%8 : int = ld [%5]
+%9 : int = add %8 %4
+store [%5] %9
+%11 : int = lt %9 %4
+branch %11 BB1 BB2
+BB2:
+%13 : int = add %0 %1
+%14 : int = add %2 %3
+%15 : int = add %13 %14
+retr %15
+}";
+        let program = parse_ir(text).unwrap();
+        let store = &program.store;
+        let function = &program.funcs["f"];
+        let liveness = LiveRegisterAnalysis::new(function).analyze(store);
+        let used_ir = function
+            .blocks
+            .iter()
+            .flat_map(|bb| bb.iter())
+            .map(|x| Rd::Ir(*x))
+            .collect();
+        let allocator = LinearAllocator::new(function, used_ir, 0, liveness, store);
+
+        let location = |inst: usize| allocator.get_location(function.blocks[0][inst]);
+        assert!(matches!(location(4), ValueCell::Register(_)));
+        assert!(matches!(location(0), ValueCell::StackOffset(_)));
+    }
+}
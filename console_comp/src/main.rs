@@ -1,22 +1,28 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
     env, fs,
+    path::Path,
 };
 
 use backend::{asm_compile, emit::emit_assembly};
-use frontend::compile;
-use frontend::parse;
+use frontend::format_source;
+use frontend::{
+    compile_module_with, parse_json_program, parse_module_files, program_to_json, runtime_units,
+    CompileOptions, Module, WarningOptions, STDLIB_VERSION,
+};
 use middleend::{
     analysis::{
         analysis::analyze_program,
         anderson::AndersenAnalysis,
         const_mem::{ConstantMemoryAnalysis, MemoryPlace},
+        dominators::{control_dependences, DominatorTree},
         lattice::FlatElem,
         live::LiveRegisterAnalysis,
         possible_mem::PossibleMemAnalysis,
     },
-    ir::{InstUUID, Register},
-    ir_interpret::run,
+    ir::{BBIndex, Function, InstStore, InstUUID, IrProgram, Register},
+    ir_interpret::run_with_args,
+    link::{library_unit, link_with_library},
 };
 
 fn printlive(result: HashMap<String, Vec<Vec<HashSet<Register>>>>) {
@@ -66,26 +72,219 @@ fn printposs(result: HashMap<String, Vec<Vec<HashMap<MemoryPlace, HashSet<Regist
     }
 }
 
+fn printdom(func: &Function, store: &InstStore) {
+    let dom = DominatorTree::new(func, store);
+    let post = DominatorTree::post(func, store);
+    let frontiers = dom.frontiers();
+    let deps = control_dependences(func, store);
+    let view = |bb: Option<BBIndex>| bb.map_or("-".to_string(), |x| format!("BB{}", x));
+    let list = |set: &BTreeSet<BBIndex>| {
+        let blocks: Vec<String> = set.iter().map(|x| format!("BB{}", x)).collect();
+        format!("[{}]", blocks.join(" "))
+    };
+    println!("function {} {{", func.name);
+    for bb in 0..func.blocks.len() {
+        println!(
+            "\tBB{}: idom {}, ipdom {}, frontier {}, controls {}",
+            bb,
+            view(dom.idom(bb)),
+            view(post.idom(bb)),
+            list(&frontiers[bb]),
+            list(&deps[bb])
+        );
+    }
+    println!("}}\n");
+}
+
+/// rewrites the files in the canonical format, with `--check`
+/// only reports the files which are not formatted, the files
+/// which can not be parsed are reported and left as they are
+fn format_files(args: &[String]) {
+    let check = args.first().map_or(false, |x| x == "--check");
+    let paths = if check { &args[1..] } else { args };
+    let mut failed = false;
+    for path in paths {
+        let content: String = fs::read_to_string(path.clone()).unwrap();
+        let formatted = match format_source(content.clone(), path.clone()) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{}: error: {}", path, e);
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == content {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", path);
+            failed = true;
+        } else {
+            fs::write(path, formatted).unwrap();
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// modules of the program, the imported ones first and the root last,
+/// ast exported by --json is a single module without imports
+fn load(path: &String, options: WarningOptions) -> Vec<Module> {
+    if path.ends_with(".json") {
+        let content: String = fs::read_to_string(path.clone()).unwrap();
+        return vec![Module {
+            name: None,
+            path: path.clone(),
+            program: parse_json_program(&content).unwrap(),
+            warnings: vec![],
+        }];
+    }
+
+    let modules = parse_module_files(path, options).unwrap();
+    for module in modules.iter() {
+        for warning in module.warnings.iter() {
+            let loc = warning.loc();
+            eprintln!(
+                "{}:{}:{}: warning: {}",
+                module.path,
+                loc.row() + 1,
+                loc.col() + 1,
+                warning
+            );
+        }
+    }
+    modules
+}
+
+/// the failed bounds checks report the path of the module
+fn compile_modules(modules: Vec<Module>, bounds_check: bool) -> Vec<(String, IrProgram)> {
+    modules
+        .into_iter()
+        .map(|m| {
+            let options = CompileOptions {
+                bounds_check,
+                file: m.path.clone(),
+            };
+            match compile_module_with(m.program, m.name.as_deref(), options) {
+                Ok(unit) => (m.path, unit),
+                Err(e) => {
+                    eprintln!("{}: error: {}", m.path, e);
+                    std::process::exit(1);
+                }
+            }
+        })
+        .collect()
+}
+
+fn compile_units(
+    paths: &[String],
+    options: WarningOptions,
+    bounds_check: bool,
+) -> Vec<(String, IrProgram)> {
+    paths
+        .iter()
+        .flat_map(|path| compile_modules(load(path, options), bounds_check))
+        .collect()
+}
+
+fn library() -> Vec<IrProgram> {
+    runtime_units().into_iter().map(|(_, unit)| unit).collect()
+}
+
+/// the needed part of the runtime is a unit of every
+/// program, it is placed next to the first unit as `runtime`
+fn with_runtime(units: Vec<(String, IrProgram)>) -> Vec<(String, IrProgram)> {
+    let dir = units
+        .first()
+        .and_then(|(path, _)| Path::new(path).parent())
+        .unwrap_or(Path::new(""))
+        .to_path_buf();
+    let programs: Vec<&IrProgram> = units.iter().map(|(_, unit)| unit).collect();
+    let runtime = library_unit(&programs, library()).unwrap();
+    let mut units = units;
+    let path = dir.join("runtime.mc").to_string_lossy().to_string();
+    units.push((path, runtime));
+    units
+}
+
+/// every module is compiled separately into the assembly next to it,
+/// the files are then linked together by the assembler and linker
+fn emit_units(units: Vec<(String, IrProgram)>) {
+    for (path, unit) in with_runtime(units) {
+        let asm_text = emit_assembly(asm_compile(unit));
+        fs::write(Path::new(&path).with_extension("s"), asm_text + "\n").unwrap();
+    }
+}
+
+fn link_units(units: Vec<(String, IrProgram)>) -> IrProgram {
+    let units = units.into_iter().map(|(_, unit)| unit).collect();
+    link_with_library(units, library()).unwrap()
+}
+
+/// runs the program with the arguments given after `--`,
+/// the first source file is the name of the program, errors
+/// of the program are reported and end the compiler
+fn interpret(ir_prog: IrProgram, args: &[String], program_args: &[String]) -> i64 {
+    let argv: Vec<String> = args[2..3].iter().chain(program_args).cloned().collect();
+    match run_with_args(ir_prog, &argv, &mut std::io::stdout()) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}: error: {}", args[2], e);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let program_args = match args.iter().position(|x| x == "--") {
+        Some(index) => args.split_off(index).split_off(1),
+        None => vec![],
+    };
+    if args.len() == 2 && args[1] == "--version" {
+        println!("stdlib {}", STDLIB_VERSION);
+        return;
+    }
+    if args.len() >= 2 && args[1] == "--fmt" {
+        format_files(&args[2..]);
+        return;
+    }
+    let options = WarningOptions {
+        shadow: args.iter().any(|x| x == "-Wshadow"),
+    };
+    let bounds_check = args.iter().any(|x| x == "--bounds-check");
+    args.retain(|x| x != "-Wshadow" && x != "--bounds-check");
+    if args.len() > 3 && args[1] == "--asm" {
+        emit_units(compile_units(&args[2..], options, bounds_check));
+        return;
+    } else if args.len() > 3 && args[1] == "--ir" {
+        let ir_prog = link_units(compile_units(&args[2..], options, bounds_check));
+        println!("{}", ir_prog);
+        println!("{}", interpret(ir_prog, &args, &program_args));
+        return;
+    }
     if args.len() != 3 {
         println!("Wrong number of args");
         return;
     }
-    let path: String = args[2].clone();
-    let content: String = fs::read_to_string(path.clone()).unwrap();
-
-    let prog = parse(content, path).unwrap();
+    let mut modules = load(&args[2], options);
 
     if args[1] == "--parse" {
-        println!("{:?}", prog);
+        println!("{:?}", modules.pop().unwrap().program);
+        return;
+    } else if args[1] == "--json" {
+        println!("{}", program_to_json(&modules.pop().unwrap().program));
+        return;
+    } else if args[1] == "--asm" && modules.len() > 1 {
+        emit_units(compile_modules(modules, bounds_check));
         return;
     }
 
-    let ir_prog = compile(prog).unwrap();
+    let ir_prog = link_units(compile_modules(modules, bounds_check));
     if args[1] == "--ir" {
         println!("{}", ir_prog);
-        let res = run(ir_prog).unwrap();
+        let res = interpret(ir_prog, &args, &program_args);
         println!("{}", res);
     } else if args[1] == "--asm" {
         let asm_prog = asm_compile(ir_prog);
@@ -122,6 +321,13 @@ fn main() {
             }
             println!("}}");
         }
+    } else if args[1] == "--dom" {
+        println!("{}", ir_prog);
+        let mut funcs: Vec<_> = ir_prog.funcs.values().collect();
+        funcs.sort_by(|a, b| a.name.cmp(&b.name));
+        for func in funcs {
+            printdom(func, &ir_prog.store);
+        }
     } else if args[1] == "--poss" {
         println!("{}", ir_prog);
         let poss_analysis =
@@ -1,6 +1,9 @@
 use std::ops::{Deref, DerefMut};
 
-use crate::{lexer::Loc, typeast::TypeDef};
+use crate::{
+    lexer::Loc,
+    typeast::{Layout, TypeDef, TypeTable},
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Operator {
@@ -31,6 +34,7 @@ pub enum Operator {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct AstData {
     loc: Loc,
+    end: Loc,
     pub node_type: Option<TypeDef>,
 }
 
@@ -38,10 +42,25 @@ impl AstData {
     pub fn new(loc: Loc) -> Self {
         Self {
             loc,
+            end: loc,
             node_type: None,
         }
     }
 
+    pub fn loc(&self) -> Loc {
+        self.loc
+    }
+
+    /// location of the last token of the node, it is
+    /// only tracked for nodes ending with closing curly
+    pub fn end(&self) -> Loc {
+        self.end
+    }
+
+    pub fn set_end(&mut self, end: Loc) {
+        self.end = end;
+    }
+
     pub fn set_type(&mut self, t: TypeDef) -> TypeDef {
         self.node_type = Some(t.clone());
         t
@@ -117,6 +136,8 @@ pub enum Val {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Program {
     pub items: Vec<TopLevel>,
+    // structs of the program, filled by the typecheck
+    pub types: TypeTable,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -124,11 +145,31 @@ pub enum TopLevel {
     Function(FnDef),
     Var(VarDecl),
     Structure(StructDef),
+    // declaration of a variable or function defined elsewhere
+    Extern(Box<TopLevel>),
+    Import(Import),
+    // function, variable or struct visible to the importing modules
+    Pub(Box<TopLevel>),
+}
+
+impl TopLevel {
+    pub fn loc(&self) -> Loc {
+        match self {
+            TopLevel::Function(f) => f.loc(),
+            TopLevel::Var(v) => v.loc(),
+            TopLevel::Structure(s) => s.loc(),
+            TopLevel::Extern(item) | TopLevel::Pub(item) => item.loc(),
+            TopLevel::Import(import) => import.loc(),
+        }
+    }
 }
 
 impl Default for Program {
     fn default() -> Self {
-        Self { items: vec![] }
+        Self {
+            items: vec![],
+            types: TypeTable::default(),
+        }
     }
 }
 
@@ -155,6 +196,17 @@ pub enum ExprType {
     Address(Box<Expr>),
     Cast(TypeDef, Box<Expr>),
     FieldAccess(Box<Expr>, String),
+    // offset of the field in bytes
+    OffsetOf(TypeDef, String),
+    // string literal, it is a pointer to read-only chars ended by zero
+    Str(String),
+    // position of the first variadic argument is stored into the va_list
+    VaStart(Box<Expr>),
+    // next variadic argument of the type
+    VaArg(Box<Expr>, TypeDef),
+    VaEnd(Box<Expr>),
+    // condition checked at runtime, the failure reports the file and line
+    Assert(Box<Expr>, String),
 }
 
 pub type VarDecl = AstNode<VarDeclType>;
@@ -189,6 +241,8 @@ pub enum StatementType {
         Box<Statement>,
     ),
     While(Expr, Box<Statement>),
+    // local variable kept between the calls
+    Static(VarDecl),
     Break,
     Continue,
     Return(Option<Box<Expr>>),
@@ -201,6 +255,8 @@ pub struct FnDeclType {
     pub name: String,
     pub params: Vec<(String, TypeDef)>,
     pub ret_type: TypeDef,
+    // more arguments can follow the params
+    pub variadic: bool,
 }
 
 pub type FnDef = AstNode<FnDefType>;
@@ -213,28 +269,14 @@ pub struct FnDefType {
 
 pub type StructDef = AstNode<StructDefType>;
 
+/// path of the imported module as written in the source
+pub type Import = AstNode<String>;
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct StructDefType {
     pub name: String,
-    pub fields: Option<Vec<VarDecl>>,
-}
-
-impl StructDefType {
-    pub fn field_type(&self, field: &String) -> Option<TypeDef> {
-        if let Some(fields) = &self.fields {
-            let f = fields.iter().find(|x| &x.name == field)?;
-            Some(f.var_type.clone())
-        } else {
-            None
-        }
-    }
-}
-
-impl TypeDef {
-    pub fn sized(&self) -> bool {
-        match self {
-            TypeDef::Struct(s) => s.fields.is_some(),
-            _ => true,
-        }
-    }
+    pub is_union: bool,
+    pub layout: Layout,
+    // fields with their own attributes
+    pub fields: Option<Vec<(Layout, VarDecl)>>,
 }
@@ -0,0 +1,846 @@
+//! Json export and import of the (typed) ast.
+//!
+//! The document has the form `{"version": 1, "items": [top level], "structs": [struct]}`,
+//! the version is [`AST_SCHEMA_VERSION`] and it is bumped on every
+//! incompatible change of the format below once it is released.
+//!
+//! Every node is an object with the `kind` field selecting the
+//! variant. Nodes of the ast additionally carry
+//! - `loc`: `[row, col, position]` of the first token (0 based)
+//! - `end`: the same for the closing curly, only for blocks and structs
+//! - `type`: type of the node given by the typecheck or `null`
+//!
+//! `loc`, `end` and `type` may be left out when the ast is
+//! generated, the types are computed again on import.
+//!
+//! Top level items
+//! - `Function`: `header` (`name`, `params: [{name, type}]`, `ret_type`, `variadic`, `loc`,
+//!   `type`), `body`
+//! - `Var`: variable declaration
+//! - `Struct`: `name`, `union`, `layout`, `fields` (array of variable
+//!   declarations with their own `layout` or `null`)
+//! - `Extern`: `item` (`Function` without body or `Var`)
+//! - `Import`: `path`
+//! - `Pub`: `item` (`Function`, `Var` or `Struct`)
+//!
+//! Variable declaration is `{name, var_type, init}` with the `kind` `VarDecl`.
+//!
+//! Layout attributes are `{packed, aligned}` with `aligned` being a number
+//! or `null`, the `layout`, `union` and `variadic` fields may be left out.
+//!
+//! `structs` is the type table filled by the typecheck, every struct is
+//! `{name, union, layout, size, align, fields}` where `fields` is
+//! `[{name, type, layout, offset}]` or `null` for the declared struct, it
+//! may be left out together with the types. Sizes and offsets are only
+//! informative, they are computed again on import.
+//!
+//! Statements
+//! - `Expr`: `expr`
+//! - `VarDecl`: fields of the declaration
+//! - `Block`: `body`
+//! - `If`: `cond`, `then`
+//! - `IfElse`: `cond`, `then`, `else`
+//! - `For`: `init`, `cond`, `update`, `body` (first three can be `null`)
+//! - `While`: `cond`, `body`
+//! - `Static`: `decl` (variable declaration)
+//! - `Break`, `Continue`
+//! - `Return`: `expr` (can be `null`)
+//!
+//! Expressions
+//! - `BinOp`: `op`, `left`, `right`
+//! - `UnaryPreOp`, `UnaryPostOp`: `op`, `expr`
+//! - `Int`: `value` number, `Char`: `value` string with one character
+//! - `Ident`: `name`
+//! - `Call`: `target`, `args`
+//! - `SysCall`: `number`, `args`
+//! - `Index`: `expr`, `index`
+//! - `Deref`, `Address`: `expr`
+//! - `Cast`: `target_type`, `expr`
+//! - `FieldAccess`: `expr`, `field`
+//! - `OffsetOf`: `target_type`, `field`
+//! - `Str`: `value` string
+//! - `VaStart`, `VaEnd`: `expr` (the va_list)
+//! - `VaArg`: `expr`, `target_type`
+//! - `Assert`: `expr`, `file`
+//!
+//! Operators are written by name (`Add`, `Sub`, `Assign`, ...).
+//!
+//! Types
+//! - `Void`, `Int`, `Char`
+//! - `Pointer`: `inner`
+//! - `Const`: `inner`
+//! - `Array`: `inner`, `size`
+//! - `Function`: `params`, `ret_type`, `body_def`, `variadic`
+//! - `Alias`: `name`
+//! - `Struct`: `id` (index into `structs`), `name`
+
+use crate::{
+    ast::{
+        AstData, Expr, ExprType, FnDecl, FnDeclType, FnDef, FnDefType, Import, Operator, Program,
+        Statement, StatementType, StructDef, StructDefType, TopLevel, Val, VarDecl, VarDeclType,
+    },
+    errors::JsonError,
+    json::JsonValue,
+    lexer::Loc,
+    typeast::{ArrayType, FnType, Layout, PrimType, TypeDef, TypeId, TypeTable},
+};
+
+pub const AST_SCHEMA_VERSION: i64 = 1;
+
+const OPERATORS: [Operator; 22] = [
+    Operator::Add,
+    Operator::Sub,
+    Operator::Mul,
+    Operator::Div,
+    Operator::Inc,
+    Operator::Dec,
+    Operator::Lt,
+    Operator::Le,
+    Operator::Gt,
+    Operator::Ge,
+    Operator::Eql,
+    Operator::Neq,
+    Operator::Assign,
+    Operator::BitOr,
+    Operator::Or,
+    Operator::BitAnd,
+    Operator::And,
+    Operator::Not,
+    Operator::BitNot,
+    Operator::Mod,
+    Operator::ShiftLeft,
+    Operator::ShiftRight,
+];
+
+pub fn program_to_json(program: &Program) -> JsonValue {
+    JsonValue::object([
+        ("version", AST_SCHEMA_VERSION.into()),
+        (
+            "items",
+            program.items.iter().map(top_to_json).collect::<Vec<_>>().into(),
+        ),
+        ("structs", types_to_json(&program.types)),
+    ])
+}
+
+fn layout_to_json(layout: &Layout) -> JsonValue {
+    JsonValue::object([
+        ("packed", layout.packed.into()),
+        ("aligned", layout.aligned.into()),
+    ])
+}
+
+fn layout_from_json(value: &JsonValue) -> Result<Layout, JsonError> {
+    let Some(layout) = value.get_opt("layout") else {
+        return Ok(Layout::default());
+    };
+    Ok(Layout {
+        packed: layout.get_opt("packed").map_or(Ok(false), |x| x.as_bool())?,
+        aligned: match layout.get_opt("aligned") {
+            Some(align) => Some(align.as_i64()? as usize),
+            None => None,
+        },
+    })
+}
+
+fn is_union(value: &JsonValue) -> Result<bool, JsonError> {
+    value.get_opt("union").map_or(Ok(false), |x| x.as_bool())
+}
+
+fn types_to_json(types: &TypeTable) -> JsonValue {
+    let structs = types.structs().iter().map(|s| {
+        let fields = s.fields.as_ref().map(|fields| {
+            let fields = fields.iter().map(|field| {
+                JsonValue::object([
+                    ("name", field.name.as_str().into()),
+                    ("type", type_to_json(&field.field_type)),
+                    ("layout", layout_to_json(&field.layout)),
+                    ("offset", field.offset.into()),
+                ])
+            });
+            fields.collect::<Vec<_>>()
+        });
+        JsonValue::object([
+            ("name", s.name.as_str().into()),
+            ("union", s.is_union.into()),
+            ("layout", layout_to_json(&s.layout)),
+            ("size", s.size.into()),
+            ("align", s.align.into()),
+            ("fields", fields.into()),
+        ])
+    });
+    structs.collect::<Vec<_>>().into()
+}
+
+fn types_from_json(value: Option<&JsonValue>) -> Result<TypeTable, JsonError> {
+    let mut types = TypeTable::default();
+    let structs = match value {
+        Some(value) => value.as_array()?.as_slice(),
+        None => &[],
+    };
+    for s in structs {
+        let id = types.add_struct(&string(s, "name")?);
+        if let Some(fields) = s.get_opt("fields") {
+            let fields = fields
+                .as_array()?
+                .iter()
+                .map(|field| {
+                    Ok((
+                        string(field, "name")?,
+                        type_from_json(field.get("type")?)?,
+                        layout_from_json(field)?,
+                    ))
+                })
+                .collect::<Result<_, JsonError>>()?;
+            types.complete(&id, is_union(s)?, layout_from_json(s)?, fields);
+        }
+    }
+    Ok(types)
+}
+
+pub fn program_from_json(value: &JsonValue) -> Result<Program, JsonError> {
+    let version = value.get("version")?.as_i64()?;
+    if version != AST_SCHEMA_VERSION {
+        return Err(JsonError::UnsupportedVersion(version));
+    }
+    let items = value
+        .get("items")?
+        .as_array()?
+        .iter()
+        .map(top_from_json)
+        .collect::<Result<_, _>>()?;
+    let types = types_from_json(value.get_opt("structs"))?;
+    Ok(Program { items, types })
+}
+
+/// compares the programs without the locations of the nodes,
+/// the same program written differently is still the same program
+pub fn same_syntax(left: &Program, right: &Program) -> bool {
+    fn strip(value: JsonValue) -> JsonValue {
+        match value {
+            JsonValue::Object(map) => JsonValue::Object(
+                map.into_iter()
+                    .filter(|(key, _)| key != "loc" && key != "end")
+                    .map(|(key, value)| (key, strip(value)))
+                    .collect(),
+            ),
+            JsonValue::Array(values) => JsonValue::Array(values.into_iter().map(strip).collect()),
+            value => value,
+        }
+    }
+    strip(program_to_json(left)) == strip(program_to_json(right))
+}
+
+fn loc_to_json(loc: Loc) -> JsonValue {
+    vec![loc.row().into(), loc.col().into(), loc.position().into()].into()
+}
+
+fn loc_from_json(value: &JsonValue) -> Result<Loc, JsonError> {
+    match value.as_array()?.as_slice() {
+        [row, col, position] => Ok(Loc::new(
+            row.as_i64()? as usize,
+            col.as_i64()? as usize,
+            position.as_i64()? as usize,
+        )),
+        _ => Err(JsonError::InvalidValue("loc".to_string())),
+    }
+}
+
+/// creates node object from the kind, fields and the ast data
+fn node<const N: usize>(kind: &str, data: &AstData, fields: [(&str, JsonValue); N]) -> JsonValue {
+    let mut result = JsonValue::object(fields);
+    if let JsonValue::Object(map) = &mut result {
+        map.insert("kind".to_string(), kind.into());
+        map.insert("loc".to_string(), loc_to_json(data.loc()));
+        if data.end() != data.loc() {
+            map.insert("end".to_string(), loc_to_json(data.end()));
+        }
+        map.insert(
+            "type".to_string(),
+            data.node_type.as_ref().map(type_to_json).into(),
+        );
+    }
+    result
+}
+
+fn data_from_json(value: &JsonValue) -> Result<AstData, JsonError> {
+    let loc = match value.get_opt("loc") {
+        Some(loc) => loc_from_json(loc)?,
+        None => Loc::default(),
+    };
+    let mut data = AstData::new(loc);
+    if let Some(end) = value.get_opt("end") {
+        data.set_end(loc_from_json(end)?);
+    }
+    if let Some(t) = value.get_opt("type") {
+        data.set_type(type_from_json(t)?);
+    }
+    Ok(data)
+}
+
+fn kind(value: &JsonValue) -> Result<&str, JsonError> {
+    value.get("kind")?.as_str()
+}
+
+fn string(value: &JsonValue, field: &str) -> Result<String, JsonError> {
+    Ok(value.get(field)?.as_str()?.to_string())
+}
+
+fn op_from_json(value: &JsonValue) -> Result<Operator, JsonError> {
+    let name = value.as_str()?;
+    OPERATORS
+        .into_iter()
+        .find(|op| format!("{:?}", op) == name)
+        .ok_or_else(|| JsonError::UnknownKind(name.to_string()))
+}
+
+fn op_to_json(op: &Operator) -> JsonValue {
+    format!("{:?}", op).as_str().into()
+}
+
+pub fn type_to_json(type_def: &TypeDef) -> JsonValue {
+    match type_def {
+        TypeDef::Void => JsonValue::object([("kind", "Void".into())]),
+        TypeDef::PrimType(PrimType::Int) => JsonValue::object([("kind", "Int".into())]),
+        TypeDef::PrimType(PrimType::Char) => JsonValue::object([("kind", "Char".into())]),
+        TypeDef::PointerType(inner) => {
+            JsonValue::object([("kind", "Pointer".into()), ("inner", type_to_json(inner))])
+        }
+        TypeDef::Const(inner) => {
+            JsonValue::object([("kind", "Const".into()), ("inner", type_to_json(inner))])
+        }
+        TypeDef::Array(arr) => JsonValue::object([
+            ("kind", "Array".into()),
+            ("inner", type_to_json(&arr.inner_type)),
+            ("size", arr.index.into()),
+        ]),
+        TypeDef::Function(f) => JsonValue::object([
+            ("kind", "Function".into()),
+            (
+                "params",
+                f.params.iter().map(type_to_json).collect::<Vec<_>>().into(),
+            ),
+            ("ret_type", type_to_json(&f.ret_type)),
+            ("body_def", f.body_def.into()),
+            ("variadic", f.variadic.into()),
+        ]),
+        TypeDef::Alias(name) => {
+            JsonValue::object([("kind", "Alias".into()), ("name", name.as_str().into())])
+        }
+        TypeDef::Struct(id) => JsonValue::object([
+            ("kind", "Struct".into()),
+            ("id", id.index().into()),
+            ("name", id.name().into()),
+        ]),
+    }
+}
+
+pub fn type_from_json(value: &JsonValue) -> Result<TypeDef, JsonError> {
+    Ok(match kind(value)? {
+        "Void" => TypeDef::Void,
+        "Int" => TypeDef::PrimType(PrimType::Int),
+        "Char" => TypeDef::PrimType(PrimType::Char),
+        "Pointer" => TypeDef::PointerType(Box::new(type_from_json(value.get("inner")?)?)),
+        "Const" => TypeDef::Const(Box::new(type_from_json(value.get("inner")?)?)),
+        "Array" => TypeDef::Array(ArrayType {
+            inner_type: Box::new(type_from_json(value.get("inner")?)?),
+            index: value.get("size")?.as_i64()? as usize,
+        }),
+        "Function" => TypeDef::Function(FnType {
+            params: value
+                .get("params")?
+                .as_array()?
+                .iter()
+                .map(type_from_json)
+                .collect::<Result<_, _>>()?,
+            ret_type: Box::new(type_from_json(value.get("ret_type")?)?),
+            body_def: value.get("body_def")?.as_bool()?,
+            variadic: variadic_from_json(value)?,
+        }),
+        "Alias" => TypeDef::Alias(string(value, "name")?),
+        "Struct" => TypeDef::Struct(TypeId::new(
+            value.get("id")?.as_i64()? as usize,
+            &string(value, "name")?,
+        )),
+        other => return Err(JsonError::UnknownKind(other.to_string())),
+    })
+}
+
+fn field_to_json((layout, field): &(Layout, VarDecl)) -> JsonValue {
+    let mut result = var_to_json(field);
+    if let JsonValue::Object(map) = &mut result {
+        map.insert("layout".to_string(), layout_to_json(layout));
+    }
+    result
+}
+
+fn fields_to_json(fields: &Option<Vec<(Layout, VarDecl)>>) -> JsonValue {
+    fields
+        .as_ref()
+        .map(|fields| fields.iter().map(field_to_json).collect::<Vec<_>>())
+        .into()
+}
+
+fn fields_from_json(value: &JsonValue) -> Result<Option<Vec<(Layout, VarDecl)>>, JsonError> {
+    value
+        .get_opt("fields")
+        .map(|fields| {
+            let fields = fields.as_array()?.iter();
+            fields
+                .map(|field| Ok((layout_from_json(field)?, var_from_json(field)?)))
+                .collect()
+        })
+        .transpose()
+}
+
+fn top_to_json(item: &TopLevel) -> JsonValue {
+    match item {
+        TopLevel::Function(f) => node(
+            "Function",
+            &f.data,
+            [
+                ("header", header_to_json(&f.header)),
+                ("body", f.body.as_ref().map(stmt_to_json).into()),
+            ],
+        ),
+        TopLevel::Var(v) => {
+            let mut result = var_to_json(v);
+            if let JsonValue::Object(map) = &mut result {
+                map.insert("kind".to_string(), "Var".into());
+            }
+            result
+        }
+        TopLevel::Structure(s) => node(
+            "Struct",
+            &s.data,
+            [
+                ("name", s.name.as_str().into()),
+                ("union", s.is_union.into()),
+                ("layout", layout_to_json(&s.layout)),
+                ("fields", fields_to_json(&s.fields)),
+            ],
+        ),
+        TopLevel::Extern(item) => {
+            JsonValue::object([("kind", "Extern".into()), ("item", top_to_json(item))])
+        }
+        TopLevel::Import(import) => {
+            node("Import", &import.data, [("path", import.value.as_str().into())])
+        }
+        TopLevel::Pub(item) => {
+            JsonValue::object([("kind", "Pub".into()), ("item", top_to_json(item))])
+        }
+    }
+}
+
+fn top_from_json(value: &JsonValue) -> Result<TopLevel, JsonError> {
+    Ok(match kind(value)? {
+        "Function" => TopLevel::Function(FnDef::new(
+            FnDefType {
+                header: header_from_json(value.get("header")?)?,
+                body: value.get_opt("body").map(stmt_from_json).transpose()?,
+            },
+            data_from_json(value)?,
+        )),
+        "Var" => TopLevel::Var(var_from_json(value)?),
+        "Extern" => match top_from_json(value.get("item")?)? {
+            item @ (TopLevel::Function(_) | TopLevel::Var(_)) => TopLevel::Extern(Box::new(item)),
+            _ => return Err(JsonError::InvalidValue("item".to_string())),
+        },
+        "Import" => TopLevel::Import(Import::new(string(value, "path")?, data_from_json(value)?)),
+        "Pub" => match top_from_json(value.get("item")?)? {
+            item @ (TopLevel::Function(_) | TopLevel::Var(_) | TopLevel::Structure(_)) => {
+                TopLevel::Pub(Box::new(item))
+            }
+            _ => return Err(JsonError::InvalidValue("item".to_string())),
+        },
+        "Struct" => TopLevel::Structure(StructDef::new(
+            StructDefType {
+                name: string(value, "name")?,
+                is_union: is_union(value)?,
+                layout: layout_from_json(value)?,
+                fields: fields_from_json(value)?,
+            },
+            data_from_json(value)?,
+        )),
+        other => return Err(JsonError::UnknownKind(other.to_string())),
+    })
+}
+
+fn variadic_from_json(value: &JsonValue) -> Result<bool, JsonError> {
+    value.get_opt("variadic").map_or(Ok(false), |x| x.as_bool())
+}
+
+fn header_to_json(header: &FnDecl) -> JsonValue {
+    let params = header
+        .params
+        .iter()
+        .map(|(name, t)| JsonValue::object([("name", name.as_str().into()), ("type", type_to_json(t))]))
+        .collect::<Vec<_>>();
+    node(
+        "FnDecl",
+        &header.data,
+        [
+            ("name", header.name.as_str().into()),
+            ("params", params.into()),
+            ("ret_type", type_to_json(&header.ret_type)),
+            ("variadic", header.variadic.into()),
+        ],
+    )
+}
+
+fn header_from_json(value: &JsonValue) -> Result<FnDecl, JsonError> {
+    let params = value
+        .get("params")?
+        .as_array()?
+        .iter()
+        .map(|param| Ok((string(param, "name")?, type_from_json(param.get("type")?)?)))
+        .collect::<Result<_, JsonError>>()?;
+    Ok(FnDecl::new(
+        FnDeclType {
+            name: string(value, "name")?,
+            params,
+            ret_type: type_from_json(value.get("ret_type")?)?,
+            variadic: variadic_from_json(value)?,
+        },
+        data_from_json(value)?,
+    ))
+}
+
+fn var_to_json(var: &VarDecl) -> JsonValue {
+    node(
+        "VarDecl",
+        &var.data,
+        [
+            ("name", var.name.as_str().into()),
+            ("var_type", type_to_json(&var.var_type)),
+            ("init", var.init_val.as_ref().map(expr_to_json).into()),
+        ],
+    )
+}
+
+fn var_from_json(value: &JsonValue) -> Result<VarDecl, JsonError> {
+    Ok(VarDecl::new(
+        VarDeclType {
+            name: string(value, "name")?,
+            var_type: type_from_json(value.get("var_type")?)?,
+            init_val: value.get_opt("init").map(expr_from_json).transpose()?,
+        },
+        data_from_json(value)?,
+    ))
+}
+
+fn boxed_stmt(value: &JsonValue, field: &str) -> Result<Box<Statement>, JsonError> {
+    Ok(Box::new(stmt_from_json(value.get(field)?)?))
+}
+
+fn opt_stmt(value: &JsonValue, field: &str) -> Result<Option<Box<Statement>>, JsonError> {
+    value
+        .get_opt(field)
+        .map(|x| Ok(Box::new(stmt_from_json(x)?)))
+        .transpose()
+}
+
+pub fn stmt_to_json(stmt: &Statement) -> JsonValue {
+    let data = &stmt.data;
+    match &stmt.value {
+        StatementType::Expr(e) => node("Expr", data, [("expr", expr_to_json(e))]),
+        StatementType::VarDecl(v) => var_to_json(v),
+        StatementType::Block(stmts) => node(
+            "Block",
+            data,
+            [("body", stmts.iter().map(stmt_to_json).collect::<Vec<_>>().into())],
+        ),
+        StatementType::If(cond, then) => node(
+            "If",
+            data,
+            [("cond", expr_to_json(cond)), ("then", stmt_to_json(then))],
+        ),
+        StatementType::IfElse(cond, then, other) => node(
+            "IfElse",
+            data,
+            [
+                ("cond", expr_to_json(cond)),
+                ("then", stmt_to_json(then)),
+                ("else", stmt_to_json(other)),
+            ],
+        ),
+        StatementType::For(init, cond, update, body) => node(
+            "For",
+            data,
+            [
+                ("init", init.as_ref().map(|x| stmt_to_json(x)).into()),
+                ("cond", cond.as_ref().map(expr_to_json).into()),
+                ("update", update.as_ref().map(|x| stmt_to_json(x)).into()),
+                ("body", stmt_to_json(body)),
+            ],
+        ),
+        StatementType::While(cond, body) => node(
+            "While",
+            data,
+            [("cond", expr_to_json(cond)), ("body", stmt_to_json(body))],
+        ),
+        StatementType::Static(v) => node("Static", data, [("decl", var_to_json(v))]),
+        StatementType::Break => node("Break", data, []),
+        StatementType::Continue => node("Continue", data, []),
+        StatementType::Return(e) => node(
+            "Return",
+            data,
+            [("expr", e.as_ref().map(|x| expr_to_json(x)).into())],
+        ),
+    }
+}
+
+pub fn stmt_from_json(value: &JsonValue) -> Result<Statement, JsonError> {
+    let stmt_type = match kind(value)? {
+        "Expr" => StatementType::Expr(expr_from_json(value.get("expr")?)?),
+        "VarDecl" => StatementType::VarDecl(var_from_json(value)?),
+        "Block" => StatementType::Block(
+            value
+                .get("body")?
+                .as_array()?
+                .iter()
+                .map(stmt_from_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        "If" => StatementType::If(expr_from_json(value.get("cond")?)?, boxed_stmt(value, "then")?),
+        "IfElse" => StatementType::IfElse(
+            expr_from_json(value.get("cond")?)?,
+            boxed_stmt(value, "then")?,
+            boxed_stmt(value, "else")?,
+        ),
+        "For" => StatementType::For(
+            opt_stmt(value, "init")?,
+            value.get_opt("cond").map(expr_from_json).transpose()?,
+            opt_stmt(value, "update")?,
+            boxed_stmt(value, "body")?,
+        ),
+        "While" => {
+            StatementType::While(expr_from_json(value.get("cond")?)?, boxed_stmt(value, "body")?)
+        }
+        "Static" => StatementType::Static(var_from_json(value.get("decl")?)?),
+        "Break" => StatementType::Break,
+        "Continue" => StatementType::Continue,
+        "Return" => StatementType::Return(
+            value
+                .get_opt("expr")
+                .map(|x| Ok(Box::new(expr_from_json(x)?)))
+                .transpose()?,
+        ),
+        other => return Err(JsonError::UnknownKind(other.to_string())),
+    };
+    Ok(Statement::new(stmt_type, data_from_json(value)?))
+}
+
+fn exprs_to_json(exprs: &[Expr]) -> JsonValue {
+    exprs.iter().map(expr_to_json).collect::<Vec<_>>().into()
+}
+
+fn exprs_from_json(value: &JsonValue) -> Result<Vec<Expr>, JsonError> {
+    value.as_array()?.iter().map(expr_from_json).collect()
+}
+
+fn boxed_expr(value: &JsonValue, field: &str) -> Result<Box<Expr>, JsonError> {
+    Ok(Box::new(expr_from_json(value.get(field)?)?))
+}
+
+pub fn expr_to_json(expr: &Expr) -> JsonValue {
+    let data = &expr.data;
+    match &expr.value {
+        ExprType::BinOp(op, left, right) => node(
+            "BinOp",
+            data,
+            [
+                ("op", op_to_json(op)),
+                ("left", expr_to_json(left)),
+                ("right", expr_to_json(right)),
+            ],
+        ),
+        ExprType::UnaryPreOp(op, e) => node(
+            "UnaryPreOp",
+            data,
+            [("op", op_to_json(op)), ("expr", expr_to_json(e))],
+        ),
+        ExprType::UnaryPostOp(op, e) => node(
+            "UnaryPostOp",
+            data,
+            [("op", op_to_json(op)), ("expr", expr_to_json(e))],
+        ),
+        ExprType::Value(Val::Integer(n)) => node("Int", data, [("value", (*n).into())]),
+        ExprType::Value(Val::Char(c)) => {
+            node("Char", data, [("value", c.to_string().as_str().into())])
+        }
+        ExprType::Ident(name) => node("Ident", data, [("name", name.as_str().into())]),
+        ExprType::Call(target, args) => node(
+            "Call",
+            data,
+            [("target", expr_to_json(target)), ("args", exprs_to_json(args))],
+        ),
+        ExprType::SysCall(number, args) => node(
+            "SysCall",
+            data,
+            [("number", (*number).into()), ("args", exprs_to_json(args))],
+        ),
+        ExprType::Index(e, index) => node(
+            "Index",
+            data,
+            [("expr", expr_to_json(e)), ("index", expr_to_json(index))],
+        ),
+        ExprType::Deref(e) => node("Deref", data, [("expr", expr_to_json(e))]),
+        ExprType::Address(e) => node("Address", data, [("expr", expr_to_json(e))]),
+        ExprType::Cast(t, e) => node(
+            "Cast",
+            data,
+            [("target_type", type_to_json(t)), ("expr", expr_to_json(e))],
+        ),
+        ExprType::FieldAccess(e, field) => node(
+            "FieldAccess",
+            data,
+            [("expr", expr_to_json(e)), ("field", field.as_str().into())],
+        ),
+        ExprType::OffsetOf(t, field) => node(
+            "OffsetOf",
+            data,
+            [("target_type", type_to_json(t)), ("field", field.as_str().into())],
+        ),
+        ExprType::Str(s) => node("Str", data, [("value", s.as_str().into())]),
+        ExprType::VaStart(list) => node("VaStart", data, [("expr", expr_to_json(list))]),
+        ExprType::VaArg(list, t) => node(
+            "VaArg",
+            data,
+            [("expr", expr_to_json(list)), ("target_type", type_to_json(t))],
+        ),
+        ExprType::VaEnd(list) => node("VaEnd", data, [("expr", expr_to_json(list))]),
+        ExprType::Assert(cond, file) => node(
+            "Assert",
+            data,
+            [("expr", expr_to_json(cond)), ("file", file.as_str().into())],
+        ),
+    }
+}
+
+pub fn expr_from_json(value: &JsonValue) -> Result<Expr, JsonError> {
+    let expr_type = match kind(value)? {
+        "BinOp" => ExprType::BinOp(
+            op_from_json(value.get("op")?)?,
+            boxed_expr(value, "left")?,
+            boxed_expr(value, "right")?,
+        ),
+        "UnaryPreOp" => {
+            ExprType::UnaryPreOp(op_from_json(value.get("op")?)?, boxed_expr(value, "expr")?)
+        }
+        "UnaryPostOp" => {
+            ExprType::UnaryPostOp(op_from_json(value.get("op")?)?, boxed_expr(value, "expr")?)
+        }
+        "Int" => ExprType::Value(Val::Integer(value.get("value")?.as_i64()?)),
+        "Char" => {
+            let mut chars = value.get("value")?.as_str()?.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => ExprType::Value(Val::Char(c)),
+                _ => return Err(JsonError::InvalidValue("value".to_string())),
+            }
+        }
+        "Ident" => ExprType::Ident(string(value, "name")?),
+        "Call" => ExprType::Call(
+            boxed_expr(value, "target")?,
+            exprs_from_json(value.get("args")?)?,
+        ),
+        "SysCall" => ExprType::SysCall(
+            value.get("number")?.as_i64()?,
+            exprs_from_json(value.get("args")?)?,
+        ),
+        "Index" => ExprType::Index(boxed_expr(value, "expr")?, boxed_expr(value, "index")?),
+        "Deref" => ExprType::Deref(boxed_expr(value, "expr")?),
+        "Address" => ExprType::Address(boxed_expr(value, "expr")?),
+        "Cast" => ExprType::Cast(
+            type_from_json(value.get("target_type")?)?,
+            boxed_expr(value, "expr")?,
+        ),
+        "FieldAccess" => ExprType::FieldAccess(boxed_expr(value, "expr")?, string(value, "field")?),
+        "OffsetOf" => ExprType::OffsetOf(
+            type_from_json(value.get("target_type")?)?,
+            string(value, "field")?,
+        ),
+        "Str" => ExprType::Str(string(value, "value")?),
+        "VaStart" => ExprType::VaStart(boxed_expr(value, "expr")?),
+        "VaArg" => ExprType::VaArg(
+            boxed_expr(value, "expr")?,
+            type_from_json(value.get("target_type")?)?,
+        ),
+        "VaEnd" => ExprType::VaEnd(boxed_expr(value, "expr")?),
+        "Assert" => ExprType::Assert(boxed_expr(value, "expr")?, string(value, "file")?),
+        other => return Err(JsonError::UnknownKind(other.to_string())),
+    };
+    Ok(Expr::new(expr_type, data_from_json(value)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{json::parse_json, parse, parse_json_program};
+
+    fn round_trip(input: &str) {
+        let program = parse(input.to_string(), "tmp".to_string()).unwrap();
+        let text = program_to_json(&program).to_string();
+        let value = parse_json(&text).unwrap();
+        assert_eq!(program_from_json(&value).unwrap(), program);
+        // types are computed again when the program is imported
+        assert_eq!(parse_json_program(&text).unwrap(), program);
+    }
+
+    #[test]
+    fn json_ast_round_trip() {
+        round_trip("int main() { int x = 1; x = x + 2 * 3; return x; }");
+        round_trip("struct A { int a; char b; } int f(A a) { return a.a; }");
+        round_trip(
+            "int g; int f(int a, char* b); int f(int a, char* b) { int arr[5]; for (int i = 0; i < 5; i++) { arr[i] = -i; } while (a) { if (a > 2) break; else continue; } @(1, a, 'x'); return cast<int>(b[0]); }",
+        );
+        round_trip("pub struct A { int a; } pub int x; pub int f() { return x; }");
+        round_trip(
+            "struct packed P { char c; aligned(16) int x; } union U { P p; int w[3]; } int main() { U u; return offsetof(P, x) + u.w[1]; }",
+        );
+        round_trip(
+            "int f(int n, ...) { va_list ap; va_start(ap, n); char* s = va_arg(ap, char*); va_end(ap); return printf(\"%s\\n\", s); }",
+        );
+    }
+
+    #[test]
+    fn json_ast_import() {
+        let text = r#"{"version": 1, "items": [{"kind": "Import", "path": "lib/a.mc"}]}"#;
+        let program = program_from_json(&parse_json(text).unwrap()).unwrap();
+        assert!(matches!(&program.items[0], TopLevel::Import(import) if import.value == "lib/a.mc"));
+        let exported = program_to_json(&program).to_string();
+        assert_eq!(program_from_json(&parse_json(&exported).unwrap()).unwrap(), program);
+
+        let text = r#"{"version": 1, "items": [{"kind": "Pub", "item": {"kind": "Import", "path": "a"}}]}"#;
+        assert_eq!(
+            program_from_json(&parse_json(text).unwrap()),
+            Err(JsonError::InvalidValue("item".to_string()))
+        );
+    }
+
+    #[test]
+    fn json_ast_generated() {
+        // hand written ast without locations and types
+        let text = r#"{"version": 1, "items": [{"kind": "Function",
+            "header": {"name": "main", "params": [], "ret_type": {"kind": "Int"}},
+            "body": {"kind": "Block", "body": [
+                {"kind": "Return", "expr": {"kind": "BinOp", "op": "Add",
+                    "left": {"kind": "Int", "value": 1}, "right": {"kind": "Int", "value": 2}}}
+            ]}}]}"#;
+        let program = parse_json_program(text).unwrap();
+        let expected = parse("int main() { return 1 + 2; }".to_string(), "tmp".to_string()).unwrap();
+        assert!(same_syntax(&program, &expected));
+    }
+
+    #[test]
+    fn json_ast_errors() {
+        assert_eq!(
+            parse_json_program(r#"{"version": 0, "items": []}"#),
+            Err(JsonError::UnsupportedVersion(0).into())
+        );
+        assert_eq!(
+            parse_json_program(r#"{"version": 1, "items": [{"kind": "Foo"}]}"#),
+            Err(JsonError::UnknownKind("Foo".to_string()).into())
+        );
+    }
+}
@@ -1,23 +1,57 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
 
 use crate::{
     ast::{
         Expr, ExprType, FnDef, Operator, Program, Statement, StatementType, TopLevel, Val, VarDecl,
     },
-    typeast::{PrimType, TypeDef},
+    format::format_expr,
+    typeast::{PrimType, TypeDef, TypeTable},
 };
 
 use middleend::{
     builder::{FunctionBuilder, IrBuilder, IrBuilderError},
     inst::{
-        ImmC, ImmI, ImmIRegs, InstructionType, Reg, RegReg, RegRegImm, RegRegs, SymRegs,
+        ImmC, ImmI, ImmIRegs, ImmS, InstructionType, Reg, RegReg, RegRegImm, RegRegs, SymRegs,
         Terminator, TerminatorBranch, TerminatorJump, TerminatorReg,
     },
-    ir::{IrProgram, RegType, Register},
+    ir::{BBIndex, IrProgram, RegType, Register},
+    syscall::{Syscall, Target},
+    BOUNDS_FAIL,
 };
 
 pub fn compile(program: Program) -> Result<IrProgram, IrCompErr> {
-    let mut compiler = IrCompiler::default();
+    compile_module(program, None)
+}
+
+/// Compiles one module of the program, its functions and globals
+/// get the symbols `module.name` so the modules can be linked together
+pub fn compile_module(program: Program, module: Option<&str>) -> Result<IrProgram, IrCompErr> {
+    compile_module_with(program, module, CompileOptions::default())
+}
+
+/// Optional code generation of the compiler
+#[derive(Debug, Default, Clone)]
+pub struct CompileOptions {
+    /// array indexes are checked before the access, the failed
+    /// check reports `file` with the location and aborts
+    pub bounds_check: bool,
+    pub file: String,
+}
+
+/// Same as [`compile_module`] with the code generation set by the options
+pub fn compile_module_with(
+    program: Program,
+    module: Option<&str>,
+    options: CompileOptions,
+) -> Result<IrProgram, IrCompErr> {
+    let mut compiler = IrCompiler {
+        module: module.map(|x| x.to_string()),
+        options,
+        ..Default::default()
+    };
     compiler.compile(program)
 }
 
@@ -31,9 +65,23 @@ type Env = HashMap<String, Register>;
 pub enum IrCompErr {
     Builder(IrBuilderError),
     NonExistingVar(String),
+    NonConstantInit(String),
     Unknown,
 }
 
+impl Display for IrCompErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IrCompErr::Builder(e) => write!(f, "ir builder error {:?}", e),
+            IrCompErr::NonExistingVar(name) => write!(f, "variable {} does not exist", name),
+            IrCompErr::NonConstantInit(name) => {
+                write!(f, "initializer of {} is not a constant", name)
+            }
+            IrCompErr::Unknown => write!(f, "unknown compile error"),
+        }
+    }
+}
+
 impl From<IrBuilderError> for IrCompErr {
     fn from(e: IrBuilderError) -> Self {
         IrCompErr::Builder(e)
@@ -42,13 +90,80 @@ impl From<IrBuilderError> for IrCompErr {
 
 struct IrCompiler {
     env: Vec<Env>,
+    // global variables are accessed by the symbol
+    globals: HashSet<String>,
+    module: Option<String>,
+    // top level name to its symbol
+    symbols: HashMap<String, String>,
+    // layout of the structs
+    types: TypeTable,
+    // symbol of the compiled function
+    function: String,
+    // named parameters of the compiled function
+    params: usize,
+    // static locals and string literals of the function, they are
+    // added to the program after the function is built
+    fn_globals: Vec<(String, Vec<u8>, bool)>,
+    // numbers of the syscalls behind the intrinsics
+    target: Target,
+    options: CompileOptions,
+    // globals initialized by the code of the unit's init function
+    dynamic_inits: Vec<VarDecl>,
 }
 
 impl Default for IrCompiler {
     fn default() -> Self {
         Self {
             env: vec![HashMap::new()],
+            globals: HashSet::new(),
+            module: None,
+            symbols: HashMap::new(),
+            types: TypeTable::default(),
+            function: String::new(),
+            params: 0,
+            fn_globals: vec![],
+            target: Target::default(),
+            options: CompileOptions::default(),
+            dynamic_inits: vec![],
+        }
+    }
+}
+
+/// value of the global initializer, it has to be known at compile time
+pub(crate) fn const_value(expr: &Expr, types: &TypeTable) -> Option<i64> {
+    match &expr.value {
+        ExprType::Value(Val::Integer(num)) => Some(*num),
+        ExprType::Value(Val::Char(c)) => Some(*c as i64),
+        ExprType::BinOp(op, l, r) => {
+            let (l, r) = (const_value(l, types)?, const_value(r, types)?);
+            match op {
+                Operator::Add => l.checked_add(r),
+                Operator::Sub => l.checked_sub(r),
+                Operator::Mul => l.checked_mul(r),
+                Operator::Div => l.checked_div(r),
+                Operator::Mod => l.checked_rem(r),
+                Operator::ShiftLeft => l.checked_shl(r.try_into().ok()?),
+                Operator::ShiftRight => l.checked_shr(r.try_into().ok()?),
+                Operator::BitAnd => Some(l & r),
+                Operator::BitOr => Some(l | r),
+                _ => None,
+            }
         }
+        ExprType::Cast(_, e) => const_value(e, types),
+        ExprType::OffsetOf(t, field) => match t.unqual() {
+            TypeDef::Struct(id) => Some(types.field(id, field)?.offset as i64),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// const globals are never written so they can be in the read only memory
+fn read_only(t: &TypeDef) -> bool {
+    match t {
+        TypeDef::Const(_) => true,
+        TypeDef::Array(arr) => read_only(&arr.inner_type),
+        _ => false,
     }
 }
 
@@ -57,6 +172,7 @@ impl From<TypeDef> for RegType {
         match t {
             TypeDef::Void => RegType::Void,
             TypeDef::PrimType(PrimType::Char) => RegType::Char,
+            TypeDef::Const(t) => (*t).into(),
             _ => RegType::Int,
         }
     }
@@ -65,12 +181,13 @@ impl From<TypeDef> for RegType {
 impl IrCompiler {
     fn compile(&mut self, prog: Program) -> Result<IrProgram, IrCompErr> {
         let mut ir_builder = IrBuilder::default();
+        self.types = prog.types;
         for top in prog.items {
-            match top {
-                TopLevel::Function(fn_def) => self.function(fn_def, &mut ir_builder)?,
-                TopLevel::Var(_) => todo!(),
-                TopLevel::Structure(_) => todo!(),
-            }
+            self.top_level(top, &mut ir_builder)?;
+        }
+        if !self.dynamic_inits.is_empty() {
+            let symbol = self.init_function(&mut ir_builder)?;
+            ir_builder.add(I::CallDirect(SymRegs(symbol, vec![])), RegType::Void);
         }
         ir_builder.add(I::Exit(Terminator), RegType::Void);
 
@@ -79,6 +196,45 @@ impl IrCompiler {
         Ok(res)
     }
 
+    fn top_level(&mut self, top: TopLevel, ir_builder: &mut IrBuilder) -> Result<(), IrCompErr> {
+        match top {
+            TopLevel::Function(fn_def) => self.function(fn_def, ir_builder)?,
+            TopLevel::Var(decl) => self.global(decl, ir_builder)?,
+            // only the layout is needed and it is in the type table
+            TopLevel::Structure(_) => (),
+            // extern symbols are never mangled, they can be defined in c
+            TopLevel::Extern(item) => match *item {
+                TopLevel::Var(decl) => {
+                    self.declare(&decl.name, true);
+                    self.globals.insert(decl.name.clone());
+                }
+                TopLevel::Function(fn_def) => _ = self.declare(&fn_def.header.name, true),
+                _ => unreachable!("only variables and functions can be extern"),
+            },
+            TopLevel::Import(_) => (),
+            TopLevel::Pub(item) => self.top_level(*item, ir_builder)?,
+        }
+        Ok(())
+    }
+
+    /// symbol of the top level name of this module, the first
+    /// declaration decides it
+    fn declare(&mut self, name: &String, external: bool) -> String {
+        let symbol = match &self.module {
+            Some(module) if !external => format!("{}.{}", module, name),
+            _ => name.clone(),
+        };
+        self.symbols.entry(name.clone()).or_insert(symbol).clone()
+    }
+
+    /// symbol of the name, qualified `module::name` is `module.name`
+    fn symbol(&self, name: &String) -> String {
+        match name.split_once("::") {
+            Some((module, name)) => format!("{}.{}", module, name),
+            None => self.symbols.get(name).unwrap_or(name).clone(),
+        }
+    }
+
     fn compile_val(&mut self, val: &Val, f_b: &mut FunctionBuilder) -> Result<Register, IrCompErr> {
         match val {
             Val::Integer(num) => Ok(f_b.add(I::Ldi(ImmI(*num)), RegType::Int)),
@@ -137,8 +293,8 @@ impl IrCompiler {
             ExprType::UnaryPostOp(_, _) => todo!(),
             ExprType::Value(v) => self.compile_val(v, f_b),
             ExprType::Ident(name) => {
-                let reg = self.get_addreg(name.clone())?;
-                Ok(f_b.add(I::Ld(Reg(reg)), expr.get_type().into()))
+                let reg = self.get_addreg(name.clone(), &expr.get_type(), f_b)?;
+                Ok(self.load(reg, &expr.get_type(), f_b))
             }
             ExprType::Call(target, args) => {
                 let mut args_regs: Vec<Register> = vec![];
@@ -146,10 +302,18 @@ impl IrCompiler {
                     args_regs.push(self.compile_expr(arg, f_b)?);
                 }
                 match &target.value {
-                    ExprType::Ident(name) if self.get_addreg(name.clone()).is_err() => Ok(f_b.add(
-                        I::CallDirect(SymRegs(name.clone(), args_regs)),
-                        expr.get_type().into(),
-                    )),
+                    ExprType::Ident(name) if !self.is_var(name) => {
+                        match Syscall::from_intrinsic(name) {
+                            Some(syscall) => Ok(f_b.add(
+                                I::SysCall(ImmIRegs(syscall.number(self.target), args_regs)),
+                                expr.get_type().into(),
+                            )),
+                            None => Ok(f_b.add(
+                                I::CallDirect(SymRegs(self.symbol(name), args_regs)),
+                                expr.get_type().into(),
+                            )),
+                        }
+                    }
                     _ => {
                         let target = self.compile_expr(target, f_b)?;
                         Ok(f_b.add(I::Call(RegRegs(target, args_regs)), expr.get_type().into()))
@@ -161,36 +325,202 @@ impl IrCompiler {
                 for arg in args {
                     regs.push(self.compile_expr(arg, f_b)?);
                 }
-                Ok(f_b.add(I::SysCall(ImmIRegs(*number, regs)), RegType::Void))
+                Ok(f_b.add(I::SysCall(ImmIRegs(*number, regs)), RegType::Int))
             }
             ExprType::Index(e, index) => {
                 let start = self.compile_expr(e, f_b)?;
                 let index = self.compile_expr(index, f_b)?;
-                let size = IrCompiler::get_type_size(&expr.get_type(), f_b);
+                self.check_index(expr, e, index, f_b);
+                let size = self.types.size_of(&expr.get_type());
                 let addr = f_b.add(I::Gep(size, RegRegImm(start, index, 0)), RegType::Int);
 
-                Ok(f_b.add(I::Ld(Reg(addr)), expr.get_type().into()))
+                Ok(self.load(addr, &expr.get_type(), f_b))
             }
             ExprType::Deref(pointer) => {
                 let reg = self.compile_expr(pointer, f_b)?;
-                Ok(f_b.add(I::Ld(Reg(reg)), expr.get_type().into()))
+                Ok(self.load(reg, &expr.get_type(), f_b))
             }
-            ExprType::Address(e) => match &e.value {
-                ExprType::Ident(name) => self.get_addreg(name.clone()),
-                _ => todo!(),
-            },
-            ExprType::Cast(_, _) => todo!(),
-            ExprType::FieldAccess(_, _) => todo!(),
+            ExprType::Address(e) => self.compile_lvalue(e, f_b),
+            // the types with the same register type share the value
+            ExprType::Cast(_, e) => {
+                let reg = self.compile_expr(e, f_b)?;
+                let reg_type = RegType::from(expr.get_type());
+                if RegType::from(e.get_type()) != reg_type {
+                    return Ok(f_b.add(I::Conv(Reg(reg)), reg_type));
+                }
+                Ok(reg)
+            }
+            ExprType::FieldAccess(_, _) => {
+                let addr = self.compile_lvalue(expr, f_b)?;
+                match expr.get_type() {
+                    // array in the struct is not behind a pointer
+                    t if matches!(t.unqual(), TypeDef::Array(_)) => Ok(addr),
+                    t => Ok(self.load(addr, &t, f_b)),
+                }
+            }
+            ExprType::OffsetOf(t, field) => {
+                let offset = match t.unqual() {
+                    TypeDef::Struct(id) => self.types.field(id, field).unwrap().offset,
+                    _ => unreachable!("typecheck allows only structs"),
+                };
+                Ok(f_b.add(I::Ldi(ImmI(offset as i64)), RegType::Int))
+            }
+            // literal is a read only global `function.str.index`
+            ExprType::Str(s) => Ok(self.string(s, f_b)),
+            // the failed condition is reported by the runtime with its source
+            ExprType::Assert(cond, file) => {
+                let cond_reg = self.compile_expr(cond, f_b)?;
+                let fail = f_b.create_bb();
+                let after = f_b.create_bb();
+                f_b.set_predecesors(fail, &[f_b.get_act_bb()]);
+                f_b.set_predecesors(after, &[f_b.get_act_bb()]);
+                f_b.add(
+                    I::Branch(TerminatorBranch(cond_reg, after, fail)),
+                    RegType::Void,
+                );
+                f_b.set_bb(fail);
+                let text = self.string(&format_expr(cond), f_b);
+                let file = self.string(file, f_b);
+                let line = f_b.add(I::Ldi(ImmI(expr.loc().row() as i64 + 1)), RegType::Int);
+                let args = vec![text, file, line];
+                let call = f_b.add(
+                    I::CallDirect(SymRegs("__assert_fail".to_string(), args)),
+                    RegType::Void,
+                );
+                self.jump_to(after, f_b);
+                f_b.set_bb(after);
+                Ok(call)
+            }
+            ExprType::VaStart(list) => {
+                let addr = self.compile_lvalue(list, f_b)?;
+                let start = f_b.add(I::VaStart(ImmI(self.params as i64)), RegType::Int);
+                Ok(f_b.add(I::St(RegReg(addr, start)), RegType::Void))
+            }
+            // every variadic argument takes 8 bytes
+            ExprType::VaArg(list, t) => {
+                let addr = self.compile_lvalue(list, f_b)?;
+                let arg = f_b.add(I::Ld(Reg(addr)), RegType::Int);
+                let value = self.load(arg, t, f_b);
+                let size = f_b.add(I::Ldi(ImmI(8)), RegType::Int);
+                let next = f_b.add(I::Add(RegReg(arg, size)), RegType::Int);
+                f_b.add(I::St(RegReg(addr, next)), RegType::Void);
+                Ok(value)
+            }
+            // nothing has to be released
+            ExprType::VaEnd(list) => self.compile_expr(list, f_b),
+        }
+    }
+
+    /// initial memory of the global or static variable
+    fn global_data(&self, decl: &VarDecl) -> Result<Vec<u8>, IrCompErr> {
+        let size = self.types.size_of(&decl.var_type);
+        let mut data = vec![0; size];
+        if let Some(init) = &decl.init_val {
+            let value = const_value(init, &self.types)
+                .ok_or_else(|| IrCompErr::NonConstantInit(decl.name.clone()))?;
+            for (byte, value) in data.iter_mut().zip(value.to_le_bytes()) {
+                *byte = value;
+            }
+        }
+        Ok(data)
+    }
+
+    /// global with the initializer not known at compile time starts zeroed
+    /// and it is written by the init function of the unit
+    fn global(&mut self, decl: VarDecl, ir_builder: &mut IrBuilder) -> Result<(), IrCompErr> {
+        let symbol = self.declare(&decl.name, false);
+        self.globals.insert(decl.name.clone());
+        let dynamic = decl
+            .init_val
+            .as_ref()
+            .is_some_and(|init| const_value(init, &self.types).is_none());
+        if dynamic {
+            let data = vec![0; self.types.size_of(&decl.var_type)];
+            ir_builder.add_global(&symbol, data, false)?;
+            self.dynamic_inits.push(decl);
+        } else {
+            let data = self.global_data(&decl)?;
+            ir_builder.add_global(&symbol, data, read_only(&decl.var_type))?;
+        }
+        Ok(())
+    }
+
+    /// function `__init_globals.module` runs the dynamic initializers in
+    /// the order of the declarations, the global block of the unit calls it
+    fn init_function(&mut self, ir_builder: &mut IrBuilder) -> Result<String, IrCompErr> {
+        let module = self.module.as_deref().unwrap_or("unit");
+        let symbol = format!("__init_globals.{}", module);
+        self.function = symbol.clone();
+        self.params = 0;
+        let mut fn_b = FunctionBuilder::new(0, RegType::Void, &mut ir_builder.store);
+        for decl in std::mem::take(&mut self.dynamic_inits) {
+            let init = decl.init_val.as_ref().expect("only initialized globals are dynamic");
+            self.compile_named_assign(decl.name.clone(), init, &mut fn_b)?;
         }
+        fn_b.add(I::Ret(Terminator), RegType::Void);
+        let res = fn_b.create(&symbol);
+        ir_builder.add_fn(res)?;
+        for (symbol, data, read_only) in std::mem::take(&mut self.fn_globals) {
+            ir_builder.add_global(&symbol, data, read_only)?;
+        }
+        Ok(symbol)
+    }
+
+    /// static local lives in the global memory under the symbol
+    /// `function.name.index`, it is initialised only once
+    fn compile_static(
+        &mut self,
+        decl: &VarDecl,
+        f_b: &mut FunctionBuilder,
+    ) -> Result<(), IrCompErr> {
+        let data = self.global_data(decl)?;
+        let symbol = format!("{}.{}.{}", self.function, decl.name, self.fn_globals.len());
+        self.fn_globals
+            .push((symbol.clone(), data, read_only(&decl.var_type)));
+        let addr = f_b.add(I::Ldg(ImmS(symbol)), RegType::Int);
+        let reg = match decl.var_type {
+            // arrays are behind a pointer as the local ones
+            TypeDef::Array(_) => {
+                let reg = f_b.add(I::Alloca(ImmI(8)), RegType::Int);
+                f_b.add(I::St(RegReg(reg, addr)), RegType::Void);
+                reg
+            }
+            _ => addr,
+        };
+        self.env.last_mut().unwrap().insert(decl.name.clone(), reg);
+        Ok(())
+    }
+
+    fn is_var(&self, name: &String) -> bool {
+        self.env.iter().any(|env| env.contains_key(name)) || self.globals.contains(name)
     }
 
-    fn get_addreg(&mut self, name: String) -> Result<Register, IrCompErr> {
+    /// variables of other modules are qualified, the typecheck
+    /// allows only the global ones
+    fn get_addreg(
+        &mut self,
+        name: String,
+        var_type: &TypeDef,
+        f_b: &mut FunctionBuilder,
+    ) -> Result<Register, IrCompErr> {
         for i in (0..self.env.len()).rev() {
             if let Some(reg) = self.env[i].get(&name) {
                 return Ok(*reg);
             }
         }
-        Err(IrCompErr::NonExistingVar(name))
+        if !self.globals.contains(&name) && !name.contains("::") {
+            return Err(IrCompErr::NonExistingVar(name));
+        }
+        let addr = f_b.add(I::Ldg(ImmS(self.symbol(&name))), RegType::Int);
+        match var_type {
+            // local arrays are kept behind a pointer, the global one is the same
+            TypeDef::Array(_) => {
+                let reg = f_b.add(I::Alloca(ImmI(8)), RegType::Int);
+                f_b.add(I::St(RegReg(reg, addr)), RegType::Void);
+                Ok(reg)
+            }
+            _ => Ok(addr),
+        }
     }
 
     fn compile_named_assign(
@@ -199,26 +529,74 @@ impl IrCompiler {
         expr: &Expr,
         f_b: &mut FunctionBuilder,
     ) -> Result<(), IrCompErr> {
-        let reg_store = self.get_addreg(name)?;
+        let reg_store = self.get_addreg(name, &expr.get_type(), f_b)?;
         let reg_val = self.compile_expr(expr, f_b)?;
-        f_b.add(I::St(RegReg(reg_store, reg_val)), RegType::Void);
+        self.store(reg_store, reg_val, &expr.get_type(), f_b);
         Ok(())
     }
 
+    /// value at the address, structs are kept as their address
+    fn load(&self, addr: Register, t: &TypeDef, f_b: &mut FunctionBuilder) -> Register {
+        match t.unqual() {
+            TypeDef::Struct(_) => addr,
+            t => f_b.add(I::Ld(Reg(addr)), t.clone().into()),
+        }
+    }
+
+    /// stores the value, struct is copied from the address in the value
+    fn store(&self, addr: Register, value: Register, t: &TypeDef, f_b: &mut FunctionBuilder) {
+        if !matches!(t.unqual(), TypeDef::Struct(_)) {
+            f_b.add(I::St(RegReg(addr, value)), RegType::Void);
+            return;
+        }
+        let size = self.types.size_of(t);
+        let zero = f_b.add(I::Ldi(ImmI(0)), RegType::Int);
+        let mut offset = 0;
+        while offset < size {
+            // whole words while they fit, the rest by bytes
+            let (reg_type, step) = if size - offset >= 8 {
+                (RegType::Int, 8)
+            } else {
+                (RegType::Char, 1)
+            };
+            let imm = RegRegImm(value, zero, offset as i64);
+            let from = f_b.add(I::Gep(1, imm), RegType::Int);
+            let to = f_b.add(
+                I::Gep(1, RegRegImm(addr, zero, offset as i64)),
+                RegType::Int,
+            );
+            let byte = f_b.add(I::Ld(Reg(from)), reg_type);
+            f_b.add(I::St(RegReg(to, byte)), RegType::Void);
+            offset += step;
+        }
+    }
+
     fn compile_lvalue(
         &mut self,
         store: &Expr,
         f_b: &mut FunctionBuilder,
     ) -> Result<Register, IrCompErr> {
         match &store.value {
-            ExprType::Ident(name) => self.get_addreg(name.clone()),
+            ExprType::Ident(name) => self.get_addreg(name.clone(), &store.get_type(), f_b),
             ExprType::Deref(e) => self.compile_expr(e, f_b),
             ExprType::Index(e, index) => {
                 let start = self.compile_expr(e, f_b)?;
                 let index = self.compile_expr(index, f_b)?;
-                let size = IrCompiler::get_type_size(&store.get_type(), f_b);
+                self.check_index(store, e, index, f_b);
+                let size = self.types.size_of(&store.get_type());
                 Ok(f_b.add(I::Gep(size, RegRegImm(start, index, 0)), RegType::Int))
             }
+            ExprType::FieldAccess(e, field) => {
+                // struct value is its address
+                let base = self.compile_expr(e, f_b)?;
+                let offset = match e.get_type().unqual() {
+                    TypeDef::Struct(id) => self.types.field(id, field).unwrap().offset,
+                    _ => unreachable!("typecheck allows only structs"),
+                };
+                let zero = f_b.add(I::Ldi(ImmI(0)), RegType::Int);
+                let imm = RegRegImm(base, zero, offset as i64);
+                Ok(f_b.add(I::Gep(1, imm), RegType::Int))
+            }
             _ => todo!(),
         }
     }
@@ -231,31 +609,16 @@ impl IrCompiler {
     ) -> Result<(), IrCompErr> {
         let reg_store = self.compile_lvalue(store, f_b)?;
         let reg_val = self.compile_expr(expr, f_b)?;
-        f_b.add(I::St(RegReg(reg_store, reg_val)), RegType::Void);
+        self.store(reg_store, reg_val, &store.get_type(), f_b);
         Ok(())
     }
 
-    fn get_type_size(type_def: &TypeDef, f_b: &mut FunctionBuilder) -> usize {
-        match type_def {
-            TypeDef::Void => unreachable!(),
-            TypeDef::PrimType(PrimType::Int) => 8,
-            TypeDef::PrimType(PrimType::Char) => 1,
-            TypeDef::PointerType(_) => 8,
-            TypeDef::Function(_) => todo!(),
-            TypeDef::Alias(_) => todo!(),
-            TypeDef::Struct(_) => todo!(),
-            TypeDef::Array(array_type) => {
-                array_type.index * IrCompiler::get_type_size(&array_type.inner_type, f_b)
-            }
-        }
-    }
-
     fn compile_vardecl(
         &mut self,
         decl: &VarDecl,
         f_b: &mut FunctionBuilder,
     ) -> Result<(), IrCompErr> {
-        let size = IrCompiler::get_type_size(&decl.value.var_type, f_b) as i64;
+        let size = self.types.size_of(&decl.value.var_type) as i64;
         let reg = if let TypeDef::Array(_) = decl.value.var_type {
             let addr_reg = f_b.add(I::Alloca(ImmI(size)), RegType::Int);
             let reg = f_b.add(I::Alloca(ImmI(8)), RegType::Int);
@@ -271,6 +634,95 @@ impl IrCompiler {
         Ok(())
     }
 
+    fn push_env(&mut self) {
+        self.env.push(HashMap::new());
+    }
+
+    fn pop_env(&mut self) {
+        self.env.pop();
+    }
+
+    /// compiles statement in its own scope, same as the typecheck does
+    /// for bodies of if and loops
+    fn compile_scoped(
+        &mut self,
+        stmt: &Statement,
+        f_b: &mut FunctionBuilder,
+    ) -> Result<(), IrCompErr> {
+        self.push_env();
+        let res = self.compile_stmt(stmt, f_b);
+        self.pop_env();
+        res
+    }
+
+    fn compile_stmts(
+        &mut self,
+        stmts: &[Statement],
+        f_b: &mut FunctionBuilder,
+    ) -> Result<(), IrCompErr> {
+        for s in stmts {
+            self.compile_stmt(s, f_b)?;
+        }
+        Ok(())
+    }
+
+    /// read-only global with the zero ended string
+    fn string(&mut self, s: &str, f_b: &mut FunctionBuilder) -> Register {
+        let symbol = format!("{}.str.{}", self.function, self.fn_globals.len());
+        let mut data: Vec<u8> = s.chars().map(|c| c as u8).collect();
+        data.push(0);
+        self.fn_globals.push((symbol.clone(), data, true));
+        f_b.add(I::Ldg(ImmS(symbol)), RegType::Int)
+    }
+
+    /// with `bounds_check` the index into the array is compared with its
+    /// length, the runtime reports the failed check and aborts, pointers
+    /// have no known length and are not checked
+    fn check_index(
+        &mut self,
+        expr: &Expr,
+        array: &Expr,
+        index: Register,
+        f_b: &mut FunctionBuilder,
+    ) {
+        let len = match array.get_type().unqual() {
+            TypeDef::Array(arr) if self.options.bounds_check => arr.index as i64,
+            _ => return,
+        };
+        let zero = f_b.add(I::Ldi(ImmI(0)), RegType::Int);
+        let len_reg = f_b.add(I::Ldi(ImmI(len)), RegType::Int);
+        let above = f_b.add(I::Ge(RegReg(index, zero)), RegType::Int);
+        let below = f_b.add(I::Lt(RegReg(index, len_reg)), RegType::Int);
+        let ok = f_b.add(I::And(RegReg(above, below)), RegType::Int);
+        let fail = f_b.create_bb();
+        let cont = f_b.create_bb();
+        f_b.set_predecesors(fail, &[f_b.get_act_bb()]);
+        f_b.set_predecesors(cont, &[f_b.get_act_bb()]);
+        f_b.add(I::Branch(TerminatorBranch(ok, cont, fail)), RegType::Void);
+
+        f_b.set_bb(fail);
+        let loc = expr.loc();
+        let at = format!("{}:{}:{}", self.options.file, loc.row() + 1, loc.col() + 1);
+        let at = self.string(&at, f_b);
+        let name = self.string(&format_expr(array), f_b);
+        let args = vec![at, name, index, len_reg];
+        f_b.add(
+            I::CallDirect(SymRegs(BOUNDS_FAIL.to_string(), args)),
+            RegType::Void,
+        );
+        self.jump_to(cont, f_b);
+        f_b.set_bb(cont);
+    }
+
+    /// the block where the nested statements ended is
+    /// the predecessor, not the one where they started
+    fn jump_to(&self, target: BBIndex, f_b: &mut FunctionBuilder) {
+        if !f_b.terminated() {
+            f_b.set_predecesors(target, &[f_b.get_act_bb()]);
+            f_b.add(I::Jmp(TerminatorJump(target)), RegType::Void);
+        }
+    }
+
     fn compile_stmt(
         &mut self,
         stmt: &Statement,
@@ -282,26 +734,26 @@ impl IrCompiler {
                 _ => _ = self.compile_expr(e, f_b)?,
             },
             StatementType::VarDecl(decl) => self.compile_vardecl(decl, f_b)?,
+            StatementType::Static(decl) => self.compile_static(decl, f_b)?,
             StatementType::Block(stmts) => {
-                for s in stmts {
-                    self.compile_stmt(s, f_b)?;
-                }
+                self.push_env();
+                let res = self.compile_stmts(stmts, f_b);
+                self.pop_env();
+                res?
             }
             StatementType::If(guard, block) => {
                 let guard_reg = self.compile_expr(guard, f_b)?;
                 let then = f_b.create_bb();
                 let after = f_b.create_bb();
                 f_b.set_predecesors(then, &[f_b.get_act_bb()]);
-                f_b.set_predecesors(after, &[f_b.get_act_bb(), then]);
+                f_b.set_predecesors(after, &[f_b.get_act_bb()]);
                 f_b.add(
                     I::Branch(TerminatorBranch(guard_reg, then, after)),
                     RegType::Void,
                 );
                 f_b.set_bb(then);
-                self.compile_stmt(block, f_b)?;
-                if !f_b.terminated() {
-                    f_b.add(I::Jmp(TerminatorJump(after)), RegType::Void);
-                }
+                self.compile_scoped(block, f_b)?;
+                self.jump_to(after, f_b);
                 f_b.set_bb(after);
             }
             StatementType::IfElse(guard, then_block, else_block) => {
@@ -311,21 +763,16 @@ impl IrCompiler {
                 let after = f_b.create_bb();
                 f_b.set_predecesors(then_bb, &[f_b.get_act_bb()]);
                 f_b.set_predecesors(else_bb, &[f_b.get_act_bb()]);
-                f_b.set_predecesors(after, &[then_bb, else_bb]);
                 f_b.add(
                     I::Branch(TerminatorBranch(guard_reg, then_bb, else_bb)),
                     RegType::Void,
                 );
                 f_b.set_bb(then_bb);
-                self.compile_stmt(then_block, f_b)?;
-                if !f_b.terminated() {
-                    f_b.add(I::Jmp(TerminatorJump(after)), RegType::Void);
-                }
+                self.compile_scoped(then_block, f_b)?;
+                self.jump_to(after, f_b);
                 f_b.set_bb(else_bb);
-                self.compile_stmt(else_block, f_b)?;
-                if !f_b.terminated() {
-                    f_b.add(I::Jmp(TerminatorJump(after)), RegType::Void);
-                }
+                self.compile_scoped(else_block, f_b)?;
+                self.jump_to(after, f_b);
                 f_b.set_bb(after);
             }
             StatementType::For(init, guard, after, body) => {
@@ -336,6 +783,8 @@ impl IrCompiler {
                 f_b.set_predecesors(body_bb, &[check_bb]);
                 f_b.set_predecesors(after_bb, &[check_bb]);
 
+                // variables declared in init are visible only in the loop
+                self.push_env();
                 if let Some(init) = init {
                     self.compile_stmt(init, f_b)?;
                 }
@@ -354,12 +803,13 @@ impl IrCompiler {
                 );
 
                 f_b.set_bb(body_bb);
-                self.compile_stmt(body, f_b)?;
+                self.compile_scoped(body, f_b)?;
                 if let Some(after) = after {
                     self.compile_stmt(after, f_b)?;
                 }
                 f_b.set_predecesors(check_bb, &[f_b.get_act_bb()]);
                 f_b.add(I::Jmp(TerminatorJump(check_bb)), RegType::Void);
+                self.pop_env();
 
                 f_b.set_bb(after_bb);
             }
@@ -381,7 +831,7 @@ impl IrCompiler {
                     RegType::Void,
                 );
                 f_b.set_bb(body_bb);
-                self.compile_stmt(body, f_b)?;
+                self.compile_scoped(body, f_b)?;
                 f_b.set_predecesors(check_bb, &[f_b.get_act_bb()]);
                 f_b.add(I::Jmp(TerminatorJump(check_bb)), RegType::Void);
                 f_b.set_bb(after_bb);
@@ -400,6 +850,9 @@ impl IrCompiler {
     }
 
     fn function(&mut self, func: FnDef, ir_builder: &mut IrBuilder) -> Result<(), IrCompErr> {
+        let symbol = self.declare(&func.header.name, false);
+        self.function = symbol.clone();
+        self.params = func.header.params.len();
         if let Some(body) = &func.body {
             let mut fn_b = FunctionBuilder::new(
                 func.header.params.len() as u64,
@@ -407,6 +860,7 @@ impl IrCompiler {
                 &mut ir_builder.store,
             );
 
+            self.push_env();
             for index in 0..func.header.params.len() {
                 let t: RegType = func.header.params[index].1.clone().into();
                 let reg = fn_b.add(I::Arg(ImmI(index as i64)), t);
@@ -418,13 +872,452 @@ impl IrCompiler {
                     .insert(func.header.params[index].0.clone(), addr);
             }
 
-            self.compile_stmt(body, &mut fn_b)?;
+            // parameters share the scope with the body
+            match &body.value {
+                StatementType::Block(stmts) => self.compile_stmts(stmts, &mut fn_b)?,
+                _ => self.compile_stmt(body, &mut fn_b)?,
+            }
+            self.pop_env();
             if !fn_b.terminated() {
                 fn_b.add(I::Ret(Terminator), RegType::Void);
             }
-            let res = fn_b.create(&func.header.name);
+            let res = fn_b.create(&symbol);
             ir_builder.add_fn(res)?;
+            for (symbol, data, read_only) in std::mem::take(&mut self.fn_globals) {
+                ir_builder.add_global(&symbol, data, read_only)?;
+            }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::parse;
+
+    fn run(input: &str) -> i64 {
+        let program = parse(input.to_string(), "test.mc".to_string()).unwrap();
+        let ir = super::compile(program).unwrap();
+        middleend::ir_interpret::run(ir).unwrap()
+    }
+
+    #[test]
+    fn static_locals() {
+        let input = "int counter() {
+            static int count = 10;
+            count = count + 1;
+            return count;
+        }
+        int other() {
+            static int count;
+            count = count + 100;
+            return count;
+        }
+        int main() {
+            counter();
+            other();
+            counter();
+            return counter() + other();
+        }";
+        assert_eq!(run(input), 13 + 200);
+    }
+
+    #[test]
+    fn const_globals_are_read_only() {
+        let input = "const int limit = 5; int counter; int main() {
+            static const char c = 'a';
+            return limit;
+        }";
+        let program = parse(input.to_string(), "test.mc".to_string()).unwrap();
+        let ir = super::compile(program).unwrap();
+        let read_only: Vec<(&str, bool)> = ir
+            .globals
+            .iter()
+            .map(|x| (x.name.as_str(), x.read_only))
+            .collect();
+        assert_eq!(
+            read_only,
+            vec![("limit", true), ("counter", false), ("main.c.0", true)]
+        );
+        assert_eq!(middleend::ir_interpret::run(ir).unwrap(), 5);
+    }
+
+    #[test]
+    fn dynamic_global_initializers() {
+        // the globals not known at compile time are written by the init
+        // function before main, in the order of the declarations
+        let input = "int f() { return 40; }
+        int g = 2;
+        int x = f() + g;
+        const int* p = &g;
+        const char* s = \"hi\";
+        int main() {
+            return x + *p + cast<int>(s[1]);
+        }";
+        let program = parse(input.to_string(), "test.mc".to_string()).unwrap();
+        let ir = super::compile(program).unwrap();
+        let read_only: Vec<(&str, bool)> = ir
+            .globals
+            .iter()
+            .filter(|x| !x.name.contains(".str."))
+            .map(|x| (x.name.as_str(), x.read_only))
+            .collect();
+        assert_eq!(
+            read_only,
+            vec![("g", false), ("x", false), ("p", false), ("s", false)]
+        );
+        assert_eq!(middleend::ir_interpret::run(ir).unwrap(), 42 + 2 + 'i' as i64);
+    }
+
+    #[test]
+    fn const_lookup_through_pointer() {
+        let input = "const int first = 3;
+        const int second = 4;
+        int get(const int* p) {
+            return *p;
+        }
+        int main() {
+            const int local = 5;
+            return get(&first) * 100 + get(&second) * 10 + get(&local);
+        }";
+        assert_eq!(run(input), 345);
+    }
+
+    #[test]
+    fn store_after_nested_if() {
+        // the store is in the block after the nested branches, it is
+        // only reached when the predecessors of the blocks are right
+        let input = "int f(int x) {return x;}
+        int main() {
+            int last = 0;
+            int i = 0;
+            while (i < 3) {
+                if (i == 1) {
+                    if (i == 2) {
+                        f(1);
+                    } else if (i == 3) {
+                        f(2);
+                    }
+                    last = i + 1;
+                }
+                i = i + 1;
+            }
+            return last;
+        }";
+        assert_eq!(run(input), 2);
+    }
+
+    #[test]
+    fn store_through_pointer_in_loop() {
+        // the load of arr[0] in the loop sees the store through p
+        let input = "int main() {
+            int arr[4];
+            int* p = &arr[0];
+            int i = 0;
+            int s = 0;
+            arr[0] = 1;
+            while (i < 4) {
+                s = s + arr[0];
+                *p = *p + 1;
+                i = i + 1;
+            }
+            return s;
+        }";
+        assert_eq!(run(input), 10);
+    }
+
+    #[test]
+    fn cast_between_int_and_char() {
+        let input = "int main() {
+            int x = 300;
+            char c = cast<char>(x);
+            int back = cast<int>(c);
+            return back + cast<int>(cast<char>(0 - 1));
+        }";
+        assert_eq!(run(input), 44 + 255);
+    }
+
+    #[test]
+    fn char_read_of_int_store() {
+        // the stored int is not forwarded to the narrower load as it is
+        let union_read = "union U {
+            int i;
+            char c;
+        }
+        int main() {
+            U u;
+            u.i = 258;
+            return cast<int>(u.c);
+        }";
+        assert_eq!(run(union_read), 2);
+        let pointer_read = "int main() {
+            int x = 258;
+            char* p = cast<char*>(&x);
+            return cast<int>(*p);
+        }";
+        assert_eq!(run(pointer_read), 2);
+    }
+
+    #[test]
+    fn stack_freed_after_return() {
+        // every call takes 8 kB of the stack, together more than it has
+        let input = "int get(int* p, int x) {
+            return p[x];
+        }
+        int f(int x) {
+            int buf[1024];
+            buf[x] = x;
+            return get(&buf[0], x);
+        }
+        int main() {
+            int i = 0;
+            int sum = 0;
+            while (i < 4000) {
+                sum = sum + f(i % 1024);
+                i = i + 1;
+            }
+            return sum % 256;
+        }";
+        let expected = (0..4000).map(|x| x % 1024).sum::<i64>() % 256;
+        assert_eq!(run(input), expected);
+    }
+
+    #[test]
+    fn shadowing_in_block() {
+        let input = "int main() {
+            int x = 1;
+            {
+                int x = 10;
+                x = x + 1;
+            }
+            return x;
+        }";
+        assert_eq!(run(input), 1);
+    }
+
+    #[test]
+    fn shadowing_in_for_and_if() {
+        let input = "int main() {
+            int i = 100;
+            int sum = 0;
+            for (int i = 0; i < 5; i = i + 1) {
+                int sum = i;
+                if (i > 2) {
+                    int i = 1000;
+                    sum = i;
+                }
+            }
+            for (int i = 0; i < 3; i = i + 1) {
+                sum = sum + i;
+            }
+            return i + sum;
+        }";
+        assert_eq!(run(input), 103);
+    }
+
+    #[test]
+    fn prototypes_and_globals() {
+        let input = "int odd(int n);
+        int base = 3 * 2;
+        char c;
+        int table[4];
+        int even(int n) {
+            if (n == 0) {
+                return 1;
+            }
+            return odd(n - 1);
+        }
+        int odd(int n) {
+            if (n == 0) {
+                return 0;
+            }
+            return even(n - 1);
+        }
+        int main() {
+            table[2] = 30;
+            c = 'a';
+            base = base + even(10) + odd(7) * 10;
+            return base + table[2];
+        }";
+        assert_eq!(run(input), 47);
+    }
+
+    #[test]
+    fn separate_units() {
+        let main = "extern int counter;
+        int bump(int by);
+        int main() {
+            int counter = 100;
+            bump(2);
+            return bump(3) + counter;
+        }";
+        let lib = "int counter = 10;
+        int bump(int by) {
+            counter = counter + by;
+            return counter;
+        }";
+        let units = [main, lib]
+            .map(|x| super::compile(parse(x.to_string(), "test.mc".to_string()).unwrap()).unwrap());
+        let program = middleend::link::link(units.into()).unwrap();
+        assert_eq!(middleend::ir_interpret::run(program).unwrap(), 115);
+
+        let alone = super::compile(parse(main.to_string(), "test.mc".to_string()).unwrap());
+        assert!(middleend::ir_interpret::run(alone.unwrap()).is_err());
+    }
+
+    #[test]
+    fn parameter_shadowed_in_inner_block() {
+        let input = "int f(int a) {
+            if (a > 0) {
+                int a = 5;
+                return a;
+            }
+            return a;
+        }
+        int main() {
+            return f(1) * 10 + f(0);
+        }";
+        assert_eq!(run(input), 50);
+    }
+
+    #[test]
+    fn modules_with_same_names() {
+        let files = [
+            (
+                "main.mc",
+                "import \"lib/a.mc\";
+                import \"lib/b.mc\";
+                int helper() {
+                    return 1000;
+                }
+                int main() {
+                    a::total = a::total + 1;
+                    return helper() + a::helper() + b::helper() + a::total;
+                }",
+            ),
+            (
+                "lib/a.mc",
+                "pub int total = 5;
+                pub int helper() {
+                    return 100;
+                }",
+            ),
+            (
+                "lib/b.mc",
+                "import \"a.mc\";
+                int helper2();
+                pub int helper() {
+                    return helper2() + a::helper();
+                }
+                int helper2() {
+                    return 20;
+                }",
+            ),
+        ];
+        let modules = crate::parse_modules("main.mc", Default::default(), |path| {
+            files.iter().find(|x| x.0 == path).map(|x| x.1.to_string())
+        })
+        .unwrap();
+        let units: Vec<_> = modules
+            .into_iter()
+            .map(|m| super::compile_module(m.program, m.name.as_deref()).unwrap())
+            .collect();
+        assert!(units[0].funcs.contains_key("a.helper"));
+        let program = middleend::link::link(units).unwrap();
+        assert_eq!(middleend::ir_interpret::run(program).unwrap(), 1226);
+    }
+
+    #[test]
+    fn structs_and_unions() {
+        let input = "struct P {
+            int x;
+            char tag;
+            int y;
+        }
+        struct packed Header {
+            char tag;
+            int len;
+        }
+        union Packet {
+            Header header;
+            int words[2];
+        }
+        P global;
+        int len_offset = offsetof(Header, len);
+        int main() {
+            P a;
+            a.x = 3;
+            a.tag = 'a';
+            a.y = 4;
+            P b = a;
+            global = b;
+            P* p = &global;
+            (*p).y = (*p).y * 10;
+
+            Packet packet;
+            packet.words[0] = 0;
+            packet.words[1] = 0;
+            packet.header.len = 3;
+
+            char buf[16];
+            Header* h = cast<Header*>(buf);
+            (*h).len = 5;
+            int* raw = cast<int*>(buf);
+            return packet.words[0] + raw[0] * 10000 + len_offset * 1000 + global.x + global.y;
+        }";
+        // len is stored from the second byte of the word
+        assert_eq!(run(input), 768 + 1280 * 10000 + 1000 + 3 + 40);
+    }
+
+    #[test]
+    fn ir_text_round_trip_examples() {
+        use middleend::{ir::IrProgram, ir_parse::parse_ir, link::link_with_library};
+
+        let preds = |program: &IrProgram| -> Vec<Vec<Vec<usize>>> {
+            let mut funcs: Vec<_> = program.funcs.values().collect();
+            funcs.sort_by(|a, b| a.name.cmp(&b.name));
+            funcs
+                .into_iter()
+                .chain([&program.glob])
+                .map(|f| {
+                    f.blocks
+                        .iter()
+                        .map(|bb| {
+                            let mut pred = bb.pred();
+                            pred.sort();
+                            pred
+                        })
+                        .collect()
+                })
+                .collect()
+        };
+        // the examples which are not compiled, every other one has to round trip
+        let skipped = [
+            // its optimizations take minutes
+            "benchadding.mc",
+            // `struct A a;` declaration of the variable is not supported
+            "struct.mc",
+            // returns char from int function on purpose
+            "error.mc",
+        ];
+        for entry in std::fs::read_dir("../examples/tests").unwrap() {
+            let path = entry.unwrap().path();
+            if skipped.iter().any(|name| path.ends_with(name)) {
+                continue;
+            }
+            let input = std::fs::read_to_string(&path).unwrap();
+            let program = parse(input, path.to_string_lossy().to_string())
+                .unwrap_or_else(|e| panic!("{:?}: {}", path, e));
+            let library = crate::runtime_units()
+                .into_iter()
+                .map(|(_, unit)| unit)
+                .collect();
+            let program =
+                link_with_library(vec![super::compile(program).unwrap()], library).unwrap();
+            let text = program.to_string();
+            let parsed = parse_ir(&text).unwrap_or_else(|e| panic!("{:?}: {}", path, e));
+            assert_eq!(parsed.to_string(), text, "{:?}", path);
+            assert_eq!(preds(&parsed), preds(&program), "{:?}", path);
+        }
+    }
+}
@@ -1,6 +1,9 @@
+use std::fmt::Display;
+
 use crate::{
     ast::{Expr, Operator},
-    lexer::TokenType,
+    format::{format_expr, type_name},
+    lexer::{Loc, TokenType},
     typeast::TypeDef,
 };
 
@@ -9,6 +12,8 @@ pub enum LexerError {
     UnexpectedCharacter(char),
     UnexpectedEof,
     CharNotClosed,
+    StringNotClosed,
+    CommentNotClosed,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -45,6 +50,50 @@ pub enum TypeError {
     NonStructType,
     MissingField(String),
     DontHaveAddr(Expr),
+    DeclarationMismatch(String),
+    ExternDefinition(String),
+    UnknownModule(String),
+    NotPublic(String),
+    InvalidAlignment(usize),
+    // variadic function called with less than the named parameters
+    TooFewArguments(usize, usize),
+    InvalidVariadicType(TypeDef),
+    VaStartOutsideVariadic,
+    InvalidVaList(TypeDef),
+    // static locals are initialized once, before the program runs
+    NonConstantStatic(String),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Warning {
+    Shadow(Loc, String),
+}
+
+impl Warning {
+    pub fn loc(&self) -> Loc {
+        match self {
+            Warning::Shadow(loc, _) => *loc,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum JsonError {
+    UnexpectedEof,
+    UnexpectedCharacter(usize),
+    MissingField(String),
+    InvalidValue(String),
+    UnknownKind(String),
+    UnsupportedVersion(i64),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ModuleError {
+    NotFound(String),
+    // paths of the modules forming the cycle, the first one is repeated at the end
+    Cycle(Vec<String>),
+    // two modules with the same name are imported
+    NameClash(String),
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -52,6 +101,37 @@ pub enum FrontendError {
     Lexer(LexerError),
     Parser(ParserError),
     Type(Vec<TypeError>),
+    Json(JsonError),
+    Module(ModuleError),
+    Located(Loc, Box<FrontendError>),
+    // error in the imported module with the given path
+    InModule(String, Box<FrontendError>),
+}
+
+impl FrontendError {
+    /// attaches location to the error, if the error already
+    /// has location it is kept because it is more precise
+    pub fn with_loc(self, loc: Loc) -> Self {
+        match self {
+            FrontendError::Located(_, _) => self,
+            e => FrontendError::Located(loc, Box::new(e)),
+        }
+    }
+
+    pub fn loc(&self) -> Option<Loc> {
+        match self {
+            FrontendError::Located(loc, _) => Some(*loc),
+            _ => None,
+        }
+    }
+
+    /// error without the location and the module
+    pub fn inner(&self) -> &FrontendError {
+        match self {
+            FrontendError::Located(_, e) | FrontendError::InModule(_, e) => e.inner(),
+            e => e,
+        }
+    }
 }
 
 impl From<LexerError> for FrontendError {
@@ -71,3 +151,183 @@ impl From<TypeError> for FrontendError {
         FrontendError::Type(vec![e])
     }
 }
+
+impl From<ModuleError> for FrontendError {
+    fn from(e: ModuleError) -> Self {
+        FrontendError::Module(e)
+    }
+}
+
+impl From<JsonError> for FrontendError {
+    fn from(e: JsonError) -> Self {
+        FrontendError::Json(e)
+    }
+}
+
+impl Display for LexerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LexerError::UnexpectedCharacter(c) => write!(f, "unexpected character '{}'", c),
+            LexerError::UnexpectedEof => write!(f, "unexpected end of file"),
+            LexerError::CharNotClosed => write!(f, "character literal is not closed"),
+            LexerError::StringNotClosed => write!(f, "string literal is not closed"),
+            LexerError::CommentNotClosed => write!(f, "comment is not closed"),
+        }
+    }
+}
+
+impl Display for ParserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParserError::Undefiened => write!(f, "syntax error"),
+            ParserError::UnexpectedToken(t) => write!(f, "unexpected token {:?}", t),
+            ParserError::InvalidType(t) => write!(f, "expected type, found {:?}", t),
+            ParserError::VarDeclInvalidName => write!(f, "invalid variable name"),
+            ParserError::FieldCannotHaveInit => write!(f, "struct field cannot be initialized"),
+            ParserError::NonNumberAsSize => write!(f, "array size must be a number"),
+            ParserError::NegativeArraySize => write!(f, "array size cannot be negative"),
+        }
+    }
+}
+
+impl Display for TypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TypeError::VariableTypeError(name, expected, found) => write!(
+                f,
+                "variable {} has type {} but is initialized with {}",
+                name,
+                type_name(expected),
+                type_name(found)
+            ),
+            TypeError::ExpectingRet => write!(f, "expected return"),
+            TypeError::UnexpectedRet => write!(f, "unexpected return"),
+            TypeError::ReturnTypeError(found, expected) => write!(
+                f,
+                "returning {} from function returning {}",
+                type_name(found),
+                type_name(expected)
+            ),
+            TypeError::IdentDoesNotExist(name) => write!(f, "{} does not exist", name),
+            TypeError::IdentAlreadyExists(name) => write!(f, "{} already exists", name),
+            TypeError::NonFunctionCall => write!(f, "called value is not a function"),
+            TypeError::WrongNumberOfParametes(expected, found) => write!(
+                f,
+                "expected {} arguments, found {}",
+                expected, found
+            ),
+            TypeError::WrongParamType(expected, found) => write!(
+                f,
+                "expected argument of type {}, found {}",
+                type_name(expected),
+                type_name(found)
+            ),
+            TypeError::NonPointerDeref => write!(f, "dereference of non pointer type"),
+            TypeError::IndexMustBeInteger => write!(f, "index must be int"),
+            TypeError::ConditionMustBeInt => write!(f, "condition must be int"),
+            TypeError::InvalidOperation(op) => write!(f, "invalid operation {:?}", op),
+            TypeError::BinaryTypeMissmatch(op, left, right) => write!(
+                f,
+                "operation {:?} on {} and {}",
+                op,
+                type_name(left),
+                type_name(right)
+            ),
+            TypeError::BinaryOperatorError => write!(f, "invalid operand of binary operation"),
+            TypeError::CannotAssignInto(e) => match e.data.node_type {
+                Some(TypeDef::Const(_)) => write!(f, "cannot assign into {}, it is const", format_expr(e)),
+                _ => write!(f, "cannot assign into {}", format_expr(e)),
+            },
+            TypeError::TypeParametrMissmatch => {
+                write!(f, "parameters do not match the declaration")
+            }
+            TypeError::TypeIsNotSized => write!(f, "type is not sized"),
+            TypeError::NonStructType => write!(f, "field access on non struct type"),
+            TypeError::MissingField(name) => write!(f, "missing field {}", name),
+            TypeError::InvalidAlignment(align) => {
+                write!(f, "alignment {} is not a power of two", align)
+            }
+            TypeError::DontHaveAddr(e) => write!(f, "cannot take address of {}", format_expr(e)),
+            TypeError::DeclarationMismatch(name) => {
+                write!(f, "declaration of {} does not match the previous one", name)
+            }
+            TypeError::ExternDefinition(name) => {
+                write!(f, "extern declaration of {} cannot have a definition", name)
+            }
+            TypeError::UnknownModule(name) => write!(f, "module {} is not imported", name),
+            TypeError::NotPublic(name) => write!(f, "{} is not public", name),
+            TypeError::TooFewArguments(expected, found) => write!(
+                f,
+                "expected at least {} arguments, found {}",
+                expected, found
+            ),
+            TypeError::InvalidVariadicType(t) => {
+                write!(f, "{} cannot be a variadic argument", type_name(t))
+            }
+            TypeError::VaStartOutsideVariadic => {
+                write!(f, "va_start used outside of a variadic function")
+            }
+            TypeError::InvalidVaList(t) => {
+                write!(f, "expected assignable va_list, found {}", type_name(t))
+            }
+            TypeError::NonConstantStatic(name) => {
+                write!(f, "initializer of static {} is not a constant", name)
+            }
+        }
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::Shadow(_, name) => {
+                write!(f, "declaration of {} shadows outer declaration [-Wshadow]", name)
+            }
+        }
+    }
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonError::UnexpectedEof => write!(f, "unexpected end of json"),
+            JsonError::UnexpectedCharacter(pos) => write!(f, "unexpected character at {}", pos),
+            JsonError::MissingField(name) => write!(f, "missing field {}", name),
+            JsonError::InvalidValue(name) => write!(f, "invalid value of {}", name),
+            JsonError::UnknownKind(kind) => write!(f, "unknown kind {}", kind),
+            JsonError::UnsupportedVersion(v) => write!(f, "unsupported schema version {}", v),
+        }
+    }
+}
+
+impl Display for ModuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModuleError::NotFound(path) => write!(f, "module {} not found", path),
+            ModuleError::Cycle(paths) => write!(f, "import cycle {}", paths.join(" -> ")),
+            ModuleError::NameClash(name) => write!(f, "two modules are named {}", name),
+        }
+    }
+}
+
+impl Display for FrontendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrontendError::Lexer(e) => write!(f, "{}", e),
+            FrontendError::Parser(e) => write!(f, "{}", e),
+            FrontendError::Type(errors) => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", messages.join(", "))
+            }
+            FrontendError::Json(e) => write!(f, "{}", e),
+            FrontendError::Module(e) => write!(f, "{}", e),
+            FrontendError::Located(loc, e) => {
+                write!(f, "{}:{}: {}", loc.row() + 1, loc.col() + 1, e)
+            }
+            FrontendError::InModule(path, e) => match e.as_ref() {
+                FrontendError::Located(_, _) => write!(f, "{}:{}", path, e),
+                _ => write!(f, "{}: {}", path, e),
+            },
+        }
+    }
+}
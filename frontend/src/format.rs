@@ -0,0 +1,832 @@
+//! Pretty printer which turns the ast back into the
+//! canonical source form of the language.
+//!
+//! Comments are not part of the ast, they are emitted
+//! before the first node which starts after them.
+
+use crate::{
+    ast::{
+        Expr, ExprType, FnDef, Operator, Program, Statement, StatementType, StructDef, TopLevel,
+        Val, VarDecl,
+    },
+    errors::FrontendError,
+    lexer::{Comment, Lexer, Loc},
+    parser::Parser,
+    typeast::{Layout, PrimType, TypeDef},
+};
+
+const INDENT: &str = "    ";
+
+/// Parses the source and prints it back in the canonical form
+pub fn format_source(input: String, filename: String) -> Result<String, FrontendError> {
+    let lex = Lexer::new(filename, input.chars().peekable());
+    let mut parser = Parser::new(lex)?;
+    let program = parser.parse()?;
+    Ok(format_program(&program, parser.comments()))
+}
+
+pub fn format_program(program: &Program, comments: &[Comment]) -> String {
+    let mut formatter = Formatter::new(comments);
+    formatter.program(program);
+    formatter.output
+}
+
+// binding power of the expressions, higher binds tighter
+const PREC_ASSIGN: u8 = 1;
+const PREC_UNARY: u8 = 11;
+const PREC_POSTFIX: u8 = 12;
+const PREC_PRIMARY: u8 = 13;
+
+impl Operator {
+    fn precedence(&self) -> u8 {
+        match self {
+            Operator::Assign => PREC_ASSIGN,
+            Operator::Or => 2,
+            Operator::And => 3,
+            Operator::BitOr => 4,
+            Operator::BitAnd => 5,
+            Operator::Eql | Operator::Neq => 6,
+            Operator::Lt | Operator::Le | Operator::Gt | Operator::Ge => 7,
+            Operator::ShiftLeft | Operator::ShiftRight => 8,
+            Operator::Add | Operator::Sub => 9,
+            Operator::Mul | Operator::Div | Operator::Mod => 10,
+            Operator::Inc | Operator::Dec | Operator::Not | Operator::BitNot => PREC_UNARY,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Inc => "++",
+            Operator::Dec => "--",
+            Operator::Lt => "<",
+            Operator::Le => "<=",
+            Operator::Gt => ">",
+            Operator::Ge => ">=",
+            Operator::Eql => "==",
+            Operator::Neq => "!=",
+            Operator::Assign => "=",
+            Operator::BitOr => "|",
+            Operator::Or => "||",
+            Operator::BitAnd => "&",
+            Operator::And => "&&",
+            Operator::Not => "!",
+            Operator::BitNot => "~",
+            Operator::Mod => "%",
+            Operator::ShiftLeft => "<<",
+            Operator::ShiftRight => ">>",
+        }
+    }
+}
+
+fn expr_precedence(expr: &Expr) -> u8 {
+    match &expr.value {
+        ExprType::BinOp(op, _, _) => op.precedence(),
+        ExprType::UnaryPreOp(_, _) | ExprType::Deref(_) | ExprType::Address(_) => PREC_UNARY,
+        ExprType::UnaryPostOp(_, _)
+        | ExprType::Call(_, _)
+        | ExprType::Index(_, _)
+        | ExprType::FieldAccess(_, _) => PREC_POSTFIX,
+        ExprType::Value(Val::Integer(n)) if *n < 0 => PREC_UNARY,
+        ExprType::Value(_)
+        | ExprType::Ident(_)
+        | ExprType::SysCall(_, _)
+        | ExprType::Cast(_, _)
+        | ExprType::OffsetOf(_, _)
+        | ExprType::Str(_)
+        | ExprType::VaStart(_)
+        | ExprType::VaArg(_, _)
+        | ExprType::VaEnd(_)
+        | ExprType::Assert(_, _) => PREC_PRIMARY,
+    }
+}
+
+pub fn type_name(type_def: &TypeDef) -> String {
+    match type_def {
+        TypeDef::Void => "void".to_string(),
+        TypeDef::PrimType(PrimType::Int) => "int".to_string(),
+        TypeDef::PrimType(PrimType::Char) => "char".to_string(),
+        TypeDef::PointerType(inner) => type_name(inner) + "*",
+        // const pointer is written after the star
+        TypeDef::Const(inner) if inner.is_pointer() => type_name(inner) + " const",
+        TypeDef::Const(inner) => "const ".to_string() + &type_name(inner),
+        TypeDef::Alias(name) => name.clone(),
+        TypeDef::Struct(id) => id.name().to_string(),
+        TypeDef::Array(arr) => format!("{}[{}]", type_name(&arr.inner_type), arr.index),
+        TypeDef::Function(f) => {
+            let mut params: Vec<String> = f.params.iter().map(type_name).collect();
+            if f.variadic {
+                params.push("...".to_string());
+            }
+            format!("{}({})", type_name(&f.ret_type), params.join(", "))
+        }
+    }
+}
+
+fn declaration(type_def: &TypeDef, name: &str) -> String {
+    match type_def {
+        TypeDef::Array(arr) => format!("{} {}[{}]", type_name(&arr.inner_type), name, arr.index),
+        t => format!("{} {}", type_name(t), name),
+    }
+}
+
+/// attributes followed by the space, empty without them
+fn layout(layout: &Layout) -> String {
+    let mut result = String::new();
+    if layout.packed {
+        result += "packed ";
+    }
+    if let Some(align) = layout.aligned {
+        result += &format!("aligned({}) ", align);
+    }
+    result
+}
+
+/// character as written inside the literal delimited by the quote
+fn escaped(c: char, quote: char) -> String {
+    match c {
+        '\n' => "\\n".to_string(),
+        '\t' => "\\t".to_string(),
+        '\0' => "\\0".to_string(),
+        '\\' => "\\\\".to_string(),
+        c if c == quote => format!("\\{}", c),
+        c => c.to_string(),
+    }
+}
+
+fn char_literal(c: char) -> String {
+    format!("'{}'", escaped(c, '\''))
+}
+
+fn string_literal(s: &str) -> String {
+    let inner: String = s.chars().map(|c| escaped(c, '"')).collect();
+    format!("\"{}\"", inner)
+}
+
+/// tokens which would be glued together by the lexer
+/// when written without space (`- -a` is not `--a`)
+fn needs_space(left: &str, right: &str) -> bool {
+    match (left.chars().last(), right.chars().next()) {
+        (Some(l), Some(r)) => l == r && "+-&|<>=".contains(l),
+        _ => false,
+    }
+}
+
+pub fn format_expr(expr: &Expr) -> String {
+    match &expr.value {
+        ExprType::BinOp(op, left, right) => {
+            let prec = op.precedence();
+            // assignment is the only right associative operator
+            let (left_prec, right_prec) = if *op == Operator::Assign {
+                (prec + 1, prec)
+            } else {
+                (prec, prec + 1)
+            };
+            format!(
+                "{} {} {}",
+                format_operand(left, left_prec),
+                op.symbol(),
+                format_operand(right, right_prec)
+            )
+        }
+        ExprType::UnaryPreOp(op, e) => prefix(op.symbol(), e),
+        ExprType::Deref(e) => prefix("*", e),
+        ExprType::Address(e) => prefix("&", e),
+        ExprType::UnaryPostOp(op, e) => format_operand(e, PREC_POSTFIX) + op.symbol(),
+        ExprType::Value(Val::Integer(n)) => n.to_string(),
+        ExprType::Value(Val::Char(c)) => char_literal(*c),
+        ExprType::Ident(name) => name.clone(),
+        ExprType::Call(target, args) => format!(
+            "{}({})",
+            format_operand(target, PREC_POSTFIX),
+            args.iter()
+                .map(|x| format_operand(x, PREC_ASSIGN))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        ExprType::SysCall(num, args) => {
+            let mut result = format!("@({}", num);
+            for arg in args {
+                result += ", ";
+                result += &format_operand(arg, PREC_ASSIGN);
+            }
+            result + ")"
+        }
+        ExprType::Index(e, index) => format!(
+            "{}[{}]",
+            format_operand(e, PREC_POSTFIX),
+            format_expr(index)
+        ),
+        ExprType::Cast(t, e) => format!("cast<{}>({})", type_name(t), format_expr(e)),
+        ExprType::FieldAccess(e, field) => {
+            format!("{}.{}", format_operand(e, PREC_POSTFIX), field)
+        }
+        ExprType::OffsetOf(t, field) => format!("offsetof({}, {})", type_name(t), field),
+        ExprType::Str(s) => string_literal(s),
+        ExprType::VaStart(list) => format!("va_start({})", format_expr(list)),
+        ExprType::VaArg(list, t) => format!("va_arg({}, {})", format_expr(list), type_name(t)),
+        ExprType::VaEnd(list) => format!("va_end({})", format_expr(list)),
+        ExprType::Assert(cond, _) => format!("assert({})", format_expr(cond)),
+    }
+}
+
+fn prefix(symbol: &str, operand: &Expr) -> String {
+    let operand = format_operand(operand, PREC_UNARY);
+    if needs_space(symbol, &operand) {
+        format!("{} {}", symbol, operand)
+    } else {
+        format!("{}{}", symbol, operand)
+    }
+}
+
+fn format_operand(expr: &Expr, min_prec: u8) -> String {
+    if expr_precedence(expr) < min_prec {
+        format!("({})", format_expr(expr))
+    } else {
+        format_expr(expr)
+    }
+}
+
+fn expr_max_row(expr: &Expr) -> usize {
+    let row = expr.loc().row();
+    let inner = match &expr.value {
+        ExprType::BinOp(_, l, r) => expr_max_row(l).max(expr_max_row(r)),
+        ExprType::UnaryPreOp(_, e)
+        | ExprType::UnaryPostOp(_, e)
+        | ExprType::Deref(e)
+        | ExprType::Address(e)
+        | ExprType::Cast(_, e)
+        | ExprType::FieldAccess(e, _)
+        | ExprType::VaStart(e)
+        | ExprType::VaArg(e, _)
+        | ExprType::VaEnd(e)
+        | ExprType::Assert(e, _) => expr_max_row(e),
+        ExprType::Call(e, args) => args.iter().map(expr_max_row).fold(expr_max_row(e), usize::max),
+        ExprType::SysCall(_, args) => args.iter().map(expr_max_row).fold(row, usize::max),
+        ExprType::Index(e, i) => expr_max_row(e).max(expr_max_row(i)),
+        ExprType::Value(_) | ExprType::Ident(_) | ExprType::OffsetOf(_, _) | ExprType::Str(_) => {
+            row
+        }
+    };
+    row.max(inner)
+}
+
+/// last row in the source occupied by the statement
+fn stmt_max_row(stmt: &Statement) -> usize {
+    let row = stmt.loc().row().max(stmt.data.end().row());
+    let inner = match &stmt.value {
+        StatementType::Expr(e) => expr_max_row(e),
+        StatementType::VarDecl(v) | StatementType::Static(v) => {
+            v.init_val.as_ref().map_or(0, expr_max_row)
+        }
+        StatementType::Block(stmts) => stmts.iter().map(stmt_max_row).max().unwrap_or(0),
+        StatementType::If(c, b) | StatementType::While(c, b) => {
+            expr_max_row(c).max(stmt_max_row(b))
+        }
+        StatementType::IfElse(c, t, e) => expr_max_row(c)
+            .max(stmt_max_row(t))
+            .max(stmt_max_row(e)),
+        StatementType::For(init, cond, update, body) => init
+            .iter()
+            .chain(update.iter())
+            .map(|x| stmt_max_row(x))
+            .chain(cond.iter().map(expr_max_row))
+            .fold(stmt_max_row(body), usize::max),
+        StatementType::Return(e) => e.as_ref().map_or(0, |x| expr_max_row(x)),
+        StatementType::Break | StatementType::Continue => 0,
+    };
+    row.max(inner)
+}
+
+fn fn_header(f: &FnDef) -> String {
+    let mut params: Vec<String> = f
+        .header
+        .params
+        .iter()
+        .map(|(name, t)| declaration(t, name))
+        .collect();
+    if f.header.variadic {
+        params.push("...".to_string());
+    }
+    format!(
+        "{} {}({})",
+        type_name(&f.header.ret_type),
+        f.header.name,
+        params.join(", ")
+    )
+}
+
+fn top_max_row(top: &TopLevel) -> usize {
+    match top {
+        TopLevel::Function(f) => f
+            .body
+            .as_ref()
+            .map_or(f.loc().row(), stmt_max_row),
+        TopLevel::Var(v) => v
+            .init_val
+            .as_ref()
+            .map_or(v.loc().row(), expr_max_row),
+        TopLevel::Structure(s) => s.data.end().row(),
+        TopLevel::Extern(item) | TopLevel::Pub(item) => top_max_row(item),
+        TopLevel::Import(import) => import.loc().row(),
+    }
+}
+
+fn has_body(top: &TopLevel) -> bool {
+    match top {
+        TopLevel::Function(f) => f.body.is_some(),
+        TopLevel::Structure(s) => s.fields.is_some(),
+        TopLevel::Pub(item) => has_body(item),
+        TopLevel::Var(_) | TopLevel::Extern(_) | TopLevel::Import(_) => false,
+    }
+}
+
+struct Formatter<'a> {
+    output: String,
+    comments: &'a [Comment],
+    next_comment: usize,
+    indent: usize,
+    // last source row which was already emitted
+    last_row: Option<usize>,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(comments: &'a [Comment]) -> Self {
+        Self {
+            output: String::new(),
+            comments,
+            next_comment: 0,
+            indent: 0,
+            last_row: None,
+        }
+    }
+
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.output += INDENT;
+        }
+        self.output += text;
+        self.output.push('\n');
+    }
+
+    /// keeps at most one empty line from the original source
+    fn gap(&mut self, row: usize) {
+        if let Some(last) = self.last_row {
+            if row > last + 1 && !self.output.ends_with("{\n") {
+                self.output.push('\n');
+            }
+        }
+    }
+
+    /// emits all comments which start before the position
+    fn comments_before(&mut self, position: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.loc.position() >= position {
+                break;
+            }
+            self.gap(comment.loc.row());
+            self.line(&comment.text.clone());
+            self.last_row = Some(comment.end_row());
+            self.next_comment += 1;
+        }
+    }
+
+    /// start of node, flushes the comments and empty lines
+    fn start(&mut self, loc: Loc) {
+        self.comments_before(loc.position());
+        self.gap(loc.row());
+    }
+
+    /// comment on the same line as the end of the statement
+    fn trailing_comment(&mut self, row: usize) {
+        if let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.loc.row() == row && !comment.text.contains('\n') {
+                self.output.pop();
+                self.output.push(' ');
+                self.output += &comment.text.clone();
+                self.output.push('\n');
+                self.next_comment += 1;
+            }
+        }
+    }
+
+    fn program(&mut self, program: &Program) {
+        let mut prev_body = false;
+        for item in program.items.iter() {
+            let loc = item.loc();
+            let has_body = has_body(item);
+            self.comments_before(loc.position());
+            // definitions with body are always separated
+            if (has_body || prev_body) && !self.output.is_empty() && !self.output.ends_with("\n\n")
+            {
+                self.output.push('\n');
+            } else {
+                self.gap(loc.row());
+            }
+
+            self.top_level(item);
+            let row = top_max_row(item);
+            self.trailing_comment(row);
+            self.last_row = Some(row);
+            prev_body = has_body;
+        }
+        self.comments_before(usize::MAX);
+    }
+
+    fn top_level(&mut self, item: &TopLevel) {
+        match item {
+            TopLevel::Function(f) => self.function(f),
+            TopLevel::Var(v) => {
+                self.line(&(self.var_decl(v) + ";"));
+            }
+            TopLevel::Structure(s) => self.structure(s),
+            TopLevel::Extern(item) => match item.as_ref() {
+                TopLevel::Function(f) => self.line(&format!("extern {};", fn_header(f))),
+                TopLevel::Var(v) => self.line(&format!("extern {};", self.var_decl(v))),
+                _ => unreachable!("only variables and functions can be extern"),
+            },
+            TopLevel::Import(import) => self.line(&format!("import {};", string_literal(&import.value))),
+            // the first line of the item gets the prefix
+            TopLevel::Pub(item) => {
+                let start = self.output.len();
+                self.top_level(item);
+                self.output.insert_str(start, "pub ");
+            }
+        }
+    }
+
+    fn structure(&mut self, s: &StructDef) {
+        let keyword = if s.is_union { "union" } else { "struct" };
+        let header = format!("{} {}{}", keyword, layout(&s.layout), s.name);
+        match &s.fields {
+            None => self.line(&format!("{};", header)),
+            Some(fields) if fields.is_empty() && self.no_comment_before(s.data.end()) => {
+                self.line(&format!("{} {{}}", header))
+            }
+            Some(fields) => {
+                self.line(&format!("{} {{", header));
+                self.last_row = Some(s.loc().row());
+                self.indent += 1;
+                for (field_layout, field) in fields {
+                    self.start(field.loc());
+                    self.line(&(layout(field_layout) + &self.var_decl(field) + ";"));
+                    self.trailing_comment(field.loc().row());
+                    self.last_row = Some(field.loc().row());
+                }
+                self.comments_before(s.data.end().position());
+                self.indent -= 1;
+                self.line("}");
+            }
+        }
+    }
+
+    fn no_comment_before(&self, loc: Loc) -> bool {
+        self.comments
+            .get(self.next_comment)
+            .is_none_or(|c| c.loc.position() > loc.position())
+    }
+
+    fn function(&mut self, f: &FnDef) {
+        let header = fn_header(f);
+        match &f.body {
+            Some(body) => {
+                self.last_row = Some(f.loc().row());
+                self.block_with(&header, body);
+            }
+            None => self.line(&(header + ";")),
+        }
+    }
+
+    fn var_decl(&self, v: &VarDecl) -> String {
+        let mut result = declaration(&v.var_type, &v.name);
+        if let Some(init) = &v.init_val {
+            result += " = ";
+            result += &format_expr(init);
+        }
+        result
+    }
+
+    /// statement written without the trailing semicolon,
+    /// used in the header of for loop
+    fn simple(&self, stmt: &Statement) -> String {
+        match &stmt.value {
+            StatementType::Expr(e) => format_expr(e),
+            StatementType::VarDecl(v) => self.var_decl(v),
+            _ => unreachable!("only expressions and declarations are in for header"),
+        }
+    }
+
+    /// prints `prefix {` then statements of the block and `}`
+    fn block_with(&mut self, prefix: &str, block: &Statement) {
+        let stmts = match &block.value {
+            StatementType::Block(stmts) => stmts,
+            _ => unreachable!(),
+        };
+        if prefix.is_empty() {
+            self.line("{");
+        } else {
+            self.line(&format!("{} {{", prefix));
+        }
+        self.indent += 1;
+        for stmt in stmts {
+            self.statement(stmt);
+        }
+        self.comments_before(block.data.end().position());
+        self.indent -= 1;
+        self.line("}");
+        self.last_row = Some(block.data.end().row());
+    }
+
+    /// body of the control flow statement, blocks are
+    /// kept on the same line other statements are indented
+    fn body(&mut self, prefix: &str, body: &Statement) {
+        if let StatementType::Block(_) = body.value {
+            self.block_with(prefix, body);
+        } else {
+            self.line(prefix);
+            self.indent += 1;
+            // no empty line between the header and the body
+            self.last_row = None;
+            self.statement(body);
+            self.indent -= 1;
+        }
+    }
+
+    /// joins `else` to the closing curly of the previous block
+    fn else_prefix(&mut self, rest: &str) -> String {
+        if self.output.ends_with("}\n") {
+            self.output.pop();
+            // the line was already indented so only the keyword is added
+            let len = self.output.len();
+            let line_start = self.output.rfind('\n').map_or(0, |x| x + 1);
+            let closing = self.output[line_start..len].to_string();
+            self.output.truncate(line_start);
+            format!("{} else{}", closing.trim_start(), rest)
+        } else {
+            format!("else{}", rest)
+        }
+    }
+
+    fn if_chain(&mut self, cond: &Expr, then: &Statement, other: Option<&Statement>) {
+        self.body(&format!("if ({})", format_expr(cond)), then);
+        let mut other = other;
+        while let Some(stmt) = other {
+            match &stmt.value {
+                StatementType::If(cond, then) | StatementType::IfElse(cond, then, _)
+                    if self.no_comment_before(stmt.loc()) =>
+                {
+                    let prefix = self.else_prefix(&format!(" if ({})", format_expr(cond)));
+                    self.body(&prefix, then);
+                    other = match &stmt.value {
+                        StatementType::IfElse(_, _, e) => Some(e),
+                        _ => None,
+                    };
+                }
+                _ => {
+                    let prefix = self.else_prefix("");
+                    self.body(&prefix, stmt);
+                    other = None;
+                }
+            }
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        self.start(stmt.loc());
+        match &stmt.value {
+            StatementType::Expr(_) | StatementType::VarDecl(_) => {
+                self.line(&(self.simple(stmt) + ";"));
+            }
+            StatementType::Static(v) => self.line(&format!("static {};", self.var_decl(v))),
+            StatementType::Block(_) => self.block_with("", stmt),
+            StatementType::If(cond, then) => self.if_chain(cond, then, None),
+            StatementType::IfElse(cond, then, other) => self.if_chain(cond, then, Some(other)),
+            StatementType::For(init, cond, update, body) => {
+                let init = init.as_ref().map_or(String::new(), |x| self.simple(x));
+                let cond = cond
+                    .as_ref()
+                    .map_or(String::new(), |x| " ".to_string() + &format_expr(x));
+                let update = update
+                    .as_ref()
+                    .map_or(String::new(), |x| " ".to_string() + &self.simple(x));
+                self.body(&format!("for ({};{};{})", init, cond, update), body);
+            }
+            StatementType::While(cond, body) => {
+                self.body(&format!("while ({})", format_expr(cond)), body)
+            }
+            StatementType::Break => self.line("break;"),
+            StatementType::Continue => self.line("continue;"),
+            StatementType::Return(None) => self.line("return;"),
+            StatementType::Return(Some(e)) => self.line(&format!("return {};", format_expr(e))),
+        }
+        let row = stmt_max_row(stmt);
+        self.trailing_comment(row);
+        self.last_row = Some(row);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_json::same_syntax;
+
+    fn parse_syntax(input: &str) -> Program {
+        let lex = Lexer::new("tmp".to_string(), input.chars().peekable());
+        let mut parser = Parser::new(lex).unwrap();
+        parser.parse().unwrap()
+    }
+
+    fn format(input: &str) -> String {
+        format_source(input.to_string(), "tmp".to_string()).unwrap()
+    }
+
+    fn round_trip(input: &str) {
+        let formatted = format(input);
+        assert!(same_syntax(&parse_syntax(input), &parse_syntax(&formatted)));
+        assert_eq!(formatted, format(&formatted));
+    }
+
+    #[test]
+    fn format_basic() {
+        assert_eq!(
+            format("int main(){int x=1+2*3;return x;}"),
+            "int main() {\n    int x = 1 + 2 * 3;\n    return x;\n}\n"
+        );
+        assert_eq!(format("int   *  a;"), "int* a;\n");
+        assert_eq!(format("struct A;struct B{int a;char b;}"), "struct A;\n\nstruct B {\n    int a;\n    char b;\n}\n");
+        assert_eq!(format("extern  int x;extern int f(int a);"), "extern int x;\nextern int f(int a);\n");
+        assert_eq!(
+            format("import  \"lib/a.mc\";pub int x;pub struct A {int a;}"),
+            "import \"lib/a.mc\";\npub int x;\n\npub struct A {\n    int a;\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_parentheses() {
+        assert_eq!(format("int a = (1 + 2) * 3;"), "int a = (1 + 2) * 3;\n");
+        assert_eq!(format("int a = (1 * 2) + 3;"), "int a = 1 * 2 + 3;\n");
+        assert_eq!(format("int a = 1 - (2 - 3);"), "int a = 1 - (2 - 3);\n");
+        assert_eq!(format("int a = (1 - 2) - 3;"), "int a = 1 - 2 - 3;\n");
+        assert_eq!(format("void f() { a = (b = c); }"), "void f() {\n    a = b = c;\n}\n");
+        assert_eq!(format("void f() { (a = b) = c; }"), "void f() {\n    (a = b) = c;\n}\n");
+        assert_eq!(format("void f() { x = -(-a); }"), "void f() {\n    x = - -a;\n}\n");
+        assert_eq!(format("void f() { x = (*p)[1] + *(p[1]); }"), "void f() {\n    x = (*p)[1] + *p[1];\n}\n");
+    }
+
+    #[test]
+    fn format_control_flow() {
+        assert_eq!(
+            format("int f(int a){if(a){return 1;}else if(a>1)return 2;else{return 3;}}"),
+            "int f(int a) {\n    if (a) {\n        return 1;\n    } else if (a > 1)\n        return 2;\n    else {\n        return 3;\n    }\n}\n"
+        );
+        assert_eq!(
+            format("void f(){for(;;){} for(int i=0;i<3;i=i+1) while(1) break;}"),
+            "void f() {\n    for (;;) {\n    }\n    for (int i = 0; i < 3; i = i + 1)\n        while (1)\n            break;\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_comments() {
+        let input = "// header\n\nint main() { // entry\n    int x = 1; // one\n\n    /* block\n       comment */\n    return x;\n    // last\n}\n// end\n";
+        let formatted = format(input);
+        assert_eq!(formatted, input.replace("{ // entry\n", "{\n    // entry\n"));
+        round_trip(input);
+    }
+
+    #[test]
+    fn format_examples() {
+        // benchadding.mc has very deep expressions
+        std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(format_examples_inner)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    fn format_examples_inner() {
+        // the examples which are not formatted, every other one has to round trip
+        let skipped = [
+            // `struct A a;` declaration of the variable is not supported
+            "struct.mc",
+        ];
+        for entry in std::fs::read_dir("../examples/tests").unwrap() {
+            let path = entry.unwrap().path();
+            if skipped.iter().any(|name| path.ends_with(name)) {
+                continue;
+            }
+            let input = std::fs::read_to_string(&path).unwrap();
+            let formatted = format_source(input.clone(), path.to_string_lossy().to_string())
+                .unwrap_or_else(|e| panic!("{:?}: {}", path, e));
+            assert!(
+                same_syntax(&parse_syntax(&input), &parse_syntax(&formatted)),
+                "{:?}",
+                path
+            );
+            assert_eq!(formatted, format(&formatted), "{:?}", path);
+        }
+    }
+
+    /// small generator so the test does not need external crates
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 33) % bound
+        }
+    }
+
+    fn random_expr(rng: &mut Random, depth: u32) -> String {
+        const BINARY: [&str; 16] = [
+            "+", "-", "*", "/", "%", "<", "<=", ">", ">=", "==", "!=", "|", "||", "&", "&&", "=",
+        ];
+        const PREFIX: [&str; 8] = ["-", "+", "!", "~", "*", "&", "++", "--"];
+        if depth == 0 {
+            return match rng.next(4) {
+                0 => rng.next(100).to_string(),
+                1 => "'c'".to_string(),
+                2 => "a".to_string(),
+                _ => "b".to_string(),
+            };
+        }
+        match rng.next(7) {
+            0..=2 => format!(
+                "({} {} {})",
+                random_expr(rng, depth - 1),
+                BINARY[rng.next(16) as usize],
+                random_expr(rng, depth - 1)
+            ),
+            3 => format!(
+                "{}({})",
+                PREFIX[rng.next(8) as usize],
+                random_expr(rng, depth - 1)
+            ),
+            4 => format!("({})[{}]", random_expr(rng, depth - 1), random_expr(rng, depth - 1)),
+            5 => format!("f({}, {})", random_expr(rng, depth - 1), random_expr(rng, depth - 1)),
+            _ => format!("({}).x++", random_expr(rng, depth - 1)),
+        }
+    }
+
+    #[test]
+    fn format_modules() {
+        let input = "import \"m.mc\"; pub int f() {m::P p; m::x = m::f('\\t'); return m::g(1) * 2;}";
+        round_trip(input);
+        assert_eq!(
+            format(input),
+            "import \"m.mc\";\n\npub int f() {\n    m::P p;\n    m::x = m::f('\\t');\n    return m::g(1) * 2;\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_layout() {
+        let input = "union packed aligned(8) U {aligned(16) int x; packed char c[3];} struct S; int f() {return offsetof(U,x);}";
+        round_trip(input);
+        assert_eq!(
+            format(input),
+            "union packed aligned(8) U {\n    aligned(16) int x;\n    packed char c[3];\n}\n\nstruct S;\n\nint f() {\n    return offsetof(U, x);\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_const_and_static() {
+        let input = "const int x = 1; int f(const char * const s) {static int* const p; return x;}";
+        round_trip(input);
+        assert_eq!(
+            format(input),
+            "const int x = 1;\n\nint f(const char* const s) {\n    static int* const p;\n    return x;\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_variadic() {
+        let input = "int log(const char*fmt,...) {va_list ap; va_start(ap, fmt); int x = va_arg(ap,char*)[0]; va_end(ap); return printf(\"%d\\t\\\"\", x);}";
+        round_trip(input);
+        assert_eq!(
+            format(input),
+            "int log(const char* fmt, ...) {\n    va_list ap;\n    va_start(ap);\n    int x = va_arg(ap, char*)[0];\n    va_end(ap);\n    return printf(\"%d\\t\\\"\", x);\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_assert() {
+        let input = "void f(int*p) {assert(p!=cast<int*>(0)&&*p>1);}";
+        round_trip(input);
+        assert_eq!(
+            format(input),
+            "void f(int* p) {\n    assert(p != cast<int*>(0) && *p > 1);\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_round_trip_property() {
+        let mut rng = Random(42);
+        for _ in 0..500 {
+            let expr = random_expr(&mut rng, 4);
+            round_trip(&format!("void f() {{ {}; }}", expr));
+        }
+    }
+}
@@ -0,0 +1,349 @@
+//! Minimal json value with parser and printer, it is only
+//! meant for the ast export so there is no need for
+//! external dependency.
+
+use std::{collections::BTreeMap, fmt::Display, iter::Peekable, str::Chars};
+
+use crate::errors::JsonError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(BTreeMap<String, JsonValue>),
+}
+
+impl JsonValue {
+    pub fn object<const N: usize>(fields: [(&str, JsonValue); N]) -> Self {
+        JsonValue::Object(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, field: &str) -> Result<&JsonValue, JsonError> {
+        match self {
+            JsonValue::Object(fields) => fields
+                .get(field)
+                .ok_or_else(|| JsonError::MissingField(field.to_string())),
+            _ => Err(JsonError::InvalidValue(field.to_string())),
+        }
+    }
+
+    /// missing field and null are both treated as None
+    pub fn get_opt(&self, field: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(fields) => match fields.get(field) {
+                Some(JsonValue::Null) | None => None,
+                Some(value) => Some(value),
+            },
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Result<i64, JsonError> {
+        match self {
+            JsonValue::Number(n) => Ok(*n),
+            _ => Err(JsonError::InvalidValue("number".to_string())),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool, JsonError> {
+        match self {
+            JsonValue::Bool(b) => Ok(*b),
+            _ => Err(JsonError::InvalidValue("bool".to_string())),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str, JsonError> {
+        match self {
+            JsonValue::String(s) => Ok(s),
+            _ => Err(JsonError::InvalidValue("string".to_string())),
+        }
+    }
+
+    pub fn as_array(&self) -> Result<&Vec<JsonValue>, JsonError> {
+        match self {
+            JsonValue::Array(arr) => Ok(arr),
+            _ => Err(JsonError::InvalidValue("array".to_string())),
+        }
+    }
+}
+
+impl From<i64> for JsonValue {
+    fn from(n: i64) -> Self {
+        JsonValue::Number(n)
+    }
+}
+
+impl From<usize> for JsonValue {
+    fn from(n: usize) -> Self {
+        JsonValue::Number(n as i64)
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(b: bool) -> Self {
+        JsonValue::Bool(b)
+    }
+}
+
+impl From<&str> for JsonValue {
+    fn from(s: &str) -> Self {
+        JsonValue::String(s.to_string())
+    }
+}
+
+impl From<Vec<JsonValue>> for JsonValue {
+    fn from(arr: Vec<JsonValue>) -> Self {
+        JsonValue::Array(arr)
+    }
+}
+
+impl<T> From<Option<T>> for JsonValue
+where
+    T: Into<JsonValue>,
+{
+    fn from(opt: Option<T>) -> Self {
+        match opt {
+            Some(x) => x.into(),
+            None => JsonValue::Null,
+        }
+    }
+}
+
+fn write_string(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl Display for JsonValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonValue::Null => write!(f, "null"),
+            JsonValue::Bool(b) => write!(f, "{}", b),
+            JsonValue::Number(n) => write!(f, "{}", n),
+            JsonValue::String(s) => write_string(f, s),
+            JsonValue::Array(arr) => {
+                write!(f, "[")?;
+                for (i, value) in arr.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            JsonValue::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+pub fn parse_json(input: &str) -> Result<JsonValue, JsonError> {
+    let mut parser = JsonParser {
+        input: input.chars().peekable(),
+        position: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_white();
+    match parser.input.peek() {
+        None => Ok(value),
+        Some(_) => Err(JsonError::UnexpectedCharacter(parser.position)),
+    }
+}
+
+struct JsonParser<'a> {
+    input: Peekable<Chars<'a>>,
+    position: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn next(&mut self) -> Result<char, JsonError> {
+        self.position += 1;
+        self.input.next().ok_or(JsonError::UnexpectedEof)
+    }
+
+    fn peek(&mut self) -> Result<char, JsonError> {
+        self.input.peek().copied().ok_or(JsonError::UnexpectedEof)
+    }
+
+    fn skip_white(&mut self) {
+        while let Some(c) = self.input.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.input.next();
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        self.skip_white();
+        if self.next()? != expected {
+            return Err(JsonError::UnexpectedCharacter(self.position - 1));
+        }
+        Ok(())
+    }
+
+    fn keyword(&mut self, keyword: &str, value: JsonValue) -> Result<JsonValue, JsonError> {
+        for c in keyword.chars() {
+            if self.next()? != c {
+                return Err(JsonError::UnexpectedCharacter(self.position - 1));
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<JsonValue, JsonError> {
+        self.skip_white();
+        match self.peek()? {
+            'n' => self.keyword("null", JsonValue::Null),
+            't' => self.keyword("true", JsonValue::Bool(true)),
+            'f' => self.keyword("false", JsonValue::Bool(false)),
+            '"' => Ok(JsonValue::String(self.string()?)),
+            '[' => self.array(),
+            '{' => self.object(),
+            '-' | '0'..='9' => self.number(),
+            _ => Err(JsonError::UnexpectedCharacter(self.position)),
+        }
+    }
+
+    fn number(&mut self) -> Result<JsonValue, JsonError> {
+        let start = self.position;
+        let mut text = String::new();
+        if self.peek()? == '-' {
+            text.push(self.next()?);
+        }
+        while let Some(c) = self.input.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            text.push(self.next()?);
+        }
+        text.parse::<i64>()
+            .map(JsonValue::Number)
+            .map_err(|_| JsonError::UnexpectedCharacter(start))
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut result = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(result),
+                '\\' => {
+                    let c = match self.next()? {
+                        '"' => '"',
+                        '\\' => '\\',
+                        '/' => '/',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'u' => {
+                            let mut code = 0;
+                            for _ in 0..4 {
+                                let digit = self
+                                    .next()?
+                                    .to_digit(16)
+                                    .ok_or(JsonError::UnexpectedCharacter(self.position - 1))?;
+                                code = code * 16 + digit;
+                            }
+                            char::from_u32(code)
+                                .ok_or(JsonError::UnexpectedCharacter(self.position - 1))?
+                        }
+                        _ => return Err(JsonError::UnexpectedCharacter(self.position - 1)),
+                    };
+                    result.push(c);
+                }
+                c => result.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<JsonValue, JsonError> {
+        self.expect('[')?;
+        let mut result = vec![];
+        self.skip_white();
+        if self.peek()? == ']' {
+            self.next()?;
+            return Ok(JsonValue::Array(result));
+        }
+        loop {
+            result.push(self.value()?);
+            self.skip_white();
+            match self.next()? {
+                ',' => continue,
+                ']' => return Ok(JsonValue::Array(result)),
+                _ => return Err(JsonError::UnexpectedCharacter(self.position - 1)),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<JsonValue, JsonError> {
+        self.expect('{')?;
+        let mut result = BTreeMap::new();
+        self.skip_white();
+        if self.peek()? == '}' {
+            self.next()?;
+            return Ok(JsonValue::Object(result));
+        }
+        loop {
+            self.skip_white();
+            let name = self.string()?;
+            self.expect(':')?;
+            result.insert(name, self.value()?);
+            self.skip_white();
+            match self.next()? {
+                ',' => continue,
+                '}' => return Ok(JsonValue::Object(result)),
+                _ => return Err(JsonError::UnexpectedCharacter(self.position - 1)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let input = r#"{"a": [1, -2, true, null], "b": "x\"\nA", "c": {}}"#;
+        let value = parse_json(input).unwrap();
+        assert_eq!(
+            value.get("b").unwrap(),
+            &JsonValue::String("x\"\nA".to_string())
+        );
+        assert_eq!(parse_json(&value.to_string()).unwrap(), value);
+        assert!(parse_json("[1, 2").is_err());
+        assert!(parse_json("[1] 2").is_err());
+    }
+}
@@ -35,6 +35,19 @@ pub enum Keyword {
     Conti,
     Return,
     Struct,
+    Extern,
+    Import,
+    Pub,
+    Union,
+    Packed,
+    Aligned,
+    OffsetOf,
+    Static,
+    Const,
+    VaStart,
+    VaArg,
+    VaEnd,
+    Assert,
 }
 
 impl Into<TokenType> for Keyword {
@@ -60,6 +73,19 @@ impl FromStr for Keyword {
             "continue" => Ok(Keyword::Conti),
             "return" => Ok(Keyword::Return),
             "struct" => Ok(Keyword::Struct),
+            "extern" => Ok(Keyword::Extern),
+            "import" => Ok(Keyword::Import),
+            "pub" => Ok(Keyword::Pub),
+            "union" => Ok(Keyword::Union),
+            "packed" => Ok(Keyword::Packed),
+            "aligned" => Ok(Keyword::Aligned),
+            "offsetof" => Ok(Keyword::OffsetOf),
+            "static" => Ok(Keyword::Static),
+            "const" => Ok(Keyword::Const),
+            "va_start" => Ok(Keyword::VaStart),
+            "va_arg" => Ok(Keyword::VaArg),
+            "va_end" => Ok(Keyword::VaEnd),
+            "assert" => Ok(Keyword::Assert),
             _ => Err(()),
         }
     }
@@ -74,6 +100,7 @@ pub enum TokenType {
     Int(i64),
     //Double(f64),
     Char(char),
+    Str(String),
     Kw(Keyword),
     LeftBrac,
     RightBrac,
@@ -84,6 +111,7 @@ pub enum TokenType {
     Semicol,
     Comma,
     Dot,
+    Ellipsis,
     At,
 }
 
@@ -101,11 +129,45 @@ pub struct Loc {
     position: usize,
 }
 
+impl Loc {
+    pub fn new(row: usize, col: usize, position: usize) -> Self {
+        Self { row, col, position }
+    }
+
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    pub fn col(&self) -> usize {
+        self.col
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+/// Comment found in the source, the text contains
+/// the delimiters so it can be emitted back as is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    pub loc: Loc,
+    pub text: String,
+}
+
+impl Comment {
+    /// row on which the comment ends
+    pub fn end_row(&self) -> usize {
+        self.loc.row + self.text.matches('\n').count()
+    }
+}
+
 pub struct Lexer {
     act_loc: Loc,
     last_loc: Loc,
     file_name: String,
     input: Vec<char>,
+    comments: Vec<Comment>,
 }
 
 impl Lexer {
@@ -115,14 +177,33 @@ impl Lexer {
             last_loc: Loc::default(),
             file_name,
             input: input.collect(),
+            comments: vec![],
         }
     }
 
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn comments(&self) -> &Vec<Comment> {
+        &self.comments
+    }
+
+    /// current location of the lexer, used for error reporting
+    pub fn loc(&self) -> Loc {
+        self.act_loc
+    }
+
     pub fn reset_to(&mut self, position: Loc) {
         self.act_loc = position;
         self.last_loc = position;
     }
 
+    /// token used in place of the rest of the input after error
+    pub fn eof_token(&self) -> Token {
+        self.create_token(TokenType::Eof)
+    }
+
     fn create_token(&self, tok_type: TokenType) -> Token {
         Token {
             position: self.last_loc,
@@ -133,6 +214,10 @@ impl Lexer {
 
     fn ignore_white(&mut self) -> Result<(), LexerError> {
         while let Ok(x) = self.peek_char() {
+            if x == '/' && self.comment_start() {
+                self.comment()?;
+                continue;
+            }
             if !x.is_whitespace() {
                 break;
             }
@@ -141,6 +226,52 @@ impl Lexer {
         Ok(())
     }
 
+    fn comment_start(&self) -> bool {
+        matches!(
+            self.input.get(self.act_loc.position + 1),
+            Some('/') | Some('*')
+        )
+    }
+
+    fn comment(&mut self) -> Result<(), LexerError> {
+        let loc = self.act_loc;
+        let mut text = String::new();
+        text.push(self.next_char()?);
+        let block = self.next_char()? == '*';
+        text.push(if block { '*' } else { '/' });
+
+        if block {
+            loop {
+                let c = self.next_char().map_err(|_| LexerError::CommentNotClosed)?;
+                text.push(c);
+                if c == '*' && self.peek_char() == Ok('/') {
+                    text.push(self.next_char()?);
+                    break;
+                }
+            }
+        } else {
+            while let Ok(c) = self.peek_char() {
+                if c == '\n' {
+                    break;
+                }
+                text.push(self.next_char()?);
+            }
+        }
+
+        // after reset_to the same comment could be read again
+        if self
+            .comments
+            .last()
+            .is_none_or(|c| c.loc.position < loc.position)
+        {
+            self.comments.push(Comment {
+                loc,
+                text: text.trim_end().to_string(),
+            });
+        }
+        Ok(())
+    }
+
     fn peek_char(&mut self) -> Result<char, LexerError> {
         if self.act_loc.position < self.input.len() {
             Ok(self.input[self.act_loc.position].clone())
@@ -213,6 +344,12 @@ impl Lexer {
             ';' => Ok(self.create_token(single_char(TokenType::Semicol))),
             ',' => Ok(self.create_token(single_char(TokenType::Comma))),
             '0' => Ok(self.create_token(single_char(TokenType::Int(0)))),
+            '.' if self.ellipsis_next() => {
+                for _ in 0..3 {
+                    self.next_char()?;
+                }
+                Ok(self.create_token(TokenType::Ellipsis))
+            }
             '.' => Ok(self.create_token(single_char(TokenType::Dot))),
             '@' => Ok(self.create_token(single_char(TokenType::At))),
             c if c.is_alphabetic() || c == '_' => {
@@ -227,6 +364,10 @@ impl Lexer {
                 let c = self.char_tok()?;
                 Ok(self.create_token(TokenType::Char(c)))
             }
+            '"' => {
+                let s = self.str_tok()?;
+                Ok(self.create_token(TokenType::Str(s)))
+            }
             c => Err(LexerError::UnexpectedCharacter(c)),
         }?;
 
@@ -254,21 +395,45 @@ impl Lexer {
         }
     }
 
+    /// identifier, qualified names as `module::name` are one token
     fn ident(&mut self) -> Result<String, LexerError> {
         let mut res = "".to_string();
-        while let Ok(x) = self.peek_char() {
-            if !x.is_ident_char() {
+        loop {
+            while let Ok(x) = self.peek_char() {
+                if !x.is_ident_char() {
+                    break;
+                }
+                res += self.peek_char()?.to_string().as_str();
+                match self.next_char() {
+                    Ok(_) => (),
+                    Err(_) => break,
+                };
+            }
+            if !self.qualified_next() {
                 break;
             }
-            res += self.peek_char()?.to_string().as_str();
-            match self.next_char() {
-                Ok(_) => (),
-                Err(_) => break,
-            };
+            res += "::";
+            self.next_char()?;
+            self.next_char()?;
         }
         Ok(res)
     }
 
+    fn qualified_next(&self) -> bool {
+        let pos = self.act_loc.position;
+        self.input.get(pos) == Some(&':')
+            && self.input.get(pos + 1) == Some(&':')
+            && self
+                .input
+                .get(pos + 2)
+                .is_some_and(|c| c.is_alphabetic() || *c == '_')
+    }
+
+    fn ellipsis_next(&self) -> bool {
+        let pos = self.act_loc.position;
+        self.input.get(pos + 1) == Some(&'.') && self.input.get(pos + 2) == Some(&'.')
+    }
+
     pub fn num(&mut self) -> Result<i64, LexerError> {
         let mut res: i64 = 0;
         while let Ok(x) = self.peek_char() {
@@ -290,11 +455,7 @@ impl Lexer {
     pub fn char_tok(&mut self) -> Result<char, LexerError> {
         self.compare('\'')?;
         let c = if self.peek_char()? == '\\' {
-            self.compare('\\')?;
-            match self.next_char()? {
-                'n' => '\n',
-                x => return Err(LexerError::UnexpectedCharacter(x)),
-            }
+            self.escape()?
         } else {
             self.next_char()?
         };
@@ -302,6 +463,32 @@ impl Lexer {
         Ok(c)
     }
 
+    fn escape(&mut self) -> Result<char, LexerError> {
+        self.compare('\\')?;
+        match self.next_char()? {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            '0' => Ok('\0'),
+            x @ ('\\' | '\'' | '"') => Ok(x),
+            x => Err(LexerError::UnexpectedCharacter(x)),
+        }
+    }
+
+    pub fn str_tok(&mut self) -> Result<String, LexerError> {
+        self.compare('"')?;
+        let mut res = String::new();
+        loop {
+            match self.peek_char() {
+                Ok('"') => break,
+                Ok('\\') => res.push(self.escape()?),
+                Ok('\n') | Err(_) => return Err(LexerError::StringNotClosed),
+                Ok(_) => res.push(self.next_char()?),
+            }
+        }
+        self.compare('"')?;
+        Ok(res)
+    }
+
     pub fn check_keyword(ident: String) -> TokenType {
         match ident.parse::<Keyword>() {
             Ok(k) => k.into(),
@@ -465,4 +652,72 @@ mod tests {
                 .collect::<Vec<TokenType>>()
         );
     }
+
+    #[test]
+    fn test_comments() {
+        let input = "// first\nx /* inner */ / y /* multi\nline */".to_string();
+
+        let mut lex = Lexer::new("filename.tc".to_string(), input.chars().peekable());
+        let mut tokens: Vec<TokenType> = vec![];
+        loop {
+            let token = lex.get_token().unwrap();
+            tokens.push(token.tok);
+            if tokens.last().unwrap() == &TokenType::Eof {
+                break;
+            }
+        }
+        assert_eq!(
+            tokens,
+            vec![
+                TokenType::Ident("x".to_string()),
+                Operator::Div.into(),
+                TokenType::Ident("y".to_string()),
+                TokenType::Eof,
+            ]
+        );
+        let comments: Vec<&String> = lex.comments().iter().map(|x| &x.text).collect();
+        assert_eq!(comments, vec!["// first", "/* inner */", "/* multi\nline */"]);
+        assert_eq!(lex.comments()[2].end_row(), 2);
+
+        let mut lex = Lexer::new("filename.tc".to_string(), "/* open".chars().peekable());
+        assert!(lex.get_token().is_err());
+    }
+
+    #[test]
+    fn test_strings_and_qualified() {
+        let input = "import \"lib/a.mc\"; m::f(x::y) '\\t' \"a\\\"\\n\" s.x, ...";
+
+        let mut lex = Lexer::new("filename.tc".to_string(), input.chars().peekable());
+        let mut tokens: Vec<TokenType> = vec![];
+        loop {
+            let token = lex.get_token().unwrap();
+            tokens.push(token.tok);
+            if tokens.last().unwrap() == &TokenType::Eof {
+                break;
+            }
+        }
+        assert_eq!(
+            tokens,
+            vec![
+                Keyword::Import.into(),
+                TokenType::Str("lib/a.mc".to_string()),
+                TokenType::Semicol,
+                TokenType::Ident("m::f".to_string()),
+                TokenType::LeftBrac,
+                TokenType::Ident("x::y".to_string()),
+                TokenType::RightBrac,
+                TokenType::Char('\t'),
+                TokenType::Str("a\"\n".to_string()),
+                TokenType::Ident("s".to_string()),
+                TokenType::Dot,
+                TokenType::Ident("x".to_string()),
+                TokenType::Comma,
+                TokenType::Ellipsis,
+                TokenType::Eof,
+            ]
+        );
+
+        let mut lex = Lexer::new("filename.tc".to_string(), "\"open".chars().peekable());
+        assert_eq!(lex.get_token(), Err(LexerError::StringNotClosed));
+    }
 }
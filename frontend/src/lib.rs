@@ -1,18 +1,27 @@
 use ast::Program;
-use errors::FrontendError;
+use errors::{FrontendError, Warning};
 use lexer::Lexer;
 use parser::Parser;
-use typecheck::type_program;
+use typecheck::{type_program, type_program_warn};
 
 pub mod ast;
+pub mod ast_json;
 mod compile;
-mod errors;
-mod lexer;
+pub mod errors;
+pub mod format;
+pub mod json;
+pub mod lexer;
+mod modules;
 mod parser;
+mod runtime;
 pub mod typeast;
 mod typecheck;
 
-pub use compile::compile;
+pub use compile::{compile, compile_module, compile_module_with, CompileOptions};
+pub use format::format_source;
+pub use modules::{parse_modules, Module};
+pub use runtime::{runtime_units, STDLIB_VERSION};
+pub use typecheck::WarningOptions;
 
 pub fn parse(input: String, filename: String) -> Result<Program, FrontendError> {
     let lex = Lexer::new(filename, input.chars().peekable());
@@ -22,3 +31,55 @@ pub fn parse(input: String, filename: String) -> Result<Program, FrontendError>
     type_program(&mut program)?;
     Ok(program)
 }
+
+/// Same as [`parse`], also returns the warnings enabled in the options
+pub fn parse_warn(
+    input: String,
+    filename: String,
+    options: WarningOptions,
+) -> Result<(Program, Vec<Warning>), FrontendError> {
+    let lex = Lexer::new(filename, input.chars().peekable());
+    let mut parser = Parser::new(lex)?;
+
+    let mut program = parser.parse()?;
+    let warnings = type_program_warn(&mut program, options)?;
+    Ok((program, warnings))
+}
+
+/// Parses the module at the path with all modules it imports,
+/// see [`parse_modules`]
+pub fn parse_module_files(
+    root: &str,
+    options: WarningOptions,
+) -> Result<Vec<Module>, FrontendError> {
+    parse_modules(root, options, |path| std::fs::read_to_string(path).ok())
+}
+
+/// Parses and typechecks the program, unlike [`parse`] the program
+/// is returned even if the typecheck fails, the nodes checked before
+/// the error keep their types
+pub fn analyze(
+    input: String,
+    filename: String,
+) -> Result<(Program, Option<FrontendError>), FrontendError> {
+    let lex = Lexer::new(filename, input.chars().peekable());
+    let mut parser = Parser::new(lex)?;
+
+    let mut program = parser.parse()?;
+    let error = type_program(&mut program).err();
+    Ok((program, error))
+}
+
+/// Serializes the program into json described in [`ast_json`]
+pub fn program_to_json(program: &Program) -> String {
+    ast_json::program_to_json(program).to_string()
+}
+
+/// Reads the program from json and computes the types, so
+/// the result can be passed directly to [`compile`]
+pub fn parse_json_program(input: &str) -> Result<Program, FrontendError> {
+    let value = json::parse_json(input)?;
+    let mut program = ast_json::program_from_json(&value)?;
+    type_program(&mut program)?;
+    Ok(program)
+}
@@ -0,0 +1,275 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    ast::{Program, TopLevel},
+    errors::{FrontendError, ModuleError, Warning},
+    lexer::Lexer,
+    parser::Parser,
+    typeast::TypeTable,
+    typecheck::{type_module, ModuleSymbols, WarningOptions},
+};
+
+/// Typechecked module of the program
+#[derive(Debug, Clone)]
+pub struct Module {
+    /// name used in the qualified access and in the symbols,
+    /// the root module has none so its symbols are not mangled
+    pub name: Option<String>,
+    pub path: String,
+    pub program: Program,
+    pub warnings: Vec<Warning>,
+}
+
+/// name of the module imported from the path, it is the file name
+/// without the extension
+pub fn module_name(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map_or(path.to_string(), |x| x.to_string_lossy().to_string())
+}
+
+/// path of the import relative to the importing module, `.` and `..`
+/// are removed so the same file has always the same path
+fn resolve(from: &str, import: &str) -> String {
+    let base = Path::new(from).parent().unwrap_or(Path::new(""));
+    let path = base.join(import);
+    let mut result: Vec<Component> = vec![];
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir if matches!(result.last(), Some(Component::Normal(_))) => {
+                result.pop();
+            }
+            c => result.push(c),
+        }
+    }
+    result
+        .iter()
+        .collect::<PathBuf>()
+        .to_string_lossy()
+        .to_string()
+}
+
+struct Resolver<F> {
+    load: F,
+    options: WarningOptions,
+    // paths of the finished modules
+    done: HashSet<String>,
+    // name to the path of the imported modules
+    names: HashMap<String, String>,
+    symbols: HashMap<String, ModuleSymbols>,
+    // structs of all modules checked so far
+    types: TypeTable,
+    // modules which are being resolved, used to find the cycles
+    stack: Vec<String>,
+    modules: Vec<Module>,
+}
+
+impl<F> Resolver<F>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    fn visit(&mut self, path: String, root: bool) -> Result<(), FrontendError> {
+        if self.done.contains(&path) {
+            return Ok(());
+        }
+        if let Some(index) = self.stack.iter().position(|x| *x == path) {
+            let mut cycle = self.stack[index..].to_vec();
+            cycle.push(path);
+            return Err(ModuleError::Cycle(cycle).into());
+        }
+
+        let source = (self.load)(&path).ok_or_else(|| ModuleError::NotFound(path.clone()))?;
+        let in_module = |e: FrontendError| FrontendError::InModule(path.clone(), Box::new(e));
+        let lex = Lexer::new(path.clone(), source.chars().peekable());
+        let mut program = Parser::new(lex)
+            .and_then(|mut parser| parser.parse())
+            .map_err(in_module)?;
+
+        self.stack.push(path.clone());
+        for item in program.items.iter() {
+            if let TopLevel::Import(import) = item {
+                self.visit(resolve(&path, &import.value), false)
+                    .map_err(|e| match e {
+                        FrontendError::Module(_) => in_module(e.with_loc(import.loc())),
+                        e => e,
+                    })?;
+            }
+        }
+        self.stack.pop();
+
+        let name = module_name(&path);
+        if !root && self.names.insert(name.clone(), path.clone()).is_some() {
+            return Err(ModuleError::NameClash(name).into());
+        }
+        let types = std::mem::take(&mut self.types);
+        let result = type_module(&mut program, &self.symbols, types, self.options);
+        self.types = program.types.clone();
+        let (warnings, symbols) = result.map_err(in_module)?;
+        if !root {
+            self.symbols.insert(name.clone(), symbols);
+        }
+
+        self.done.insert(path.clone());
+        self.modules.push(Module {
+            name: (!root).then_some(name),
+            path,
+            program,
+            warnings,
+        });
+        Ok(())
+    }
+}
+
+/// Parses and typechecks the root module and all modules it imports,
+/// `load` returns the source of the module with the path. Every module
+/// is processed once and the result is ordered so the imported modules
+/// come before the modules importing them, the root is the last one
+pub fn parse_modules(
+    root: &str,
+    options: WarningOptions,
+    load: impl FnMut(&str) -> Option<String>,
+) -> Result<Vec<Module>, FrontendError> {
+    let mut resolver = Resolver {
+        load,
+        options,
+        done: HashSet::new(),
+        names: HashMap::new(),
+        symbols: HashMap::new(),
+        types: TypeTable::default(),
+        stack: vec![],
+        modules: vec![],
+    };
+    resolver.visit(root.to_string(), true)?;
+    // every module can use the structs of the modules it imports
+    let mut modules = resolver.modules;
+    for module in modules.iter_mut() {
+        module.program.types = resolver.types.clone();
+    }
+    Ok(modules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::TypeError;
+
+    fn modules(files: &[(&str, &str)]) -> (Result<Vec<Module>, FrontendError>, Vec<String>) {
+        let files: HashMap<String, String> = files
+            .iter()
+            .map(|(path, source)| (path.to_string(), source.to_string()))
+            .collect();
+        let mut loaded = vec![];
+        let result = parse_modules(
+            files.keys().min().unwrap(),
+            WarningOptions::default(),
+            |path| {
+                loaded.push(path.to_string());
+                files.get(path).cloned()
+            },
+        );
+        (result, loaded)
+    }
+
+    fn type_error(result: Result<Vec<Module>, FrontendError>) -> TypeError {
+        match result.unwrap_err() {
+            FrontendError::InModule(_, e) => match e.inner() {
+                FrontendError::Type(errors) => errors[0].clone(),
+                e => panic!("{:?}", e),
+            },
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn resolve_paths() {
+        assert_eq!(resolve("src/main.mc", "lib/a.mc"), "src/lib/a.mc");
+        assert_eq!(resolve("src/lib/a.mc", "../b.mc"), "src/b.mc");
+        assert_eq!(resolve("main.mc", "./b.mc"), "b.mc");
+        assert_eq!(module_name("src/lib/vec.mc"), "vec");
+    }
+
+    #[test]
+    fn import_graph() {
+        let (result, loaded) = modules(&[
+            (
+                "a.mc",
+                "import \"lib/b.mc\"; import \"lib/c.mc\"; int main() {return b::f() + c::g();}",
+            ),
+            ("lib/b.mc", "import \"c.mc\"; pub int f() {return c::g();}"),
+            ("lib/c.mc", "pub int g() {return 1;}"),
+        ]);
+        let result = result.unwrap();
+        let names: Vec<Option<&str>> = result.iter().map(|m| m.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("c"), Some("b"), None]);
+        // the module imported twice is compiled only once
+        assert_eq!(loaded, vec!["a.mc", "lib/b.mc", "lib/c.mc"]);
+    }
+
+    #[test]
+    fn import_errors() {
+        let (result, _) = modules(&[
+            ("a.mc", "import \"b.mc\"; int main() {return 0;}"),
+            ("b.mc", "import \"c.mc\";"),
+            ("c.mc", "import \"b.mc\";"),
+        ]);
+        let cycle = vec!["b.mc".to_string(), "c.mc".to_string(), "b.mc".to_string()];
+        assert!(matches!(
+            result.unwrap_err(),
+            FrontendError::InModule(path, e) if path == "c.mc"
+                && *e.inner() == FrontendError::Module(ModuleError::Cycle(cycle))
+        ));
+
+        let (result, _) = modules(&[("a.mc", "import \"missing.mc\";")]);
+        let e = result.unwrap_err();
+        assert_eq!(e.to_string(), "a.mc:1:1: module missing.mc not found");
+
+        let (result, _) = modules(&[
+            ("a.mc", "import \"b/m.mc\"; import \"c/m.mc\";"),
+            ("b/m.mc", ""),
+            ("c/m.mc", ""),
+        ]);
+        let clash = FrontendError::Module(ModuleError::NameClash("m".to_string()));
+        assert_eq!(*result.unwrap_err().inner(), clash);
+
+        let (result, _) = modules(&[
+            ("a.mc", "import \"b.mc\"; int main() {return b::hidden();}"),
+            ("b.mc", "int hidden() {return 1;}"),
+        ]);
+        assert_eq!(
+            type_error(result),
+            TypeError::NotPublic("hidden".to_string())
+        );
+
+        let (result, _) = modules(&[
+            ("a.mc", "import \"b.mc\"; int main() {return c::f();}"),
+            ("b.mc", "import \"c.mc\";"),
+            ("c.mc", "pub int f() {return 1;}"),
+        ]);
+        assert_eq!(
+            type_error(result),
+            TypeError::UnknownModule("c".to_string())
+        );
+    }
+
+    #[test]
+    fn qualified_types_and_globals() {
+        let (result, _) = modules(&[
+            (
+                "a.mc",
+                "import \"b.mc\"; int main() {b::P p; b::counter = 2; return b::counter;}",
+            ),
+            ("b.mc", "pub struct P {int x;} pub int counter;"),
+        ]);
+        assert!(result.is_ok());
+
+        let (result, _) = modules(&[
+            ("a.mc", "import \"b.mc\"; int main() {b::P p; return 0;}"),
+            ("b.mc", "struct P {int x;}"),
+        ]);
+        assert_eq!(type_error(result), TypeError::NotPublic("P".to_string()));
+    }
+}
@@ -2,28 +2,38 @@ use std::collections::HashSet;
 
 use crate::{
     ast::{
-        AstData, Expr, ExprType, FnDecl, FnDeclType, FnDef, FnDefType, Operator, Program,
+        AstData, Expr, ExprType, FnDecl, FnDeclType, FnDef, FnDefType, Import, Operator, Program,
         Statement, StatementType, StructDef, StructDefType, TopLevel, Val, VarDecl, VarDeclType,
     },
     errors::{FrontendError, ParserError},
-    lexer::{Keyword, Lexer, Loc, Token, TokenType},
-    typeast::{ArrayType, PrimType, TypeDef},
+    lexer::{Comment, Keyword, Lexer, Loc, Token, TokenType},
+    typeast::{ArrayType, Layout, PrimType, TypeDef},
 };
 
 pub struct Parser {
     lexer: Lexer,
     curr_tok: Token,
     type_names: HashSet<String>,
+    // location of the last consumed token
+    last_loc: Loc,
+    // lexer error found while consuming tokens,
+    // the rest of the input is treated as eof
+    lexer_error: Option<FrontendError>,
 }
 
 impl Parser {
     pub fn new(lexer: Lexer) -> Result<Self, FrontendError> {
         let mut lexer = lexer;
-        let curr_tok = lexer.get_token()?;
+        let curr_tok = lexer
+            .get_token()
+            .map_err(|e| FrontendError::from(e).with_loc(lexer.loc()))?;
         Ok(Self {
+            last_loc: curr_tok.position,
             lexer,
             curr_tok,
-            type_names: HashSet::new(),
+            // the only predeclared type
+            type_names: HashSet::from(["va_list".to_string()]),
+            lexer_error: None,
         })
     }
 
@@ -39,7 +49,17 @@ impl Parser {
 
     fn pop(&mut self) -> Token {
         let tmp = self.top();
-        self.curr_tok = self.lexer.get_token().unwrap();
+        self.last_loc = tmp.position;
+        self.curr_tok = match self.lexer.get_token() {
+            Ok(tok) => tok,
+            Err(e) => {
+                let loc = self.lexer.loc();
+                if self.lexer_error.is_none() {
+                    self.lexer_error = Some(FrontendError::from(e).with_loc(loc));
+                }
+                self.lexer.eof_token()
+            }
+        };
         tmp
     }
 
@@ -56,34 +76,110 @@ impl Parser {
         AstData::new(self.top().position)
     }
 
+    pub fn comments(&self) -> &Vec<Comment> {
+        self.lexer.comments()
+    }
+
+    /// parses the whole input, the error has the location
+    /// of the last consumed token
     pub fn parse(&mut self) -> Result<Program, FrontendError> {
+        let result = self.items();
+        if let Some(e) = self.lexer_error.take() {
+            return Err(e);
+        }
+        result.map_err(|e| e.with_loc(self.last_loc))
+    }
+
+    fn items(&mut self) -> Result<Program, FrontendError> {
         let mut items: Vec<TopLevel> = vec![];
 
         while self.top().tok != TokenType::Eof {
-            if self.top().tok == Keyword::Struct.into() {
+            if self.is_struct() {
                 items.push(TopLevel::Structure(self.struct_def()?));
-            } else {
-                let position = self.top().position;
-                self.type_parse()?;
-                self.get_ident()?;
-                let tmp = self.top().tok;
-                self.reset_to(position)?;
-                if tmp == TokenType::LeftBrac {
-                    items.push(TopLevel::Function(self.fn_decl()?));
+            } else if self.top().tok == Keyword::Extern.into() {
+                self.pop();
+                items.push(TopLevel::Extern(Box::new(self.declaration()?)));
+            } else if self.top().tok == Keyword::Import.into() {
+                items.push(TopLevel::Import(self.import()?));
+            } else if self.top().tok == Keyword::Pub.into() {
+                self.pop();
+                let item = if self.is_struct() {
+                    TopLevel::Structure(self.struct_def()?)
                 } else {
-                    items.push(TopLevel::Var(self.var_decl()?));
-                    self.compare(TokenType::Semicol)?;
-                }
+                    self.declaration()?
+                };
+                items.push(TopLevel::Pub(Box::new(item)));
+            } else {
+                items.push(self.declaration()?);
             }
         }
 
-        Ok(Program { items })
+        Ok(Program {
+            items,
+            ..Default::default()
+        })
     }
 
-    fn struct_def(&mut self) -> Result<StructDef, FrontendError> {
+    /// global variable or function
+    fn declaration(&mut self) -> Result<TopLevel, FrontendError> {
+        let position = self.top().position;
+        self.type_parse()?;
+        self.get_ident()?;
+        let tmp = self.top().tok;
+        self.reset_to(position)?;
+        if tmp == TokenType::LeftBrac {
+            Ok(TopLevel::Function(self.fn_decl()?))
+        } else {
+            let var = self.var_decl()?;
+            self.compare(TokenType::Semicol)?;
+            Ok(TopLevel::Var(var))
+        }
+    }
+
+    fn import(&mut self) -> Result<Import, FrontendError> {
         let data = self.act_data();
-        self.compare(Keyword::Struct.into())?;
-        let name = self.get_ident()?;
+        self.compare(Keyword::Import.into())?;
+        let path = match self.pop().tok {
+            TokenType::Str(path) => path,
+            t => return Err(ParserError::UnexpectedToken(t).into()),
+        };
+        self.compare(TokenType::Semicol)?;
+        Ok(Import::new(path, data))
+    }
+
+    fn is_struct(&self) -> bool {
+        self.top().tok == Keyword::Struct.into() || self.top().tok == Keyword::Union.into()
+    }
+
+    /// `packed` and `aligned(N)` attributes in any order
+    fn layout(&mut self) -> Result<Layout, FrontendError> {
+        let mut layout = Layout::default();
+        loop {
+            match self.top().tok {
+                TokenType::Kw(Keyword::Packed) => {
+                    self.pop();
+                    layout.packed = true;
+                }
+                TokenType::Kw(Keyword::Aligned) => {
+                    self.pop();
+                    self.compare(TokenType::LeftBrac)?;
+                    let align = match self.pop().tok {
+                        TokenType::Int(align) if align > 0 => align as usize,
+                        t => return Err(ParserError::UnexpectedToken(t).into()),
+                    };
+                    self.compare(TokenType::RightBrac)?;
+                    layout.aligned = Some(align);
+                }
+                _ => return Ok(layout),
+            }
+        }
+    }
+
+    fn struct_def(&mut self) -> Result<StructDef, FrontendError> {
+        let mut data = self.act_data();
+        let is_union = self.pop().tok == Keyword::Union.into();
+        let layout = self.layout()?;
+        let name = self.get_name()?;
 
         self.type_names.insert(name.clone());
         let fields = if self.top().tok == TokenType::Semicol {
@@ -92,21 +188,28 @@ impl Parser {
         } else {
             self.compare(TokenType::LeftCurly)?;
 
-            let mut vars: Vec<VarDecl> = vec![];
+            let mut vars: Vec<(Layout, VarDecl)> = vec![];
             while self.top().tok != TokenType::RightCurly {
+                let layout = self.layout()?;
                 let var = self.var_decl()?;
                 if let Some(_) = var.value.init_val {
                     return Err(ParserError::FieldCannotHaveInit.into());
                 }
-                vars.push(var);
+                vars.push((layout, var));
                 self.compare(TokenType::Semicol)?;
             }
 
+            data.set_end(self.top().position);
             self.compare(TokenType::RightCurly)?;
             Some(vars)
         };
 
-        let res = StructDefType { name, fields };
+        let res = StructDefType {
+            name,
+            is_union,
+            layout,
+            fields,
+        };
         let res = StructDef::new(res, data);
 
         Ok(res)
@@ -115,26 +218,25 @@ impl Parser {
     pub fn fn_decl(&mut self) -> Result<FnDef, FrontendError> {
         let data = self.act_data();
         let ret_type = self.type_parse()?;
-        let tmp = self.pop().tok;
-        let name = if let TokenType::Ident(x) = tmp {
-            Ok(x)
-        } else {
-            Err(ParserError::UnexpectedToken(tmp))
-        }?;
+        let name = self.get_name()?;
 
         self.compare(TokenType::LeftBrac)?;
         let mut params = vec![];
+        let mut variadic = false;
 
-        if self.top().tok != TokenType::RightBrac {
+        while self.top().tok != TokenType::RightBrac {
+            if !params.is_empty() {
+                self.compare(TokenType::Comma)?;
+            }
+            // the ellipsis is always the last one
+            if self.top().tok == TokenType::Ellipsis {
+                self.pop();
+                variadic = true;
+                break;
+            }
             let t = self.type_parse()?;
             let i = self.get_ident()?;
             params.push((i, t));
-            while self.top().tok == TokenType::Comma {
-                self.pop();
-                let t = self.type_parse()?;
-                let i = self.get_ident()?;
-                params.push((i, t));
-            }
         }
 
         self.compare(TokenType::RightBrac)?;
@@ -149,6 +251,7 @@ impl Parser {
             name,
             params,
             ret_type,
+            variadic,
         };
 
         let header = FnDecl::new(header, data.clone());
@@ -165,6 +268,15 @@ impl Parser {
         }
     }
 
+    /// name of a new declaration, it cannot be qualified
+    fn get_name(&mut self) -> Result<String, FrontendError> {
+        let name = self.get_ident()?;
+        if name.contains("::") {
+            return Err(ParserError::VarDeclInvalidName.into());
+        }
+        Ok(name)
+    }
+
     fn statement(&mut self) -> Result<Statement, FrontendError> {
         let data = self.act_data();
         match self.top().tok {
@@ -192,6 +304,12 @@ impl Parser {
                 Ok(Statement::new(result, data))
             }
             TokenType::LeftCurly => self.block_statement(),
+            TokenType::Kw(Keyword::Static) => {
+                self.pop();
+                let var = self.var_decl()?;
+                self.compare(TokenType::Semicol)?;
+                Ok(Statement::new(StatementType::Static(var), data))
+            }
             _ => {
                 let res = self.expr_or_vars()?;
                 self.compare(TokenType::Semicol)?;
@@ -267,13 +385,14 @@ impl Parser {
     }
 
     fn block_statement(&mut self) -> Result<Statement, FrontendError> {
-        let data = self.act_data();
+        let mut data = self.act_data();
         self.compare(TokenType::LeftCurly)?;
         let mut statements = vec![];
 
         while self.top().tok != TokenType::RightCurly {
             statements.push(self.statement()?);
         }
+        data.set_end(self.top().position);
         self.compare(TokenType::RightCurly)?;
 
         Ok(Statement::new(StatementType::Block(statements), data))
@@ -283,7 +402,7 @@ impl Parser {
         let data = self.act_data();
         let mut var_type = self.type_parse()?;
 
-        let name = self.get_ident()?;
+        let name = self.get_name()?;
 
         if self.top().tok == TokenType::LeftSquare {
             self.pop();
@@ -322,11 +441,13 @@ impl Parser {
 
     fn expr_or_vars(&mut self) -> Result<Statement, FrontendError> {
         let p = self.top().position;
-        let t = self.type_parse();
+        // qualified name is taken as a type, the declaration
+        // is recognized by the name following it
+        let t = self.type_parse().map(|_| matches!(self.top().tok, TokenType::Ident(_)));
         self.reset_to(p)?;
         match t {
-            Ok(_) => Ok(self.var_decl()?.into()),
-            Err(_) => Ok(self.expr()?.into()),
+            Ok(true) => Ok(self.var_decl()?.into()),
+            _ => Ok(self.expr()?.into()),
         }
     }
 
@@ -606,6 +727,7 @@ impl Parser {
             TokenType::At => Ok(Expr::new(ExprType::Ident("@".to_string()), data)),
             TokenType::Int(num) => Ok(Expr::new(ExprType::Value(Val::Integer(num)), data)),
             TokenType::Char(c) => Ok(Expr::new(ExprType::Value(Val::Char(c)), data)),
+            TokenType::Str(s) => Ok(Expr::new(ExprType::Str(s), data)),
             TokenType::LeftBrac => {
                 let e = self.expr()?;
                 self.compare(TokenType::RightBrac)?;
@@ -620,13 +742,54 @@ impl Parser {
                 self.compare(TokenType::RightBrac)?;
                 Ok(Expr::new(ExprType::Cast(t, Box::new(e)), data))
             }
+            TokenType::Kw(Keyword::OffsetOf) => {
+                self.compare(TokenType::LeftBrac)?;
+                let t = self.type_parse()?;
+                self.compare(TokenType::Comma)?;
+                let field = self.get_ident()?;
+                self.compare(TokenType::RightBrac)?;
+                Ok(Expr::new(ExprType::OffsetOf(t, field), data))
+            }
+            TokenType::Kw(Keyword::VaStart) => {
+                self.compare(TokenType::LeftBrac)?;
+                let list = self.expr()?;
+                // the last named parameter is not needed, as in C23
+                if self.top().tok == TokenType::Comma {
+                    self.pop();
+                    self.get_ident()?;
+                }
+                self.compare(TokenType::RightBrac)?;
+                Ok(Expr::new(ExprType::VaStart(Box::new(list)), data))
+            }
+            TokenType::Kw(Keyword::VaArg) => {
+                self.compare(TokenType::LeftBrac)?;
+                let list = self.expr()?;
+                self.compare(TokenType::Comma)?;
+                let t = self.type_parse()?;
+                self.compare(TokenType::RightBrac)?;
+                Ok(Expr::new(ExprType::VaArg(Box::new(list), t), data))
+            }
+            TokenType::Kw(Keyword::VaEnd) => {
+                self.compare(TokenType::LeftBrac)?;
+                let list = self.expr()?;
+                self.compare(TokenType::RightBrac)?;
+                Ok(Expr::new(ExprType::VaEnd(Box::new(list)), data))
+            }
+            TokenType::Kw(Keyword::Assert) => {
+                let file = self.lexer.file_name().to_string();
+                self.compare(TokenType::LeftBrac)?;
+                let cond = self.expr()?;
+                self.compare(TokenType::RightBrac)?;
+                Ok(Expr::new(ExprType::Assert(Box::new(cond), file), data))
+            }
             t => Err(ParserError::UnexpectedToken(t).into()),
         }
     }
 
     fn base_type(&mut self) -> Result<TypeDef, FrontendError> {
         if let TokenType::Ident(name) = self.top().tok {
-            if self.type_names.contains(&name) {
+            // types of other modules are known only after the import
+            if self.type_names.contains(&name) || name.contains("::") {
                 self.pop();
                 return Ok(TypeDef::Alias(name));
             }
@@ -640,11 +803,22 @@ impl Parser {
         }
     }
 
+    /// `const` before the type applies to the base type,
+    /// `const` after the star to the pointer
     fn type_parse(&mut self) -> Result<TypeDef, FrontendError> {
-        let mut t = self.base_type()?;
+        let mut t = if self.top().tok == Keyword::Const.into() {
+            self.pop();
+            TypeDef::Const(Box::new(self.base_type()?))
+        } else {
+            self.base_type()?
+        };
         while self.top().tok == Operator::Mul.into() {
             self.pop();
             t = TypeDef::PointerType(Box::new(t));
+            if self.top().tok == Keyword::Const.into() {
+                self.pop();
+                t = TypeDef::Const(Box::new(t));
+            }
         }
 
         Ok(t)
@@ -724,6 +898,14 @@ mod tests {
         program_ok("struct A { int a; } int main() {A a; return a.a.a + 4;}");
         program_err("struct A { int a; } int main() {A a; return a.+;}");
         program_err("struct A { int a; } int main() {A a; return a.1;}");
+        program_ok("union U { int a; char b[8]; }");
+        program_ok("struct packed aligned(8) H { char tag; aligned(4) int len; packed int x; }");
+        program_ok("union aligned(16) U;");
+        program_err("struct aligned H {}");
+        program_err("struct aligned(0) H {}");
+        program_err("struct H { int x packed; }");
+        program_ok("struct H { int x; } int main() {return offsetof(H, x);}");
+        program_err("struct H { int x; } int main() {return offsetof(H);}");
         program_err("int main() {return a.1;}");
         program_ok("int main() {return (1+2).a.a + 4;}");
     }
@@ -752,4 +934,28 @@ mod tests {
         program_err("void main() {int a; a += 1;}");
         program_err("void main() {int a; a -= 1;}");
     }
+
+    #[test]
+    fn test_modules_parser() {
+        program_ok("import \"lib/math.mc\"; int main() {return math::add(1, 2);}");
+        program_ok("import \"a.mc\"; int main() {a::Point p; a::Point * q; a::x = 1; return 0;}");
+        program_ok("pub struct A {int a;} pub int x; pub int f() {return x;}");
+        program_err("import a.mc;");
+        program_err("pub extern int x;");
+        program_err("int m::x;");
+        program_err("int m::f() {}");
+    }
+
+    #[test]
+    fn test_variadic_parser() {
+        program_ok("int printf(const char* fmt, ...);");
+        program_ok("int f(...);");
+        program_ok(
+            "int sum(int n, ...) {va_list ap; va_start(ap, n); int x = va_arg(ap, int); va_end(ap); return x;}",
+        );
+        program_ok("int main() {return printf(\"%d\\n\", 1);}");
+        program_err("int f(..., int a);");
+        program_err("int f(int a ...);");
+        program_err("int main() {return va_arg(ap);}");
+    }
 }
@@ -0,0 +1,279 @@
+//! Runtime and the standard library of the language written in the
+//! language itself. The prelude declares its functions in every module
+//! and the needed parts of the compiled runtime are linked together
+//! with the program.
+
+use middleend::ir::IrProgram;
+
+use crate::{
+    ast::Program, compile::compile_module, lexer::Lexer, modules::parse_modules, parser::Parser,
+    typecheck::WarningOptions,
+};
+
+/// version of the standard library, bumped on every change of the prelude
+pub const STDLIB_VERSION: u32 = 2;
+
+const PRELUDE: &str = include_str!("../runtime/prelude.mc");
+
+// every unit is compiled as a root module, so its symbols are not mangled
+const UNITS: &[(&str, &str)] = &[
+    ("runtime/stdio.mc", include_str!("../runtime/stdio.mc")),
+    ("runtime/string.mc", include_str!("../runtime/string.mc")),
+    ("runtime/stdlib.mc", include_str!("../runtime/stdlib.mc")),
+    ("runtime/malloc.mc", include_str!("../runtime/malloc.mc")),
+];
+
+/// declarations of the runtime functions, they are not typechecked
+pub(crate) fn prelude() -> Program {
+    let lex = Lexer::new("prelude.mc".to_string(), PRELUDE.chars().peekable());
+    Parser::new(lex)
+        .and_then(|mut parser| parser.parse())
+        .expect("prelude is valid")
+}
+
+/// compiled units of the runtime with the paths of their sources
+pub fn runtime_units() -> Vec<(String, IrProgram)> {
+    UNITS
+        .iter()
+        .map(|(path, source)| {
+            let mut modules =
+                parse_modules(path, WarningOptions::default(), |_| Some(source.to_string()))
+                    .unwrap_or_else(|e| panic!("runtime {} is invalid: {}", path, e));
+            let module = modules.pop().unwrap();
+            let unit = compile_module(module.program, None).expect("runtime compiles");
+            (path.to_string(), unit)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use middleend::{
+        ir::IrProgram,
+        ir_interpret::{run_with_args, run_with_output, run_with_runtime_heap, InterpretError},
+        link::link_with_library,
+    };
+
+    use crate::{compile, compile_module_with, parse, runtime::runtime_units, CompileOptions};
+
+    fn linked(input: &str) -> IrProgram {
+        let program = parse(input.to_string(), "test.mc".to_string()).unwrap();
+        let library = runtime_units().into_iter().map(|(_, unit)| unit).collect();
+        link_with_library(vec![compile(program).unwrap()], library).unwrap()
+    }
+
+    fn linked_checked(input: &str) -> IrProgram {
+        let program = parse(input.to_string(), "test.mc".to_string()).unwrap();
+        let options = CompileOptions {
+            bounds_check: true,
+            file: "test.mc".to_string(),
+        };
+        let unit = compile_module_with(program, None, options).unwrap();
+        let library = runtime_units().into_iter().map(|(_, unit)| unit).collect();
+        link_with_library(vec![unit], library).unwrap()
+    }
+
+    /// output of the program linked with the runtime
+    fn output(input: &str) -> (i64, String) {
+        let mut out = vec![];
+        let result = run_with_output(linked(input), &mut out).unwrap();
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn printf_conversions() {
+        let input = "int main() {
+            return printf(\"%d %d %d|%c|%s|%x %x|%% %q%\", 42, 0 - 7, 0, 'z', \"world\", 255, 0);
+        }";
+        let text = "42 -7 0|z|world|ff 0|% %q";
+        assert_eq!(output(input), (text.len() as i64, text.to_string()));
+    }
+
+    #[test]
+    fn user_variadic_function() {
+        let input = "int sum(int n, ...) {
+            va_list ap;
+            int total = 0;
+            va_start(ap, n);
+            while (n > 0) {
+                total = total + va_arg(ap, int);
+                n = n - 1;
+            }
+            va_end(ap);
+            return total;
+        }
+        int main() {
+            printf(\"%d\\n\", sum(3, 1, 2, 3));
+            return sum(10, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10);
+        }";
+        assert_eq!(output(input), (55, "6\n".to_string()));
+    }
+
+    #[test]
+    fn syscall_intrinsics() {
+        let input = "int main() {
+            char* start = __brk(cast<char*>(0));
+            char* end = __brk(start + 16);
+            int written;
+            start[0] = 'o';
+            start[1] = 'k';
+            written = __write(1, start, 2);
+            if (end == start + 16) {
+                __exit(written + 40);
+            }
+            return 1;
+        }";
+        assert_eq!(output(input), (42, "ok".to_string()));
+    }
+
+    const LIST: &str = "struct Node { int value; Node* next; }
+        Node* push(Node* list, int value) {
+            Node* node = malloc(16);
+            (*node).value = value;
+            (*node).next = list;
+            return node;
+        }
+        int main() {
+            Node* list = cast<Node*>(0);
+            Node* next;
+            int* numbers = calloc(4, 8);
+            int i = 0;
+            int sum = 0;
+            while (i < 4) {
+                list = push(list, i + 1);
+                numbers[i] = 10;
+                i = i + 1;
+            }
+            numbers = realloc(numbers, 64);
+            while (list != cast<Node*>(0)) {
+                sum = sum + (*list).value * numbers[(*list).value - 1];
+                next = (*list).next;
+                free(list);
+                list = next;
+            }
+            list = push(list, 5);
+            free(numbers);
+            return sum + (*list).value;
+        }";
+
+    #[test]
+    fn heap_linked_list() {
+        assert_eq!(output(LIST), (105, String::new()));
+        let mut out = vec![];
+        assert_eq!(run_with_runtime_heap(linked(LIST), &mut out).unwrap(), 105);
+    }
+
+    #[test]
+    fn heap_invalid_free() {
+        let double = "int main() { int* p = malloc(8); free(p); free(p); return 0; }";
+        let invalid = "int main() { int x; free(&x); return 0; }";
+        let mut out = vec![];
+        assert!(matches!(
+            run_with_output(linked(double), &mut out),
+            Err(InterpretError::DoubleFree(_))
+        ));
+        assert!(matches!(
+            run_with_output(linked(invalid), &mut out),
+            Err(InterpretError::InvalidFree(_))
+        ));
+    }
+
+    #[test]
+    fn main_arguments() {
+        let input = "int main(int argc, char** argv, char** envp) {
+            int i = 0;
+            while (i < argc) {
+                printf(\"%s;\", argv[i]);
+                i = i + 1;
+            }
+            i = 0;
+            while (envp[i] != cast<char*>(0)) {
+                i = i + 1;
+            }
+            return argc * 10 + (argv[argc] == cast<char*>(0));
+        }";
+        let args = ["prog".to_string(), "-v".to_string(), "x".to_string()];
+        let mut out = vec![];
+        assert_eq!(run_with_args(linked(input), &args, &mut out).unwrap(), 31);
+        assert_eq!(String::from_utf8(out).unwrap(), "prog;-v;x;");
+    }
+
+    #[test]
+    fn stdlib_functions() {
+        let input = "int main() {
+            char buf[24];
+            char copy[24];
+            int arr[5];
+            int len = itoa(0 - 1205, buf);
+            memset(copy, 'x', 23);
+            memcpy(copy, buf, len + 1);
+            puts(copy);
+            putchar('!');
+            arr[0] = 4;
+            arr[1] = 0 - 2;
+            arr[2] = 9;
+            arr[3] = 4;
+            arr[4] = 1;
+            assert(is_sorted_ints(arr, 5) == 0);
+            sort_ints(arr, 5);
+            assert(is_sorted_ints(arr, 5));
+            printf(\"%d %d %d\", arr[0], arr[4], strlen(copy));
+            return strcmp(\"abc\", \"abd\") * 100 + strcmp(\"b\", \"a\") * 10 + strcmp(\"\", \"\");
+        }";
+        assert_eq!(output(input), (0 - 90, "-1205\n!-2 9 5".to_string()));
+    }
+
+    #[test]
+    fn failed_assert_aborts() {
+        let input = "int main() { int x = 3; assert(x < 3); return 0; }";
+        assert_eq!(output(input).0, 134);
+    }
+
+    #[test]
+    fn library_linked_on_demand() {
+        let program = linked("int main() { return strlen(\"four\"); }");
+        assert!(program.funcs.contains_key("strlen"));
+        assert!(!program.funcs.contains_key("printf"));
+        assert!(!program.funcs.contains_key("malloc"));
+        // the definition of the program is used instead of the library one
+        let program =
+            linked("void exit(int code) { __exit(code + 1); } int main() { exit(4); return 0; }");
+        let mut out = vec![];
+        assert_eq!(run_with_output(program, &mut out).unwrap(), 5);
+    }
+
+    #[test]
+    fn bounds_check_traps() {
+        let input = "int get(int i) {
+            int arr[4];
+            int j = 0;
+            while (j < 4) {
+                arr[j] = j * 10;
+                j = j + 1;
+            }
+            return arr[i];
+        }
+        int main(int argc) { return get(2) + get(argc + 4); }";
+        let mut out = vec![];
+        assert_eq!(
+            run_with_output(linked_checked(input), &mut out).unwrap(),
+            134
+        );
+        // the report names the location of the access and the array
+        let program = linked_checked(input);
+        let strings: Vec<&[u8]> = program.globals.iter().map(|g| &g.data[..]).collect();
+        assert!(strings.contains(&&b"test.mc:8:23\0"[..]));
+        assert!(strings.contains(&&b"arr\0"[..]));
+        // without the mode the access is not checked
+        assert!(!linked(input).funcs.contains_key("__bounds_fail"));
+    }
+
+    #[test]
+    fn constant_index_not_checked() {
+        let input = "int main() { int arr[3]; arr[2] = 5; return arr[2]; }";
+        let program = linked_checked(input);
+        assert!(!program.funcs.contains_key("__bounds_fail"));
+        let mut out = vec![];
+        assert_eq!(run_with_output(program, &mut out).unwrap(), 5);
+    }
+}
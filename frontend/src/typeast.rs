@@ -1,4 +1,6 @@
-use crate::ast::{FnDecl, FnDef, StructDefType};
+use std::rc::Rc;
+
+use crate::ast::{FnDecl, FnDef};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum PrimType {
@@ -17,6 +19,7 @@ pub struct FnType {
     pub params: Vec<TypeDef>,
     pub ret_type: Box<TypeDef>,
     pub body_def: bool,
+    pub variadic: bool,
 }
 
 impl From<FnType> for TypeDef {
@@ -31,6 +34,7 @@ impl From<FnDecl> for FnType {
             params: decl.params.iter().map(|x| x.1.clone()).collect(),
             ret_type: Box::new(decl.ret_type.clone()),
             body_def: false,
+            variadic: decl.variadic,
         }
     }
 }
@@ -41,6 +45,7 @@ impl From<FnDef> for FnType {
             params: fn_def.header.params.iter().map(|x| x.1.clone()).collect(),
             ret_type: Box::new(fn_def.header.ret_type.clone()),
             body_def: fn_def.body.is_some(),
+            variadic: fn_def.header.variadic,
         }
     }
 }
@@ -51,51 +56,233 @@ pub struct ArrayType {
     pub index: usize,
 }
 
-#[derive(Debug, Eq, Clone)]
+/// Struct in the [`TypeTable`], two struct types are the same only when
+/// they come from the same definition, the name is kept for the messages
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TypeId {
+    index: usize,
+    name: Rc<str>,
+}
+
+impl TypeId {
+    pub(crate) fn new(index: usize, name: &str) -> Self {
+        Self {
+            index,
+            name: name.into(),
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum TypeDef {
     Void,
     PrimType(PrimType),
     PointerType(Box<TypeDef>),
     Function(FnType),
     Alias(String),
-    Struct(StructDefType),
+    Struct(TypeId),
     Array(ArrayType),
+    // value of the type cannot be changed
+    Const(Box<TypeDef>),
 }
 
-impl PartialEq for TypeDef {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (TypeDef::Void, TypeDef::Void) => true,
-            (TypeDef::PrimType(prim_a), TypeDef::PrimType(prim_b)) => prim_a == prim_b,
-            (TypeDef::PointerType(inner_a), TypeDef::PointerType(inner_b)) => inner_a == inner_b,
-            (
-                TypeDef::PointerType(inner_ptr),
-                TypeDef::Array(ArrayType {
-                    inner_type,
-                    index: _,
-                }),
-            ) => inner_ptr == inner_type,
-            (TypeDef::Function(fn_type_a), TypeDef::Function(fn_type_b)) => fn_type_a == fn_type_b,
-            (TypeDef::Alias(name_a), TypeDef::Alias(name_b)) => name_a == name_b,
-            (TypeDef::Struct(struct_a), TypeDef::Struct(struct_b)) => struct_a == struct_b,
-            (
-                TypeDef::Array(ArrayType {
-                    inner_type,
-                    index: _,
-                }),
-                TypeDef::PointerType(inner_ptr),
-            ) => inner_type == inner_ptr,
-            (TypeDef::Array(arr_a), TypeDef::Array(arr_b)) => arr_a == arr_b,
-            _ => false,
+impl TypeDef {
+    pub fn is_pointer(&self) -> bool {
+        matches!(self, TypeDef::PointerType(_))
+    }
+
+    /// type of the value read from the variable, array is the pointer
+    /// to its first element and the const of the variable is dropped
+    pub fn decay(self) -> TypeDef {
+        match self {
+            TypeDef::Array(arr) => TypeDef::PointerType(arr.inner_type),
+            TypeDef::Const(t) => t.decay(),
+            t => t,
         }
     }
-}
 
-impl TypeDef {
-    pub fn is_pointer(&self) -> bool {
+    /// type without the outer const
+    pub fn unqual(&self) -> &TypeDef {
         match self {
-            TypeDef::PointerType(_) | TypeDef::Array(_) => true,
-            _ => false,
+            TypeDef::Const(t) => t.unqual(),
+            t => t,
+        }
+    }
+
+    pub fn is_const(&self) -> bool {
+        matches!(self, TypeDef::Const(_))
+    }
+
+    /// value can be stored into the target, the pointed type can
+    /// only gain the const, `void*` converts from and to any pointer
+    pub fn converts_to(&self, target: &TypeDef) -> bool {
+        match (self.unqual(), target.unqual()) {
+            (TypeDef::PointerType(from), TypeDef::PointerType(to))
+                if *from.unqual() == TypeDef::Void || *to.unqual() == TypeDef::Void =>
+            {
+                !from.is_const() || to.is_const()
+            }
+            (TypeDef::PointerType(from), TypeDef::PointerType(to)) => {
+                from == to || (to.is_const() && from.as_ref() == to.unqual())
+            }
+            (from, to) => from == to,
+        }
+    }
+}
+
+/// Layout attributes written before the struct or its field
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Layout {
+    // fields are placed without the padding
+    pub packed: bool,
+    // minimal alignment, power of two
+    pub aligned: Option<usize>,
+}
+
+/// Field of the struct with the offset given by the layout
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Field {
+    pub name: String,
+    pub field_type: TypeDef,
+    pub layout: Layout,
+    pub offset: usize,
+}
+
+/// Struct definition in the [`TypeTable`], the fields
+/// are missing until the declared struct is defined
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StructType {
+    pub name: String,
+    // all fields of the union start at the offset 0
+    pub is_union: bool,
+    pub layout: Layout,
+    pub fields: Option<Vec<Field>>,
+    pub size: usize,
+    pub align: usize,
+}
+
+fn align_to(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+/// Arena of the structs of the program, [`TypeDef::Struct`] points into it
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct TypeTable {
+    structs: Vec<StructType>,
+}
+
+impl TypeTable {
+    /// adds incomplete struct
+    pub fn add_struct(&mut self, name: &str) -> TypeId {
+        self.structs.push(StructType {
+            name: name.to_string(),
+            is_union: false,
+            layout: Layout::default(),
+            fields: None,
+            size: 0,
+            align: 1,
+        });
+        TypeId::new(self.structs.len() - 1, name)
+    }
+
+    /// defines the struct and computes its layout, the types
+    /// of the fields have to be sized
+    pub fn complete(
+        &mut self,
+        id: &TypeId,
+        is_union: bool,
+        layout: Layout,
+        fields: Vec<(String, TypeDef, Layout)>,
+    ) {
+        let mut end = 0;
+        let mut struct_align = layout.aligned.unwrap_or(1);
+        let mut result = vec![];
+        for (name, field_type, field_layout) in fields {
+            let natural = if layout.packed || field_layout.packed {
+                1
+            } else {
+                self.align_of(&field_type)
+            };
+            let align = natural.max(field_layout.aligned.unwrap_or(1));
+            let size = self.size_of(&field_type);
+            let offset = if is_union { 0 } else { align_to(end, align) };
+            end = end.max(offset + size);
+            struct_align = struct_align.max(align);
+            result.push(Field {
+                name,
+                field_type,
+                layout: field_layout,
+                offset,
+            });
+        }
+
+        let s = &mut self.structs[id.index];
+        s.is_union = is_union;
+        s.layout = layout;
+        s.fields = Some(result);
+        s.size = align_to(end, struct_align);
+        s.align = struct_align;
+    }
+
+    pub fn get(&self, id: &TypeId) -> &StructType {
+        &self.structs[id.index]
+    }
+
+    pub fn structs(&self) -> &[StructType] {
+        &self.structs
+    }
+
+    pub fn field(&self, id: &TypeId, field: &str) -> Option<&Field> {
+        let fields = self.get(id).fields.as_ref()?;
+        fields.iter().find(|x| x.name == field)
+    }
+
+    pub fn field_type(&self, id: &TypeId, field: &str) -> Option<TypeDef> {
+        self.field(id, field).map(|x| x.field_type.clone())
+    }
+
+    /// variables can have only types with known size
+    pub fn sized(&self, t: &TypeDef) -> bool {
+        match t {
+            TypeDef::Struct(id) => self.get(id).fields.is_some(),
+            TypeDef::Array(arr) => self.sized(&arr.inner_type),
+            TypeDef::Const(t) => self.sized(t),
+            _ => true,
+        }
+    }
+
+    /// size in bytes of the sized type
+    pub fn size_of(&self, t: &TypeDef) -> usize {
+        match t {
+            TypeDef::PrimType(PrimType::Int) | TypeDef::PointerType(_) => 8,
+            TypeDef::PrimType(PrimType::Char) => 1,
+            TypeDef::Array(arr) => arr.index * self.size_of(&arr.inner_type),
+            TypeDef::Struct(id) => self.get(id).size,
+            TypeDef::Const(t) => self.size_of(t),
+            TypeDef::Void | TypeDef::Function(_) | TypeDef::Alias(_) => {
+                unreachable!("{:?} does not have size", t)
+            }
+        }
+    }
+
+    pub fn align_of(&self, t: &TypeDef) -> usize {
+        match t {
+            TypeDef::PrimType(PrimType::Int) | TypeDef::PointerType(_) => 8,
+            TypeDef::PrimType(PrimType::Char) => 1,
+            TypeDef::Array(arr) => self.align_of(&arr.inner_type),
+            TypeDef::Struct(id) => self.get(id).align,
+            TypeDef::Const(t) => self.align_of(t),
+            TypeDef::Void | TypeDef::Function(_) | TypeDef::Alias(_) => {
+                unreachable!("{:?} does not have alignment", t)
+            }
         }
     }
 }
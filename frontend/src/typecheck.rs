@@ -1,17 +1,25 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
     ast::{
         Expr, ExprType, FnDecl, FnDef, Operator, Program, Statement, StatementType, StructDef,
-        StructDefType, TopLevel, Val, VarDecl,
+        TopLevel, Val, VarDecl,
     },
-    errors::{FrontendError, TypeError},
-    typeast::{FnType, PrimType, TypeDef},
+    compile::const_value,
+    errors::{FrontendError, TypeError, Warning},
+    lexer::Loc,
+    modules::module_name,
+    runtime::prelude,
+    typeast::{ArrayType, FnType, Layout, PrimType, TypeDef, TypeTable},
 };
 
 struct EnvLevel {
     env: HashMap<String, TypeDef>,
     ret: Option<TypeDef>,
+    // fields of struct do not shadow anything
+    fields: bool,
+    // inside of the variadic function
+    variadic: bool,
 }
 
 impl EnvLevel {
@@ -19,6 +27,8 @@ impl EnvLevel {
         Self {
             env: HashMap::new(),
             ret,
+            fields: false,
+            variadic: false,
         }
     }
 
@@ -31,26 +41,82 @@ impl EnvLevel {
     }
 }
 
+/// Public symbols of a typechecked module, available
+/// to the modules which import it as `module::name`
+#[derive(Debug, Default, Clone)]
+pub struct ModuleSymbols {
+    values: HashMap<String, TypeDef>,
+    types: HashMap<String, TypeDef>,
+    // names defined without pub
+    private: HashSet<String>,
+}
+
+impl ModuleSymbols {
+    fn lookup(
+        &self,
+        map: &HashMap<String, TypeDef>,
+        name: &str,
+    ) -> Result<TypeDef, FrontendError> {
+        match map.get(name) {
+            Some(t) => Ok(t.clone()),
+            None if self.private.contains(name) => {
+                Err(TypeError::NotPublic(name.to_string()).into())
+            }
+            None => Err(TypeError::IdentDoesNotExist(name.to_string()).into()),
+        }
+    }
+}
+
+/// Optional warnings of the typecheck
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WarningOptions {
+    pub shadow: bool,
+}
+
 pub struct TypeData {
     type_map: HashMap<String, TypeDef>,
+    types: TypeTable,
     env: Vec<EnvLevel>,
+    // globals declared as extern without definition yet
+    externs: HashSet<String>,
+    // modules which can be imported and the ones which were
+    modules: HashMap<String, ModuleSymbols>,
+    imported: HashSet<String>,
+    public: HashSet<String>,
+    options: WarningOptions,
+    warnings: Vec<Warning>,
 }
 
 impl Default for TypeData {
     fn default() -> Self {
+        // va_list points to the next variadic argument
+        let va_list = TypeDef::PointerType(Box::new(PrimType::Char.into()));
         Self {
-            type_map: HashMap::new(),
+            type_map: HashMap::from([("va_list".to_string(), va_list)]),
+            types: TypeTable::default(),
             env: vec![EnvLevel::new(None)],
+            externs: HashSet::new(),
+            modules: HashMap::new(),
+            imported: HashSet::new(),
+            public: HashSet::new(),
+            options: WarningOptions::default(),
+            warnings: vec![],
         }
     }
 }
 
 impl TypeData {
-    fn add_var(&mut self, name: &String, var_type: TypeDef) -> Result<(), FrontendError> {
-        if let Ok(_) = self.get_ident_type(name) {
+    /// only the innermost scope must not contain the name,
+    /// declarations from the outer scopes are shadowed
+    fn add_var(&mut self, name: &String, var_type: TypeDef, loc: Loc) -> Result<(), FrontendError> {
+        let last_index = self.env.len() - 1;
+        if self.env[last_index].get_type(name).is_some() {
             return Err(TypeError::IdentAlreadyExists(name.to_string()).into());
         }
-        let last_index = self.env.len() - 1;
+        if self.options.shadow && !self.env[last_index].fields && self.get_ident_type(name).is_ok()
+        {
+            self.warnings.push(Warning::Shadow(loc, name.clone()));
+        }
         self.env[last_index].add_var(name, var_type);
         Ok(())
     }
@@ -61,17 +127,68 @@ impl TypeData {
         Ok(())
     }
 
+    /// global variable can be declared again only with the same type
+    fn redeclare(&mut self, var: &VarDecl) -> Result<(), FrontendError> {
+        let t = self.translate_type(var.var_type.clone())?;
+        match self.env[0].get_type(&var.name) {
+            Some(declared) if declared == t => Ok(()),
+            _ => Err(TypeError::DeclarationMismatch(var.name.clone()).into()),
+        }
+    }
+
     fn add_type(&mut self, name: &String, value: TypeDef) {
         self.type_map.insert(name.clone(), value);
     }
 
     fn get_type(&mut self, name: &String) -> Result<TypeDef, FrontendError> {
-        Ok(self.type_map[name].clone())
+        if let Some((module, name)) = name.split_once("::") {
+            let module = self.module(module)?;
+            return module.lookup(&module.types, name);
+        }
+        match self.type_map.get(name) {
+            Some(t) => Ok(t.clone()),
+            None => Err(TypeError::IdentDoesNotExist(name.clone()).into()),
+        }
+    }
+
+    fn module(&self, name: &str) -> Result<&ModuleSymbols, FrontendError> {
+        match self.modules.get(name) {
+            Some(module) if self.imported.contains(name) => Ok(module),
+            _ => Err(TypeError::UnknownModule(name.to_string()).into()),
+        }
+    }
+
+    /// public symbols of the module checked so far
+    fn exports(&self) -> ModuleSymbols {
+        let mut symbols = ModuleSymbols::default();
+        for (name, t) in self.env[0].env.iter() {
+            if self.public.contains(name) {
+                symbols.values.insert(name.clone(), t.clone());
+            } else {
+                symbols.private.insert(name.clone());
+            }
+        }
+        for (name, t) in self.type_map.iter() {
+            if self.public.contains(name) {
+                symbols.types.insert(name.clone(), t.clone());
+            } else {
+                symbols.private.insert(name.clone());
+            }
+        }
+        symbols
     }
 
     fn translate_type(&mut self, type_def: TypeDef) -> Result<TypeDef, FrontendError> {
         match type_def {
             TypeDef::Alias(name) => self.get_type(&name),
+            TypeDef::PointerType(inner) => {
+                Ok(TypeDef::PointerType(Box::new(self.translate_type(*inner)?)))
+            }
+            TypeDef::Const(inner) => Ok(TypeDef::Const(Box::new(self.translate_type(*inner)?))),
+            TypeDef::Array(arr) => Ok(TypeDef::Array(ArrayType {
+                inner_type: Box::new(self.translate_type(*arr.inner_type)?),
+                index: arr.index,
+            })),
             t => Ok(t),
         }
     }
@@ -82,6 +199,10 @@ impl TypeData {
     }
 
     fn get_ident_type(&self, name: &String) -> Result<TypeDef, FrontendError> {
+        if let Some((module, name)) = name.split_once("::") {
+            let module = self.module(module)?;
+            return module.lookup(&module.values, name);
+        }
         for e in self.env.iter().rev() {
             if let Some(t) = e.get_type(name) {
                 return Ok(t);
@@ -90,12 +211,24 @@ impl TypeData {
         Err(TypeError::IdentDoesNotExist(name.clone()).into())
     }
 
+    fn variadic(&self) -> bool {
+        self.env.last().is_some_and(|x| x.variadic)
+    }
+
     fn push_env(&mut self) {
-        self.env.push(EnvLevel::new(self.ret()))
+        let variadic = self.variadic();
+        self.env.push(EnvLevel::new(self.ret()));
+        self.env.last_mut().unwrap().variadic = variadic;
     }
 
-    fn push_fn(&mut self, ret_type: TypeDef) {
-        self.env.push(EnvLevel::new(Some(ret_type)))
+    fn push_fn(&mut self, ret_type: TypeDef, variadic: bool) {
+        self.env.push(EnvLevel::new(Some(ret_type)));
+        self.env.last_mut().unwrap().variadic = variadic;
+    }
+
+    fn push_fields(&mut self) {
+        self.push_env();
+        self.env.last_mut().unwrap().fields = true;
     }
 
     fn pop_env(&mut self) {
@@ -103,11 +236,29 @@ impl TypeData {
     }
 }
 
+/// checks statements in the current scope, the result
+/// is the return type of the last statement
+fn typecheck_stmts(stmts: &mut [Statement], data: &mut TypeData) -> Result<TypeDef, FrontendError> {
+    let mut ret_type = TypeDef::Void;
+    for s in stmts {
+        ret_type = s.typecheck(data)?;
+    }
+    Ok(ret_type)
+}
+
 trait TypecheckAst<T>
 where
     T: PartialEq + Eq + Clone,
 {
-    fn typecheck(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError>;
+    fn typecheck_node(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError>;
+
+    fn node_loc(&self) -> Loc;
+
+    /// errors get the location of the innermost node which failed
+    fn typecheck(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
+        let loc = self.node_loc();
+        self.typecheck_node(data).map_err(|e| e.with_loc(loc))
+    }
 }
 
 fn unary_op(
@@ -116,7 +267,10 @@ fn unary_op(
     data: &mut TypeData,
 ) -> Result<TypeDef, FrontendError> {
     expr.typecheck(data)?;
-    let t = match (op, expr.get_type()) {
+    if matches!(op, Operator::Inc | Operator::Dec) && !expr.assignable() {
+        return Err(TypeError::CannotAssignInto(*expr.clone()).into());
+    }
+    let t = match (op, expr.get_type().decay()) {
         (Operator::Not, TypeDef::PrimType(PrimType::Int)) => PrimType::Int.into(),
         (_, TypeDef::PrimType(t)) => t.into(),
         (_, TypeDef::PointerType(t)) => TypeDef::PointerType(t),
@@ -127,7 +281,8 @@ fn unary_op(
 }
 
 impl Expr {
-    fn assignable(&self) -> bool {
+    /// the value has an address, const values have it as well
+    fn lvalue(&self) -> bool {
         match &self.value {
             ExprType::Ident(_) => {
                 if let TypeDef::Function(_) = self.get_type() {
@@ -136,12 +291,31 @@ impl Expr {
                     true
                 }
             }
-            ExprType::Index(arr, _) => arr.assignable(),
+            ExprType::Index(arr, _) => arr.lvalue(),
             ExprType::Deref(_) => true,
-            ExprType::FieldAccess(s, _) => s.assignable(),
+            ExprType::FieldAccess(s, _) => s.lvalue(),
             _ => false,
         }
     }
+
+    fn assignable(&self) -> bool {
+        self.lvalue() && !self.get_type().is_const()
+    }
+}
+
+/// only values fitting into the register are passed as variadic arguments
+fn variadic_type(t: &TypeDef) -> bool {
+    matches!(t.unqual(), TypeDef::PrimType(_) | TypeDef::PointerType(_))
+}
+
+/// va_list is modified by va_start and va_arg so it has to be assignable
+fn va_list(list: &mut Box<Expr>, data: &mut TypeData) -> Result<(), FrontendError> {
+    list.typecheck(data)?;
+    let t = list.get_type();
+    if t != TypeDef::PointerType(Box::new(PrimType::Char.into())) || !list.assignable() {
+        return Err(TypeError::InvalidVaList(t).into());
+    }
+    Ok(())
 }
 
 fn assign(left: &Expr, _right: &Expr) -> Result<TypeDef, FrontendError> {
@@ -160,19 +334,27 @@ fn binary_op(
 ) -> Result<TypeDef, FrontendError> {
     left.typecheck(data)?;
     right.typecheck(data)?;
-    if !((left.get_type() == right.get_type())
-        || (op == Operator::Add
-            && left.get_type().is_pointer()
-            && right.get_type() == PrimType::Int.into()))
+    // arrays are used as pointers, only the assigned one is not
+    let left_type = match op {
+        Operator::Assign => left.get_type(),
+        _ => left.get_type().decay(),
+    };
+    let right_type = right.get_type().decay();
+    let same = match op {
+        Operator::Assign => right_type.converts_to(&left_type),
+        _ => left_type == right_type,
+    };
+    if !(same
+        || (op == Operator::Add && left_type.is_pointer() && right_type == PrimType::Int.into()))
     {
-        return Err(TypeError::BinaryTypeMissmatch(op, left.get_type(), right.get_type()).into());
+        return Err(TypeError::BinaryTypeMissmatch(op, left_type, right_type).into());
     }
 
-    if let TypeDef::Function(_) = left.get_type() {
+    if let TypeDef::Function(_) = left_type {
         return Err(TypeError::BinaryOperatorError.into());
     }
 
-    let t: TypeDef = match (op, left.get_type()) {
+    let t: TypeDef = match (op, left_type.unqual().clone()) {
         (Operator::Add, TypeDef::PointerType(p)) => TypeDef::PointerType(p),
         (Operator::Sub, TypeDef::PointerType(p)) => TypeDef::PointerType(p),
         (Operator::Add, TypeDef::PrimType(t)) => t.into(),
@@ -204,7 +386,11 @@ fn binary_op(
 }
 
 impl TypecheckAst<Expr> for Expr {
-    fn typecheck(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
+    fn node_loc(&self) -> Loc {
+        self.loc()
+    }
+
+    fn typecheck_node(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
         match &mut self.value {
             ExprType::BinOp(op, left, right) => {
                 let t = binary_op(op.clone(), left, right, data)?;
@@ -250,7 +436,12 @@ impl TypecheckAst<Expr> for Expr {
                     Err(TypeError::NonFunctionCall.into())
                 }?;
 
-                if fn_type.params.len() != params.len() {
+                if fn_type.variadic && fn_type.params.len() > params.len() {
+                    return Err(
+                        TypeError::TooFewArguments(fn_type.params.len(), params.len()).into(),
+                    );
+                }
+                if !fn_type.variadic && fn_type.params.len() != params.len() {
                     return Err(TypeError::WrongNumberOfParametes(
                         fn_type.params.len(),
                         params.len(),
@@ -260,10 +451,18 @@ impl TypecheckAst<Expr> for Expr {
 
                 for i in 0..params.len() {
                     params[i].typecheck(data)?;
-                    if params[i].get_type() != fn_type.params[i] {
+                    if i >= fn_type.params.len() {
+                        let param_type = params[i].get_type().decay();
+                        if !variadic_type(&param_type) {
+                            return Err(TypeError::InvalidVariadicType(param_type).into());
+                        }
+                        continue;
+                    }
+                    let param_type = params[i].get_type().decay();
+                    if !param_type.converts_to(&fn_type.params[i]) {
                         return Err(TypeError::WrongParamType(
                             fn_type.params[i].clone(),
-                            params[i].get_type(),
+                            param_type,
                         )
                         .into());
                     }
@@ -276,11 +475,11 @@ impl TypecheckAst<Expr> for Expr {
             ExprType::Index(object, index) => {
                 object.typecheck(data)?;
                 index.typecheck(data)?;
-                if index.get_type() != PrimType::Int.into() {
+                if index.get_type().decay() != PrimType::Int.into() {
                     return Err(TypeError::IndexMustBeInteger.into());
                 }
-                let t = if let TypeDef::PointerType(t) = object.get_type() {
-                    Ok::<TypeDef, FrontendError>(*t)
+                let t = if let TypeDef::PointerType(t) = object.get_type().unqual() {
+                    Ok::<TypeDef, FrontendError>(*t.clone())
                 } else if let TypeDef::Array(arr) = object.get_type() {
                     Ok::<TypeDef, FrontendError>(*arr.inner_type)
                 } else {
@@ -292,7 +491,7 @@ impl TypecheckAst<Expr> for Expr {
 
             ExprType::Deref(e) => {
                 e.typecheck(data)?;
-                let t = if let TypeDef::PointerType(t) = e.get_type() {
+                let t = if let TypeDef::PointerType(t) = e.get_type().decay() {
                     Ok::<Box<TypeDef>, FrontendError>(t)
                 } else {
                     Err(TypeError::NonPointerDeref.into())
@@ -302,22 +501,30 @@ impl TypecheckAst<Expr> for Expr {
             }
             ExprType::Address(e) => {
                 e.typecheck(data)?;
-                if !e.assignable() {
+                if !e.lvalue() {
                     return Err(TypeError::DontHaveAddr(*e.clone()).into());
                 }
+                // address of the const value points to the const
                 let t = e.get_type();
                 self.set_type(TypeDef::PointerType(Box::new(t)));
                 Ok(TypeDef::Void)
             }
-            ExprType::Cast(t, _) => {
-                let t = t.clone();
+            ExprType::Cast(t, e) => {
+                e.typecheck(data)?;
+                let t = data.translate_type(t.clone())?;
                 self.set_type(t);
                 Ok(TypeDef::Void)
             }
             ExprType::FieldAccess(e, field) => {
                 e.typecheck(data)?;
-                if let TypeDef::Struct(s) = e.get_type() {
-                    if let Some(t) = s.field_type(field) {
+                let object_type = e.get_type();
+                if let TypeDef::Struct(id) = object_type.unqual() {
+                    if let Some(t) = data.types.field_type(id, field) {
+                        // fields of the const struct are const as well
+                        let t = match object_type.is_const() && !t.is_const() {
+                            true => TypeDef::Const(Box::new(t)),
+                            false => t,
+                        };
                         self.set_type(t);
                         Ok(TypeDef::Void)
                     } else {
@@ -331,28 +538,84 @@ impl TypecheckAst<Expr> for Expr {
                 for arg in args {
                     arg.typecheck(data)?;
                 }
-                Ok(self.set_type(TypeDef::Void))
+                Ok(self.set_type(PrimType::Int.into()))
+            }
+            ExprType::Str(_) => {
+                let t = TypeDef::Const(Box::new(PrimType::Char.into()));
+                self.set_type(TypeDef::PointerType(Box::new(t)));
+                Ok(TypeDef::Void)
+            }
+            ExprType::VaStart(list) => {
+                if !data.variadic() {
+                    return Err(TypeError::VaStartOutsideVariadic.into());
+                }
+                va_list(list, data)?;
+                self.set_type(TypeDef::Void);
+                Ok(TypeDef::Void)
+            }
+            ExprType::VaArg(list, t) => {
+                va_list(list, data)?;
+                *t = data.translate_type(t.clone())?;
+                if !variadic_type(t) {
+                    return Err(TypeError::InvalidVariadicType(t.clone()).into());
+                }
+                let t = t.clone();
+                self.set_type(t);
+                Ok(TypeDef::Void)
+            }
+            ExprType::VaEnd(list) => {
+                va_list(list, data)?;
+                self.set_type(TypeDef::Void);
+                Ok(TypeDef::Void)
+            }
+            ExprType::Assert(cond, _) => {
+                cond.typecheck(data)?;
+                if cond.get_type().decay() != PrimType::Int.into() {
+                    return Err(TypeError::ConditionMustBeInt.into());
+                }
+                self.set_type(TypeDef::Void);
+                Ok(TypeDef::Void)
+            }
+            ExprType::OffsetOf(t, field) => {
+                // the compile needs the struct, not its name
+                *t = data.translate_type(t.clone())?;
+                let id = match t.unqual() {
+                    TypeDef::Struct(id) if data.types.sized(t) => id,
+                    TypeDef::Struct(_) => return Err(TypeError::TypeIsNotSized.into()),
+                    _ => return Err(TypeError::NonStructType.into()),
+                };
+                if data.types.field(id, field).is_none() {
+                    return Err(TypeError::MissingField(field.clone()).into());
+                }
+                self.set_type(PrimType::Int.into());
+                Ok(TypeDef::Void)
             }
         }
     }
 }
 
 impl TypecheckAst<VarDecl> for VarDecl {
-    fn typecheck(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
+    fn node_loc(&self) -> Loc {
+        self.loc()
+    }
+
+    fn typecheck_node(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
         let t = self.var_type.clone();
         let t = data.translate_type(t)?;
 
-        if !t.sized() {
+        if !data.types.sized(&t) {
             return Err(TypeError::TypeIsNotSized.into());
         }
+        self.var_type = t.clone();
 
-        data.add_var(&self.name, t.clone())?;
+        data.add_var(&self.name, t.clone(), self.loc())?;
         let name = self.value.name.clone();
         if let Some(init) = &mut self.init_val {
             init.typecheck(data)?;
 
-            if init.get_type() != t {
-                return Err(TypeError::VariableTypeError(name, t, init.get_type()).into());
+            let init_type = init.get_type().decay();
+            if !init_type.converts_to(&t) {
+                return Err(TypeError::VariableTypeError(name, t, init_type).into());
             }
 
             self.set_type(TypeDef::Void);
@@ -364,7 +627,11 @@ impl TypecheckAst<VarDecl> for VarDecl {
 }
 
 impl TypecheckAst<Statement> for Statement {
-    fn typecheck(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
+    fn node_loc(&self) -> Loc {
+        self.loc()
+    }
+
+    fn typecheck_node(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
         match &mut self.value {
             StatementType::Expr(e) => {
                 e.typecheck(data)?;
@@ -378,19 +645,27 @@ impl TypecheckAst<Statement> for Statement {
                 self.set_type(t);
                 Ok(TypeDef::Void)
             }
+            StatementType::Static(v) => {
+                v.typecheck(data)?;
+                // the same evaluation as the compiler does for the memory
+                let init = v.init_val.as_ref();
+                if init.is_some_and(|init| const_value(init, &data.types).is_none()) {
+                    return Err(TypeError::NonConstantStatic(v.name.clone()).into());
+                }
+                let t = v.get_type();
+                self.set_type(t);
+                Ok(TypeDef::Void)
+            }
             StatementType::Block(stmts) => {
-                let mut ret_type = TypeDef::Void;
                 data.push_env();
-                for s in stmts {
-                    ret_type = s.typecheck(data)?;
-                }
+                let ret_type = typecheck_stmts(stmts, data)?;
                 data.pop_env();
                 self.set_type(TypeDef::Void);
                 Ok(ret_type)
             }
             StatementType::If(cond, then_body) => {
                 cond.typecheck(data)?;
-                if cond.get_type() != PrimType::Int.into() {
+                if cond.get_type().decay() != PrimType::Int.into() {
                     return Err(TypeError::ConditionMustBeInt.into());
                 }
                 data.push_env();
@@ -401,7 +676,7 @@ impl TypecheckAst<Statement> for Statement {
             }
             StatementType::IfElse(cond, then_body, else_body) => {
                 cond.typecheck(data)?;
-                if cond.get_type() != PrimType::Int.into() {
+                if cond.get_type().decay() != PrimType::Int.into() {
                     return Err(TypeError::ConditionMustBeInt.into());
                 }
                 data.push_env();
@@ -419,12 +694,14 @@ impl TypecheckAst<Statement> for Statement {
                 }
             }
             StatementType::For(init, cond, update, body) => {
+                // variables declared in init are visible only in the loop
+                data.push_env();
                 if let Some(init) = init {
                     init.typecheck(data)?;
                 }
                 if let Some(cond) = cond {
                     cond.typecheck(data)?;
-                    if cond.get_type() != PrimType::Int.into() {
+                    if cond.get_type().decay() != PrimType::Int.into() {
                         return Err(TypeError::ConditionMustBeInt.into());
                     }
                 }
@@ -432,12 +709,13 @@ impl TypecheckAst<Statement> for Statement {
                     update.typecheck(data)?;
                 }
                 body.typecheck(data)?;
+                data.pop_env();
                 self.set_type(TypeDef::Void);
                 Ok(TypeDef::Void)
             }
             StatementType::While(cond, body) => {
                 cond.typecheck(data)?;
-                if cond.get_type() != PrimType::Int.into() {
+                if cond.get_type().decay() != PrimType::Int.into() {
                     return Err(TypeError::ConditionMustBeInt.into());
                 }
                 data.push_env();
@@ -464,11 +742,11 @@ impl TypecheckAst<Statement> for Statement {
                 (Some(res), Some(exp)) => {
                     res.typecheck(data)?;
 
-                    if res.data.node_type != Some(exp.clone()) {
-                        return Err(TypeError::ReturnTypeError(res.get_type(), exp).into());
+                    let ret_type = res.get_type().decay();
+                    if !ret_type.converts_to(&exp) {
+                        return Err(TypeError::ReturnTypeError(ret_type, exp).into());
                     }
 
-                    let ret_type = res.get_type().clone();
                     self.set_type(TypeDef::Void);
                     Ok(ret_type)
                 }
@@ -478,35 +756,17 @@ impl TypecheckAst<Statement> for Statement {
 }
 
 impl TypecheckAst<FnDef> for FnDef {
-    fn typecheck(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
+    fn node_loc(&self) -> Loc {
+        self.loc()
+    }
+
+    fn typecheck_node(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
         let f_ret = data.translate_type(self.header.ret_type.clone())?;
 
-        if !f_ret.sized() {
+        if !data.types.sized(&f_ret) {
             return Err(TypeError::TypeIsNotSized.into());
         }
 
-        if let Ok(t) = data.get_ident_type(&self.value.header.name) {
-            match t {
-                TypeDef::Function(f_type)
-                    if !f_type.body_def
-                        && f_type.params.len() == self.header.params.len()
-                        && *f_type.ret_type == f_ret =>
-                {
-                    for i in 0..f_type.params.len() {
-                        if f_type.params[i] != self.header.params[i].1 {
-                            return Err(TypeError::TypeParametrMissmatch.into());
-                        }
-                    }
-                    ()
-                }
-                _ => {
-                    return Err(
-                        TypeError::IdentAlreadyExists(self.value.header.name.clone()).into(),
-                    )
-                }
-            }
-        }
-
         self.header.typed(TypeDef::Void);
         self.header.ret_type = f_ret;
         let mut translated_params: Vec<(String, TypeDef)> = vec![];
@@ -514,18 +774,48 @@ impl TypecheckAst<FnDef> for FnDef {
             translated_params.push((name.clone(), data.translate_type(var_type.clone())?));
         }
         self.header.params = translated_params;
-        let t: FnType = self.header.clone().into();
+        let mut t: FnType = self.header.clone().into();
+        t.body_def = self.body.is_some();
+
+        // prototypes can be repeated before and after the definition
+        let name = self.value.header.name.clone();
+        if let Ok(declared) = data.get_ident_type(&name) {
+            match declared {
+                TypeDef::Function(f_type) => {
+                    if f_type.params != t.params
+                        || f_type.ret_type != t.ret_type
+                        || f_type.variadic != t.variadic
+                    {
+                        return Err(TypeError::DeclarationMismatch(name).into());
+                    }
+                    if f_type.body_def && t.body_def {
+                        return Err(TypeError::IdentAlreadyExists(name).into());
+                    }
+                    t.body_def |= f_type.body_def;
+                }
+                _ => return Err(TypeError::IdentAlreadyExists(name).into()),
+            }
+        }
 
-        data.add_force(&self.value.header.name, t.into())?;
+        data.add_force(&name, t.into())?;
 
         let ret_type = self.header.ret_type.clone();
         let params = self.header.params.clone();
+        let loc = self.loc();
         if let Some(body) = &mut self.value.body {
-            data.push_fn(ret_type);
+            data.push_fn(ret_type, self.value.header.variadic);
             for (name, var_type) in params {
-                data.add_var(&name, var_type.clone())?;
+                data.add_var(&name, var_type.clone(), loc)?;
             }
-            let ret_type = body.typecheck(data)?;
+            // parameters share the scope with the body
+            let ret_type = match &mut body.value {
+                StatementType::Block(stmts) => {
+                    let ret_type = typecheck_stmts(stmts, data)?;
+                    body.set_type(TypeDef::Void);
+                    ret_type
+                }
+                _ => body.typecheck(data)?,
+            };
             data.pop_env();
 
             if ret_type != self.header.ret_type {
@@ -539,54 +829,159 @@ impl TypecheckAst<FnDef> for FnDef {
 }
 
 impl TypecheckAst<FnDecl> for FnDecl {
-    fn typecheck(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
+    fn node_loc(&self) -> Loc {
+        self.loc()
+    }
+
+    fn typecheck_node(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
         let t: FnType = self.clone().into();
-        data.add_var(&self.name, t.into())?;
+        let loc = self.loc();
+        data.add_var(&self.name, t.into(), loc)?;
         self.set_type(TypeDef::Void);
         Ok(TypeDef::Void)
     }
 }
 
+fn check_layout(layout: &Layout) -> Result<(), FrontendError> {
+    match layout.aligned {
+        Some(align) if !align.is_power_of_two() => Err(TypeError::InvalidAlignment(align).into()),
+        _ => Ok(()),
+    }
+}
+
 impl TypecheckAst<StructDef> for StructDef {
-    fn typecheck(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
-        data.add_type(
-            &self.name,
-            TypeDef::Struct(StructDefType {
-                name: self.name.clone(),
-                fields: None,
-            }),
-        );
-        if let Some(fields) = &mut self.fields {
-            data.push_env();
-            for field in fields {
+    fn node_loc(&self) -> Loc {
+        self.loc()
+    }
+
+    fn typecheck_node(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
+        // declared struct is completed by the definition
+        let id = match data.type_map.get(&self.name) {
+            Some(TypeDef::Struct(id)) if data.types.get(id).fields.is_none() => id.clone(),
+            Some(_) if self.fields.is_none() => return Ok(TypeDef::Void),
+            Some(_) => return Err(TypeError::IdentAlreadyExists(self.name.clone()).into()),
+            None => {
+                let id = data.types.add_struct(&self.name);
+                data.add_type(&self.name, TypeDef::Struct(id.clone()));
+                id
+            }
+        };
+        if let Some(fields) = &mut self.value.fields {
+            check_layout(&self.value.layout)?;
+            data.push_fields();
+            let mut translated = vec![];
+            for (layout, field) in fields {
+                check_layout(layout)?;
                 field.typecheck(data)?;
+                translated.push((field.name.clone(), field.var_type.clone(), *layout));
             }
             data.pop_env();
+            data.types
+                .complete(&id, self.value.is_union, self.value.layout, translated);
         }
-        data.add_type(&self.name, TypeDef::Struct(self.value.clone()));
         Ok(TypeDef::Void)
     }
 }
 
 impl TypecheckAst<TopLevel> for TopLevel {
-    fn typecheck(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
+    fn node_loc(&self) -> Loc {
+        self.loc()
+    }
+
+    fn typecheck_node(&mut self, data: &mut TypeData) -> Result<TypeDef, FrontendError> {
         match self {
             TopLevel::Function(f) => f.typecheck(data)?,
-            TopLevel::Var(v) => v.typecheck(data)?,
+            TopLevel::Var(v) => {
+                // the definition replaces the extern declaration
+                if data.externs.remove(&v.name) {
+                    data.redeclare(v)?;
+                    data.env[0].env.remove(&v.name);
+                }
+                v.typecheck(data)?
+            }
             TopLevel::Structure(s) => s.typecheck(data)?,
+            TopLevel::Extern(item) => match item.as_mut() {
+                TopLevel::Function(f) if f.body.is_none() => f.typecheck(data)?,
+                TopLevel::Var(v) if v.init_val.is_none() => {
+                    if data.env[0].get_type(&v.name).is_some() {
+                        data.redeclare(v)?;
+                        v.set_type(TypeDef::Void)
+                    } else {
+                        data.externs.insert(v.name.clone());
+                        v.typecheck(data)?
+                    }
+                }
+                TopLevel::Function(f) => {
+                    return Err(TypeError::ExternDefinition(f.header.name.clone()).into())
+                }
+                TopLevel::Var(v) => return Err(TypeError::ExternDefinition(v.name.clone()).into()),
+                _ => unreachable!("only variables and functions can be extern"),
+            },
+            TopLevel::Import(import) => {
+                let name = module_name(&import.value);
+                if !data.modules.contains_key(&name) {
+                    return Err(TypeError::UnknownModule(name).into());
+                }
+                data.imported.insert(name);
+                import.set_type(TypeDef::Void)
+            }
+            TopLevel::Pub(item) => {
+                item.typecheck(data)?;
+                let name = match item.as_ref() {
+                    TopLevel::Function(f) => f.header.name.clone(),
+                    TopLevel::Var(v) => v.name.clone(),
+                    TopLevel::Structure(s) => s.name.clone(),
+                    _ => unreachable!("only definitions can be public"),
+                };
+                data.public.insert(name);
+                TypeDef::Void
+            }
         };
         Ok(TypeDef::Void)
     }
 }
 
 pub fn type_program(program: &mut Program) -> Result<(), FrontendError> {
-    let mut data = TypeData::default();
+    type_program_warn(program, WarningOptions::default())?;
+    Ok(())
+}
 
-    for i in 0..program.items.len() {
-        program.items[i].typecheck(&mut data)?;
-    }
+/// typechecks the program and returns the enabled warnings
+pub fn type_program_warn(
+    program: &mut Program,
+    options: WarningOptions,
+) -> Result<Vec<Warning>, FrontendError> {
+    let (warnings, _) = type_module(program, &HashMap::new(), TypeTable::default(), options)?;
+    Ok(warnings)
+}
 
-    Ok(())
+/// typechecks one module, the modules it imports have to be checked
+/// before, returns the warnings and the public symbols of the module.
+/// The structs are added to the types of the imported modules and the
+/// table is stored in the program even when the typecheck fails
+pub fn type_module(
+    program: &mut Program,
+    modules: &HashMap<String, ModuleSymbols>,
+    types: TypeTable,
+    options: WarningOptions,
+) -> Result<(Vec<Warning>, ModuleSymbols), FrontendError> {
+    let mut data = TypeData {
+        options,
+        modules: modules.clone(),
+        types,
+        ..Default::default()
+    };
+
+    let result = prelude()
+        .items
+        .iter_mut()
+        .chain(program.items.iter_mut())
+        .try_for_each(|item| item.typecheck(&mut data).map(|_| ()));
+    program.types = std::mem::take(&mut data.types);
+    result?;
+
+    let exports = data.exports();
+    Ok((data.warnings, exports))
 }
 
 #[cfg(test)]
@@ -817,6 +1212,180 @@ mod tests {
         type_err("struct A { int a; } A f() {A a; return a;} int main() {f().a = 5; return 1;}");
     }
 
+    #[test]
+    fn nominal_struct_test_typedef() {
+        type_err("struct A { int x; } struct B { int x; } void f() { A a; B b = a; }");
+        type_err("struct A { int x; } struct A { int y; }");
+        type_ok("struct A { int x; } struct A; A a;");
+        type_ok(
+            "struct N; N* head; struct N { int v; N* next; }
+            int main() { N n; n.next = head; head = &n; return head[0].v; }",
+        );
+        type_err("struct N; int f(N* n) { return (*n).v; }");
+        type_ok("struct A { int x; } int main() { A* p; A arr[2]; p = arr; return p[1].x; }");
+        type_ok("struct A { int x; } int main() { A* p = cast<A*>(0); return 0; }");
+    }
+
+    fn layout(input: &str, name: &str) -> (usize, usize, Vec<usize>) {
+        let program = crate::parse(input.to_string(), "tmp".to_string()).unwrap();
+        let s = program
+            .types
+            .structs()
+            .iter()
+            .find(|s| s.name == name)
+            .unwrap();
+        let offsets = s
+            .fields
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|x| x.offset)
+            .collect();
+        (s.size, s.align, offsets)
+    }
+
+    #[test]
+    fn layout_test_typedef() {
+        assert_eq!(
+            layout("struct A { char c; int x; char d; }", "A"),
+            (24, 8, vec![0, 8, 16])
+        );
+        assert_eq!(layout("struct A {}", "A"), (0, 1, vec![]));
+        assert_eq!(
+            layout("struct packed A { char c; int x; char d; }", "A"),
+            (10, 1, vec![0, 1, 9])
+        );
+        assert_eq!(
+            layout("struct A { char c; packed int x; }", "A"),
+            (9, 1, vec![0, 1])
+        );
+        assert_eq!(
+            layout("struct A { char c; aligned(16) int x; }", "A"),
+            (32, 16, vec![0, 16])
+        );
+        assert_eq!(
+            layout("struct aligned(32) A { char c; }", "A"),
+            (32, 32, vec![0])
+        );
+        assert_eq!(
+            layout("struct packed aligned(4) A { char c; int x; }", "A"),
+            (12, 4, vec![0, 1])
+        );
+        assert_eq!(
+            layout("union U { char c[9]; int x; }", "U"),
+            (16, 8, vec![0, 0])
+        );
+        assert_eq!(
+            layout("union U { char c[3]; char d; }", "U"),
+            (3, 1, vec![0, 0])
+        );
+        assert_eq!(
+            layout(
+                "struct packed H { char t; int l; } struct A { char c; H h[2]; }",
+                "A"
+            ),
+            (19, 1, vec![0, 1])
+        );
+        assert_eq!(
+            layout("union U { int x; } struct A { char c; U u; }", "A"),
+            (16, 8, vec![0, 8])
+        );
+
+        type_err("struct aligned(3) A { int x; }");
+        type_err("struct A { aligned(6) int x; }");
+        type_ok("struct A { int x; } int main() { return offsetof(A, x); }");
+        type_err("struct A { int x; } int main() { return offsetof(A, y); }");
+        type_err("int main() { return offsetof(int, x); }");
+        type_err("struct A; int main() { return offsetof(A, x); }");
+        type_err("struct A { int x; } int main() { char c = offsetof(A, x); return 0; }");
+        type_ok("union U { int x; char c; } int main() { U u; u.c = 'a'; return u.x; }");
+    }
+
+    #[test]
+    fn decay_test_typedef() {
+        type_ok("int main() { int a[3]; int* p = a; p = a + 1; return *a + p[0]; }");
+        type_ok("int f(int* p) { return *p; } int main() { int a[2]; return f(a); }");
+        type_ok("int* f(int* p) { int a[2]; return a; }");
+        type_err("char* f() { int a[2]; return a; }");
+        type_err("int main() { int a[2]; int b[2]; a = b; return 0; }");
+        type_err("int main() { int* p; int a[2] = p; return 0; }");
+        type_err("int f(char* p) { return 0; } int main() { int a[2]; return f(a); }");
+    }
+
+    #[test]
+    fn const_test_typedef() {
+        type_ok("const int x = 3; int main() { const int y = x; return x + y; }");
+        type_ok(
+            "int main() { int a = 1; const int* p = &a; int* const q = &a; *q = 2; return *p; }",
+        );
+        type_ok("char f(const char* s) { return s[0]; } int main() { char c; f(&c); return 0; }");
+        type_ok("struct P {int x;} int main() { const P p; return p.x; }");
+        type_err("const int x = 3; int main() { x = 4; return x; }");
+        type_err("int main() { int a; const int* p = &a; *p = 2; return 0; }");
+        type_err("int main() { int a; int* const p = &a; p = &a; return 0; }");
+        type_err("int main() { const int a[2]; a[0] = 1; return 0; }");
+        type_err("struct P {int x;} int main() { const P p; p.x = 1; return 0; }");
+        // the const cannot be dropped from the pointed type
+        type_err("int main() { const int a = 1; int* p = &a; return 0; }");
+        type_err("int f(char* s) { return 0; } int main() { const char c; return f(&c); }");
+        type_err("int main() { int a; const int* p = &a; int* q; q = p; return 0; }");
+    }
+
+    #[test]
+    fn const_address_test_typedef() {
+        // const values have an address, it points to the const
+        type_ok("const int y = 1; int main() { const int* p = &y; return *p; }");
+        type_ok("const int y = 1; int main() { int* p = cast<int*>(&y); return *p; }");
+        type_ok("struct P {int x;} int main() { const P p; const int* x = &p.x; return *x; }");
+        type_ok("int main() { const int a[2]; const int* p = &a[1]; return *p; }");
+        type_err("const int y = 1; int main() { const int* p = &y; *p = 2; return 0; }");
+
+        let input = "int main() { const int a = 1; int* p = &a; return 0; }";
+        let lex = Lexer::new("tmp".to_string(), input.chars().peekable());
+        let mut res = Parser::new(lex).unwrap().parse().unwrap();
+        let error = type_program(&mut res).unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("variable p has type int* but is initialized with const int*"));
+    }
+
+    #[test]
+    fn void_pointer_test_typedef() {
+        type_ok("int main() { int* p = malloc(8); free(p); return *p; }");
+        type_ok("int main() { int a; void* p = &a; char* c = p; return 0; }");
+        type_ok("int main() { int a; const void* p = &a; return 0; }");
+        type_err("int main() { const int a = 1; void* p = &a; return 0; }");
+        type_err("int main() { const void* p; int* q = p; return 0; }");
+    }
+
+    #[test]
+    fn const_error_message() {
+        let input = "int main() { const int x = 1; x = 2; return 0; }";
+        let lex = Lexer::new("tmp".to_string(), input.chars().peekable());
+        let mut res = Parser::new(lex).unwrap().parse().unwrap();
+        let error = type_program(&mut res).unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("cannot assign into x, it is const"));
+    }
+
+    #[test]
+    fn static_initializer_test_typedef() {
+        type_ok("int f() {return 1;} int x = f(); int* p = &x; const char* s = \"a\"; int main() {return x;}");
+        type_ok("int main() { static int y = 2 * 3; static char c = 'a'; return y; }");
+        type_err("int g; int main() { static int* p = &g; return 0; }");
+        type_err("int main() { static const char* s = \"a\"; return 0; }");
+
+        let input = "int f() {return 1;}\nint main() { static int y = f(); return y; }";
+        let lex = Lexer::new("tmp".to_string(), input.chars().peekable());
+        let mut res = Parser::new(lex).unwrap().parse().unwrap();
+        let error = type_program(&mut res).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "2:14: initializer of static y is not a constant"
+        );
+    }
+
     #[test]
     fn array_test_typedef() {
         type_ok("int main() {int * a; return a[0]; }");
@@ -835,4 +1404,68 @@ mod tests {
         type_err("int main(int a) { while (a) {return a;}  }");
         type_ok("int main(int a) { while (a) {return a;} return 1;}");
     }
+
+    fn shadow_warnings(input: &str) -> Vec<Warning> {
+        let lex = Lexer::new("tmp".to_string(), input.chars().peekable());
+        let mut parser = Parser::new(lex).unwrap();
+        let mut res = parser.parse().unwrap();
+        type_program_warn(&mut res, WarningOptions { shadow: true }).unwrap()
+    }
+
+    #[test]
+    fn scopes_test_typedef() {
+        type_ok("int main() {int x = 5; {char x = 'a';} return x;}");
+        type_ok("int main() {int x = 5; {char x = 'a'; {int x = 1;}} return x;}");
+        type_err("int main() {{char x = 'a';} return x;}");
+        type_ok("int main() {for (int i = 0; i < 5; i = i + 1) {int i = 2;} return 1;}");
+        type_err("int main() {for (int i = 0; i < 5; i = i + 1) {} return i;}");
+        type_ok("int x; int main() {int x = 1; return x;}");
+        type_ok("int main(int a) {{int a = 1;} return a;}");
+        type_err("int main(int a) {int a = 1; return a;}");
+        type_err("int main() {int x = 1; {int y; char y;} return x;}");
+    }
+
+    #[test]
+    fn shadow_warning_test() {
+        assert!(shadow_warnings("int main() {int x = 5; return x;}").is_empty());
+        let warnings = shadow_warnings("int x; int main() {int x = 5; {int x = 1;} return x;}");
+        assert_eq!(warnings.len(), 2);
+        assert!(matches!(&warnings[0], Warning::Shadow(_, name) if name == "x"));
+        assert_eq!(warnings[1].loc().row(), 0);
+        assert!(shadow_warnings("int x; struct A {int x;} int main() {return x;}").is_empty());
+    }
+
+    #[test]
+    fn declarations_test_typedef() {
+        type_ok("int odd(int n); int even(int n) {return odd(n);} int odd(int n) {return even(n);}");
+        type_ok("int f(int n); int f(int n); int f(int n) {return n;} int f(int n);");
+        type_err("int f(int n); char f(int n) {return 'a';}");
+        type_err("int f(int n); int f(char n) {return 1;}");
+        type_err("int f() {return 1;} int f() {return 2;}");
+        type_err("int x; int x() {return 1;}");
+        type_ok("extern int x; extern int x; int main() {return x;}");
+        type_ok("extern int x; int x = 5; extern int x; int main() {return x;}");
+        type_err("extern int x; char x;");
+        type_err("int x; int x;");
+        type_err("extern int x = 5;");
+        type_err("extern int f() {return 1;}");
+        type_ok("extern int f(int a); int main() {return f(1);}");
+    }
+
+    #[test]
+    fn variadic_test_typedef() {
+        type_ok("int main() {return printf(\"%d %s\", 1, \"a\");}");
+        type_ok("int main() {char c = 'a'; int* p; return printf(\"\", c, p, &c);}");
+        type_err("int main() {return printf();}");
+        type_err("int main() {return printf(1);}");
+        type_err("struct A {int x;} int main() {A a; return printf(\"\", a);}");
+        type_ok("int f(int n, ...) {va_list ap; va_start(ap, n); int x = va_arg(ap, int); va_end(ap); return x;}");
+        type_ok("char* f(int n, ...) {va_list ap; if (n) {va_start(ap);} return va_arg(ap, char*);}");
+        type_err("int f(int n) {va_list ap; va_start(ap); return 0;}");
+        type_err("int f(int n, ...) {int ap; va_start(ap); return 0;}");
+        type_err("int f(int n, ...) {va_list ap; va_start(ap); return va_arg(ap, void);}");
+        type_err("int f(int n, ...); int f(int n) {return n;}");
+        type_err("int main() {char* s = \"a\"; return 0;}");
+        type_ok("int main() {const char* s = \"a\"; return 0;}");
+    }
 }
@@ -0,0 +1,757 @@
+//! Collects everything the editor asks for from one version
+//! of the document: diagnostics, identifiers with their types
+//! and definitions, symbols and visible declarations.
+//!
+//! Positions are rows and columns in characters, same as
+//! the locations of the lexer.
+
+use std::collections::HashMap;
+
+use frontend::{
+    analyze,
+    ast::{Expr, ExprType, FnDef, Program, Statement, StatementType, StructDef, TopLevel, VarDecl},
+    format::type_name,
+    lexer::{Lexer, Loc, Token, TokenType},
+    typeast::TypeDef,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Position {
+    pub row: usize,
+    pub col: usize,
+}
+
+impl From<Loc> for Position {
+    fn from(loc: Loc) -> Self {
+        Self {
+            row: loc.row(),
+            col: loc.col(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Range {
+    pub fn contains(&self, pos: Position) -> bool {
+        self.start <= pos && pos <= self.end
+    }
+
+    /// range of the token starting at the location
+    fn token(loc: Loc, len: usize) -> Self {
+        let start: Position = loc.into();
+        Self {
+            start,
+            end: Position {
+                row: start.row,
+                col: start.col + len,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub range: Range,
+    pub message: String,
+}
+
+/// identifier in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Occurrence {
+    pub range: Range,
+    pub hover: String,
+    pub definition: Option<Range>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Variable,
+    Struct,
+    Field,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub detail: String,
+    pub range: Range,
+    pub selection: Range,
+    pub children: Vec<Symbol>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    pub field_type: TypeDef,
+    pub range: Range,
+}
+
+/// declaration with the part of the document where it is visible
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declaration {
+    pub name: String,
+    pub decl_type: TypeDef,
+    pub visible: Range,
+}
+
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    pub occurrences: Vec<Occurrence>,
+    pub symbols: Vec<Symbol>,
+    pub structs: HashMap<String, Vec<Field>>,
+    pub declarations: Vec<Declaration>,
+    // false when the document could not be parsed
+    pub parsed: bool,
+}
+
+const END_OF_FILE: Position = Position {
+    row: usize::MAX,
+    col: usize::MAX,
+};
+
+/// all tokens of the input until the first lexer error
+pub fn tokens(text: &str) -> Vec<Token> {
+    let mut lexer = Lexer::new(String::new(), text.chars().peekable());
+    let mut result = vec![];
+    while let Ok(token) = lexer.get_token() {
+        if token.tok == TokenType::Eof {
+            break;
+        }
+        result.push(token);
+    }
+    result
+}
+
+/// range of the token starting at the location, the lexer
+/// stops right after its last character
+fn token_range(text: &str, loc: Loc) -> Range {
+    let mut lexer = Lexer::new(String::new(), text.chars().peekable());
+    lexer.reset_to(loc);
+    match lexer.get_token() {
+        Ok(token) if token.tok != TokenType::Eof => Range {
+            start: loc.into(),
+            end: lexer.loc().into(),
+        },
+        _ => Range::token(loc, 1),
+    }
+}
+
+pub fn analyze_document(text: &str) -> Analysis {
+    let tokens = tokens(text);
+    let mut analysis = Analysis::default();
+    let (program, error) = match analyze(text.to_string(), String::new()) {
+        Ok((program, error)) => (Some(program), error),
+        Err(e) => (None, Some(e)),
+    };
+
+    if let Some(error) = error {
+        let range = match error.loc() {
+            Some(loc) => token_range(text, loc),
+            None => Range::default(),
+        };
+        analysis.diagnostics.push(Diagnostic {
+            range,
+            message: error.inner().to_string(),
+        });
+    }
+
+    if let Some(program) = program {
+        let mut resolver = Resolver {
+            tokens: &tokens,
+            scopes: vec![Scope::new(END_OF_FILE)],
+            analysis: &mut analysis,
+        };
+        resolver.program(&program);
+        analysis.parsed = true;
+    }
+    analysis
+}
+
+#[derive(Debug, Clone)]
+struct Definition {
+    range: Range,
+    def_type: TypeDef,
+}
+
+struct Scope {
+    names: HashMap<String, Definition>,
+    end: Position,
+}
+
+impl Scope {
+    fn new(end: Position) -> Self {
+        Self {
+            names: HashMap::new(),
+            end,
+        }
+    }
+}
+
+/// text shown on hover, declaration like `int* x`
+fn describe(name: &str, t: &TypeDef) -> String {
+    match t {
+        TypeDef::Function(f) => format!(
+            "{} {}({})",
+            type_name(&f.ret_type),
+            name,
+            f.params.iter().map(type_name).collect::<Vec<_>>().join(", ")
+        ),
+        t => format!("{} {}", type_name(t), name),
+    }
+}
+
+fn struct_name(t: &TypeDef) -> Option<&str> {
+    match t.unqual() {
+        TypeDef::Struct(id) => Some(id.name()),
+        TypeDef::Alias(name) => Some(name),
+        _ => None,
+    }
+}
+
+fn block_end(stmt: &Statement, parent: Position) -> Position {
+    match stmt.value {
+        StatementType::Block(_) => stmt.data.end().into(),
+        _ => parent,
+    }
+}
+
+struct Resolver<'a> {
+    tokens: &'a [Token],
+    scopes: Vec<Scope>,
+    analysis: &'a mut Analysis,
+}
+
+impl<'a> Resolver<'a> {
+    /// range of the first identifier with the name after the location
+    fn name_range(&self, name: &str, after: Loc) -> Range {
+        self.tokens
+            .iter()
+            .find(|x| {
+                x.position.position() >= after.position()
+                    && x.tok == TokenType::Ident(name.to_string())
+            })
+            .map_or(Range::token(after, 0), |x| {
+                Range::token(x.position, name.chars().count())
+            })
+    }
+
+    fn scope_end(&self) -> Position {
+        self.scopes.last().unwrap().end
+    }
+
+    fn push_scope(&mut self, end: Position) {
+        self.scopes.push(Scope::new(end));
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn define(&mut self, name: &str, range: Range, def_type: TypeDef) {
+        self.analysis.declarations.push(Declaration {
+            name: name.to_string(),
+            decl_type: def_type.clone(),
+            visible: Range {
+                start: range.start,
+                end: self.scope_end(),
+            },
+        });
+        self.occurrence(name, range, &def_type, range);
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .names
+            .insert(name.to_string(), Definition { range, def_type });
+    }
+
+    fn occurrence(&mut self, name: &str, range: Range, t: &TypeDef, definition: Range) {
+        self.analysis.occurrences.push(Occurrence {
+            range,
+            hover: describe(name, t),
+            definition: Some(definition),
+        });
+    }
+
+    fn lookup(&self, name: &str) -> Option<Definition> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.names.get(name))
+            .cloned()
+    }
+
+    fn program(&mut self, program: &Program) {
+        for item in program.items.iter() {
+            self.top_level(item);
+        }
+    }
+
+    fn top_level(&mut self, item: &TopLevel) {
+        match item {
+            TopLevel::Function(f) => self.function(f),
+            TopLevel::Var(v) => {
+                let range = self.var_decl(v);
+                self.analysis.symbols.push(Symbol {
+                    name: v.name.clone(),
+                    kind: SymbolKind::Variable,
+                    detail: type_name(&v.var_type),
+                    range: Range {
+                        start: v.loc().into(),
+                        end: range.end,
+                    },
+                    selection: range,
+                    children: vec![],
+                });
+            }
+            TopLevel::Structure(s) => self.structure(s),
+            TopLevel::Extern(item) | TopLevel::Pub(item) => self.top_level(item),
+            TopLevel::Import(_) => (),
+        }
+    }
+
+    fn structure(&mut self, s: &StructDef) {
+        let range = self.name_range(&s.name, s.loc());
+        self.analysis.occurrences.push(Occurrence {
+            range,
+            hover: format!("{} {}", if s.is_union { "union" } else { "struct" }, s.name),
+            definition: Some(range),
+        });
+        let Some(fields) = &s.fields else {
+            return;
+        };
+
+        let mut children = vec![];
+        let mut result = vec![];
+        for (_, field) in fields {
+            let field_range = self.name_range(&field.name, field.loc());
+            self.analysis.occurrences.push(Occurrence {
+                range: field_range,
+                hover: describe(&field.name, &field.var_type),
+                definition: Some(field_range),
+            });
+            result.push(Field {
+                name: field.name.clone(),
+                field_type: field.var_type.clone(),
+                range: field_range,
+            });
+            children.push(Symbol {
+                name: field.name.clone(),
+                kind: SymbolKind::Field,
+                detail: type_name(&field.var_type),
+                range: Range {
+                    start: field.loc().into(),
+                    end: field_range.end,
+                },
+                selection: field_range,
+                children: vec![],
+            });
+        }
+        self.analysis.structs.insert(s.name.clone(), result);
+        let mut end: Position = s.data.end().into();
+        end.col += 1;
+        self.analysis.symbols.push(Symbol {
+            name: s.name.clone(),
+            kind: SymbolKind::Struct,
+            detail: String::new(),
+            range: Range {
+                start: s.loc().into(),
+                end,
+            },
+            selection: range,
+            children,
+        });
+    }
+
+    fn function(&mut self, f: &FnDef) {
+        let name = &f.header.name;
+        let range = self.name_range(name, f.loc());
+        let fn_type = TypeDef::Function(f.clone().into());
+
+        // definition with body is preferred over the prototype
+        let definition = match self.lookup(name) {
+            Some(def) if f.body.is_none() => def.range,
+            _ => {
+                self.define(name, range, fn_type.clone());
+                self.analysis.occurrences.pop();
+                range
+            }
+        };
+        self.occurrence(name, range, &fn_type, definition);
+
+        let body_end = f
+            .body
+            .as_ref()
+            .map_or(range.end, |body| block_end(body, range.end));
+        self.analysis.symbols.push(Symbol {
+            name: name.clone(),
+            kind: SymbolKind::Function,
+            detail: describe(name, &fn_type),
+            range: Range {
+                start: f.loc().into(),
+                end: Position {
+                    row: body_end.row,
+                    col: body_end.col + 1,
+                },
+            },
+            selection: range,
+            children: vec![],
+        });
+
+        self.push_scope(body_end);
+        let mut after = f.loc();
+        for (param, param_type) in f.header.params.iter() {
+            // parameters are searched one after another
+            let param_range = self.name_range(param, after);
+            if let Some(token) = self
+                .tokens
+                .iter()
+                .find(|x| Position::from(x.position) == param_range.start)
+            {
+                after = token.position;
+            }
+            if param_range.start > range.start {
+                self.define(param, param_range, param_type.clone());
+            }
+        }
+        if let Some(body) = &f.body {
+            self.statement(body);
+        }
+        self.pop_scope();
+    }
+
+    fn var_decl(&mut self, v: &VarDecl) -> Range {
+        let range = self.name_range(&v.name, v.loc());
+        self.define(&v.name, range, v.var_type.clone());
+        if let Some(init) = &v.init_val {
+            self.expr(init);
+        }
+        range
+    }
+
+    fn scoped(&mut self, stmt: &Statement) {
+        let end = block_end(stmt, self.scope_end());
+        self.push_scope(end);
+        self.statement(stmt);
+        self.pop_scope();
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match &stmt.value {
+            StatementType::Expr(e) => self.expr(e),
+            StatementType::VarDecl(v) | StatementType::Static(v) => {
+                self.var_decl(v);
+            }
+            StatementType::Block(stmts) => {
+                self.push_scope(stmt.data.end().into());
+                for s in stmts {
+                    self.statement(s);
+                }
+                self.pop_scope();
+            }
+            StatementType::If(cond, then) => {
+                self.expr(cond);
+                self.scoped(then);
+            }
+            StatementType::IfElse(cond, then, other) => {
+                self.expr(cond);
+                self.scoped(then);
+                self.scoped(other);
+            }
+            StatementType::For(init, cond, update, body) => {
+                self.push_scope(block_end(body, self.scope_end()));
+                if let Some(init) = init {
+                    self.statement(init);
+                }
+                if let Some(cond) = cond {
+                    self.expr(cond);
+                }
+                if let Some(update) = update {
+                    self.statement(update);
+                }
+                self.statement(body);
+                self.pop_scope();
+            }
+            StatementType::While(cond, body) => {
+                self.expr(cond);
+                self.scoped(body);
+            }
+            StatementType::Return(Some(e)) => self.expr(e),
+            StatementType::Return(None) | StatementType::Break | StatementType::Continue => (),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.value {
+            ExprType::Ident(name) => {
+                let range = Range::token(expr.loc(), name.chars().count());
+                let def = self.lookup(name);
+                let t = expr
+                    .data
+                    .node_type
+                    .clone()
+                    .or(def.as_ref().map(|x| x.def_type.clone()));
+                self.analysis.occurrences.push(Occurrence {
+                    range,
+                    hover: t.map_or(name.clone(), |t| describe(name, &t)),
+                    definition: def.map(|x| x.range),
+                });
+            }
+            ExprType::FieldAccess(e, field) => {
+                self.expr(e);
+                let range = self.name_range(field, expr.loc());
+                let found = e
+                    .data
+                    .node_type
+                    .as_ref()
+                    .and_then(struct_name)
+                    .and_then(|s| self.analysis.structs.get(s))
+                    .and_then(|fields| fields.iter().find(|x| &x.name == field))
+                    .cloned();
+                let t = expr
+                    .data
+                    .node_type
+                    .clone()
+                    .or(found.as_ref().map(|x| x.field_type.clone()));
+                self.analysis.occurrences.push(Occurrence {
+                    range,
+                    hover: t.map_or(field.clone(), |t| describe(field, &t)),
+                    definition: found.map(|x| x.range),
+                });
+            }
+            ExprType::BinOp(_, left, right) => {
+                self.expr(left);
+                self.expr(right);
+            }
+            ExprType::UnaryPreOp(_, e)
+            | ExprType::UnaryPostOp(_, e)
+            | ExprType::Deref(e)
+            | ExprType::Address(e)
+            | ExprType::Cast(_, e)
+            | ExprType::VaStart(e)
+            | ExprType::VaArg(e, _)
+            | ExprType::VaEnd(e)
+            | ExprType::Assert(e, _) => self.expr(e),
+            ExprType::Call(target, args) => {
+                self.expr(target);
+                args.iter().for_each(|x| self.expr(x));
+            }
+            ExprType::SysCall(_, args) => args.iter().for_each(|x| self.expr(x)),
+            ExprType::Index(e, index) => {
+                self.expr(e);
+                self.expr(index);
+            }
+            ExprType::Value(_) | ExprType::OffsetOf(_, _) | ExprType::Str(_) => (),
+        }
+    }
+}
+
+impl Analysis {
+    pub fn hover(&self, pos: Position) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|x| x.range.contains(pos))
+    }
+
+    pub fn definition(&self, pos: Position) -> Option<Range> {
+        self.hover(pos).and_then(|x| x.definition)
+    }
+
+    /// innermost declaration of the name visible at the position
+    fn declaration(&self, name: &str, pos: Position) -> Option<&Declaration> {
+        self.declarations
+            .iter()
+            .filter(|x| x.name == name && x.visible.contains(pos))
+            .max_by_key(|x| x.visible.start)
+    }
+
+    fn struct_fields(&self, t: &TypeDef) -> Option<&Vec<Field>> {
+        struct_name(t).and_then(|name| self.structs.get(name))
+    }
+
+    /// fields which can follow the `.` before the position in the
+    /// text, the text can be newer than the analysis
+    pub fn complete_fields(&self, text: &str, pos: Position) -> Vec<Field> {
+        let tokens: Vec<Token> = tokens(text)
+            .into_iter()
+            .filter(|x| Position::from(x.position) < pos)
+            .collect();
+        let mut rest = tokens.as_slice();
+        // partially written field name
+        if let [init @ .., last] = rest {
+            if let TokenType::Ident(_) = last.tok {
+                rest = init;
+            }
+        }
+        let [init @ .., dot] = rest else {
+            return vec![];
+        };
+        if dot.tok != TokenType::Dot {
+            return vec![];
+        }
+
+        // chain of field accesses and indexing before the dot
+        enum Access {
+            Field(String),
+            Index,
+        }
+        let mut chain = vec![];
+        let mut rest = init;
+        let root = loop {
+            match rest {
+                [init @ .., dot, last] if dot.tok == TokenType::Dot => {
+                    let TokenType::Ident(name) = &last.tok else {
+                        return vec![];
+                    };
+                    chain.push(Access::Field(name.clone()));
+                    rest = init;
+                }
+                [.., last] if last.tok == TokenType::RightSquare => {
+                    let mut depth = 0;
+                    let mut index = rest.len();
+                    loop {
+                        if index == 0 {
+                            return vec![];
+                        }
+                        index -= 1;
+                        match rest[index].tok {
+                            TokenType::RightSquare => depth += 1,
+                            TokenType::LeftSquare => depth -= 1,
+                            _ => (),
+                        }
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    chain.push(Access::Index);
+                    rest = &rest[..index];
+                }
+                [.., last] => match &last.tok {
+                    TokenType::Ident(name) => break name.clone(),
+                    _ => return vec![],
+                },
+                [] => return vec![],
+            }
+        };
+
+        let Some(declaration) = self.declaration(&root, pos) else {
+            return vec![];
+        };
+        let mut current = declaration.decl_type.clone();
+        for access in chain.iter().rev() {
+            current = match (access, current.unqual().clone()) {
+                (Access::Index, TypeDef::PointerType(inner)) => *inner,
+                (Access::Index, TypeDef::Array(arr)) => *arr.inner_type,
+                (Access::Field(name), t) => {
+                    let Some(field) = self
+                        .struct_fields(&t)
+                        .and_then(|fields| fields.iter().find(|x| &x.name == name))
+                    else {
+                        return vec![];
+                    };
+                    field.field_type.clone()
+                }
+                _ => return vec![],
+            };
+        }
+        self.struct_fields(&current).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "struct A {\n    int x;\n    A* next;\n}\n\nint f(int y) {\n    A a;\n    int z = a.x + y;\n    return z;\n}\n\nint main() {\n    A b;\n    return f(b.x);\n}\n";
+
+    fn pos(row: usize, col: usize) -> Position {
+        Position { row, col }
+    }
+
+    #[test]
+    fn analysis_hover_and_definition() {
+        let analysis = analyze_document(PROGRAM);
+        assert!(analysis.diagnostics.is_empty(), "{:?}", analysis.diagnostics);
+
+        // `y` in `a.x + y`
+        let y = analysis.hover(pos(7, 18)).unwrap();
+        assert_eq!(y.hover, "int y");
+        assert_eq!(y.definition.unwrap().start, pos(5, 10));
+
+        // field `x` in `a.x`
+        let x = analysis.hover(pos(7, 14)).unwrap();
+        assert_eq!(x.hover, "int x");
+        assert_eq!(x.definition.unwrap().start, pos(1, 8));
+
+        // call of `f`
+        let f = analysis.hover(pos(13, 11)).unwrap();
+        assert_eq!(f.hover, "int f(int)");
+        assert_eq!(f.definition.unwrap().start, pos(5, 4));
+    }
+
+    #[test]
+    fn analysis_symbols() {
+        let analysis = analyze_document(PROGRAM);
+        let names: Vec<(&str, SymbolKind)> = analysis
+            .symbols
+            .iter()
+            .map(|x| (x.name.as_str(), x.kind))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("A", SymbolKind::Struct),
+                ("f", SymbolKind::Function),
+                ("main", SymbolKind::Function)
+            ]
+        );
+        assert_eq!(analysis.symbols[0].children.len(), 2);
+        assert_eq!(analysis.symbols[1].range.end, pos(9, 1));
+    }
+
+    #[test]
+    fn analysis_diagnostics() {
+        let analysis = analyze_document("int main() {\n    return x;\n}\n");
+        assert_eq!(analysis.diagnostics.len(), 1);
+        assert_eq!(analysis.diagnostics[0].range.start, pos(1, 11));
+        assert_eq!(analysis.diagnostics[0].range.end, pos(1, 12));
+        assert!(analysis.parsed);
+
+        let analysis = analyze_document("int main() {\n    return 1\n}\n");
+        assert_eq!(analysis.diagnostics.len(), 1);
+        assert!(!analysis.parsed);
+
+        let analysis = analyze_document("int main() { /* }");
+        assert_eq!(analysis.diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn diagnostic_covers_token() {
+        let range = |text: &str| analyze_document(text).diagnostics[0].range;
+        // keyword, operator and string literal
+        let keyword = range("int main() {\n    return 1 while;\n}\n");
+        assert_eq!((keyword.start, keyword.end), (pos(1, 13), pos(1, 18)));
+        let operator = range("int main() {\n    return == 1;\n}\n");
+        assert_eq!((operator.start, operator.end), (pos(1, 11), pos(1, 13)));
+        let string = range("int main() {\n    return 1 \"a b\";\n}\n");
+        assert_eq!((string.start, string.end), (pos(1, 13), pos(1, 18)));
+    }
+
+    #[test]
+    fn analysis_completion() {
+        let analysis = analyze_document(PROGRAM);
+        let text = PROGRAM.replace("return f(b.x);", "b.n");
+        let fields: Vec<String> = analysis
+            .complete_fields(&text, pos(13, 7))
+            .into_iter()
+            .map(|x| x.name)
+            .collect();
+        assert_eq!(fields, vec!["x", "next"]);
+        assert!(analysis.complete_fields(&text, pos(13, 5)).is_empty());
+    }
+}
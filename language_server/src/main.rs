@@ -0,0 +1,36 @@
+//! Language server for the .mc files, it communicates
+//! over stdin and stdout
+
+use std::io::{self, BufReader};
+
+use rpc::{read_message, write_message, RpcError};
+use server::{Outcome, Server};
+
+mod analysis;
+mod rpc;
+mod server;
+
+fn main() {
+    let mut input = BufReader::new(io::stdin());
+    let mut output = io::stdout();
+    let mut server = Server::default();
+
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) | Err(RpcError::Io(_)) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        match server.handle(&message) {
+            Outcome::Continue(messages) => {
+                for message in messages {
+                    write_message(&mut output, &message).unwrap();
+                }
+            }
+            Outcome::Exit(code) => std::process::exit(code),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Framing of the json rpc messages, every message is
+//! preceded by the `Content-Length` header.
+
+use std::io::{BufRead, Write};
+
+use frontend::json::{parse_json, JsonValue};
+
+#[derive(Debug)]
+pub enum RpcError {
+    Io(std::io::Error),
+    MissingLength,
+    InvalidMessage(String),
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcError::Io(e) => write!(f, "{}", e),
+            RpcError::MissingLength => write!(f, "missing Content-Length header"),
+            RpcError::InvalidMessage(e) => write!(f, "invalid message: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for RpcError {
+    fn from(e: std::io::Error) -> Self {
+        RpcError::Io(e)
+    }
+}
+
+/// reads one message, returns None at the end of the input
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<JsonValue>, RpcError> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length.ok_or(RpcError::MissingLength)?;
+    let mut content = vec![0; length];
+    input.read_exact(&mut content)?;
+    let content =
+        String::from_utf8(content).map_err(|e| RpcError::InvalidMessage(e.to_string()))?;
+    parse_json(&content)
+        .map(Some)
+        .map_err(|e| RpcError::InvalidMessage(e.to_string()))
+}
+
+pub fn write_message(output: &mut impl Write, message: &JsonValue) -> Result<(), RpcError> {
+    let content = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", content.len(), content)?;
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rpc_round_trip() {
+        let message = JsonValue::object([("id", JsonValue::Number(1)), ("method", "ščř".into())]);
+        let mut buffer = vec![];
+        write_message(&mut buffer, &message).unwrap();
+        write_message(&mut buffer, &message).unwrap();
+
+        let mut input = buffer.as_slice();
+        assert_eq!(read_message(&mut input).unwrap(), Some(message.clone()));
+        assert_eq!(read_message(&mut input).unwrap(), Some(message));
+        assert!(read_message(&mut input).unwrap().is_none());
+    }
+}
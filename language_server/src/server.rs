@@ -0,0 +1,498 @@
+//! Handlers of the language server protocol requests.
+//!
+//! Only full document synchronization is supported. The analysis
+//! counts the columns in characters, they are converted to the
+//! UTF-16 code units of the protocol unless the client accepts
+//! `utf-32` position encoding.
+
+use std::{collections::HashMap, panic};
+
+use frontend::json::JsonValue;
+
+use crate::analysis::{analyze_document, Analysis, Position, Range, Symbol, SymbolKind};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+struct Document {
+    text: String,
+    analysis: Analysis,
+    // last analysis of the document which could be parsed,
+    // used for the completion while the user is typing
+    last_parsed: Option<Analysis>,
+}
+
+/// units of the columns the client and server agreed on
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum PositionEncoding {
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    encoding: PositionEncoding,
+    shutdown: bool,
+}
+
+/// converts the columns between the characters of the
+/// analysis and the units of the client
+struct Columns<'a> {
+    lines: Vec<&'a str>,
+    encoding: PositionEncoding,
+}
+
+impl<'a> Columns<'a> {
+    fn new(text: &'a str, encoding: PositionEncoding) -> Self {
+        Self {
+            lines: text.split('\n').collect(),
+            encoding,
+        }
+    }
+
+    /// the columns after the end of the line are left as they are
+    fn to_client(&self, pos: Position) -> Position {
+        let line = match self.lines.get(pos.row) {
+            Some(line) if self.encoding == PositionEncoding::Utf16 => line,
+            _ => return pos,
+        };
+        let units: usize = line.chars().take(pos.col).map(char::len_utf16).sum();
+        let after = pos.col.saturating_sub(line.chars().count());
+        Position {
+            row: pos.row,
+            col: units + after,
+        }
+    }
+
+    fn from_client(&self, pos: Position) -> Position {
+        let line = match self.lines.get(pos.row) {
+            Some(line) if self.encoding == PositionEncoding::Utf16 => line,
+            _ => return pos,
+        };
+        let mut units = 0;
+        let mut col = 0;
+        for c in line.chars() {
+            if units >= pos.col {
+                break;
+            }
+            units += c.len_utf16();
+            col += 1;
+        }
+        Position {
+            row: pos.row,
+            col: col + pos.col.saturating_sub(units),
+        }
+    }
+}
+
+/// what should be done after the message was handled
+pub enum Outcome {
+    Continue(Vec<JsonValue>),
+    Exit(i32),
+}
+
+fn position_to_json(pos: Position, columns: &Columns) -> JsonValue {
+    let pos = columns.to_client(pos);
+    JsonValue::object([("line", pos.row.into()), ("character", pos.col.into())])
+}
+
+fn range_to_json(range: Range, columns: &Columns) -> JsonValue {
+    JsonValue::object([
+        ("start", position_to_json(range.start, columns)),
+        ("end", position_to_json(range.end, columns)),
+    ])
+}
+
+fn position_from_json(value: &JsonValue) -> Option<Position> {
+    Some(Position {
+        row: value.get("line").ok()?.as_i64().ok()? as usize,
+        col: value.get("character").ok()?.as_i64().ok()? as usize,
+    })
+}
+
+fn symbol_to_json(symbol: &Symbol, columns: &Columns) -> JsonValue {
+    let kind: i64 = match symbol.kind {
+        SymbolKind::Function => 12,
+        SymbolKind::Variable => 13,
+        SymbolKind::Struct => 23,
+        SymbolKind::Field => 8,
+    };
+    JsonValue::object([
+        ("name", symbol.name.as_str().into()),
+        ("detail", symbol.detail.as_str().into()),
+        ("kind", kind.into()),
+        ("range", range_to_json(symbol.range, columns)),
+        ("selectionRange", range_to_json(symbol.selection, columns)),
+        (
+            "children",
+            symbol
+                .children
+                .iter()
+                .map(|x| symbol_to_json(x, columns))
+                .collect::<Vec<_>>()
+                .into(),
+        ),
+    ])
+}
+
+fn response(id: &JsonValue, result: JsonValue) -> JsonValue {
+    JsonValue::object([
+        ("jsonrpc", "2.0".into()),
+        ("id", id.clone()),
+        ("result", result),
+    ])
+}
+
+fn error_response(id: &JsonValue, code: i64, message: &str) -> JsonValue {
+    JsonValue::object([
+        ("jsonrpc", "2.0".into()),
+        ("id", id.clone()),
+        (
+            "error",
+            JsonValue::object([("code", code.into()), ("message", message.into())]),
+        ),
+    ])
+}
+
+fn notification(method: &str, params: JsonValue) -> JsonValue {
+    JsonValue::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", method.into()),
+        ("params", params),
+    ])
+}
+
+/// the analysis must not bring down the whole server
+fn safe_analyze(text: &str) -> Analysis {
+    panic::catch_unwind(|| analyze_document(text)).unwrap_or_else(|_| {
+        let mut analysis = Analysis::default();
+        analysis.diagnostics.push(crate::analysis::Diagnostic {
+            range: Range::default(),
+            message: "internal error of the compiler".to_string(),
+        });
+        analysis
+    })
+}
+
+impl Server {
+    pub fn handle(&mut self, message: &JsonValue) -> Outcome {
+        let method = message
+            .get("method")
+            .and_then(|x| x.as_str())
+            .unwrap_or_default();
+        let params = message.get("params").unwrap_or(&JsonValue::Null);
+        let id = message.get_opt("id");
+
+        if method == "exit" {
+            return Outcome::Exit(if self.shutdown { 0 } else { 1 });
+        }
+
+        let Some(id) = id else {
+            return Outcome::Continue(self.notification(method, params));
+        };
+
+        let result = match method {
+            "initialize" => {
+                self.encoding = Self::negotiate_encoding(params);
+                Some(self.capabilities())
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Some(JsonValue::Null)
+            }
+            "textDocument/hover" => self.request(params, Self::hover),
+            "textDocument/definition" => self.request(params, Self::definition),
+            "textDocument/documentSymbol" => self.request(params, Self::symbols),
+            "textDocument/completion" => self.request(params, Self::completion),
+            _ => {
+                return Outcome::Continue(vec![error_response(
+                    id,
+                    METHOD_NOT_FOUND,
+                    &format!("unknown method {}", method),
+                )])
+            }
+        };
+        Outcome::Continue(vec![match result {
+            Some(result) => response(id, result),
+            None => error_response(id, INVALID_PARAMS, "invalid params"),
+        }])
+    }
+
+    /// the characters are used directly when the client accepts them
+    fn negotiate_encoding(params: &JsonValue) -> PositionEncoding {
+        let accepted = params
+            .get("capabilities")
+            .and_then(|x| x.get("general"))
+            .and_then(|x| x.get("positionEncodings"))
+            .and_then(|x| x.as_array());
+        match accepted {
+            Ok(encodings) if encodings.iter().any(|x| x.as_str() == Ok("utf-32")) => {
+                PositionEncoding::Utf32
+            }
+            _ => PositionEncoding::Utf16,
+        }
+    }
+
+    fn capabilities(&self) -> JsonValue {
+        let encoding = match self.encoding {
+            PositionEncoding::Utf16 => "utf-16",
+            PositionEncoding::Utf32 => "utf-32",
+        };
+        JsonValue::object([
+            (
+                "capabilities",
+                JsonValue::object([
+                    ("positionEncoding", encoding.into()),
+                    ("textDocumentSync", JsonValue::Number(1)),
+                    ("hoverProvider", true.into()),
+                    ("definitionProvider", true.into()),
+                    ("documentSymbolProvider", true.into()),
+                    (
+                        "completionProvider",
+                        JsonValue::object([("triggerCharacters", vec![".".into()].into())]),
+                    ),
+                ]),
+            ),
+            (
+                "serverInfo",
+                JsonValue::object([("name", env!("CARGO_PKG_NAME").into())]),
+            ),
+        ])
+    }
+
+    fn notification(&mut self, method: &str, params: &JsonValue) -> Vec<JsonValue> {
+        let document = params.get("textDocument").ok();
+        let Some(uri) = document.and_then(|x| x.get("uri").ok()?.as_str().ok()) else {
+            return vec![];
+        };
+        let text = match method {
+            "textDocument/didOpen" => document.and_then(|x| x.get("text").ok()),
+            // full synchronization so the last change is the whole text
+            "textDocument/didChange" => params
+                .get("contentChanges")
+                .and_then(|x| x.as_array())
+                .ok()
+                .and_then(|x| x.last()?.get("text").ok()),
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                let columns = Columns::new("", self.encoding);
+                return vec![Self::diagnostics(uri, &Analysis::default(), &columns)];
+            }
+            _ => return vec![],
+        };
+        let Some(text) = text.and_then(|x| x.as_str().ok()) else {
+            return vec![];
+        };
+
+        let analysis = safe_analyze(text);
+        let last_parsed = self.documents.remove(uri).and_then(|x| {
+            if x.analysis.parsed {
+                Some(x.analysis)
+            } else {
+                x.last_parsed
+            }
+        });
+        let result = Self::diagnostics(uri, &analysis, &Columns::new(text, self.encoding));
+        self.documents.insert(
+            uri.to_string(),
+            Document {
+                text: text.to_string(),
+                analysis,
+                last_parsed,
+            },
+        );
+        vec![result]
+    }
+
+    fn diagnostics(uri: &str, analysis: &Analysis, columns: &Columns) -> JsonValue {
+        let diagnostics = analysis
+            .diagnostics
+            .iter()
+            .map(|x| {
+                JsonValue::object([
+                    ("range", range_to_json(x.range, columns)),
+                    ("severity", JsonValue::Number(1)),
+                    ("source", "mc".into()),
+                    ("message", x.message.as_str().into()),
+                ])
+            })
+            .collect::<Vec<_>>();
+        notification(
+            "textDocument/publishDiagnostics",
+            JsonValue::object([("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+        )
+    }
+
+    /// finds the document and position of the request
+    fn request(
+        &self,
+        params: &JsonValue,
+        handler: fn(&Document, &Columns, &str, Position) -> JsonValue,
+    ) -> Option<JsonValue> {
+        let uri = params.get("textDocument").ok()?.get("uri").ok()?.as_str().ok()?;
+        let pos = params
+            .get_opt("position")
+            .and_then(position_from_json)
+            .unwrap_or_default();
+        Some(match self.documents.get(uri) {
+            Some(document) => {
+                let columns = Columns::new(&document.text, self.encoding);
+                handler(document, &columns, uri, columns.from_client(pos))
+            }
+            None => JsonValue::Null,
+        })
+    }
+
+    fn hover(document: &Document, columns: &Columns, _uri: &str, pos: Position) -> JsonValue {
+        match document.analysis.hover(pos) {
+            Some(occurrence) => JsonValue::object([
+                (
+                    "contents",
+                    JsonValue::object([
+                        ("kind", "markdown".into()),
+                        (
+                            "value",
+                            format!("```mc\n{}\n```", occurrence.hover).as_str().into(),
+                        ),
+                    ]),
+                ),
+                ("range", range_to_json(occurrence.range, columns)),
+            ]),
+            None => JsonValue::Null,
+        }
+    }
+
+    fn definition(document: &Document, columns: &Columns, uri: &str, pos: Position) -> JsonValue {
+        match document.analysis.definition(pos) {
+            Some(range) => JsonValue::object([
+                ("uri", uri.into()),
+                ("range", range_to_json(range, columns)),
+            ]),
+            None => JsonValue::Null,
+        }
+    }
+
+    fn symbols(document: &Document, columns: &Columns, _uri: &str, _pos: Position) -> JsonValue {
+        document
+            .analysis
+            .symbols
+            .iter()
+            .map(|x| symbol_to_json(x, columns))
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    fn completion(document: &Document, _columns: &Columns, _uri: &str, pos: Position) -> JsonValue {
+        let analysis = if document.analysis.parsed {
+            Some(&document.analysis)
+        } else {
+            document.last_parsed.as_ref()
+        };
+        let Some(analysis) = analysis else {
+            return JsonValue::Array(vec![]);
+        };
+        analysis
+            .complete_fields(&document.text, pos)
+            .into_iter()
+            .map(|field| {
+                JsonValue::object([
+                    ("label", field.name.as_str().into()),
+                    // field
+                    ("kind", JsonValue::Number(5)),
+                    (
+                        "detail",
+                        frontend::format::type_name(&field.field_type).as_str().into(),
+                    ),
+                ])
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frontend::json::parse_json;
+
+    fn handle(server: &mut Server, message: &str) -> Vec<JsonValue> {
+        match server.handle(&parse_json(message).unwrap()) {
+            Outcome::Continue(result) => result,
+            Outcome::Exit(_) => panic!("unexpected exit"),
+        }
+    }
+
+    #[test]
+    fn server_session() {
+        let mut server = Server::default();
+        let init = handle(&mut server, r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}"#);
+        assert!(init[0].get("result").unwrap().get("capabilities").is_ok());
+
+        let open = handle(
+            &mut server,
+            r#"{"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {"textDocument": {"uri": "file:///a.mc", "text": "int main() {\n    int x = 1;\n    return y;\n}\n"}}}"#,
+        );
+        let diagnostics = open[0].get("params").unwrap().get("diagnostics").unwrap();
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+
+        let change = handle(
+            &mut server,
+            r#"{"jsonrpc": "2.0", "method": "textDocument/didChange", "params": {"textDocument": {"uri": "file:///a.mc"}, "contentChanges": [{"text": "int main() {\n    int x = 1;\n    return x;\n}\n"}]}}"#,
+        );
+        let diagnostics = change[0].get("params").unwrap().get("diagnostics").unwrap();
+        assert!(diagnostics.as_array().unwrap().is_empty());
+
+        let definition = handle(
+            &mut server,
+            r#"{"jsonrpc": "2.0", "id": 2, "method": "textDocument/definition", "params": {"textDocument": {"uri": "file:///a.mc"}, "position": {"line": 2, "character": 11}}}"#,
+        );
+        let range = definition[0].get("result").unwrap().get("range").unwrap();
+        assert_eq!(
+            position_from_json(range.get("start").unwrap()),
+            Some(Position { row: 1, col: 8 })
+        );
+
+        let unknown = handle(&mut server, r#"{"jsonrpc": "2.0", "id": 3, "method": "foo"}"#);
+        assert!(unknown[0].get("error").is_ok());
+
+        handle(&mut server, r#"{"jsonrpc": "2.0", "id": 4, "method": "shutdown"}"#);
+        assert!(matches!(
+            server.handle(&parse_json(r#"{"jsonrpc": "2.0", "method": "exit"}"#).unwrap()),
+            Outcome::Exit(0)
+        ));
+    }
+
+    fn definition_start(server: &mut Server) -> Option<Position> {
+        handle(
+            server,
+            r#"{"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {"textDocument": {"uri": "file:///b.mc", "text": "int main() {\n    char* s = \"😀\"; int x = 1;\n    return x;\n}\n"}}}"#,
+        );
+        let definition = handle(
+            server,
+            r#"{"jsonrpc": "2.0", "id": 2, "method": "textDocument/definition", "params": {"textDocument": {"uri": "file:///b.mc"}, "position": {"line": 2, "character": 11}}}"#,
+        );
+        let range = definition[0].get("result").unwrap().get("range").unwrap();
+        position_from_json(range.get("start").unwrap())
+    }
+
+    #[test]
+    fn position_encoding() {
+        // the emoji is two UTF-16 code units but one character
+        let mut server = Server::default();
+        let init = handle(&mut server, r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}"#);
+        let capabilities = init[0].get("result").unwrap().get("capabilities").unwrap();
+        assert_eq!(capabilities.get("positionEncoding").unwrap().as_str(), Ok("utf-16"));
+        assert_eq!(definition_start(&mut server), Some(Position { row: 1, col: 24 }));
+
+        let mut server = Server::default();
+        let init = handle(
+            &mut server,
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"capabilities": {"general": {"positionEncodings": ["utf-32", "utf-16"]}}}}"#,
+        );
+        let capabilities = init[0].get("result").unwrap().get("capabilities").unwrap();
+        assert_eq!(capabilities.get("positionEncoding").unwrap().as_str(), Ok("utf-32"));
+        assert_eq!(definition_start(&mut server), Some(Position { row: 1, col: 23 }));
+    }
+}
@@ -2,7 +2,7 @@ use std::collections::{HashMap, HashSet};
 
 use crate::{
     analysis::cubicsolver::CubicSolver,
-    inst::{Reg, RegReg, RegRegImm},
+    inst::{ImmIRegs, InstructionType, Reg, RegReg, RegRegImm, RegRegs, SymRegs, TerminatorReg},
     ir::{BasicBlock, Function, InstStore, InstUUID, Instruction, Register},
 };
 
@@ -13,55 +13,54 @@ pub enum Cell {
     Volatile,
 }
 
+/// The register points to the cells, the memory of the cell holds
+/// the pointers to the cells. The memory of the volatile cell are
+/// the cells known outside of the function.
 #[derive(Clone, PartialEq, Eq, Hash)]
 enum Place {
     Register(Register),
-    Memory(Register),
-}
-
-impl Place {
-    fn is_register(&self) -> bool {
-        if let Place::Register(_) = self {
-            true
-        } else {
-            false
-        }
-    }
-
-    fn get_reg(&self) -> Register {
-        match self {
-            &Place::Register(reg) => reg,
-            &Place::Memory(reg) => reg,
-        }
-    }
+    Memory(Cell),
 }
 
 pub struct AndersenAnalysis<'a> {
     function: &'a Function,
-    memory: Option<Vec<Register>>,
+    cells: Option<Vec<Cell>>,
 }
 
 impl<'a> AndersenAnalysis<'a> {
     pub fn new(function: &'a Function) -> Self {
         Self {
             function,
-            memory: None,
+            cells: None,
         }
     }
 
     pub fn analyze(&mut self, store: &InstStore) -> HashMap<Register, HashSet<Cell>> {
+        registers(self.solve(store))
+    }
+
+    fn solve(&mut self, store: &InstStore) -> HashMap<Place, HashSet<Cell>> {
         let mut solver: CubicSolver<Cell, Place> = CubicSolver::new();
 
+        // the outside can hold any cell it knows in any of them
+        let outside = Place::Memory(Cell::Volatile);
+        solver.includes(Cell::Volatile, outside.clone());
+        for cell in self.get_cells(store) {
+            let memory = Place::Memory(cell.clone());
+            solver.includes_implies(
+                cell.clone(),
+                outside.clone(),
+                outside.clone(),
+                memory.clone(),
+            );
+            solver.includes_implies(cell, outside.clone(), memory, outside.clone());
+        }
+
         for bb in self.function.blocks.iter() {
             self.analyze_bb(bb, &mut solver, store);
         }
 
-        solver
-            .solution()
-            .into_iter()
-            .filter(|(place, _)| place.is_register())
-            .map(|(place, cell)| (place.get_reg(), cell))
-            .collect()
+        solver.solution()
     }
 
     fn analyze_bb(
@@ -82,76 +81,143 @@ impl<'a> AndersenAnalysis<'a> {
         store: &InstStore,
     ) {
         match &inst.data {
-            crate::inst::InstructionType::Arg(_) => {
+            crate::inst::InstructionType::Arg(_) | crate::inst::InstructionType::VaStart(_) => {
                 solver.includes(Cell::Volatile, Place::Register(inst.id))
             }
+            // the returned pointer can point anywhere, the arguments
+            // are known outside of the function
+            crate::inst::InstructionType::Call(RegRegs(_, args))
+            | crate::inst::InstructionType::CallDirect(SymRegs(_, args))
+            | crate::inst::InstructionType::SysCall(ImmIRegs(_, args)) => {
+                for arg in args {
+                    solver.add_edge(Place::Register(*arg), Place::Memory(Cell::Volatile));
+                }
+                solver.includes(Cell::Volatile, Place::Register(inst.id))
+            }
+            crate::inst::InstructionType::Retr(TerminatorReg(reg)) => {
+                solver.add_edge(Place::Register(*reg), Place::Memory(Cell::Volatile))
+            }
             crate::inst::InstructionType::Alloca(_) => {
                 solver.includes(Cell::Alloc(inst.id), Place::Register(inst.id))
             }
             crate::inst::InstructionType::Mov(Reg(reg)) => {
                 solver.add_edge(Place::Register(*reg), Place::Register(inst.id))
             }
+            // the phi can be any of its values
+            crate::inst::InstructionType::Phi(RegRegs(first, rest)) => {
+                for reg in rest.iter().chain([first]) {
+                    solver.add_edge(Place::Register(*reg), Place::Register(inst.id))
+                }
+            }
             crate::inst::InstructionType::Gep(_, RegRegImm(start, _, _)) => {
                 solver.add_edge(Place::Register(*start), Place::Register(inst.id))
             }
-            // anything that could be in the value it the
-            // address of the [reg] could be also in the inst.id
+            // pointer arithmetic stays in the same memory
+            crate::inst::InstructionType::Add(RegReg(l, r))
+            | crate::inst::InstructionType::Sub(RegReg(l, r)) => {
+                solver.add_edge(Place::Register(*l), Place::Register(inst.id));
+                solver.add_edge(Place::Register(*r), Place::Register(inst.id));
+            }
+            // anything in the memory of the cells the address
+            // points to could be in the inst.id
             crate::inst::InstructionType::Ld(Reg(addr)) => {
-                // every thing that could be on the address [addr] could be in the register
-                solver.add_edge(Place::Memory(*addr), Place::Register(inst.id));
-
-                for mem in self.get_memory(store) {
+                for cell in self.get_cells(store) {
                     solver.includes_implies(
-                        Cell::Alloc(mem),
-                        Place::Memory(*addr),
-                        Place::Memory(mem),
+                        cell.clone(),
+                        Place::Register(*addr),
+                        Place::Memory(cell),
                         Place::Register(inst.id),
                     );
                 }
             }
-            // anything that could be in the register reg
-            // could be in the in the memory on the address [addr]
+            // anything that could be in the register reg could be
+            // in the memory of the cells the address points to
             crate::inst::InstructionType::St(RegReg(addr, reg)) => {
-                solver.add_edge(Place::Register(*reg), Place::Memory(*addr));
-                for mem in self.get_memory(store) {
+                for cell in self.get_cells(store) {
                     solver.includes_implies(
-                        Cell::Alloc(mem),
-                        Place::Memory(*addr),
+                        cell.clone(),
+                        Place::Register(*addr),
                         Place::Register(*reg),
-                        Place::Memory(mem),
+                        Place::Memory(cell),
                     );
                 }
             }
-            // I am still not using this so fuck it
-            crate::inst::InstructionType::Allocg(_) => todo!(),
+            // globals can be changed by any call
+            crate::inst::InstructionType::Ldg(_) => {
+                solver.includes(Cell::Volatile, Place::Register(inst.id))
+            }
             _ => (),
         }
     }
 
-    fn get_memory(&mut self, store: &InstStore) -> Vec<Register> {
-        if let Some(memory) = &self.memory {
-            memory.clone()
+    /// allocas of the function and the volatile cell
+    fn get_cells(&mut self, store: &InstStore) -> Vec<Cell> {
+        if let Some(cells) = &self.cells {
+            cells.clone()
         } else {
-            let memory: HashSet<Register> = self
+            let cells = self
                 .function
                 .blocks
                 .iter()
-                .map(|bb| {
-                    bb.iter().filter(|inst| match store.get(**inst).data {
-                        crate::inst::InstructionType::St(_) => true,
-                        crate::inst::InstructionType::Ld(_) => true,
-                        _ => false,
-                    })
-                })
-                .flatten()
-                .map(|inst| match store.get(*inst).data {
-                    crate::inst::InstructionType::St(RegReg(addr, _)) => addr,
-                    crate::inst::InstructionType::Ld(Reg(addr)) => addr,
-                    _ => unreachable!(),
-                })
+                .flat_map(|bb| bb.iter())
+                .filter(|inst| matches!(store.get(**inst).data, InstructionType::Alloca(_)))
+                .map(|inst| Cell::Alloc(*inst))
+                .chain([Cell::Volatile])
                 .collect();
-            self.memory = Some(memory.into_iter().collect());
-            self.get_memory(store)
+            self.cells = Some(cells);
+            self.get_cells(store)
+        }
+    }
+}
+
+/// cells the registers point to
+fn registers(solution: HashMap<Place, HashSet<Cell>>) -> HashMap<Register, HashSet<Cell>> {
+    solution
+        .into_iter()
+        .filter_map(|(place, cells)| match place {
+            Place::Register(reg) => Some((reg, cells)),
+            Place::Memory(_) => None,
+        })
+        .collect()
+}
+
+/// May alias queries over the result of the analysis. The unknown memory
+/// can be only the allocas whose address leaves the function or goes
+/// into the memory known outside of it.
+pub struct Aliases {
+    points_to: HashMap<Register, HashSet<Cell>>,
+    escaped: HashSet<Cell>,
+}
+
+impl Aliases {
+    pub fn new(function: &Function, store: &InstStore) -> Self {
+        let mut solution = AndersenAnalysis::new(function).solve(store);
+        let escaped = solution
+            .remove(&Place::Memory(Cell::Volatile))
+            .unwrap_or_default();
+        Self {
+            points_to: registers(solution),
+            escaped,
+        }
+    }
+
+    /// the addresses without any known cells can point anywhere
+    pub fn may_alias(&self, a: Register, b: Register) -> bool {
+        let (a, b) = match (self.points_to.get(&a), self.points_to.get(&b)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return true,
+        };
+        let unknown = |x: &HashSet<Cell>, y: &HashSet<Cell>| {
+            x.contains(&Cell::Volatile) && !y.is_disjoint(&self.escaped)
+        };
+        !a.is_disjoint(b) || unknown(a, b) || unknown(b, a)
+    }
+
+    /// the memory on the address can be used outside of the function
+    pub fn escapes(&self, addr: Register) -> bool {
+        match self.points_to.get(&addr) {
+            Some(cells) => !cells.is_disjoint(&self.escaped),
+            None => true,
         }
     }
 }
@@ -1,9 +1,9 @@
 use std::collections::{HashMap, HashSet};
 
 use crate::{
-    analysis::lattice::Lattice,
-    inst::{ImmIRegs, InstructionType, RegReg, SymRegs},
-    ir::{Function, InstStore, Register},
+    analysis::{anderson::Aliases, lattice::Lattice},
+    inst::{InstructionType, RegReg},
+    ir::{Function, InstStore, RegType, Register},
 };
 
 use super::{
@@ -20,6 +20,8 @@ pub struct ConstantMemoryAnalysis<'a> {
     function: &'a Function,
     store: &'a InstStore,
     inner_lattice: ConstLattice,
+    // the stores change the places which can share a cell with their address
+    aliases: Aliases,
 }
 
 impl<'a> ConstantMemoryAnalysis<'a> {
@@ -31,6 +33,7 @@ impl<'a> ConstantMemoryAnalysis<'a> {
                 ConstantMemoryAnalysis::get_stores(function, store),
                 FlatLattice::new(),
             ),
+            aliases: Aliases::new(function, store),
         }
     }
 
@@ -74,6 +77,7 @@ impl<'a> DataFlowAnalysis<'a, HashMap<MemoryPlace, FlatElem<Register>>, ConstLat
             ConstantMemoryAnalysis::get_stores(func, self.store),
             FlatLattice::new(),
         );
+        self.aliases = Aliases::new(func, self.store);
     }
 
     fn direction(&self) -> super::dataflow::DataflowType {
@@ -88,22 +92,38 @@ impl<'a> DataFlowAnalysis<'a, HashMap<MemoryPlace, FlatElem<Register>>, ConstLat
     ) -> HashMap<MemoryPlace, FlatElem<Register>> {
         use InstructionType::*;
         let (_, bb_idx, inst_idx) = pos;
+        // nothing is known about the memory at the start
+        let mut state = match (bb_idx, inst_idx) {
+            (0, 0) => self.inner_lattice.top(),
+            _ => state,
+        };
         match &inst.data {
             St(RegReg(addr, reg)) => {
-                let mut state = state;
+                for (place, value) in state.iter_mut() {
+                    if place.0 != *addr && self.aliases.may_alias(place.0, *addr) {
+                        *value = FlatElem::Top;
+                    }
+                }
                 state.insert(MemoryPlace(*addr), FlatElem::Value(*reg));
                 state
             }
-            CallDirect(SymRegs(_, regs)) | SysCall(ImmIRegs(_, regs)) => {
-                let mut state = state;
-                for reg in regs.iter() {
-                    if self.inner_lattice.map.contains(&MemoryPlace(*reg)) {
-                        state.insert(MemoryPlace(*reg), FlatElem::Top);
+            // the called function can change any memory it gets to
+            Call(_) | CallDirect(_) | SysCall(_) => {
+                for value in state.values_mut() {
+                    *value = FlatElem::Top;
+                }
+                state
+            }
+            // in the loops the register gets a new value, the facts
+            // about the old one do not hold anymore
+            _ if inst.reg_type != RegType::Void => {
+                for (place, value) in state.iter_mut() {
+                    if place.0 == inst.id || *value == FlatElem::Value(inst.id) {
+                        *value = FlatElem::Top;
                     }
                 }
                 state
             }
-            _ if bb_idx == 0 && inst_idx == 0 => self.inner_lattice.bot(),
             _ => state,
         }
     }
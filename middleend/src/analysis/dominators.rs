@@ -0,0 +1,291 @@
+use std::collections::BTreeSet;
+
+use crate::ir::{BBIndex, Function, InstStore};
+
+/// Dominator tree computed by the algorithm of Cooper, Harvey and Kennedy.
+/// The post-dominator tree is the same tree over the reversed edges, its
+/// root is a virtual exit after all blocks which return. Blocks which
+/// cannot be reached from the root are not in the tree.
+pub struct DominatorTree {
+    // edges of the graph the tree is built over, reversed for the
+    // post-dominators, the last node is the virtual exit there
+    succ: Vec<Vec<usize>>,
+    pred: Vec<Vec<usize>>,
+    root: usize,
+    idom: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    // preorder and postorder numbers in the tree for constant time queries
+    enter: Vec<usize>,
+    leave: Vec<usize>,
+    blocks: usize,
+}
+
+impl DominatorTree {
+    /// Dominators, the start block is the root
+    pub fn new(function: &Function, store: &InstStore) -> Self {
+        let succ: Vec<Vec<usize>> = function
+            .blocks
+            .iter()
+            .map(|bb| valid(bb.succ(store), function.blocks.len()))
+            .collect();
+        Self::build(succ, 0, function.blocks.len())
+    }
+
+    /// Post-dominators, the blocks without successors lead to the virtual root
+    pub fn post(function: &Function, store: &InstStore) -> Self {
+        let exit = function.blocks.len();
+        let mut succ = vec![vec![]; exit + 1];
+        for (bb, block) in function.blocks.iter().enumerate() {
+            let targets = valid(block.succ(store), exit);
+            if targets.is_empty() {
+                succ[exit].push(bb);
+            }
+            for target in targets {
+                succ[target].push(bb);
+            }
+        }
+        Self::build(succ, exit, exit)
+    }
+
+    fn build(succ: Vec<Vec<usize>>, root: usize, blocks: usize) -> Self {
+        let nodes = succ.len();
+        let mut pred = vec![vec![]; nodes];
+        for (from, targets) in succ.iter().enumerate() {
+            for to in targets {
+                pred[*to].push(from);
+            }
+        }
+
+        // postorder of the nodes reachable from the root
+        let mut postorder = vec![];
+        let mut visited = vec![false; nodes];
+        let mut stack = vec![(root, 0)];
+        visited[root] = true;
+        while let Some((node, next)) = stack.pop() {
+            match succ[node].get(next) {
+                Some(&to) => {
+                    stack.push((node, next + 1));
+                    if !visited[to] {
+                        visited[to] = true;
+                        stack.push((to, 0));
+                    }
+                }
+                None => postorder.push(node),
+            }
+        }
+        let mut number = vec![usize::MAX; nodes];
+        for (index, node) in postorder.iter().enumerate() {
+            number[*node] = index;
+        }
+
+        let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while number[a] < number[b] {
+                    a = idom[a].unwrap();
+                }
+                while number[b] < number[a] {
+                    b = idom[b].unwrap();
+                }
+            }
+            a
+        };
+        let mut idom: Vec<Option<usize>> = vec![None; nodes];
+        idom[root] = Some(root);
+        let mut change = true;
+        while change {
+            change = false;
+            for node in postorder.iter().rev().filter(|x| **x != root) {
+                let mut new = None;
+                for p in pred[*node].iter().filter(|p| idom[**p].is_some()) {
+                    new = Some(match new {
+                        Some(new) => intersect(&idom, *p, new),
+                        None => *p,
+                    });
+                }
+                if new.is_some() && idom[*node] != new {
+                    idom[*node] = new;
+                    change = true;
+                }
+            }
+        }
+
+        let mut children = vec![vec![]; nodes];
+        for node in postorder.iter().rev().filter(|x| **x != root) {
+            children[idom[*node].unwrap()].push(*node);
+        }
+        let mut enter = vec![0; nodes];
+        let mut leave = vec![0; nodes];
+        let mut counter = 0;
+        let mut stack = vec![(root, false)];
+        while let Some((node, done)) = stack.pop() {
+            counter += 1;
+            if done {
+                leave[node] = counter;
+                continue;
+            }
+            enter[node] = counter;
+            stack.push((node, true));
+            for child in children[node].iter().rev() {
+                stack.push((*child, false));
+            }
+        }
+
+        Self {
+            succ,
+            pred,
+            root,
+            idom,
+            children,
+            enter,
+            leave,
+            blocks,
+        }
+    }
+
+    pub fn is_reachable(&self, bb: BBIndex) -> bool {
+        bb < self.blocks && self.idom[bb].is_some()
+    }
+
+    /// Immediate dominator, the root and the unreachable blocks have none
+    /// and neither have the blocks post-dominated only by the exit
+    pub fn idom(&self, bb: BBIndex) -> Option<BBIndex> {
+        match self.idom.get(bb).copied().flatten() {
+            Some(idom) if bb != self.root && idom < self.blocks => Some(idom),
+            _ => None,
+        }
+    }
+
+    /// Every block dominates itself
+    pub fn dominates(&self, a: BBIndex, b: BBIndex) -> bool {
+        self.is_reachable(a)
+            && self.is_reachable(b)
+            && self.enter[a] <= self.enter[b]
+            && self.leave[b] <= self.leave[a]
+    }
+
+    /// Blocks immediately dominated by the block, for the post-dominators
+    /// the children of the virtual exit are the roots
+    pub fn children(&self, bb: BBIndex) -> &[BBIndex] {
+        &self.children[bb]
+    }
+
+    /// Blocks whose immediate dominator is the root, for the dominators
+    /// it is just the start block
+    pub fn roots(&self) -> Vec<BBIndex> {
+        match self.root < self.blocks {
+            true => vec![self.root],
+            false => self.children[self.root].clone(),
+        }
+    }
+
+    /// Blocks in the order where every block comes after its dominator
+    pub fn preorder(&self) -> Vec<BBIndex> {
+        let mut order: Vec<BBIndex> = (0..self.blocks).filter(|x| self.is_reachable(*x)).collect();
+        order.sort_by_key(|x| self.enter[*x]);
+        order
+    }
+
+    /// Dominance frontier of every block, the blocks where its
+    /// dominance ends, for the post-dominators over the reversed edges
+    pub fn frontiers(&self) -> Vec<BTreeSet<BBIndex>> {
+        let mut frontiers = vec![BTreeSet::new(); self.succ.len()];
+        for node in 0..self.succ.len() {
+            let preds: Vec<usize> = self.pred[node]
+                .iter()
+                .copied()
+                .filter(|x| self.idom[*x].is_some())
+                .collect();
+            if preds.len() < 2 || self.idom[node].is_none() {
+                continue;
+            }
+            for pred in preds {
+                let mut runner = pred;
+                while Some(runner) != self.idom[node] {
+                    frontiers[runner].insert(node);
+                    runner = self.idom[runner].unwrap();
+                }
+            }
+        }
+        frontiers.truncate(self.blocks);
+        for frontier in frontiers.iter_mut() {
+            frontier.retain(|x| *x < self.blocks);
+        }
+        frontiers
+    }
+}
+
+fn valid(targets: Vec<BBIndex>, blocks: usize) -> Vec<BBIndex> {
+    let mut targets: Vec<BBIndex> = targets.into_iter().filter(|x| *x < blocks).collect();
+    targets.dedup();
+    targets
+}
+
+/// Control dependence graph, the block at the index decides whether the
+/// blocks in its set run. It is the post-dominance frontier reversed.
+pub fn control_dependences(function: &Function, store: &InstStore) -> Vec<BTreeSet<BBIndex>> {
+    let post = DominatorTree::post(function, store);
+    let mut deps = vec![BTreeSet::new(); function.blocks.len()];
+    for (bb, frontier) in post.frontiers().into_iter().enumerate() {
+        for branch in frontier {
+            deps[branch].insert(bb);
+        }
+    }
+    deps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir_parse::parse_ir;
+
+    // if in the loop, BB5 is not reachable
+    const LOOP: &str = "function f(1) : int {
+BB0:
+%0 : int = arg 0
+jmp BB1
+BB1:
+branch %0 BB2 BB4
+BB2:
+branch %0 BB3 BB1
+BB3:
+jmp BB1
+BB4:
+retr %0
+BB5:
+jmp BB4
+}";
+
+    #[test]
+    fn dominators_of_loop() {
+        let program = parse_ir(LOOP).unwrap();
+        let f = &program.funcs["f"];
+        let dom = DominatorTree::new(f, &program.store);
+        let idoms: Vec<_> = (0..6).map(|x| dom.idom(x)).collect();
+        assert_eq!(idoms, [None, Some(0), Some(1), Some(2), Some(1), None]);
+        assert!(dom.dominates(1, 3) && dom.dominates(3, 3) && !dom.dominates(3, 1));
+        assert!(!dom.is_reachable(5) && !dom.dominates(0, 5));
+        assert_eq!(dom.preorder()[..2], [0, 1]);
+
+        let frontiers = dom.frontiers();
+        assert_eq!(frontiers[2], BTreeSet::from([1]));
+        assert_eq!(frontiers[3], BTreeSet::from([1]));
+        assert_eq!(frontiers[1], BTreeSet::from([1]));
+        assert!(frontiers[4].is_empty());
+    }
+
+    #[test]
+    fn post_dominators_and_control_dependence() {
+        let program = parse_ir(LOOP).unwrap();
+        let f = &program.funcs["f"];
+        let post = DominatorTree::post(f, &program.store);
+        let ipdoms: Vec<_> = (0..6).map(|x| post.idom(x)).collect();
+        assert_eq!(ipdoms, [Some(1), Some(4), Some(1), Some(1), None, Some(4)]);
+        assert_eq!(post.roots(), vec![4]);
+
+        let deps = control_dependences(f, &program.store);
+        // the loop runs while BB1 decides so, BB3 only when BB2 does
+        assert_eq!(deps[1], BTreeSet::from([1, 2]));
+        assert_eq!(deps[2], BTreeSet::from([3]));
+        assert!(deps[0].is_empty() && deps[4].is_empty());
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::HashSet;
 
 use crate::{
-    inst::InstructionType,
+    inst::{InstructionType, RegReg},
     ir::{Function, Instruction, Register},
 };
 
@@ -59,6 +59,13 @@ impl<'a> DataFlowAnalysis<'a, HashSet<Register>, PowerSetLattice<Register>>
         match inst.data {
             Ret(_) | Exit(_) => self.inner_lattice.bot(),
             Retr(_) => HashSet::from_iter(inst.data.get_regs().into_iter()),
+            // out of ssa the copy writes the first register
+            Copy(RegReg(to, from)) => {
+                let mut state = state;
+                state.remove(&to);
+                state.insert(from);
+                state
+            }
             _ => {
                 let mut state = state;
                 state.remove(&inst.id);
@@ -0,0 +1,327 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    analysis::dominators::DominatorTree,
+    inst::{InstructionType, RegRegs, TerminatorBranch, TerminatorJump},
+    ir::{BBIndex, BasicBlock, Function, InstStore, RegType, Register},
+};
+
+type I = InstructionType;
+
+/// Natural loop, the blocks which reach a back edge into the header
+/// without going through it. The back edges with the same header make
+/// one loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loop {
+    pub header: BBIndex,
+    // sources of the back edges
+    pub latches: Vec<BBIndex>,
+    pub blocks: BTreeSet<BBIndex>,
+    // blocks out of the loop with a predecessor in it
+    pub exits: BTreeSet<BBIndex>,
+    // the only predecessor out of the loop which jumps only to the header
+    pub preheader: Option<BBIndex>,
+    // index of the smallest loop around
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    // the outermost loops have the depth 1
+    pub depth: usize,
+}
+
+/// Loop nesting forest of the function, a parent is always before
+/// its children. The loops which are not reducible are not found.
+pub struct LoopForest {
+    pub loops: Vec<Loop>,
+    // the innermost loop of every block
+    innermost: Vec<Option<usize>>,
+}
+
+impl LoopForest {
+    pub fn new(function: &Function, store: &InstStore) -> Self {
+        let dom = DominatorTree::new(function, store);
+        let mut loops: Vec<Loop> = vec![];
+        // the headers are visited before the blocks they dominate
+        for header in dom.preorder() {
+            let latches: Vec<BBIndex> = function.blocks[header]
+                .pred()
+                .into_iter()
+                .filter(|pred| dom.dominates(header, *pred))
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect();
+            if latches.is_empty() {
+                continue;
+            }
+            let mut blocks = BTreeSet::from([header]);
+            let mut work = latches.clone();
+            while let Some(bb) = work.pop() {
+                if blocks.insert(bb) {
+                    work.extend(function.blocks[bb].pred());
+                }
+            }
+            let exits = blocks
+                .iter()
+                .flat_map(|bb| function.blocks[*bb].succ(store))
+                .filter(|x| !blocks.contains(x))
+                .collect();
+            let outside: Vec<BBIndex> = function.blocks[header]
+                .pred()
+                .into_iter()
+                .filter(|x| !blocks.contains(x))
+                .collect();
+            let preheader = match outside[..] {
+                [pred] if function.blocks[pred].succ(store) == [header] => Some(pred),
+                _ => None,
+            };
+            // the last loop containing the header is the smallest one
+            let parent = loops.iter().rposition(|x| x.blocks.contains(&header));
+            let depth = parent.map_or(1, |x| loops[x].depth + 1);
+            let index = loops.len();
+            if let Some(parent) = parent {
+                loops[parent].children.push(index);
+            }
+            loops.push(Loop {
+                header,
+                latches,
+                blocks,
+                exits,
+                preheader,
+                parent,
+                children: vec![],
+                depth,
+            });
+        }
+
+        let mut innermost = vec![None; function.blocks.len()];
+        for (index, l) in loops.iter().enumerate() {
+            for bb in l.blocks.iter() {
+                innermost[*bb] = Some(index);
+            }
+        }
+        Self { loops, innermost }
+    }
+
+    /// Index of the innermost loop with the block
+    pub fn loop_of(&self, bb: BBIndex) -> Option<usize> {
+        self.innermost.get(bb).copied().flatten()
+    }
+
+    /// Number of the loops around the block, 0 out of all loops
+    pub fn depth(&self, bb: BBIndex) -> usize {
+        self.loop_of(bb).map_or(0, |x| self.loops[x].depth)
+    }
+
+    /// Loops which are not in any other loop
+    pub fn roots(&self) -> Vec<usize> {
+        (0..self.loops.len())
+            .filter(|x| self.loops[*x].parent.is_none())
+            .collect()
+    }
+}
+
+/// Moves the edges from the group of the predecessors into a new block
+/// which jumps to the block. The phis get their values from the group
+/// in a new phi, the new block takes the place of the first one.
+fn split_preds(
+    function: &mut Function,
+    store: &mut InstStore,
+    bb: BBIndex,
+    group: &BTreeSet<BBIndex>,
+) -> BBIndex {
+    let new = function.blocks.len();
+    let preds = function.blocks[bb].pred();
+    let moved: Vec<bool> = preds.iter().map(|x| group.contains(x)).collect();
+    let first_moved = moved.iter().position(|x| *x);
+    let mut block = BasicBlock::default();
+    for pred in preds.iter().filter(|x| group.contains(x)) {
+        block.add_predecesor(*pred);
+    }
+
+    for inst in function.blocks[bb].iter().copied() {
+        let (first, rest) = match &store.get(inst).data {
+            I::Phi(RegRegs(first, rest)) => (*first, rest.clone()),
+            _ => continue,
+        };
+        let values: Vec<Register> = [first].into_iter().chain(rest).collect();
+        let from_group: Vec<Register> = values
+            .iter()
+            .zip(moved.iter())
+            .filter(|(_, moved)| **moved)
+            .map(|(value, _)| *value)
+            .collect();
+        let value = match from_group[..] {
+            [value] => value,
+            _ => {
+                let reg_type = store.get(inst).reg_type;
+                let data = I::Phi(RegRegs(from_group[0], from_group[1..].to_vec()));
+                let phi = store.add_inst(data, reg_type);
+                block.push(phi);
+                phi
+            }
+        };
+        let values: Vec<Register> = values
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !moved[*i] || Some(*i) == first_moved)
+            .map(|(i, x)| if moved[i] { value } else { x })
+            .collect();
+        let reg_type = store.get(inst).reg_type;
+        store.replace_inst(
+            inst,
+            I::Phi(RegRegs(values[0], values[1..].to_vec())),
+            reg_type,
+        );
+    }
+    block.push(store.add_inst(I::Jmp(TerminatorJump(bb)), RegType::Void));
+    function.blocks.push(block);
+
+    for pred in group {
+        let last = *function.blocks[*pred].last().unwrap();
+        match &mut store.get_mut(last).data {
+            I::Jmp(TerminatorJump(target)) if *target == bb => *target = new,
+            I::Branch(TerminatorBranch(_, yes, no)) => {
+                for target in [yes, no] {
+                    if *target == bb {
+                        *target = new;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    let block = &mut function.blocks[bb];
+    for pred in preds.iter() {
+        block.remove_predecesor(*pred);
+    }
+    for (i, pred) in preds.into_iter().enumerate() {
+        if !moved[i] {
+            block.add_predecesor(pred);
+        } else if Some(i) == first_moved {
+            block.add_predecesor(new);
+        }
+    }
+    new
+}
+
+/// Gives every loop a preheader and makes its exits dedicated, all their
+/// predecessors are in the loop. The code moved out of the loop goes to
+/// the preheader and the code after it to the exits.
+pub fn normalize_loops(function: &mut Function, store: &mut InstStore) -> bool {
+    let mut change = false;
+    loop {
+        let forest = LoopForest::new(function, store);
+        let mut split = None;
+        for l in forest.loops.iter() {
+            if l.preheader.is_none() {
+                let outside = function.blocks[l.header]
+                    .pred()
+                    .into_iter()
+                    .filter(|x| !l.blocks.contains(x))
+                    .collect();
+                split = Some((l.header, outside));
+                break;
+            }
+            let shared = l.exits.iter().find(|exit| {
+                let preds = function.blocks[**exit].pred();
+                preds.iter().any(|x| !l.blocks.contains(x))
+            });
+            if let Some(exit) = shared {
+                let inside = function.blocks[*exit]
+                    .pred()
+                    .into_iter()
+                    .filter(|x| l.blocks.contains(x))
+                    .collect();
+                split = Some((*exit, inside));
+                break;
+            }
+        }
+        // the blocks are new after every split, the loops are found again
+        match split {
+            Some((bb, group)) => _ = split_preds(function, store, bb, &group),
+            None => return change,
+        }
+        change = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ir_parse::parse_ir, test_utils::reg, verify::verify_function};
+
+    // two loops in the outer one, both entered from BB1, the first
+    // inner loop is left from its header and from its body
+    const NESTED: &str = "function f(1) : int {
+BB0:
+%0 : int = arg 0
+%1 : int = ldi 0
+branch %0 BB1 BB7
+BB1:
+%2 : int = phi %1 [%6 %2]
+branch %0 BB2 BB5
+BB2:
+branch %0 BB3 BB4
+BB3:
+branch %0 BB2 BB4
+BB4:
+%6 : int = ldi 1
+branch %0 BB1 BB7
+BB5:
+branch %0 BB5 BB6
+BB6:
+jmp BB1
+BB7:
+%7 : int = phi %1 [%6]
+retr %7
+}";
+
+    #[test]
+    fn nesting_of_loops() {
+        let program = parse_ir(NESTED).unwrap();
+        let f = &program.funcs["f"];
+        let forest = LoopForest::new(f, &program.store);
+        assert_eq!(forest.loops.len(), 3);
+        let outer = &forest.loops[0];
+        assert_eq!(outer.header, 1);
+        assert_eq!(outer.latches, vec![4, 6]);
+        assert_eq!(outer.blocks, BTreeSet::from([1, 2, 3, 4, 5, 6]));
+        assert_eq!(outer.exits, BTreeSet::from([7]));
+        assert_eq!(outer.preheader, None);
+        assert_eq!(outer.children, vec![1, 2]);
+        assert_eq!(forest.roots(), vec![0]);
+
+        let inner = forest.loops.iter().find(|x| x.header == 2).unwrap();
+        assert_eq!((inner.header, inner.depth, inner.parent), (2, 2, Some(0)));
+        assert_eq!(inner.exits, BTreeSet::from([4]));
+        let depths: Vec<usize> = (0..8).map(|x| forest.depth(x)).collect();
+        assert_eq!(depths, [0, 1, 2, 2, 1, 2, 1, 0]);
+    }
+
+    #[test]
+    fn preheaders_and_dedicated_exits() {
+        let program = parse_ir(NESTED).unwrap();
+        let mut store = program.store;
+        let mut f = program.funcs.into_values().next().unwrap();
+        assert!(normalize_loops(&mut f, &mut store));
+        assert_eq!(verify_function(&f, &store), vec![]);
+        assert!(!normalize_loops(&mut f, &mut store));
+
+        let forest = LoopForest::new(&f, &store);
+        assert_eq!(forest.loops.len(), 3);
+        for l in forest.loops.iter() {
+            let preheader = l.preheader.unwrap();
+            assert_eq!(f.blocks[preheader].len(), 1);
+            for exit in l.exits.iter() {
+                assert!(f.blocks[*exit].pred().iter().all(|x| l.blocks.contains(x)));
+            }
+        }
+        // the value from the start comes through the preheader
+        let outer = &forest.loops[0];
+        let header = &f.blocks[outer.header];
+        assert_eq!(header.pred(), vec![outer.preheader.unwrap(), 4, 6]);
+        assert_eq!(
+            store.get(header[0]).data,
+            I::Phi(RegRegs(reg(1), vec![reg(6), reg(2)]))
+        );
+    }
+}
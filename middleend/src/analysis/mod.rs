@@ -3,6 +3,8 @@ pub mod anderson;
 pub mod const_mem;
 pub mod cubicsolver;
 pub mod dataflow;
+pub mod dominators;
 pub mod lattice;
 pub mod live;
+pub mod loops;
 pub mod possible_mem;
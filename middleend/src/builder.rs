@@ -1,9 +1,31 @@
 use crate::{
     inst::InstructionType,
-    ir::{BBIndex, BasicBlock, Function, InstStore, InstUUID, IrProgram, RegType, Register},
-    optimalizations::death_store_load::remove_store_load,
+    ir::{
+        BBIndex, BasicBlock, Function, GlobalVar, InstStore, InstUUID, IrProgram, RegType,
+        Register, Symbol,
+    },
+    optimalizations::{
+        bounds_check::remove_bounds_checks, death_store_load::remove_store_load,
+        gvn::number_values, licm::hoist_invariants, phinode_create::create_phinodes,
+        sccp::propagate_constants, simplify_cfg::simplify_cfg,
+    },
+    verify::debug_verify,
 };
 
+pub(crate) type Pass = fn(&mut Function, &mut InstStore) -> bool;
+
+/// transformations run on every created function until none of them
+/// changes it, the later ones run only when the earlier have nothing to do
+const PASSES: [(&str, Pass); 7] = [
+    ("create_phinodes", create_phinodes),
+    ("propagate_constants", propagate_constants),
+    ("simplify_cfg", simplify_cfg),
+    ("number_values", number_values),
+    ("hoist_invariants", hoist_invariants),
+    ("remove_store_load", remove_store_load),
+    ("remove_bounds_checks", remove_bounds_checks),
+];
+
 #[derive(Debug)]
 pub struct IrBuilder {
     pub store: InstStore,
@@ -35,6 +57,7 @@ pub enum IrBuilderError {
     NotFunction,
     CannotCreateId,
     FuncRedef,
+    GlobalRedef,
 }
 
 impl IrBuilder {
@@ -46,6 +69,23 @@ impl IrBuilder {
         Ok(())
     }
 
+    pub fn add_global(
+        &mut self,
+        name: &Symbol,
+        data: Vec<u8>,
+        read_only: bool,
+    ) -> Result<(), IrBuilderError> {
+        if self.prog.globals.iter().any(|x| &x.name == name) {
+            return Err(IrBuilderError::GlobalRedef);
+        }
+        self.prog.globals.push(GlobalVar {
+            name: name.clone(),
+            data,
+            read_only,
+        });
+        Ok(())
+    }
+
     pub fn add(&mut self, inst: InstructionType, reg_type: RegType) -> Register {
         let id = self.store.add_inst(inst, reg_type);
         self.global.blocks[0].push(id);
@@ -126,7 +166,12 @@ impl<'a> FunctionBuilder<'a> {
             blocks: self.blocks,
         };
 
-        while remove_store_load(&mut result, self.store) {}
+        debug_verify(&result, self.store, "builder");
+        while PASSES.iter().any(|(name, pass)| {
+            let change = pass(&mut result, self.store);
+            debug_verify(&result, self.store, name);
+            change
+        }) {}
 
         result
     }
@@ -144,15 +189,47 @@ mod tests {
     #[test]
     fn correct_builder_api() {
         let mut builder = IrBuilder::default();
-        let reg: Register = builder.add(I::Ldi(ImmI(5)), RegType::Int);
+        builder.add(I::Ldi(ImmI(5)), RegType::Int);
         builder.add(I::Ret(Terminator), RegType::Void);
         let mut fn_b = FunctionBuilder::new(0, RegType::Void, &mut builder.store);
+        let reg: Register = fn_b.add(I::Ldi(ImmI(5)), RegType::Int);
         let bi = fn_b.create_bb();
         fn_b.add(I::Jmp(TerminatorJump(bi)), RegType::Void);
+        fn_b.set_predecesors(bi, &[0]);
         fn_b.set_bb(bi);
         fn_b.add(I::Print(Reg(reg)), RegType::Void);
         fn_b.add(I::Ret(Terminator), RegType::Void);
         let main_fn = fn_b.create("main");
         builder.add_fn(main_fn).unwrap();
     }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(
+        expected = "builder broke main: edge BB0 -> BB1 does not match the predecessors"
+    )]
+    fn builder_checks_predecessors() {
+        let mut builder = IrBuilder::default();
+        builder.add(I::Ret(Terminator), RegType::Void);
+        let mut fn_b = FunctionBuilder::new(0, RegType::Void, &mut builder.store);
+        let bi = fn_b.create_bb();
+        fn_b.add(I::Jmp(TerminatorJump(bi)), RegType::Void);
+        fn_b.set_bb(bi);
+        fn_b.add(I::Ret(Terminator), RegType::Void);
+        fn_b.create("main");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "builder broke main: BB0: %0 is not defined in the function")]
+    fn builder_checks_global_registers() {
+        // registers of the global block are not visible in the functions
+        let mut builder = IrBuilder::default();
+        let reg: Register = builder.add(I::Ldi(ImmI(5)), RegType::Int);
+        builder.add(I::Ret(Terminator), RegType::Void);
+        let mut fn_b = FunctionBuilder::new(0, RegType::Void, &mut builder.store);
+        fn_b.add(I::Print(Reg(reg)), RegType::Void);
+        fn_b.add(I::Ret(Terminator), RegType::Void);
+        fn_b.create("main");
+    }
 }
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use crate::ir::{BBIndex, Register, Symbol};
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum InstructionType {
     // basics
     Ldi(ImmI),
@@ -10,7 +10,7 @@ pub enum InstructionType {
     Ld(Reg),
     St(RegReg), // [addr], reg
     Alloca(ImmI),
-    Allocg(ImmI),
+    Ldg(ImmS), // address of the global symbol
     Gep(usize, RegRegImm), // [addr], index, offset
     Mov(Reg),
 
@@ -31,6 +31,10 @@ pub enum InstructionType {
     // bitwise unary
     Neg(Reg),
 
+    // the value in the register type of the instruction,
+    // int to char keeps the lowest byte, char to int zero extends
+    Conv(Reg),
+
     // comparion binary
     Lt(RegReg),
     Le(RegReg),
@@ -42,6 +46,9 @@ pub enum InstructionType {
     Call(RegRegs),
     CallDirect(SymRegs),
     Arg(ImmI),
+    // address of the variadic argument with the index, the arguments
+    // are in memory one after another, each in 8 bytes
+    VaStart(ImmI),
 
     // Terminators
     Ret(Terminator),
@@ -56,6 +63,9 @@ pub enum InstructionType {
 
     // phi node
     Phi(RegRegs),
+    // reg, reg: the value of the second is written into the first one,
+    // only out of ssa where it replaces the phi nodes
+    Copy(RegReg),
 }
 
 impl InstructionType {
@@ -64,7 +74,8 @@ impl InstructionType {
             InstructionType::Ret(_)
             | InstructionType::Retr(_)
             | InstructionType::Jmp(_)
-            | InstructionType::Branch(_) => true,
+            | InstructionType::Branch(_)
+            | InstructionType::Exit(_) => true,
             _ => false,
         }
     }
@@ -86,6 +97,7 @@ impl InstructionType {
             InstructionType::Or(RegReg(a, b)) => vec![*a, *b],
             InstructionType::Xor(RegReg(a, b)) => vec![*a, *b],
             InstructionType::Neg(Reg(a)) => vec![*a],
+            InstructionType::Conv(Reg(a)) => vec![*a],
             InstructionType::Lt(RegReg(a, b)) => vec![*a, *b],
             InstructionType::Le(RegReg(a, b)) => vec![*a, *b],
             InstructionType::Gt(RegReg(a, b)) => vec![*a, *b],
@@ -106,6 +118,7 @@ impl InstructionType {
                 regs
             }
             InstructionType::SysCall(ImmIRegs(_, regs)) => regs.clone(),
+            InstructionType::Copy(RegReg(a, b)) => vec![*a, *b],
             _ => vec![],
         }
     }
@@ -117,6 +130,7 @@ impl InstructionType {
             | InstructionType::Branch(TerminatorBranch(reg, _, _))
             | InstructionType::Retr(TerminatorReg(reg))
             | InstructionType::Neg(Reg(reg))
+            | InstructionType::Conv(Reg(reg))
             | InstructionType::Print(Reg(reg))
             | InstructionType::Ld(Reg(reg)) => {
                 if renames.contains_key(reg) {
@@ -141,6 +155,7 @@ impl InstructionType {
             | InstructionType::Ge(RegReg(a, b))
             | InstructionType::Gep(_, RegRegImm(a, b, _))
             | InstructionType::St(RegReg(a, b))
+            | InstructionType::Copy(RegReg(a, b))
             | InstructionType::Eql(RegReg(a, b)) => {
                 if renames.contains_key(a) {
                     *a = *renames.get(a).unwrap();
@@ -186,29 +201,29 @@ impl InstructionType {
 }
 
 // types of instructions
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct ImmI(pub i64);
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct ImmIRegs(pub i64, pub Vec<Register>);
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct ImmC(pub char);
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct ImmS(pub String);
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Reg(pub Register);
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct RegReg(pub Register, pub Register);
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct RegRegs(pub Register, pub Vec<Register>);
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct SymRegs(pub Symbol, pub Vec<Register>);
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct RegRegImm(pub Register, pub Register, pub i64);
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Terminator;
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct TerminatorJump(pub BBIndex);
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct TerminatorBranch(pub Register, pub BBIndex, pub BBIndex);
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct TerminatorReg(pub Register);
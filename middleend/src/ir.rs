@@ -16,6 +16,14 @@ impl InstUUID {
     pub fn val(&self) -> usize {
         self.0
     }
+
+    pub(crate) fn from_val(val: usize) -> InstUUID {
+        InstUUID(val)
+    }
+
+    pub(crate) fn shifted(&self, offset: usize) -> InstUUID {
+        InstUUID(self.0 + offset)
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -37,7 +45,7 @@ impl From<Instruction> for Register {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum RegType {
     Void,
     Int,
@@ -81,6 +89,18 @@ impl BasicBlock {
         self.predecesors.push(predecesor)
     }
 
+    pub fn remove_predecesor(&mut self, predecesor: BBIndex) {
+        self.predecesors.retain(|x| *x != predecesor)
+    }
+
+    /// the new predecessor keeps the place of the old one, so the
+    /// phi nodes keep their values in the order of the predecessors
+    pub fn replace_predecesor(&mut self, old: BBIndex, new: BBIndex) {
+        for pred in self.predecesors.iter_mut().filter(|x| **x == old) {
+            *pred = new;
+        }
+    }
+
     pub fn pred(&self) -> Vec<BBIndex> {
         self.predecesors.clone()
     }
@@ -209,11 +229,22 @@ impl InstStore {
     }
 }
 
+/// Global variable defined in the program, the data
+/// are the initial value of the memory
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct GlobalVar {
+    pub name: Symbol,
+    pub data: Vec<u8>,
+    // the program never writes into it
+    pub read_only: bool,
+}
+
 #[derive(Debug)]
 pub struct IrProgram {
     pub store: InstStore,
     pub glob: Function,
     pub funcs: HashMap<String, Function>,
+    pub globals: Vec<GlobalVar>,
 }
 
 impl Default for IrProgram {
@@ -227,6 +258,7 @@ impl Default for IrProgram {
                 blocks: vec![],
             },
             funcs: HashMap::new(),
+            globals: vec![],
         }
     }
 }
@@ -2,7 +2,7 @@ use std::fmt::Display;
 
 use crate::{
     inst::{
-        ImmC, ImmI, ImmIRegs, InstructionType, Reg, RegReg, RegRegImm, RegRegs, SymRegs,
+        ImmC, ImmI, ImmIRegs, ImmS, InstructionType, Reg, RegReg, RegRegImm, RegRegs, SymRegs,
         TerminatorBranch, TerminatorJump, TerminatorReg,
     },
     ir::{BasicBlock, Function, InstStore, Instruction, IrProgram, RegType, Register},
@@ -13,17 +13,24 @@ fn reg_view(reg: Register) -> String {
     format!("%{id}")
 }
 
+fn regs_view(regs: &[Register]) -> String {
+    regs.iter()
+        .map(|x| reg_view(*x))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 impl Display for InstructionType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             InstructionType::Ldi(ImmI(n)) => write!(f, "ldi {}", n),
-            InstructionType::Ldc(ImmC(n)) => write!(f, "ldc {}", n),
+            InstructionType::Ldc(ImmC(n)) => write!(f, "ldc {:?}", n),
             InstructionType::Ld(Reg(reg)) => write!(f, "ld [{}]", reg_view(*reg)),
             InstructionType::St(RegReg(addr, val)) => {
                 write!(f, "store [{}] {}", reg_view(*addr), reg_view(*val))
             }
             InstructionType::Alloca(ImmI(n)) => write!(f, "alloca {}", n),
-            InstructionType::Allocg(_) => todo!(),
+            InstructionType::Ldg(ImmS(name)) => write!(f, "ldg @{}", name),
             InstructionType::Mov(Reg(reg)) => write!(f, "mov {}", reg_view(*reg)),
             InstructionType::Gep(size, RegRegImm(start, index, imm)) => write!(
                 f,
@@ -62,6 +69,7 @@ impl Display for InstructionType {
                 write!(f, "xor {} {}", reg_view(*l), reg_view(*r))
             }
             InstructionType::Neg(Reg(reg)) => write!(f, "neg {}", reg_view(*reg)),
+            InstructionType::Conv(Reg(reg)) => write!(f, "conv {}", reg_view(*reg)),
             InstructionType::Lt(RegReg(l, r)) => write!(f, "lt {} {}", reg_view(*l), reg_view(*r)),
             InstructionType::Le(RegReg(l, r)) => write!(f, "le {} {}", reg_view(*l), reg_view(*r)),
             InstructionType::Gt(RegReg(l, r)) => write!(f, "gt {} {}", reg_view(*l), reg_view(*r)),
@@ -69,40 +77,31 @@ impl Display for InstructionType {
             InstructionType::Eql(RegReg(l, r)) => {
                 write!(f, "eql {} {}", reg_view(*l), reg_view(*r))
             }
-            InstructionType::Call(RegRegs(reg, regs)) => write!(
-                f,
-                "call {} [{}]",
-                reg_view(*reg),
-                regs.into_iter()
-                    .map(|x| reg_view(*x))
-                    .fold("".to_string(), |acc, x| acc + x.as_str())
-            ),
-            InstructionType::CallDirect(SymRegs(sym, regs)) => write!(
-                f,
-                "calldirect {} [{}]",
-                sym,
-                regs.into_iter()
-                    .map(|x| reg_view(*x))
-                    .fold("".to_string(), |acc, x| acc + " " + x.as_str()) + " "
-            ),
+            InstructionType::Call(RegRegs(reg, regs)) => {
+                write!(f, "call {} [{}]", reg_view(*reg), regs_view(regs))
+            }
+            InstructionType::CallDirect(SymRegs(sym, regs)) => {
+                write!(f, "calldirect {} [{}]", sym, regs_view(regs))
+            }
             InstructionType::Arg(ImmI(index)) => write!(f, "arg {}", index),
+            InstructionType::VaStart(ImmI(index)) => write!(f, "va_start {}", index),
             InstructionType::Ret(_) => write!(f, "ret"),
             InstructionType::Retr(TerminatorReg(reg)) => write!(f, "retr {}", reg_view(*reg)),
             InstructionType::Jmp(TerminatorJump(to)) => write!(f, "jmp BB{}", to),
             InstructionType::Branch(TerminatorBranch(reg, true_bb, false_bb)) => {
                 write!(f, "branch {} BB{} BB{}", reg_view(*reg), true_bb, false_bb)
             }
-            InstructionType::Print(_) => todo!(),
-            InstructionType::Phi(_) => todo!(),
+            InstructionType::Print(Reg(reg)) => write!(f, "print {}", reg_view(*reg)),
+            InstructionType::Phi(RegRegs(reg, regs)) => {
+                write!(f, "phi {} [{}]", reg_view(*reg), regs_view(regs))
+            }
+            InstructionType::Copy(RegReg(to, from)) => {
+                write!(f, "copy {} {}", reg_view(*to), reg_view(*from))
+            }
             InstructionType::Exit(_) => write!(f, "exit"),
-            InstructionType::SysCall(ImmIRegs(num, regs)) => write!(
-                f,
-                "syscall {} [{}]",
-                num,
-                regs.into_iter()
-                    .map(|x| reg_view(*x))
-                    .fold("".to_string(), |acc, x| acc + x.as_str())
-            ),
+            InstructionType::SysCall(ImmIRegs(num, regs)) => {
+                write!(f, "syscall {} [{}]", num, regs_view(regs))
+            }
         }
     }
 }
@@ -164,9 +163,16 @@ impl Function {
 
 impl Display for IrProgram {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for global in self.globals.iter() {
+            let kind = if global.read_only { "const " } else { "" };
+            writeln!(f, "@{} = {}{:?}", global.name, kind, global.data)?;
+        }
         writeln!(f, "global:")?;
         self.glob.display(f, &self.store)?;
-        for func in self.funcs.values() {
+        // sorted so the text of the same program is always the same
+        let mut funcs: Vec<&Function> = self.funcs.values().collect();
+        funcs.sort_by(|a, b| a.name.cmp(&b.name));
+        for func in funcs {
             writeln!(f, "")?;
             func.display(f, &self.store)?;
         }
@@ -1,11 +1,17 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    io::{Read, Write},
+};
 
 use crate::{
     inst::{
-        ImmC, ImmI, ImmIRegs, InstructionType, Reg, RegReg, RegRegImm, SymRegs, TerminatorBranch,
+        ImmC, ImmI, ImmIRegs, ImmS, InstructionType, Reg, RegReg, RegRegImm, SymRegs, TerminatorBranch,
         TerminatorJump, TerminatorReg,
     },
-    ir::{BBIndex, BasicBlock, Function, Instruction, IrProgram, RegType, Register},
+    ir::{BBIndex, BasicBlock, Function, Instruction, IrProgram, RegType, Register, Symbol},
+    optimalizations::out_of_ssa::destruct_ssa,
+    syscall::{Syscall, Target},
 };
 
 #[derive(Debug)]
@@ -19,16 +25,89 @@ pub enum InterpretError {
     DoubleWrite(Register),
     InvalidOp(Instruction),
     NoMain,
+    UndefinedSymbol(Symbol),
     WrongMainReturn,
     BasicBlockConti,
+    // syscall with wrong arguments or failed output
+    InvalidSyscall(i64),
+    // the program called exit, [`run`] returns the code
+    Exit(i64),
+    // freeing memory which was already freed
+    DoubleFree(Value),
+    // freeing memory which was not allocated by the heap intrinsics
+    InvalidFree(Value),
     Unknown,
 }
 
+impl Display for InterpretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpretError::VoidRegister(inst) => write!(f, "void register used by {}", inst),
+            InterpretError::InvalidAddress(addr) => {
+                write!(f, "invalid address 0x{:x}", Addr::from(*addr))
+            }
+            InterpretError::OutOfBoundRead(addr) => {
+                write!(f, "read out of the memory at 0x{:x}", Addr::from(*addr))
+            }
+            InterpretError::InvalidCond(val) => write!(f, "invalid branch condition {}", val),
+            InterpretError::OutOfBoundWrite => write!(f, "write out of the memory"),
+            InterpretError::NonExistingRead(reg) => write!(f, "read of undefined {:?}", reg),
+            InterpretError::DoubleWrite(reg) => write!(f, "second write of {:?}", reg),
+            InterpretError::InvalidOp(inst) => write!(f, "invalid operation {}", inst),
+            InterpretError::NoMain => write!(f, "main function not found"),
+            InterpretError::UndefinedSymbol(sym) => write!(f, "undefined symbol {}", sym),
+            InterpretError::WrongMainReturn => write!(f, "main returned a non integer value"),
+            InterpretError::BasicBlockConti => write!(f, "basic block without terminator"),
+            InterpretError::InvalidSyscall(num) => write!(f, "invalid syscall {}", num),
+            InterpretError::Exit(code) => write!(f, "exit with code {}", code),
+            InterpretError::DoubleFree(ptr) => {
+                write!(f, "free: double free of 0x{:x}", Addr::from(*ptr))
+            }
+            InterpretError::InvalidFree(ptr) => {
+                write!(f, "free: invalid free of 0x{:x}", Addr::from(*ptr))
+            }
+            InterpretError::Unknown => write!(f, "unknown error"),
+        }
+    }
+}
+
 pub fn run(program: IrProgram) -> Result<i64, InterpretError> {
-    let mut inter = Interpret::new(program, 4096 * 4096);
-    inter.run()
+    run_with_output(program, &mut std::io::stdout())
+}
+
+/// Same as [`run`], the standard output of the program is written into `out`
+pub fn run_with_output(program: IrProgram, out: &mut dyn Write) -> Result<i64, InterpretError> {
+    run_with_args(program, &[], out)
+}
+
+/// Same as [`run_with_output`], main gets the `args` as argc and argv
+/// followed by the environment of the interpreter as envp
+pub fn run_with_args(
+    program: IrProgram,
+    args: &[String],
+    out: &mut dyn Write,
+) -> Result<i64, InterpretError> {
+    let env: Vec<String> = std::env::vars()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect();
+    let mut inter = Interpret::new(program, 4096 * 4096, out);
+    inter.run(args, &env)
+}
+
+/// Same as [`run_with_output`], but the heap functions of the linked
+/// runtime are interpreted instead of the heap intrinsics
+pub fn run_with_runtime_heap(
+    program: IrProgram,
+    out: &mut dyn Write,
+) -> Result<i64, InterpretError> {
+    let mut inter = Interpret::new(program, 4096 * 4096, out);
+    inter.heap_intrinsics = false;
+    inter.run(&[], &[])
 }
 
+/// functions of the runtime replaced by the interpreter
+pub const HEAP_INTRINSICS: [&str; 4] = ["malloc", "calloc", "realloc", "free"];
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Value {
     Signed(i64),
@@ -58,13 +137,24 @@ impl From<Value> for Addr {
 type Env = HashMap<Register, Value>;
 type Args = Vec<Value>;
 
+// allocas are in the first half of the heap, the second half
+// is split between the program break and the mmaped pages
 struct Memory {
     stack_size: usize,
     stack: Vec<u8>,
     sp: usize,
     heap: Vec<u8>,
+    brk: usize,
+    mmap: usize,
+    // blocks of the heap intrinsics with their requested sizes
+    blocks: HashMap<Addr, usize>,
+    freed: HashSet<Addr>,
 }
 
+const PAGE_SIZE: usize = 4096;
+// -ENOMEM returned by the failed mmap
+const ENOMEM: i64 = -12;
+
 impl Memory {
     fn new(stack_size: usize) -> Self {
         let mut stack = vec![];
@@ -76,6 +166,10 @@ impl Memory {
             sp: 0,
             stack,
             heap,
+            brk: stack_size * 2,
+            mmap: stack_size * 3,
+            blocks: HashMap::new(),
+            freed: HashSet::new(),
         }
     }
 
@@ -162,32 +256,121 @@ impl Memory {
         if self.sp + amount as usize > self.stack_size {
             return Err(InterpretError::OutOfBoundWrite);
         }
-        let res = self.stack.len();
-        for _ in 0..amount {
-            self.stack.push(0);
-        }
+        let res = self.stack_size + self.sp;
+        self.sp += amount as usize;
         Ok(Value::Signed(res as i64))
     }
+
+    /// moves the program break, the current break is
+    /// returned when the new one is out of the heap
+    fn set_brk(&mut self, addr: Addr) -> Value {
+        if addr >= self.stack_size * 2 && addr <= self.mmap {
+            self.brk = addr;
+        }
+        Value::Signed(self.brk as i64)
+    }
+
+    /// zeroed memory from the end of the heap, it is never returned
+    fn reserve(&mut self, len: usize) -> Option<Addr> {
+        if self.mmap - self.brk < len {
+            return None;
+        }
+        self.mmap -= len;
+        self.heap[self.mmap - self.stack_size..][..len].fill(0);
+        Some(self.mmap)
+    }
+
+    fn map(&mut self, len: usize) -> Value {
+        let len = len.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        match self.reserve(len) {
+            Some(addr) if len > 0 => Value::Signed(addr as i64),
+            _ => Value::Signed(ENOMEM),
+        }
+    }
+
+    /// the freed blocks are not reused, so every double free is found
+    fn malloc(&mut self, size: i64) -> Value {
+        if size <= 0 {
+            return Value::Signed(0);
+        }
+        let len = (size as usize).div_ceil(16) * 16;
+        match self.reserve(len) {
+            Some(addr) => {
+                self.blocks.insert(addr, size as usize);
+                Value::Signed(addr as i64)
+            }
+            None => Value::Signed(0),
+        }
+    }
+
+    fn block_size(&self, ptr: Value) -> Result<usize, InterpretError> {
+        let addr: Addr = ptr.into();
+        match self.blocks.get(&addr) {
+            Some(size) => Ok(*size),
+            None if self.freed.contains(&addr) => Err(InterpretError::DoubleFree(ptr)),
+            None => Err(InterpretError::InvalidFree(ptr)),
+        }
+    }
+
+    fn free(&mut self, ptr: Value) -> Result<(), InterpretError> {
+        let addr: Addr = ptr.into();
+        if addr != 0 {
+            self.block_size(ptr)?;
+            self.blocks.remove(&addr);
+            self.freed.insert(addr);
+        }
+        Ok(())
+    }
+
+    fn realloc(&mut self, ptr: Value, size: i64) -> Result<Value, InterpretError> {
+        let from: Addr = ptr.into();
+        if from == 0 {
+            return Ok(self.malloc(size));
+        }
+        let old_size = self.block_size(ptr)?;
+        let result = self.malloc(size);
+        let to: Addr = result.into();
+        if to != 0 {
+            let len = old_size.min(size as usize);
+            let start = from - self.stack_size;
+            self.heap.copy_within(start..start + len, to - self.stack_size);
+        }
+        // the old block stays when there is no memory for the new one
+        if to != 0 || size <= 0 {
+            self.free(ptr)?;
+        }
+        Ok(result)
+    }
 }
 
-struct Interpret {
+struct Interpret<'a> {
     mem: Memory,
     globals: Env,
+    // addresses of the global variables
+    symbols: HashMap<Symbol, Value>,
     locals: Vec<Env>,
     args: Vec<Args>,
     program: IrProgram,
     rev_val: Option<Value>,
+    out: &'a mut dyn Write,
+    // the heap functions are handled by the interpreter
+    heap_intrinsics: bool,
 }
 
-impl Interpret {
-    fn new(program: IrProgram, stack_size: usize) -> Self {
+impl<'a> Interpret<'a> {
+    fn new(program: IrProgram, stack_size: usize, out: &'a mut dyn Write) -> Self {
+        let mut program = program;
+        destruct_ssa(&mut program);
         Self {
             mem: Memory::new(stack_size),
             globals: HashMap::new(),
+            symbols: HashMap::new(),
             locals: vec![],
             args: vec![],
             program,
             rev_val: None,
+            out,
+            heap_intrinsics: true,
         }
     }
 
@@ -218,15 +401,119 @@ impl Interpret {
         Err(InterpretError::NonExistingRead(reg))
     }
 
-    fn run(&mut self) -> Result<i64, InterpretError> {
+    fn init_globals(&mut self) -> Result<(), InterpretError> {
+        for global in self.program.globals.clone() {
+            let addr = self.mem.alloca(global.data.len() as i64)?;
+            let start: Addr = addr.into();
+            for (i, byte) in global.data.iter().enumerate() {
+                self.mem.write_char(start + i, *byte)?;
+            }
+            self.symbols.insert(global.name, addr);
+        }
+        Ok(())
+    }
+
+    fn read_bytes(&self, start: Addr, len: usize) -> Result<Vec<u8>, InterpretError> {
+        let mut bytes = vec![];
+        for i in 0..len {
+            match self.mem.read_char(Value::Signed((start + i) as i64))? {
+                Value::Char(c) => bytes.push(c),
+                Value::Signed(_) => unreachable!(),
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// the syscalls are decoded by the riscv numbers, only the standard
+    /// input and output are supported, unknown syscalls are just printed
+    fn syscall(&mut self, number: i64, args: &[Value]) -> Result<Value, InterpretError> {
+        let syscall = Syscall::from_number(number, Target::Riscv64Linux);
+        match (syscall, args) {
+            (Some(Syscall::Write), &[fd, buf, len]) => {
+                let bytes = self.read_bytes(buf.into(), len.into())?;
+                let result = match Addr::from(fd) {
+                    1 => self.out.write_all(&bytes),
+                    2 => std::io::stderr().write_all(&bytes),
+                    _ => return Err(InterpretError::InvalidSyscall(number)),
+                };
+                result.map_err(|_| InterpretError::InvalidSyscall(number))?;
+                Ok(Value::Signed(bytes.len() as i64))
+            }
+            (Some(Syscall::Read), &[fd, buf, len]) => {
+                if Addr::from(fd) != 0 {
+                    return Err(InterpretError::InvalidSyscall(number));
+                }
+                let mut bytes = vec![0; len.into()];
+                let read = std::io::stdin()
+                    .read(&mut bytes)
+                    .map_err(|_| InterpretError::InvalidSyscall(number))?;
+                let start: Addr = buf.into();
+                for (i, byte) in bytes[..read].iter().enumerate() {
+                    self.mem.write_char(start + i, *byte)?;
+                }
+                Ok(Value::Signed(read as i64))
+            }
+            (Some(Syscall::Exit), &[Value::Signed(code)]) => Err(InterpretError::Exit(code)),
+            (Some(Syscall::Brk), &[addr]) => Ok(self.mem.set_brk(addr.into())),
+            (Some(Syscall::Mmap), &[_, len, _, _, _, _]) => Ok(self.mem.map(len.into())),
+            (Some(_), _) => Err(InterpretError::InvalidSyscall(number)),
+            (None, _) => {
+                println!("syscall {number}, {:?}", args);
+                Ok(Value::Signed(0))
+            }
+        }
+    }
+
+    fn heap_call(&mut self, name: &str, args: &[Value]) -> Result<Option<Value>, InterpretError> {
+        let value = match (name, args) {
+            ("malloc", &[Value::Signed(size)]) => self.mem.malloc(size),
+            ("calloc", &[Value::Signed(count), Value::Signed(size)]) => {
+                self.mem.malloc(count * size)
+            }
+            ("realloc", &[ptr, Value::Signed(size)]) => self.mem.realloc(ptr, size)?,
+            ("free", &[ptr]) => {
+                self.mem.free(ptr)?;
+                return Ok(None);
+            }
+            _ => return Err(InterpretError::UndefinedSymbol(name.to_string())),
+        };
+        Ok(Some(value))
+    }
+
+    /// null terminated array of the strings, like argv
+    fn string_array(&mut self, strings: &[String]) -> Result<Value, InterpretError> {
+        let mut pointers = vec![];
+        for string in strings.iter().map(|x| x.as_bytes()) {
+            let start: Addr = self.mem.alloca(string.len() as i64 + 1)?.into();
+            for (i, byte) in string.iter().enumerate() {
+                self.mem.write_char(start + i, *byte)?;
+            }
+            self.mem.write_char(start + string.len(), 0)?;
+            pointers.push(start as i64);
+        }
+        pointers.push(0);
+        let array: Addr = self.mem.alloca(8 * pointers.len() as i64)?.into();
+        for (i, pointer) in pointers.iter().enumerate() {
+            self.mem.write_int(array + 8 * i, *pointer)?;
+        }
+        Ok(Value::Signed(array as i64))
+    }
+
+    fn run(&mut self, args: &[String], env: &[String]) -> Result<i64, InterpretError> {
+        self.init_globals()?;
         let glob_block = self.program.glob.clone();
         self.run_func(glob_block, vec![])?;
         let main = match self.program.funcs.get(&"main".to_string()) {
             Some(x) => Ok(x.clone()),
             None => Err(InterpretError::NoMain),
         }?;
-        //self.locals.push(HashMap::new());
-        self.run_func(main.clone(), vec![])?;
+        let argv = self.string_array(args)?;
+        let envp = self.string_array(env)?;
+        let main_args = vec![Value::Signed(args.len() as i64), argv, envp];
+        match self.run_func(main.clone(), main_args) {
+            Err(InterpretError::Exit(code)) => return Ok(code),
+            result => result?,
+        };
         match self.rev_val {
             Some(Value::Signed(val)) => Ok(val),
             None => Ok(0),
@@ -241,6 +528,8 @@ impl Interpret {
     ) -> Result<Option<Value>, InterpretError> {
         self.locals.push(HashMap::new());
         self.args.push(args);
+        // the allocas of the function are freed when it returns
+        let sp = self.mem.sp;
         let mut act = func.start();
         loop {
             let index = self.run_basicblock(act)?;
@@ -250,6 +539,7 @@ impl Interpret {
                 break;
             }
         }
+        self.mem.sp = sp;
         self.args.pop();
         self.locals.pop();
         Ok(self.rev_val)
@@ -322,10 +612,14 @@ impl Interpret {
                     let value = self.get(*reg_source)?;
                     self.mem.write(addr_val, value)?;
                 }
-                InstructionType::Alloca(ImmI(imm)) | InstructionType::Allocg(ImmI(imm)) => {
+                InstructionType::Alloca(ImmI(imm)) => {
                     let addr = self.mem.alloca(*imm)?;
                     self.set(inst_id, addr)?
                 }
+                InstructionType::Ldg(ImmS(name)) => match self.symbols.get(name) {
+                    Some(addr) => self.set(inst_id, *addr)?,
+                    None => return Err(InterpretError::UndefinedSymbol(name.clone())),
+                },
                 InstructionType::Mov(Reg(reg)) => {
                     let val = self.get(*reg)?;
                     self.set(inst_id, val)?
@@ -381,6 +675,14 @@ impl Interpret {
                     };
                     self.set(inst_id, val)?;
                 }
+                InstructionType::Conv(Reg(reg)) => {
+                    let val = match (self.get(*reg)?, tmp_inst.reg_type) {
+                        (Value::Signed(x), RegType::Char) => Value::Char(x as u8),
+                        (Value::Char(x), RegType::Int) => Value::Signed(x as i64),
+                        (val, _) => val,
+                    };
+                    self.set(inst_id, val)?;
+                }
                 InstructionType::Lt(regs) => {
                     self.logic_bin_op(&tmp_inst, *regs, &|a, b| a < b, &|a, b| a < b)?
                 }
@@ -402,8 +704,15 @@ impl Interpret {
                     for reg in regs {
                         vals.push(self.get(*reg)?);
                     }
-                    let func = self.program.funcs.get(sym).unwrap();
-                    let res = self.run_func(func.clone(), vals)?;
+                    let res = if self.heap_intrinsics && HEAP_INTRINSICS.contains(&sym.as_str()) {
+                        self.heap_call(sym, &vals)?
+                    } else {
+                        let func = match self.program.funcs.get(sym) {
+                            Some(func) => func.clone(),
+                            None => return Err(InterpretError::UndefinedSymbol(sym.clone())),
+                        };
+                        self.run_func(func, vals)?
+                    };
                     match res {
                         Some(value) => self.set(inst_id, value)?,
                         None => (),
@@ -413,6 +722,15 @@ impl Interpret {
                     let val = self.args.last().unwrap()[*index as usize];
                     self.set(inst_id, val)?;
                 }
+                InstructionType::VaStart(ImmI(index)) => {
+                    // all arguments are copied into memory, each has 8 bytes
+                    let args = self.args.last().unwrap().clone();
+                    let start: Addr = self.mem.alloca(8 * args.len() as i64)?.into();
+                    for (i, arg) in args.iter().enumerate() {
+                        self.mem.write(Value::Signed((start + 8 * i) as i64), *arg)?;
+                    }
+                    self.set(inst_id, Value::Signed((start + 8 * *index as usize) as i64))?;
+                }
                 InstructionType::Ret(_) | InstructionType::Exit(_) => {
                     terminated = true;
                     next = None;
@@ -437,16 +755,23 @@ impl Interpret {
                 }
                 InstructionType::Print(Reg(reg)) => {
                     let val = self.get(*reg)?;
-                    print!("{}", val);
+                    write!(self.out, "{}", val).map_err(|_| InterpretError::Unknown)?;
+                }
+                // the phi nodes were replaced by the copies in `new`
+                InstructionType::Phi(_) => return Err(InterpretError::InvalidOp(tmp_inst)),
+                InstructionType::Copy(RegReg(to, from)) => {
+                    let val = self.get(*from)?;
+                    self.set(*to, val)?
                 }
-                InstructionType::Phi(_) => todo!(),
                 InstructionType::SysCall(ImmIRegs(imm, regs)) => {
-                    println!(
-                        "syscall {imm}, {:?}",
-                        regs.iter()
-                            .map(|x| self.get(*x).unwrap())
-                            .collect::<Vec<Value>>()
-                    );
+                    let mut args = vec![];
+                    for reg in regs {
+                        args.push(self.get(*reg)?);
+                    }
+                    let val = self.syscall(*imm, &args)?;
+                    if tmp_inst.reg_type != RegType::Void {
+                        self.set(inst_id, val)?;
+                    }
                 }
             }
         }
@@ -477,7 +802,41 @@ mod tests {
         let f = fn_b.create("main");
         builder.add_fn(f).unwrap();
 
-        let mut inter = Interpret::new(builder.create(), 1024);
-        inter.run().unwrap();
+        let mut out = vec![];
+        let mut inter = Interpret::new(builder.create(), 1024, &mut out);
+        inter.run(&[], &[]).unwrap();
+    }
+
+    fn free_error(frees: &str) -> InterpretError {
+        let text = format!(
+            "global:
+function global(0) : void {{
+BB0:
+exit
+}}
+function main(0) : int {{
+BB0:
+%0 : int = ldi 8
+%1 : int = calldirect malloc [%0]
+{frees}
+retr %0
+}}"
+        );
+        let program = crate::ir_parse::parse_ir(&text).unwrap();
+        run_with_output(program, &mut vec![]).unwrap_err()
+    }
+
+    #[test]
+    fn double_free() {
+        let error = free_error("calldirect free [%1]\ncalldirect free [%1]");
+        assert!(matches!(error, InterpretError::DoubleFree(_)));
+        assert!(error.to_string().starts_with("free: double free of 0x"));
+    }
+
+    #[test]
+    fn invalid_free() {
+        let error = free_error("%2 : int = add %1 %0\ncalldirect free [%2]");
+        assert!(matches!(error, InterpretError::InvalidFree(_)));
+        assert!(error.to_string().starts_with("free: invalid free of 0x"));
     }
 }
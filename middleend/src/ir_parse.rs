@@ -0,0 +1,564 @@
+//! Reads the text printed by [`ir_display`](crate::ir_display) back into
+//! the [`IrProgram`]. The registers keep their numbers, the instructions
+//! without result get the unused ones and the predecessors are computed
+//! from the terminators.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    inst::{
+        ImmC, ImmI, ImmIRegs, ImmS, InstructionType, Reg, RegReg, RegRegImm, RegRegs, SymRegs,
+        Terminator, TerminatorBranch, TerminatorJump, TerminatorReg,
+    },
+    ir::{BBIndex, BasicBlock, Function, GlobalVar, InstStore, IrProgram, RegType, Register},
+};
+
+/// The line of the error is counted from 1
+#[derive(Debug, PartialEq, Eq)]
+pub enum IrParseError {
+    Syntax(usize, String),
+    UnknownInstruction(usize, String),
+    RegisterRedef(usize, usize),
+    UndefinedRegister(usize, usize),
+    UnknownBlock(usize, BBIndex),
+}
+
+impl std::fmt::Display for IrParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IrParseError::Syntax(line, text) => write!(f, "{}: unexpected `{}`", line, text),
+            IrParseError::UnknownInstruction(line, name) => {
+                write!(f, "{}: unknown instruction `{}`", line, name)
+            }
+            IrParseError::RegisterRedef(line, reg) => {
+                write!(f, "{}: register %{} is already defined", line, reg)
+            }
+            IrParseError::UndefinedRegister(line, reg) => {
+                write!(f, "{}: register %{} is not defined", line, reg)
+            }
+            IrParseError::UnknownBlock(line, bb) => {
+                write!(f, "{}: block BB{} does not exist", line, bb)
+            }
+        }
+    }
+}
+
+/// instruction before it gets its place in the store
+struct Parsed {
+    line: usize,
+    id: Option<usize>,
+    reg_type: RegType,
+    data: InstructionType,
+}
+
+/// instructions of the block are the indexes into the parsed ones
+struct ParsedFunction {
+    function: Function,
+    blocks: Vec<Vec<usize>>,
+}
+
+/// rest of the line, the tokens are separated by the whitespace
+struct Cursor<'a> {
+    line: usize,
+    rest: &'a str,
+}
+
+impl<'a> Cursor<'a> {
+    fn error(&self) -> IrParseError {
+        IrParseError::Syntax(self.line, self.rest.to_string())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), IrParseError> {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(text) {
+            Some(rest) => {
+                self.rest = rest;
+                Ok(())
+            }
+            None => Err(self.error()),
+        }
+    }
+
+    fn peek(&mut self, text: &str) -> bool {
+        self.rest = self.rest.trim_start();
+        self.rest.starts_with(text)
+    }
+
+    /// characters until the whitespace or one of the delimiters
+    fn word(&mut self, delimiters: &[char]) -> Result<&'a str, IrParseError> {
+        self.rest = self.rest.trim_start();
+        let end = self
+            .rest
+            .find(|c: char| c.is_whitespace() || delimiters.contains(&c))
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            return Err(self.error());
+        }
+        let (word, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Ok(word)
+    }
+
+    fn number<T: std::str::FromStr>(&mut self, delimiters: &[char]) -> Result<T, IrParseError> {
+        let word = self.word(delimiters)?;
+        word.parse()
+            .map_err(|_| IrParseError::Syntax(self.line, word.to_string()))
+    }
+
+    fn reg(&mut self) -> Result<usize, IrParseError> {
+        self.expect("%")?;
+        self.number(&[']', '%'])
+    }
+
+    fn bb(&mut self) -> Result<BBIndex, IrParseError> {
+        self.expect("BB")?;
+        self.number(&[':'])
+    }
+
+    /// registers in the brackets `[%1 %2]`
+    fn regs(&mut self) -> Result<Vec<usize>, IrParseError> {
+        self.expect("[")?;
+        let mut regs = vec![];
+        while !self.peek("]") {
+            regs.push(self.reg()?);
+        }
+        self.expect("]")?;
+        Ok(regs)
+    }
+
+    /// char in the quotes with the escapes of the rust debug output
+    fn char(&mut self) -> Result<char, IrParseError> {
+        self.expect("'")?;
+        let mut chars = self.rest.char_indices();
+        let c = match chars.next() {
+            Some((_, '\\')) => match chars.next() {
+                Some((_, 'n')) => '\n',
+                Some((_, 't')) => '\t',
+                Some((_, 'r')) => '\r',
+                Some((_, '0')) => '\0',
+                Some((_, c @ ('\\' | '\'' | '"'))) => c,
+                Some((_, 'u')) => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or_else(|| self.error())?;
+                    let code = rest
+                        .get(1..end)
+                        .and_then(|x| u32::from_str_radix(x, 16).ok())
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| self.error())?;
+                    chars = rest[end + 1..].char_indices();
+                    code
+                }
+                _ => return Err(self.error()),
+            },
+            Some((_, c)) => c,
+            None => return Err(self.error()),
+        };
+        self.rest = chars.as_str();
+        self.expect("'")?;
+        Ok(c)
+    }
+
+    fn end(&mut self) -> Result<(), IrParseError> {
+        if self.rest.trim().is_empty() {
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+}
+
+fn reg_type(cursor: &mut Cursor) -> Result<RegType, IrParseError> {
+    match cursor.word(&['{'])? {
+        "void" => Ok(RegType::Void),
+        "int" => Ok(RegType::Int),
+        "char" => Ok(RegType::Char),
+        _ => Err(cursor.error()),
+    }
+}
+
+fn instruction(cursor: &mut Cursor) -> Result<InstructionType, IrParseError> {
+    type I = InstructionType;
+    let name = cursor.word(&[])?;
+    let r = |x: usize| Register::from_val(x);
+    let binary = |cursor: &mut Cursor| -> Result<RegReg, IrParseError> {
+        Ok(RegReg(r(cursor.reg()?), r(cursor.reg()?)))
+    };
+    let regs = |cursor: &mut Cursor| -> Result<Vec<Register>, IrParseError> {
+        Ok(cursor.regs()?.into_iter().map(r).collect())
+    };
+    let inst = match name {
+        "ldi" => I::Ldi(ImmI(cursor.number(&[])?)),
+        "ldc" => I::Ldc(ImmC(cursor.char()?)),
+        "ld" => {
+            cursor.expect("[")?;
+            let addr = cursor.reg()?;
+            cursor.expect("]")?;
+            I::Ld(Reg(r(addr)))
+        }
+        "store" => {
+            cursor.expect("[")?;
+            let addr = cursor.reg()?;
+            cursor.expect("]")?;
+            I::St(RegReg(r(addr), r(cursor.reg()?)))
+        }
+        "alloca" => I::Alloca(ImmI(cursor.number(&[])?)),
+        "ldg" => {
+            cursor.expect("@")?;
+            I::Ldg(ImmS(cursor.word(&[])?.to_string()))
+        }
+        "mov" => I::Mov(Reg(r(cursor.reg()?))),
+        "gep" => {
+            cursor.expect("<")?;
+            let size = cursor.number(&['>'])?;
+            cursor.expect(">")?;
+            cursor.expect("[")?;
+            let start = cursor.reg()?;
+            cursor.expect("]")?;
+            let index = cursor.reg()?;
+            I::Gep(size, RegRegImm(r(start), r(index), cursor.number(&[])?))
+        }
+        "add" => I::Add(binary(cursor)?),
+        "sub" => I::Sub(binary(cursor)?),
+        "mul" => I::Mul(binary(cursor)?),
+        "div" => I::Div(binary(cursor)?),
+        "mod" => I::Mod(binary(cursor)?),
+        "shr" => I::Shr(binary(cursor)?),
+        "shl" => I::Shl(binary(cursor)?),
+        "and" => I::And(binary(cursor)?),
+        "or" => I::Or(binary(cursor)?),
+        "xor" => I::Xor(binary(cursor)?),
+        "neg" => I::Neg(Reg(r(cursor.reg()?))),
+        "conv" => I::Conv(Reg(r(cursor.reg()?))),
+        "lt" => I::Lt(binary(cursor)?),
+        "le" => I::Le(binary(cursor)?),
+        "gt" => I::Gt(binary(cursor)?),
+        "ge" => I::Ge(binary(cursor)?),
+        "eql" => I::Eql(binary(cursor)?),
+        "call" => I::Call(RegRegs(r(cursor.reg()?), regs(cursor)?)),
+        "calldirect" => {
+            let sym = cursor.word(&['['])?.to_string();
+            I::CallDirect(SymRegs(sym, regs(cursor)?))
+        }
+        "arg" => I::Arg(ImmI(cursor.number(&[])?)),
+        "va_start" => I::VaStart(ImmI(cursor.number(&[])?)),
+        "ret" => I::Ret(Terminator),
+        "exit" => I::Exit(Terminator),
+        "retr" => I::Retr(TerminatorReg(r(cursor.reg()?))),
+        "jmp" => I::Jmp(TerminatorJump(cursor.bb()?)),
+        "branch" => {
+            let cond = cursor.reg()?;
+            I::Branch(TerminatorBranch(r(cond), cursor.bb()?, cursor.bb()?))
+        }
+        "print" => I::Print(Reg(r(cursor.reg()?))),
+        "phi" => I::Phi(RegRegs(r(cursor.reg()?), regs(cursor)?)),
+        "copy" => I::Copy(binary(cursor)?),
+        "syscall" => {
+            let number = cursor.number(&['['])?;
+            I::SysCall(ImmIRegs(number, regs(cursor)?))
+        }
+        _ => {
+            return Err(IrParseError::UnknownInstruction(
+                cursor.line,
+                name.to_string(),
+            ))
+        }
+    };
+    cursor.end()?;
+    Ok(inst)
+}
+
+/// `%1 : int = add %2 %3` or just `store [%1] %2` without the result
+fn parsed_instruction(cursor: &mut Cursor) -> Result<Parsed, IrParseError> {
+    let line = cursor.line;
+    if !cursor.peek("%") {
+        let data = instruction(cursor)?;
+        return Ok(Parsed {
+            line,
+            id: None,
+            reg_type: RegType::Void,
+            data,
+        });
+    }
+    let id = cursor.reg()?;
+    cursor.expect(":")?;
+    let reg_type = reg_type(cursor)?;
+    cursor.expect("=")?;
+    let data = instruction(cursor)?;
+    Ok(Parsed {
+        line,
+        id: Some(id),
+        reg_type,
+        data,
+    })
+}
+
+/// `@name = const [1, 2]`
+fn global(cursor: &mut Cursor) -> Result<GlobalVar, IrParseError> {
+    cursor.expect("@")?;
+    let name = cursor.word(&['='])?.to_string();
+    cursor.expect("=")?;
+    let read_only = cursor.peek("const");
+    if read_only {
+        cursor.expect("const")?;
+    }
+    cursor.expect("[")?;
+    let mut data = vec![];
+    while !cursor.peek("]") {
+        data.push(cursor.number(&[',', ']'])?);
+        if !cursor.peek("]") {
+            cursor.expect(",")?;
+        }
+    }
+    cursor.expect("]")?;
+    cursor.end()?;
+    Ok(GlobalVar {
+        name,
+        data,
+        read_only,
+    })
+}
+
+/// `function name(2) : int {`
+fn header(cursor: &mut Cursor) -> Result<Function, IrParseError> {
+    cursor.expect("function")?;
+    let name = cursor.word(&['('])?.to_string();
+    cursor.expect("(")?;
+    let arg_count = cursor.number(&[')'])?;
+    cursor.expect(")")?;
+    cursor.expect(":")?;
+    let ret_type = reg_type(cursor)?;
+    cursor.expect("{")?;
+    cursor.end()?;
+    Ok(Function {
+        name,
+        arg_count,
+        ret_type,
+        blocks: vec![],
+    })
+}
+
+/// Parses the text of the whole program, see the module documentation
+pub fn parse_ir(input: &str) -> Result<IrProgram, IrParseError> {
+    let mut globals = vec![];
+    let mut parsed: Vec<Parsed> = vec![];
+    let mut glob: Option<ParsedFunction> = None;
+    let mut funcs: Vec<ParsedFunction> = vec![];
+    // function whose body is being read
+    let mut act: Option<ParsedFunction> = None;
+    // the function after `global:` holds the initialization of the globals
+    let mut glob_next = false;
+    let mut in_glob = false;
+
+    for (index, text) in input.lines().enumerate() {
+        let mut cursor = Cursor {
+            line: index + 1,
+            rest: text.trim(),
+        };
+        if cursor.rest.is_empty() {
+            continue;
+        } else if cursor.rest == "global:" && glob.is_none() && act.is_none() {
+            glob_next = true;
+        } else if cursor.peek("function ") && act.is_none() {
+            act = Some(ParsedFunction {
+                function: header(&mut cursor)?,
+                blocks: vec![],
+            });
+            in_glob = glob_next;
+            glob_next = false;
+        } else if cursor.rest == "}" && act.is_some() {
+            let func = act.take();
+            if in_glob {
+                glob = func;
+            } else {
+                funcs.extend(func);
+            }
+        } else if cursor.peek("@") && act.is_none() {
+            globals.push(global(&mut cursor)?);
+        } else {
+            let func = act.as_mut().ok_or_else(|| cursor.error())?;
+            if cursor.peek("BB") && cursor.rest.ends_with(':') {
+                let bb = cursor.bb()?;
+                cursor.expect(":")?;
+                if bb != func.blocks.len() {
+                    return Err(IrParseError::UnknownBlock(cursor.line, bb));
+                }
+                func.blocks.push(vec![]);
+            } else {
+                let block = func.blocks.last_mut().ok_or_else(|| cursor.error())?;
+                block.push(parsed.len());
+                parsed.push(parsed_instruction(&mut cursor)?);
+            }
+        }
+    }
+    if let Some(func) = act {
+        return Err(IrParseError::Syntax(
+            input.lines().count(),
+            func.function.name,
+        ));
+    }
+
+    let store = store(&mut parsed)?;
+    let mut program = IrProgram {
+        store,
+        globals,
+        ..Default::default()
+    };
+    // without the global section there is nothing to initialize
+    let mut glob = glob.unwrap_or(ParsedFunction {
+        function: Function::default(),
+        blocks: vec![],
+    });
+    glob.function.blocks = blocks(&glob.blocks, &parsed, &program.store)?;
+    program.glob = glob.function;
+    for mut func in funcs {
+        func.function.blocks = blocks(&func.blocks, &parsed, &program.store)?;
+        program
+            .funcs
+            .insert(func.function.name.clone(), func.function);
+    }
+    Ok(program)
+}
+
+/// the registers are placed at their numbers, the instructions without
+/// result fill the free places and the rest is never used
+fn store(parsed: &mut [Parsed]) -> Result<InstStore, IrParseError> {
+    let mut defined = HashSet::new();
+    for inst in parsed.iter() {
+        if let Some(id) = inst.id {
+            if !defined.insert(id) {
+                return Err(IrParseError::RegisterRedef(inst.line, id));
+            }
+        }
+    }
+    for inst in parsed.iter() {
+        for reg in inst.data.get_regs() {
+            if !defined.contains(&reg.val()) {
+                return Err(IrParseError::UndefinedRegister(inst.line, reg.val()));
+            }
+        }
+    }
+
+    let mut free = (0..).filter(|x| !defined.contains(x));
+    for inst in parsed.iter_mut() {
+        if inst.id.is_none() {
+            inst.id = free.next();
+        }
+    }
+    let mut places: HashMap<usize, usize> = HashMap::new();
+    for (index, inst) in parsed.iter().enumerate() {
+        places.insert(inst.id.unwrap(), index);
+    }
+    let mut store = InstStore::default();
+    let len = places.keys().max().map_or(0, |x| x + 1);
+    for id in 0..len {
+        match places.get(&id) {
+            Some(index) => store.add_inst(parsed[*index].data.clone(), parsed[*index].reg_type),
+            None => store.add_inst(InstructionType::Ret(Terminator), RegType::Void),
+        };
+    }
+    Ok(store)
+}
+
+fn blocks(
+    blocks: &[Vec<usize>],
+    parsed: &[Parsed],
+    store: &InstStore,
+) -> Result<Vec<BasicBlock>, IrParseError> {
+    let mut result: Vec<BasicBlock> = blocks
+        .iter()
+        .map(|block| {
+            let mut bb = BasicBlock::default();
+            bb.instruction = block
+                .iter()
+                .map(|x| Register::from_val(parsed[*x].id.unwrap()))
+                .collect();
+            bb
+        })
+        .collect();
+    for (index, block) in blocks.iter().enumerate() {
+        let succ = result[index].succ(store);
+        for to in succ {
+            if to >= result.len() {
+                let line = parsed[*block.last().unwrap()].line;
+                return Err(IrParseError::UnknownBlock(line, to));
+            }
+            result[to].add_predecesor(index);
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir_interpret::run;
+
+    const LOOP: &str = "@main.str.0 = const [104, 105, 0]
+global:
+function global(0) : void {
+BB0:
+\texit
+}
+
+function main(0) : int {
+BB0:
+\t%0 : int = ldi 0
+\t%1 : int = alloca 8
+\tstore [%1] %0
+\tjmp BB1
+BB1:
+\t%2 : int = ld [%1]
+\t%3 : int = ldi 5
+\t%4 : int = lt %2 %3
+\tbranch %4 BB2 BB3
+BB2:
+\t%5 : int = ldi 1
+\t%6 : int = add %2 %5
+\tstore [%1] %6
+\tjmp BB1
+BB3:
+\t%7 : char = ldc '\\n'
+\tretr %2
+}";
+
+    #[test]
+    fn parse_loop() {
+        let program = parse_ir(LOOP).unwrap();
+        let main = &program.funcs["main"];
+        assert_eq!(main.blocks[1].pred(), vec![0, 2]);
+        assert_eq!(main.blocks[3].pred(), vec![1]);
+        assert_eq!(program.globals[0].data, b"hi\0");
+        assert!(program.globals[0].read_only);
+        // 8 registers and 7 instructions without result after them
+        assert_eq!(program.store.len(), 15);
+        assert_eq!(
+            program.store.get(Register::from_val(7)).data,
+            InstructionType::Ldc(ImmC('\n'))
+        );
+        assert_eq!(run(program).unwrap(), 5);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let redef = "function f(0) : int {\nBB0:\n%1 : int = ldi 1\n%1 : int = ldi 2\nretr %1\n}";
+        assert_eq!(
+            parse_ir(redef).err(),
+            Some(IrParseError::RegisterRedef(4, 1))
+        );
+        let undefined = "function f(0) : int {\nBB0:\nretr %3\n}";
+        assert_eq!(
+            parse_ir(undefined).err(),
+            Some(IrParseError::UndefinedRegister(3, 3))
+        );
+        let block = "function f(0) : void {\nBB0:\njmp BB4\n}";
+        assert_eq!(
+            parse_ir(block).err(),
+            Some(IrParseError::UnknownBlock(3, 4))
+        );
+        let unknown = "function f(0) : void {\nBB0:\nfoo %1\n}";
+        assert_eq!(
+            parse_ir(unknown).err(),
+            Some(IrParseError::UnknownInstruction(3, "foo".to_string()))
+        );
+    }
+}
@@ -5,4 +5,13 @@ pub mod ir;
 pub mod builder;
 pub mod ir_display;
 pub mod ir_interpret;
+pub mod ir_parse;
+pub mod link;
+pub mod syscall;
+pub mod verify;
 mod optimalizations;
+#[cfg(test)]
+mod test_utils;
+
+pub use optimalizations::bounds_check::BOUNDS_FAIL;
+pub use optimalizations::out_of_ssa::destruct_ssa;
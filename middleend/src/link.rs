@@ -0,0 +1,224 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    inst::{ImmS, InstructionType, Reg, SymRegs, Terminator},
+    ir::{BasicBlock, Function, InstStore, IrProgram, RegType, Register, Symbol},
+    verify::debug_verify,
+};
+
+#[derive(Debug)]
+pub enum LinkError {
+    DuplicateSymbol(Symbol),
+}
+
+/// Merges separately compiled programs into one, the instructions
+/// of every unit are moved into the common store and the global
+/// initialization blocks are concatenated
+pub fn link(units: Vec<IrProgram>) -> Result<IrProgram, LinkError> {
+    let mut result = IrProgram::default();
+    let mut glob = BasicBlock::default();
+    let mut symbols: HashSet<Symbol> = HashSet::new();
+
+    for unit in units {
+        let offset = result.store.len();
+        let renames: HashMap<Register, Register> = unit
+            .store
+            .iter()
+            .map(|inst| (inst.id, inst.id.shifted(offset)))
+            .collect();
+        move_store(&unit.store, &mut result.store, &renames);
+
+        for inst in unit.glob.blocks.iter().flat_map(|bb| bb.iter()) {
+            if let InstructionType::Exit(_) = unit.store.get(*inst).data {
+                continue;
+            }
+            glob.push(renames[inst]);
+        }
+
+        for global in unit.globals {
+            if !symbols.insert(global.name.clone()) {
+                return Err(LinkError::DuplicateSymbol(global.name));
+            }
+            result.globals.push(global);
+        }
+
+        for (name, func) in unit.funcs {
+            if !symbols.insert(name.clone()) {
+                return Err(LinkError::DuplicateSymbol(name));
+            }
+            result.funcs.insert(name, shift_function(func, &renames));
+        }
+    }
+
+    let exit = result
+        .store
+        .add_inst(InstructionType::Exit(Terminator), RegType::Void);
+    glob.push(exit);
+    result.glob.blocks = vec![glob];
+
+    for func in result.funcs.values().chain([&result.glob]) {
+        debug_verify(func, &result.store, "link");
+    }
+    Ok(result)
+}
+
+/// Links the units with the part of the library they need, like
+/// the static archive only the functions and globals reachable
+/// from the symbols undefined in the units are linked, the symbols
+/// defined by the units take precedence over the library
+pub fn link_with_library(
+    units: Vec<IrProgram>,
+    library: Vec<IrProgram>,
+) -> Result<IrProgram, LinkError> {
+    let mut units = units;
+    let library = library_unit(&units.iter().collect::<Vec<_>>(), library)?;
+    units.push(library);
+    link(units)
+}
+
+/// The library reduced to the functions and globals needed by the units,
+/// it can be compiled separately and linked with them afterwards
+pub fn library_unit(units: &[&IrProgram], library: Vec<IrProgram>) -> Result<IrProgram, LinkError> {
+    let mut library = link(library)?;
+    let defined: HashSet<Symbol> = units.iter().flat_map(|x| defined_symbols(x)).collect();
+    let mut needed: Vec<Symbol> = units.iter().flat_map(|x| used_symbols(x)).collect();
+    needed.extend(blocks_symbols(&library.glob.blocks, &library.store));
+
+    let mut keep: HashSet<Symbol> = HashSet::new();
+    while let Some(symbol) = needed.pop() {
+        if defined.contains(&symbol) || !keep.insert(symbol.clone()) {
+            continue;
+        }
+        if let Some(func) = library.funcs.get(&symbol) {
+            needed.extend(blocks_symbols(&func.blocks, &library.store));
+        }
+    }
+
+    library.funcs.retain(|name, _| keep.contains(name));
+    library.globals.retain(|global| keep.contains(&global.name));
+    Ok(library)
+}
+
+/// runtime function printing the value of the register type,
+/// the backend calls it in place of the print instruction
+pub fn print_routine(reg_type: RegType) -> Symbol {
+    match reg_type {
+        RegType::Char => "putchar".to_string(),
+        _ => "__print_int".to_string(),
+    }
+}
+
+fn defined_symbols(unit: &IrProgram) -> impl Iterator<Item = Symbol> + '_ {
+    let globals = unit.globals.iter().map(|global| global.name.clone());
+    unit.funcs.keys().cloned().chain(globals)
+}
+
+/// symbols of the functions called or loaded and the globals
+fn used_symbols(unit: &IrProgram) -> Vec<Symbol> {
+    let funcs = unit.funcs.values().map(|func| &func.blocks);
+    std::iter::once(&unit.glob.blocks)
+        .chain(funcs)
+        .flat_map(|blocks| blocks_symbols(blocks, &unit.store))
+        .collect()
+}
+
+fn blocks_symbols(blocks: &[BasicBlock], store: &InstStore) -> Vec<Symbol> {
+    blocks
+        .iter()
+        .flat_map(|bb| bb.iter())
+        .filter_map(|inst| match &store.get(*inst).data {
+            InstructionType::CallDirect(SymRegs(symbol, _))
+            | InstructionType::Ldg(ImmS(symbol)) => Some(symbol.clone()),
+            InstructionType::Print(Reg(reg)) => Some(print_routine(store.get(*reg).reg_type)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn move_store(from: &InstStore, to: &mut InstStore, renames: &HashMap<Register, Register>) {
+    for inst in from.iter() {
+        let mut data = inst.data.clone();
+        data.rename_regs(renames);
+        to.add_inst(data, inst.reg_type);
+    }
+}
+
+fn shift_function(func: Function, renames: &HashMap<Register, Register>) -> Function {
+    let mut func = func;
+    for bb in func.blocks.iter_mut() {
+        for inst in bb.iter_mut() {
+            *inst = renames[inst];
+        }
+    }
+    func
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        builder::{FunctionBuilder, IrBuilder},
+        inst::{ImmC, ImmI, ImmS, SymRegs, TerminatorReg},
+        ir_interpret::run,
+    };
+
+    use super::*;
+
+    // for better writing
+    type I = InstructionType;
+
+    fn unit(func: &str, body: impl Fn(&mut FunctionBuilder) -> Register) -> IrProgram {
+        let mut builder = IrBuilder::default();
+        builder.add(I::Exit(Terminator), RegType::Void);
+        let mut fn_b = FunctionBuilder::new(0, RegType::Int, &mut builder.store);
+        let reg = body(&mut fn_b);
+        fn_b.add(I::Retr(TerminatorReg(reg)), RegType::Void);
+        let f = fn_b.create(func);
+        builder.add_fn(f).unwrap();
+        builder.create()
+    }
+
+    #[test]
+    fn link_units() {
+        let mut lib = unit("f", |fn_b| {
+            let addr = fn_b.add(I::Ldg(ImmS("x".to_string())), RegType::Int);
+            fn_b.add(I::Ld(Reg(addr)), RegType::Int)
+        });
+        lib.globals.push(crate::ir::GlobalVar {
+            name: "x".to_string(),
+            data: vec![7, 0, 0, 0, 0, 0, 0, 0],
+            read_only: false,
+        });
+        let main = unit("main", |fn_b| {
+            let res = fn_b.add(
+                I::CallDirect(SymRegs("f".to_string(), vec![])),
+                RegType::Int,
+            );
+            let one = fn_b.add(I::Ldi(ImmI(1)), RegType::Int);
+            fn_b.add(I::Add(crate::inst::RegReg(res, one)), RegType::Int)
+        });
+
+        let program = link(vec![main, lib]).unwrap();
+        assert_eq!(run(program).unwrap(), 8);
+
+        let twice = link(vec![
+            unit("f", |fn_b| fn_b.add(I::Ldi(ImmI(1)), RegType::Int)),
+            unit("f", |fn_b| fn_b.add(I::Ldi(ImmI(2)), RegType::Int)),
+        ]);
+        assert!(matches!(twice, Err(LinkError::DuplicateSymbol(name)) if name == "f"));
+    }
+
+    #[test]
+    fn print_links_runtime() {
+        let main = unit("main", |fn_b| {
+            let c = fn_b.add(I::Ldc(ImmC('a')), RegType::Char);
+            fn_b.add(I::Print(Reg(c)), RegType::Void);
+            fn_b.add(I::Ldi(ImmI(0)), RegType::Int)
+        });
+        let library = vec![
+            unit("putchar", |fn_b| fn_b.add(I::Ldi(ImmI(1)), RegType::Int)),
+            unit("__print_int", |fn_b| fn_b.add(I::Ldi(ImmI(1)), RegType::Int)),
+        ];
+        let library = library_unit(&[&main], library).unwrap();
+        assert_eq!(library.funcs.keys().collect::<Vec<_>>(), vec!["putchar"]);
+    }
+}
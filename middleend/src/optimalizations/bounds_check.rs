@@ -0,0 +1,216 @@
+use std::collections::HashSet;
+
+use crate::{
+    inst::{ImmI, InstructionType, SymRegs, TerminatorBranch, TerminatorJump},
+    ir::{BBIndex, Function, InstStore, RegType, Register},
+    optimalizations::simplify_cfg::remove_unreachable,
+};
+
+/// runtime function called by the failed index check, the frontend
+/// passes it the location, the name of the array, the index and the length
+pub const BOUNDS_FAIL: &str = "__bounds_fail";
+
+/// index check ending the block, it branches to `cont` when
+/// the index is in the bounds and to `fail` otherwise
+#[derive(Debug, Clone, Copy)]
+struct Check {
+    index: Register,
+    len: i64,
+    cont: BBIndex,
+    fail: BBIndex,
+}
+
+fn fail_call(function: &Function, store: &InstStore, bb: BBIndex) -> Option<(Register, i64)> {
+    function.blocks[bb]
+        .iter()
+        .find_map(|inst| match &store.get(*inst).data {
+            InstructionType::CallDirect(SymRegs(name, args))
+                if name == BOUNDS_FAIL && args.len() == 4 =>
+            {
+                match store.get(args[3]).data {
+                    InstructionType::Ldi(ImmI(len)) => Some((args[2], len)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+}
+
+fn check(function: &Function, store: &InstStore, bb: BBIndex) -> Option<Check> {
+    let last = *function.blocks[bb].last()?;
+    match store.get(last).data {
+        InstructionType::Branch(TerminatorBranch(_, cont, fail)) => {
+            let (index, len) = fail_call(function, store, fail)?;
+            Some(Check {
+                index,
+                len,
+                cont,
+                fail,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// the fail block of the check jumps after the call, the call of the
+/// check which always fails can be merged with the code before it
+fn fail_block(function: &Function, store: &InstStore, bb: BBIndex) -> bool {
+    let jumps = match function.blocks[bb].last() {
+        Some(last) => matches!(store.get(*last).data, InstructionType::Jmp(_)),
+        None => false,
+    };
+    jumps && fail_call(function, store, bb).is_some()
+}
+
+/// the only predecessor which can reach the block, the fail blocks
+/// never return and the removed ones are unreachable
+fn single_pred(function: &Function, store: &InstStore, bb: BBIndex) -> Option<BBIndex> {
+    let preds: Vec<BBIndex> = function.blocks[bb]
+        .pred()
+        .into_iter()
+        .filter(|pred| *pred == 0 || !function.blocks[*pred].pred().is_empty())
+        .filter(|pred| !fail_block(function, store, *pred))
+        .collect();
+    match preds[..] {
+        [pred] => Some(pred),
+        _ => None,
+    }
+}
+
+/// the same index was already checked on every path to the block
+fn checked_before(function: &Function, store: &InstStore, bb: BBIndex, checked: Check) -> bool {
+    let mut visited = HashSet::from([bb]);
+    let mut act = bb;
+    while let Some(pred) = single_pred(function, store, act) {
+        if !visited.insert(pred) {
+            return false;
+        }
+        match check(function, store, pred) {
+            Some(c) if c.cont == act && c.index == checked.index && c.len <= checked.len => {
+                return true
+            }
+            _ => act = pred,
+        }
+    }
+    false
+}
+
+/// Removes the index checks of the `--bounds-check` mode which cannot fail,
+/// the constant index in the bounds and the index checked already
+/// against the same or smaller length. The fail blocks of the removed
+/// checks are deleted.
+pub fn remove_bounds_checks(function: &mut Function, store: &mut InstStore) -> bool {
+    let mut change = false;
+    for bb in 0..function.blocks.len() {
+        let checked = match check(function, store, bb) {
+            Some(c) => c,
+            None => continue,
+        };
+        let constant = match store.get(checked.index).data {
+            InstructionType::Ldi(ImmI(index)) => 0 <= index && index < checked.len,
+            _ => false,
+        };
+        if !constant && !checked_before(function, store, bb, checked) {
+            continue;
+        }
+
+        let last = *function.blocks[bb].last().unwrap();
+        store.replace_inst(
+            last,
+            InstructionType::Jmp(TerminatorJump(checked.cont)),
+            RegType::Void,
+        );
+        function.blocks[checked.fail].remove_predecesor(bb);
+        change = true;
+    }
+    if change {
+        remove_unreachable(function, store);
+    }
+    change
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        builder::FunctionBuilder,
+        inst::{
+            ImmI, InstructionType, RegReg, SymRegs, TerminatorBranch, TerminatorJump, TerminatorReg,
+        },
+        ir::{InstStore, RegType, Register},
+    };
+
+    use super::BOUNDS_FAIL;
+
+    type I = InstructionType;
+
+    fn bounds_check(f_b: &mut FunctionBuilder, index: Register, len: i64) {
+        let len = f_b.add(I::Ldi(ImmI(len)), RegType::Int);
+        let ok = f_b.add(I::Lt(RegReg(index, len)), RegType::Int);
+        let fail = f_b.create_bb();
+        let cont = f_b.create_bb();
+        f_b.set_predecesors(fail, &[f_b.get_act_bb()]);
+        f_b.set_predecesors(cont, &[f_b.get_act_bb(), fail]);
+        f_b.add(I::Branch(TerminatorBranch(ok, cont, fail)), RegType::Void);
+        f_b.set_bb(fail);
+        let args = vec![index, index, index, len];
+        f_b.add(
+            I::CallDirect(SymRegs(BOUNDS_FAIL.to_string(), args)),
+            RegType::Void,
+        );
+        f_b.add(I::Jmp(TerminatorJump(cont)), RegType::Void);
+        f_b.set_bb(cont);
+    }
+
+    #[test]
+    fn constant_and_repeated_checks_removed() {
+        let mut store = InstStore::default();
+        let mut f_b = FunctionBuilder::new(1, RegType::Int, &mut store);
+        let arg = f_b.add(I::Arg(ImmI(0)), RegType::Int);
+        let two = f_b.add(I::Ldi(ImmI(2)), RegType::Int);
+        let ten = f_b.add(I::Ldi(ImmI(10)), RegType::Int);
+        // constant in the bounds
+        bounds_check(&mut f_b, two, 4);
+        // constant out of the bounds stays
+        bounds_check(&mut f_b, ten, 4);
+        // first check of the argument stays, the second one is implied
+        bounds_check(&mut f_b, arg, 4);
+        bounds_check(&mut f_b, arg, 8);
+        // the smaller length has to be checked again
+        bounds_check(&mut f_b, arg, 2);
+        f_b.add(I::Retr(TerminatorReg(arg)), RegType::Void);
+        let func = f_b.create("f");
+
+        let calls = func
+            .blocks
+            .iter()
+            .flat_map(|bb| bb.iter())
+            .filter(|inst| {
+                matches!(&store.get(**inst).data,
+                    I::CallDirect(SymRegs(sym, _)) if sym == BOUNDS_FAIL)
+            })
+            .count();
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn fail_block_of_removed_check_deleted() {
+        let text = "function f(1) : int {
+BB0:
+%0 : int = ldi 2
+%1 : int = ldi 4
+%2 : int = lt %0 %1
+branch %2 BB2 BB1
+BB1:
+calldirect __bounds_fail [%0 %0 %0 %1]
+jmp BB2
+BB2:
+retr %0
+}";
+        let program = crate::ir_parse::parse_ir(text).unwrap();
+        let mut store = program.store;
+        let mut f = program.funcs.into_values().next().unwrap();
+        assert!(super::remove_bounds_checks(&mut f, &mut store));
+        assert_eq!(f.blocks.len(), 2);
+        assert_eq!(f.blocks[1].pred(), vec![0]);
+    }
+}
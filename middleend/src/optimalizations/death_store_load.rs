@@ -1,13 +1,13 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 use crate::{
     analysis::{
-        anderson::{AndersenAnalysis, Cell},
+        anderson::Aliases,
         const_mem::{ConstantMemoryAnalysis, MemoryPlace},
         dataflow::DataFlowAnalysis,
         lattice::FlatElem,
     },
-    inst::{ImmIRegs, InstructionType, Reg, RegReg, SymRegs},
+    inst::{InstructionType, Reg, RegReg},
     ir::{Function, InstStore, RegType, Register},
 };
 
@@ -27,12 +27,13 @@ pub fn remove_store_load(function: &mut Function, store: &mut InstStore) -> bool
             match inst.data {
                 InstructionType::Ld(Reg(addr)) => {
                     let state = &result[bb_index][inst_index];
-                    match state.get(&MemoryPlace(addr)) {
-                        Some(FlatElem::Value(val)) => {
-                            change = true;
-                            store.replace_inst(id, InstructionType::Mov(Reg(*val)), inst.reg_type);
-                        }
-                        Some(_) | None => (),
+                    let forwarded = match state.get(&MemoryPlace(addr)) {
+                        Some(FlatElem::Value(val)) => forward(*val, inst.reg_type, store),
+                        Some(_) | None => None,
+                    };
+                    if let Some(data) = forwarded {
+                        change = true;
+                        store.replace_inst(id, data, inst.reg_type);
                     }
                 }
                 _ => (),
@@ -49,6 +50,16 @@ pub fn remove_store_load(function: &mut Function, store: &mut InstStore) -> bool
     change
 }
 
+/// copy of the stored value seen by the load, the load of a char
+/// reads only the lowest byte of the stored int
+fn forward(val: Register, load_type: RegType, store: &InstStore) -> Option<InstructionType> {
+    match (store.get(val).reg_type, load_type) {
+        (stored, loaded) if stored == loaded => Some(InstructionType::Mov(Reg(val))),
+        (RegType::Int, RegType::Char) => Some(InstructionType::Conv(Reg(val))),
+        _ => None,
+    }
+}
+
 fn remove_unused_instruction(function: &mut Function, store: &InstStore) -> bool {
     let mut change = false;
     let used = function.get_used_regs(store);
@@ -56,9 +67,15 @@ fn remove_unused_instruction(function: &mut Function, store: &InstStore) -> bool
         let bb = &mut function.blocks[bb_index];
         let mut inst_index = 0;
         while inst_index < bb.len() {
-            if !used.contains(&bb[inst_index])
-                && store.get(bb[inst_index]).reg_type != RegType::Void
-            {
+            let inst = store.get(bb[inst_index]);
+            // calls have to stay even if the result is not used
+            let side_effect = matches!(
+                inst.data,
+                InstructionType::Call(_)
+                    | InstructionType::CallDirect(_)
+                    | InstructionType::SysCall(_)
+            );
+            if !used.contains(&bb[inst_index]) && inst.reg_type != RegType::Void && !side_effect {
                 bb.remove(inst_index);
                 change = true;
             } else {
@@ -70,84 +87,158 @@ fn remove_unused_instruction(function: &mut Function, store: &InstStore) -> bool
     change
 }
 
+/// the store is needed when a load can read its cell or the
+/// memory is used outside of the function
 fn remove_unused_stores(function: &mut Function, store: &InstStore) -> bool {
-    let mut change = false;
-    let mut loads: Vec<Register> = vec![];
-    for bb_index in 0..function.blocks.len() {
-        let bb = &mut function.blocks[bb_index];
-        for inst_index in 0..bb.len() {
-            match &store.get(bb[inst_index]).data {
-                InstructionType::Ld(Reg(addr)) => {
-                    loads.push(*addr);
-                }
-                crate::inst::InstructionType::CallDirect(SymRegs(_, regs))
-                | crate::inst::InstructionType::SysCall(ImmIRegs(_, regs)) => {
-                    for reg in regs {
-                        loads.push(*reg);
-                    }
-                }
-                _ => (),
-            }
-        }
-    }
-
-    let mut pointer_analysis = AndersenAnalysis::new(function);
-    let result = pointer_analysis.analyze(store);
-
-    let loads: HashSet<Cell> = loads
+    let aliases = Aliases::new(function, store);
+    let loads: Vec<Register> = function
+        .blocks
         .iter()
-        .map(|x| match result.get(x) {
-            Some(cells) => cells.clone(),
-            None => HashSet::new(),
+        .flat_map(|bb| bb.iter())
+        .filter_map(|inst| match store.get(*inst).data {
+            InstructionType::Ld(Reg(addr)) => Some(addr),
+            _ => None,
         })
-        .flatten()
         .collect();
 
-    for bb_index in 0..function.blocks.len() {
-        let bb = &mut function.blocks[bb_index];
-        let mut inst_index = 0;
-        while inst_index < bb.len() {
-            match store.get(bb[inst_index]).data {
-                InstructionType::St(RegReg(addr, _)) => {
-                    let cells = match result.get(&addr) {
-                        Some(cells) => cells.clone(),
-                        None => HashSet::new(),
-                    };
-
-                    if cells.is_disjoint(&loads) && !cells.contains(&Cell::Volatile) {
-                        change = true;
-                        bb.remove(inst_index);
-                    } else {
-                        inst_index += 1;
-                    }
-                }
-                _ => inst_index += 1,
+    let mut change = false;
+    for bb in function.blocks.iter_mut() {
+        let len = bb.len();
+        bb.retain(|inst| match store.get(*inst).data {
+            InstructionType::St(RegReg(addr, _)) => {
+                aliases.escapes(addr) || loads.iter().any(|x| aliases.may_alias(*x, addr))
             }
-        }
+            _ => true,
+        });
+        change |= bb.len() != len;
     }
 
     change
 }
 
 fn remove_movs(function: &mut Function, store: &mut InstStore) -> bool {
-    let mut change = false;
     let mut renames: HashMap<Register, Register> = HashMap::new();
-    for bb_index in 0..function.blocks.len() {
-        let bb = &mut function.blocks[bb_index];
-        let mut inst_index = 0;
-        while inst_index < bb.len() {
-            match store.get(bb[inst_index]).data {
-                InstructionType::Mov(Reg(reg)) => {
-                    renames.insert(bb[inst_index], reg);
-                    bb.remove(inst_index);
-                    change = true;
-                }
-                _ => {
-                    store.get_mut(bb[inst_index]).data.rename_regs(&renames);
-                    inst_index += 1
-                }
+    for bb in function.blocks.iter_mut() {
+        bb.retain(|x| match store.get(*x).data {
+            InstructionType::Mov(Reg(reg)) => {
+                renames.insert(*x, reg);
+                false
             }
+            _ => true,
+        });
+    }
+    // the source can be a removed mov as well, the uses can be
+    // in the blocks before the mov as the phis in the loops are
+    let sources: Vec<Register> = renames.keys().copied().collect();
+    for mov in sources {
+        let mut reg = renames[&mov];
+        while let Some(source) = renames.get(&reg) {
+            reg = *source;
         }
+        renames.insert(mov, reg);
+    }
+    for inst in function.blocks.iter().flat_map(|bb| bb.iter()) {
+        store.get_mut(*inst).data.rename_regs(&renames);
+    }
+    !renames.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ir_parse::parse_ir, test_utils::reg};
+
+    #[test]
+    fn store_in_loop_not_forwarded() {
+        // a[i] = i + 1 after reading a[i], the value stored in the
+        // previous iteration is at another address
+        let text = "function f(2) : int {
+BB0:
+%0 : int = arg 0
+%1 : int = arg 1
+%2 : int = ldi 0
+jmp BB1
+BB1:
+%3 : int = phi %2 [%7]
+%4 : int = gep <8> [%0] %3 0
+%5 : int = ld [%4]
+%6 : int = ldi 1
+%7 : int = add %3 %6
+store [%4] %7
+%8 : int = lt %7 %1
+branch %8 BB1 BB2
+BB2:
+retr %5
+}";
+        let program = parse_ir(text).unwrap();
+        let mut store = program.store;
+        let mut f = program.funcs.into_values().next().unwrap();
+        remove_store_load(&mut f, &mut store);
+        let load = f.blocks[1][2];
+        assert_eq!(load, reg(5));
+        assert_eq!(store.get(load).data, InstructionType::Ld(Reg(reg(4))));
+    }
+
+    #[test]
+    fn store_through_loaded_pointer_kept() {
+        // the pointer to x is stored and loaded through different
+        // addresses of the same slot, the store through it is live
+        let text = "function f(0) : int {
+BB0:
+%0 : int = alloca 8
+%1 : int = alloca 16
+%2 : int = ldi 0
+%3 : int = gep <8> [%1] %2 0
+%4 : int = gep <8> [%1] %2 0
+store [%3] %0
+%5 : int = ld [%4]
+%6 : int = ldi 4
+store [%5] %6
+%7 : int = ld [%0]
+retr %7
+}";
+        let program = parse_ir(text).unwrap();
+        let mut store = program.store;
+        let mut f = program.funcs.into_values().next().unwrap();
+        remove_store_load(&mut f, &mut store);
+        let stores: Vec<InstructionType> = f.blocks[0]
+            .iter()
+            .map(|x| store.get(*x).data.clone())
+            .filter(|x| matches!(x, InstructionType::St(_)))
+            .collect();
+        assert_eq!(
+            stores,
+            vec![
+                InstructionType::St(RegReg(reg(3), reg(0))),
+                InstructionType::St(RegReg(reg(5), reg(6)))
+            ]
+        );
+    }
+
+    #[test]
+    fn narrow_load_truncates() {
+        // the char load sees only the lowest byte of the int, the int
+        // load of the char store reads the bytes after it as well
+        let text = "function f(0) : int {
+BB0:
+%0 : int = alloca 8
+%1 : int = ldi 258
+store [%0] %1
+%2 : char = ld [%0]
+%3 : int = alloca 8
+%4 : char = ldc 'a'
+store [%3] %4
+%5 : int = ld [%3]
+%6 : int = conv %2
+%7 : int = add %5 %6
+retr %7
+}";
+        let program = parse_ir(text).unwrap();
+        let mut store = program.store;
+        let mut f = program.funcs.into_values().next().unwrap();
+        remove_store_load(&mut f, &mut store);
+        let data = |val| store.get(reg(val)).data.clone();
+        assert_eq!(data(2), InstructionType::Conv(Reg(reg(1))));
+        assert_eq!(data(5), InstructionType::Ld(Reg(reg(3))));
     }
-    change
 }
@@ -0,0 +1,188 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    analysis::dominators::DominatorTree,
+    inst::{InstructionType, RegReg},
+    ir::{BBIndex, Function, InstStore, RegType, Register},
+};
+
+type I = InstructionType;
+
+/// Value computed by the instruction, the operands are the numbered
+/// registers and the loads carry the state of the memory they read
+#[derive(Hash, PartialEq, Eq)]
+struct Expression(InstructionType, RegType, usize);
+
+/// expression of the pure instruction or the load, the operands of
+/// the commutative ones are sorted and `gt`, `ge` become `lt`, `le`
+fn expression(data: InstructionType, reg_type: RegType, memory: usize) -> Option<Expression> {
+    let sorted = |RegReg(a, b): RegReg| match a.val() <= b.val() {
+        true => RegReg(a, b),
+        false => RegReg(b, a),
+    };
+    let data = match data {
+        I::Add(regs) => I::Add(sorted(regs)),
+        I::Mul(regs) => I::Mul(sorted(regs)),
+        I::And(regs) => I::And(sorted(regs)),
+        I::Or(regs) => I::Or(sorted(regs)),
+        I::Xor(regs) => I::Xor(sorted(regs)),
+        I::Eql(regs) => I::Eql(sorted(regs)),
+        I::Gt(RegReg(a, b)) => I::Lt(RegReg(b, a)),
+        I::Ge(RegReg(a, b)) => I::Le(RegReg(b, a)),
+        I::Ld(_) => return Some(Expression(data, reg_type, memory)),
+        I::Ldi(_)
+        | I::Ldc(_)
+        | I::Ldg(_)
+        | I::Gep(..)
+        | I::Sub(_)
+        | I::Div(_)
+        | I::Mod(_)
+        | I::Shr(_)
+        | I::Shl(_)
+        | I::Neg(_)
+        | I::Conv(_)
+        | I::Lt(_)
+        | I::Le(_) => data,
+        _ => return None,
+    };
+    Some(Expression(data, reg_type, 0))
+}
+
+/// Global value numbering over the dominator tree, the instruction
+/// which computes the same value as one in its dominators is removed
+/// and its uses get the dominating register. The stores and the calls
+/// change the memory, the loads are equal only with no change between.
+pub fn number_values(function: &mut Function, store: &mut InstStore) -> bool {
+    let dom = DominatorTree::new(function, store);
+    let mut leaders: HashMap<Expression, Vec<(Register, BBIndex)>> = HashMap::new();
+    let mut renames: HashMap<Register, Register> = HashMap::new();
+    let mut memory_at_end = vec![0; function.blocks.len()];
+    let mut states = 0;
+    for bb in dom.preorder() {
+        // the memory is the same as at the end of the only predecessor
+        let mut memory = match function.blocks[bb].pred()[..] {
+            [pred] if dom.idom(bb) == Some(pred) => memory_at_end[pred],
+            _ => {
+                states += 1;
+                states
+            }
+        };
+        for inst in function.blocks[bb].iter() {
+            let mut data = store.get(*inst).data.clone();
+            if matches!(
+                data,
+                I::St(_) | I::Call(_) | I::CallDirect(_) | I::SysCall(_)
+            ) {
+                states += 1;
+                memory = states;
+                continue;
+            }
+            data.rename_regs(&renames);
+            let expression = match expression(data, store.get(*inst).reg_type, memory) {
+                Some(expression) => expression,
+                None => continue,
+            };
+            let candidates = leaders.entry(expression).or_default();
+            match candidates.iter().find(|(_, at)| dom.dominates(*at, bb)) {
+                Some((leader, _)) => _ = renames.insert(*inst, *leader),
+                None => candidates.push((*inst, bb)),
+            }
+        }
+        memory_at_end[bb] = memory;
+    }
+
+    if renames.is_empty() {
+        return false;
+    }
+    let removed: HashSet<Register> = renames.keys().copied().collect();
+    for bb in function.blocks.iter_mut() {
+        bb.retain(|x| !removed.contains(x));
+        for inst in bb.iter() {
+            store.get_mut(*inst).data.rename_regs(&renames);
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{reg, run_pass};
+
+    #[test]
+    fn commutative_and_dominated_geps() {
+        // i * 2 and the address of a[i] again in the branches and after them
+        let text = "function f(2) : int {
+BB0:
+%0 : int = arg 0
+%1 : int = arg 1
+%2 : int = ldi 2
+%3 : int = mul %1 %2
+%4 : int = gep <8> [%0] %1 0
+branch %3 BB1 BB2
+BB1:
+%5 : int = mul %2 %1
+%6 : int = gep <8> [%0] %1 0
+%7 : int = gt %5 %3
+jmp BB3
+BB2:
+%8 : int = mul %1 %2
+jmp BB3
+BB3:
+%9 : int = gep <8> [%0] %1 0
+retr %9
+}";
+        let (f, store) = run_pass(text, number_values);
+        // the product and the address are computed only in BB0
+        assert_eq!(f.blocks[1].len(), 2);
+        assert_eq!(
+            store.get(f.blocks[1][0]).data,
+            I::Gt(RegReg(reg(3), reg(3)))
+        );
+        assert_eq!(f.blocks[2].len(), 1);
+        assert_eq!(f.blocks[3].len(), 1);
+        assert_eq!(
+            store.get(f.blocks[3][0]).data,
+            I::Retr(crate::inst::TerminatorReg(reg(4)))
+        );
+    }
+
+    #[test]
+    fn loads_separated_by_memory_changes() {
+        let text = "function f(1) : int {
+BB0:
+%0 : int = arg 0
+%1 : int = ld [%0]
+%2 : int = ld [%0]
+%3 : int = calldirect g [%0]
+%4 : int = ld [%0]
+branch %4 BB1 BB2
+BB1:
+%5 : int = ld [%0]
+store [%0] %5
+%6 : int = ld [%0]
+jmp BB2
+BB2:
+%7 : int = ld [%0]
+%8 : int = add %1 %2
+%9 : int = add %8 %7
+retr %9
+}";
+        let (f, store) = run_pass(text, number_values);
+        let loads = |bb: usize| {
+            f.blocks[bb]
+                .iter()
+                .filter(|x| matches!(store.get(**x).data, I::Ld(_)))
+                .count()
+        };
+        // the second load is the first one, after the call the memory
+        // is new, BB1 loads again after its store and BB2 has two preds
+        assert_eq!(loads(0), 2);
+        assert_eq!(loads(1), 1);
+        assert_eq!(loads(2), 1);
+        assert_eq!(
+            store.get(f.blocks[2][1]).data,
+            I::Add(RegReg(reg(1), reg(1)))
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashSet;
+
+use crate::{
+    analysis::{
+        anderson::Aliases,
+        dominators::DominatorTree,
+        loops::{normalize_loops, Loop, LoopForest},
+    },
+    inst::{InstructionType, Reg, RegReg},
+    ir::{Function, InstStore, Register},
+};
+
+type I = InstructionType;
+
+/// instructions which give the same value wherever they run and can
+/// not fail, the division by zero could
+fn pure(data: &InstructionType) -> bool {
+    matches!(
+        data,
+        I::Ldi(_)
+            | I::Ldc(_)
+            | I::Ldg(_)
+            | I::Gep(..)
+            | I::Mov(_)
+            | I::Add(_)
+            | I::Sub(_)
+            | I::Mul(_)
+            | I::Shr(_)
+            | I::Shl(_)
+            | I::And(_)
+            | I::Or(_)
+            | I::Xor(_)
+            | I::Neg(_)
+            | I::Conv(_)
+            | I::Lt(_)
+            | I::Le(_)
+            | I::Gt(_)
+            | I::Ge(_)
+            | I::Eql(_)
+    )
+}
+
+/// Instructions of the loop with the same value in every iteration, in
+/// the order they can run in. The loads have to read the memory which
+/// the loop does not write and they have to be safe to run before it,
+/// from a stack slot or a global or in a block run on every way out.
+fn invariants(
+    function: &Function,
+    store: &InstStore,
+    dom: &DominatorTree,
+    aliases: &Aliases,
+    l: &Loop,
+) -> Vec<Register> {
+    let insts = || l.blocks.iter().flat_map(|bb| function.blocks[*bb].iter());
+    let defined: HashSet<Register> = insts().copied().collect();
+    let mut written = vec![];
+    let mut calls = false;
+    for inst in insts() {
+        match store.get(*inst).data {
+            I::St(RegReg(addr, _)) => written.push(addr),
+            I::Call(_) | I::CallDirect(_) | I::SysCall(_) => calls = true,
+            _ => (),
+        }
+    }
+
+    let mut result = vec![];
+    let mut hoisted: HashSet<Register> = HashSet::new();
+    for bb in dom.preorder().into_iter().filter(|x| l.blocks.contains(x)) {
+        let run_on_exit = !l.exits.is_empty() && l.exits.iter().all(|x| dom.dominates(bb, *x));
+        for inst in function.blocks[bb].iter() {
+            let data = &store.get(*inst).data;
+            let movable = match data {
+                I::Ld(Reg(addr)) => {
+                    let safe = matches!(store.get(*addr).data, I::Alloca(_) | I::Ldg(_));
+                    !calls
+                        && (safe || run_on_exit)
+                        && written.iter().all(|x| !aliases.may_alias(*x, *addr))
+                }
+                _ => pure(data),
+            };
+            let operands = data.get_regs();
+            if movable
+                && operands
+                    .iter()
+                    .all(|x| !defined.contains(x) || hoisted.contains(x))
+            {
+                hoisted.insert(*inst);
+                result.push(*inst);
+            }
+        }
+    }
+    result
+}
+
+/// Loop invariant code motion, the instructions which compute the same
+/// value in every iteration move to the preheader. The inner loops go
+/// first, so the code can leave more loops.
+pub fn hoist_invariants(function: &mut Function, store: &mut InstStore) -> bool {
+    let forest = LoopForest::new(function, store);
+    let dom = DominatorTree::new(function, store);
+    let aliases = Aliases::new(function, store);
+    let found = forest
+        .loops
+        .iter()
+        .any(|l| !invariants(function, store, &dom, &aliases, l).is_empty());
+    if !found {
+        return false;
+    }
+
+    // the preheaders are added only when there is something to move
+    normalize_loops(function, store);
+    let forest = LoopForest::new(function, store);
+    let dom = DominatorTree::new(function, store);
+    for l in forest.loops.iter().rev() {
+        let moved = invariants(function, store, &dom, &aliases, l);
+        let moved_set: HashSet<Register> = moved.iter().copied().collect();
+        for bb in l.blocks.iter() {
+            function.blocks[*bb].retain(|x| !moved_set.contains(x));
+        }
+        let preheader = &mut function.blocks[l.preheader.unwrap()];
+        let at = preheader.len() - 1;
+        preheader.splice(at..at, moved);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ir_parse::parse_ir,
+        test_utils::{reg, run_pass},
+    };
+
+    #[test]
+    fn invariants_moved_to_preheader() {
+        // the slot with the pointer is read in every iteration, the
+        // element it points to is written in the loop
+        let text = "function f(2) : int {
+BB0:
+%0 : int = arg 0
+%1 : int = arg 1
+%2 : int = alloca 8
+store [%2] %0
+%3 : int = ldi 0
+branch %1 BB1 BB3
+BB1:
+%4 : int = phi %3 [%11]
+%5 : int = ld [%2]
+%6 : int = ldi 1
+%7 : int = add %1 %6
+%8 : int = gep <8> [%5] %7 0
+%9 : int = ld [%8]
+%10 : int = add %9 %6
+store [%8] %10
+%11 : int = add %4 %6
+%12 : int = lt %11 %7
+branch %12 BB1 BB2
+BB2:
+retr %11
+BB3:
+retr %3
+}";
+        let (f, store) = run_pass(text, hoist_invariants);
+
+        let forest = LoopForest::new(&f, &store);
+        let preheader = &f.blocks[forest.loops[0].preheader.unwrap()];
+        assert_eq!(preheader[..4], [reg(5), reg(6), reg(7), reg(8)]);
+        let body: Vec<Register> = f.blocks[1].to_vec();
+        assert_eq!(body[..3], [reg(4), reg(9), reg(10)]);
+    }
+
+    #[test]
+    fn loads_stay_with_calls_or_unknown_stores() {
+        // the pointer from the argument can point to the escaped slot
+        let text = "function f(1) : void {
+BB0:
+%0 : int = arg 0
+%1 : int = alloca 8
+%2 : int = calldirect g [%1]
+jmp BB1
+BB1:
+%3 : int = ld [%1]
+store [%0] %3
+branch %3 BB1 BB2
+BB2:
+%4 : int = ld [%0]
+branch %4 BB3 BB4
+BB3:
+%5 : int = ld [%1]
+%6 : int = calldirect g [%5]
+branch %6 BB3 BB4
+BB4:
+ret
+}";
+        let program = parse_ir(text).unwrap();
+        let mut store = program.store;
+        let mut f = program.funcs.into_values().next().unwrap();
+        assert!(!hoist_invariants(&mut f, &mut store));
+    }
+
+    #[test]
+    fn load_stays_with_store_through_alias() {
+        // the slot is written through two addresses, the pointer read
+        // from it is the address of x, which the loop writes through it
+        let text = "function f(1) : int {
+BB0:
+%0 : int = arg 0
+%1 : int = alloca 8
+%2 : int = alloca 8
+%3 : int = alloca 16
+%4 : int = ldi 0
+%5 : int = gep <8> [%3] %4 0
+%6 : int = gep <8> [%3] %4 0
+store [%5] %2
+store [%6] %1
+store [%1] %4
+%7 : int = ld [%5]
+jmp BB1
+BB1:
+%8 : int = phi %4 [%11]
+%9 : int = ld [%1]
+store [%7] %0
+%10 : int = ldi 1
+%11 : int = add %8 %10
+%12 : int = lt %11 %0
+branch %12 BB1 BB2
+BB2:
+retr %9
+}";
+        let (f, store) = run_pass(text, hoist_invariants);
+        // only the constant leaves the loop
+        let forest = LoopForest::new(&f, &store);
+        let preheader = &f.blocks[forest.loops[0].preheader.unwrap()];
+        assert!(preheader.contains(&reg(10)));
+        assert!(f.blocks[1].contains(&reg(9)));
+    }
+}
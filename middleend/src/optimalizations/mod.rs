@@ -1,2 +1,8 @@
+pub mod bounds_check;
 pub mod death_store_load;
+pub mod gvn;
+pub mod licm;
+pub mod out_of_ssa;
 pub mod phinode_create;
+pub mod simplify_cfg;
+pub mod sccp;
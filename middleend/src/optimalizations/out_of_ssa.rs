@@ -0,0 +1,396 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    inst::{InstructionType, Reg, RegReg, RegRegs, TerminatorBranch, TerminatorJump},
+    ir::{BBIndex, BasicBlock, Function, InstStore, IrProgram, RegType, Register},
+};
+
+type I = InstructionType;
+
+/// phi nodes at the start of the block
+fn phis(function: &Function, store: &InstStore, bb: BBIndex) -> Vec<Register> {
+    function.blocks[bb]
+        .iter()
+        .copied()
+        .take_while(|x| matches!(store.get(*x).data, I::Phi(_)))
+        .collect()
+}
+
+/// values of the phi in the order of the predecessors
+fn phi_values(store: &InstStore, phi: Register) -> Vec<Register> {
+    match &store.get(phi).data {
+        I::Phi(RegRegs(first, rest)) => [*first].into_iter().chain(rest.iter().copied()).collect(),
+        _ => vec![],
+    }
+}
+
+/// The edges from the blocks with more successors into the blocks
+/// with phi nodes get a new block, so the copies run only on the edge
+fn split_edges(function: &mut Function, store: &mut InstStore) {
+    for bb in 0..function.blocks.len() {
+        if phis(function, store, bb).is_empty() {
+            continue;
+        }
+        let mut preds = function.blocks[bb].pred();
+        preds.dedup();
+        for pred in preds {
+            if function.blocks[pred].succ(store).len() < 2 {
+                continue;
+            }
+            let split = function.blocks.len();
+            let mut block = BasicBlock::default();
+            block.add_predecesor(pred);
+            block.push(store.add_inst(I::Jmp(TerminatorJump(bb)), RegType::Void));
+            function.blocks.push(block);
+
+            let last = *function.blocks[pred].last().unwrap();
+            let mut data = store.get(last).data.clone();
+            if let I::Branch(TerminatorBranch(_, yes, no)) = &mut data {
+                for target in [yes, no] {
+                    if *target == bb {
+                        *target = split;
+                    }
+                }
+            }
+            store.replace_inst(last, data, RegType::Void);
+            function.blocks[bb].replace_predecesor(pred, split);
+        }
+    }
+}
+
+/// Pairs of the registers which are live at the same time, only the
+/// `regs` are considered. The phi is defined at the start of its block
+/// and its values are used at the ends of the predecessors.
+fn interferences(
+    function: &Function,
+    store: &InstStore,
+    regs: &HashSet<Register>,
+) -> HashSet<(Register, Register)> {
+    let blocks = function.blocks.len();
+    let mut uses = vec![HashSet::new(); blocks];
+    let mut defs = vec![HashSet::new(); blocks];
+    let mut phi_uses = vec![HashSet::new(); blocks];
+    for (bb, block) in function.blocks.iter().enumerate() {
+        for inst in block.iter() {
+            let data = &store.get(*inst).data;
+            if let I::Phi(_) = data {
+                for (value, pred) in phi_values(store, *inst).into_iter().zip(block.pred()) {
+                    if regs.contains(&value) {
+                        phi_uses[pred].insert(value);
+                    }
+                }
+            } else {
+                for reg in data.get_regs().into_iter().filter(|x| regs.contains(x)) {
+                    if !defs[bb].contains(&reg) {
+                        uses[bb].insert(reg);
+                    }
+                }
+            }
+            defs[bb].insert(*inst);
+        }
+    }
+
+    let mut live_in: Vec<HashSet<Register>> = vec![HashSet::new(); blocks];
+    let mut live_out: Vec<HashSet<Register>> = phi_uses.clone();
+    let mut change = true;
+    while change {
+        change = false;
+        for bb in (0..blocks).rev() {
+            let mut out = phi_uses[bb].clone();
+            for succ in function.blocks[bb].succ(store) {
+                out.extend(live_in[succ].iter().copied());
+            }
+            let mut live: HashSet<Register> = out
+                .iter()
+                .copied()
+                .filter(|x| !defs[bb].contains(x))
+                .collect();
+            live.extend(uses[bb].iter().copied());
+            if live != live_in[bb] {
+                live_in[bb] = live;
+                change = true;
+            }
+            live_out[bb] = out;
+        }
+    }
+
+    let mut result = HashSet::new();
+    let mut add = |a: Register, b: Register| {
+        if a != b && regs.contains(&a) {
+            result.insert((a, b));
+            result.insert((b, a));
+        }
+    };
+    for (bb, block) in function.blocks.iter().enumerate() {
+        let mut live = live_out[bb].clone();
+        let mut block_phis = vec![];
+        for inst in block.iter().rev() {
+            let inst = store.get(*inst);
+            if let I::Phi(_) = inst.data {
+                block_phis.push(inst.id);
+                continue;
+            }
+            if inst.reg_type != RegType::Void {
+                for reg in live.iter() {
+                    add(inst.id, *reg);
+                }
+            }
+            live.remove(&inst.id);
+            live.extend(
+                inst.data
+                    .get_regs()
+                    .into_iter()
+                    .filter(|x| regs.contains(x)),
+            );
+        }
+        // the phis are defined at once, they interfere with each other
+        for phi in block_phis.iter() {
+            for reg in live.iter().chain(block_phis.iter()) {
+                add(*phi, *reg);
+            }
+        }
+    }
+    result
+}
+
+/// Registers which share the same place out of ssa, every class has at
+/// most one register defined by other instruction than phi, it names
+/// the class, the phis are renamed to it
+#[derive(Default)]
+struct Classes {
+    names: HashMap<Register, Register>,
+    members: HashMap<Register, Vec<Register>>,
+}
+
+impl Classes {
+    fn name(&self, reg: Register) -> Register {
+        *self.names.get(&reg).unwrap_or(&reg)
+    }
+
+    fn members(&self, name: Register) -> Vec<Register> {
+        self.members.get(&name).cloned().unwrap_or(vec![name])
+    }
+
+    /// joins the classes of the phi and of its value when their
+    /// registers never live at the same time
+    fn coalesce(
+        &mut self,
+        store: &InstStore,
+        interfere: &HashSet<(Register, Register)>,
+        phi: Register,
+        value: Register,
+    ) {
+        let (a, b) = (self.name(phi), self.name(value));
+        if a == b {
+            return;
+        }
+        let (a_members, b_members) = (self.members(a), self.members(b));
+        let is_phi = |reg: &Register| matches!(store.get(*reg).data, I::Phi(_));
+        if !is_phi(&a) && !is_phi(&b) {
+            return;
+        }
+        for x in a_members.iter() {
+            if b_members.iter().any(|y| interfere.contains(&(*x, *y))) {
+                return;
+            }
+        }
+        let (name, other) = if is_phi(&a) { (b, a) } else { (a, b) };
+        let mut members = self.members(name);
+        for member in self.members(other) {
+            self.names.insert(member, name);
+            members.push(member);
+        }
+        self.members.remove(&other);
+        self.members.insert(name, members);
+    }
+}
+
+/// Orders the parallel copies `(to, from)` so no register is written
+/// before all copies read it, the cycles are broken by a temporary
+fn sequentialize(copies: Vec<(Register, Register)>, store: &mut InstStore) -> Vec<Register> {
+    let mut result = vec![];
+    let mut pending: Vec<(Register, Register)> =
+        copies.into_iter().filter(|(to, from)| to != from).collect();
+    while !pending.is_empty() {
+        let free = pending
+            .iter()
+            .position(|(to, _)| pending.iter().all(|(_, from)| from != to));
+        match free {
+            Some(index) => {
+                let (to, from) = pending.remove(index);
+                result.push(store.add_inst(I::Copy(RegReg(to, from)), RegType::Void));
+            }
+            None => {
+                // every destination is still read, the first one is saved
+                let (to, _) = pending[0];
+                let temp = store.add_inst(I::Mov(Reg(to)), store.get(to).reg_type);
+                result.push(temp);
+                for (_, from) in pending.iter_mut().filter(|(_, from)| *from == to) {
+                    *from = temp;
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Translates the function out of ssa. The critical edges into the
+/// blocks with phi nodes are split, the phis whose registers do not
+/// interfere with their values are coalesced and the rest is replaced
+/// by the copies at the ends of the predecessors.
+pub fn destruct_function(function: &mut Function, store: &mut InstStore) -> bool {
+    let with_phis: Vec<BBIndex> = (0..function.blocks.len())
+        .filter(|bb| !phis(function, store, *bb).is_empty())
+        .collect();
+    if with_phis.is_empty() {
+        return false;
+    }
+    split_edges(function, store);
+
+    let defined: HashSet<Register> = function
+        .blocks
+        .iter()
+        .flat_map(|x| x.iter())
+        .copied()
+        .collect();
+    let mut regs = HashSet::new();
+    for bb in with_phis.iter() {
+        for phi in phis(function, store, *bb) {
+            regs.insert(phi);
+            regs.extend(phi_values(store, phi));
+        }
+    }
+    let interfere = interferences(function, store, &regs);
+
+    let mut classes = Classes::default();
+    for bb in with_phis.iter() {
+        for phi in phis(function, store, *bb) {
+            // the later predecessors are usually the ends of the loops,
+            // the copies there would run in every iteration
+            for value in phi_values(store, phi).into_iter().rev() {
+                // the place of the alloca is not a register
+                let alloca = matches!(store.get(value).data, I::Alloca(_));
+                if defined.contains(&value) && !alloca {
+                    classes.coalesce(store, &interfere, phi, value);
+                }
+            }
+        }
+    }
+
+    for bb in with_phis {
+        let block_phis = phis(function, store, bb);
+        let values: Vec<Vec<Register>> = block_phis.iter().map(|x| phi_values(store, *x)).collect();
+        for (index, pred) in function.blocks[bb].pred().into_iter().enumerate() {
+            let copies = block_phis
+                .iter()
+                .zip(values.iter())
+                .map(|(phi, values)| (classes.name(*phi), classes.name(values[index])))
+                .collect();
+            let copies = sequentialize(copies, store);
+            let block = &mut function.blocks[pred];
+            let at = block.len() - 1;
+            block.splice(at..at, copies);
+        }
+        function.blocks[bb].drain(..block_phis.len());
+    }
+
+    let renames: HashMap<Register, Register> = classes.names.into_iter().collect();
+    for inst in function.blocks.iter().flat_map(|x| x.iter()) {
+        store.get_mut(*inst).data.rename_regs(&renames);
+    }
+    true
+}
+
+/// Removes the phi nodes from all functions of the program, the
+/// interpreter and the backend run only programs out of ssa
+pub fn destruct_ssa(program: &mut IrProgram) {
+    destruct_function(&mut program.glob, &mut program.store);
+    for function in program.funcs.values_mut() {
+        destruct_function(function, &mut program.store);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ir_interpret::run_with_output,
+        ir_parse::parse_ir,
+        test_utils::{count, reg},
+    };
+
+    const GLOBAL: &str = "global:\nfunction global(0) : void {\nBB0:\nexit\n}\n";
+
+    #[test]
+    fn swapped_values_in_loop() {
+        // a, b = b, a every iteration, the phis read each other
+        let text = "function main(0) : int {
+BB0:
+%0 : int = ldi 1
+%1 : int = ldi 2
+%2 : int = ldi 3
+jmp BB1
+BB1:
+%3 : int = phi %0 [%4]
+%4 : int = phi %1 [%3]
+%5 : int = phi %2 [%7]
+%6 : int = lt %0 %5
+branch %6 BB2 BB3
+BB2:
+%7 : int = sub %5 %0
+jmp BB1
+BB3:
+%8 : int = ldi 10
+%9 : int = mul %3 %8
+%10 : int = add %9 %4
+retr %10
+}";
+        let mut program = parse_ir(&format!("{GLOBAL}{text}")).unwrap();
+        let main = program.funcs.get_mut("main").unwrap();
+        assert!(destruct_function(main, &mut program.store));
+        let main = &program.funcs["main"];
+        let store = &program.store;
+        assert_eq!(count(main, store, |x| matches!(x, I::Phi(_))), 0);
+        // the counter shares the place with its decrement and b with its
+        // initial value, the swap in the loop needs a temporary
+        assert_eq!(count(main, store, |x| matches!(x, I::Copy(_))), 4);
+        assert_eq!(count(main, store, |x| matches!(x, I::Mov(_))), 1);
+        assert_eq!(main.blocks[0].len(), 6);
+        assert_eq!(main.blocks[2].len(), 5);
+        // two iterations swap the values back
+        assert_eq!(run_with_output(program, &mut vec![]).unwrap(), 12);
+    }
+
+    #[test]
+    fn critical_edge_split() {
+        // BB0 branches to the phi directly, its copy needs its own block
+        let text = "function main(0) : int {
+BB0:
+%0 : int = ldi 0
+%1 : int = ldi 5
+branch %0 BB1 BB2
+BB1:
+%2 : int = ldi 7
+jmp BB2
+BB2:
+%3 : int = phi %1 [%2]
+%4 : int = add %3 %1
+retr %4
+}";
+        let mut program = parse_ir(&format!("{GLOBAL}{text}")).unwrap();
+        let main = program.funcs.get_mut("main").unwrap();
+        assert!(destruct_function(main, &mut program.store));
+        assert_eq!(main.blocks.len(), 4);
+        assert_eq!(main.blocks[2].pred(), vec![3, 1]);
+        assert_eq!(
+            program.store.get(*main.blocks[0].last().unwrap()).data,
+            I::Branch(TerminatorBranch(reg(0), 1, 3))
+        );
+        // the phi is coalesced with the value from BB1, 5 is copied into it
+        assert_eq!(
+            program.store.get(main.blocks[3][0]).data,
+            I::Copy(RegReg(reg(2), reg(1)))
+        );
+        assert_eq!(run_with_output(program, &mut vec![]).unwrap(), 10);
+    }
+}
@@ -1,9 +1,319 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
 use crate::{
-    analysis::{dataflow::DataFlowAnalysis, possible_mem::PossibleMemAnalysis},
-    ir::{Function, InstStore},
+    analysis::dominators::DominatorTree,
+    inst::{ImmC, ImmI, InstructionType, Reg, RegReg, RegRegs},
+    ir::{BBIndex, Function, InstStore, RegType, Register},
 };
 
-pub fn create_phinodes(function: &mut Function, store: &InstStore) {
-    let mut poss_analysis = PossibleMemAnalysis::new(function, store);
-    let result = poss_analysis.analyze(store);
+type I = InstructionType;
+
+/// Stack slot which is only loaded and stored, its address never escapes
+struct Slot {
+    reg_type: RegType,
+    // blocks with the stores into the slot
+    defs: BTreeSet<BBIndex>,
+    // blocks where the value of the slot at the start is used
+    live_in: HashSet<BBIndex>,
+}
+
+/// slots of the allocas which can become registers
+fn promotable(function: &Function, store: &InstStore) -> HashMap<Register, Slot> {
+    let mut slots: HashMap<Register, Option<RegType>> = HashMap::new();
+    for inst in function.blocks.iter().flat_map(|bb| bb.iter()) {
+        if let I::Alloca(ImmI(size)) = store.get(*inst).data {
+            if size <= 8 {
+                slots.insert(*inst, None);
+            }
+        }
+    }
+    // every use has to be the address of the load or store of the same type
+    for inst in function.blocks.iter().flat_map(|bb| bb.iter()) {
+        let inst = store.get(*inst);
+        let (addr, reg_type) = match inst.data {
+            I::Ld(Reg(addr)) => (Some(addr), inst.reg_type),
+            I::St(RegReg(addr, value)) => {
+                slots.remove(&value);
+                (Some(addr), store.get(value).reg_type)
+            }
+            _ => {
+                for reg in inst.data.get_regs() {
+                    slots.remove(&reg);
+                }
+                (None, RegType::Void)
+            }
+        };
+        if let Some(addr) = addr {
+            match slots.get(&addr) {
+                Some(Some(t)) if *t != reg_type => _ = slots.remove(&addr),
+                Some(_) => _ = slots.insert(addr, Some(reg_type)),
+                None => (),
+            }
+        }
+    }
+
+    let mut result: HashMap<Register, Slot> = slots
+        .into_iter()
+        .filter_map(|(reg, t)| {
+            let slot = Slot {
+                reg_type: t?,
+                defs: BTreeSet::new(),
+                live_in: HashSet::new(),
+            };
+            Some((reg, slot))
+        })
+        .collect();
+
+    // stores and the loads before them in every block
+    let mut killed: HashMap<Register, HashSet<BBIndex>> = HashMap::new();
+    for (bb, block) in function.blocks.iter().enumerate() {
+        for inst in block.iter() {
+            match store.get(*inst).data {
+                I::Ld(Reg(addr)) if result.contains_key(&addr) => {
+                    let slot = result.get_mut(&addr).unwrap();
+                    if !slot.defs.contains(&bb) {
+                        slot.live_in.insert(bb);
+                    }
+                }
+                I::St(RegReg(addr, _)) if result.contains_key(&addr) => {
+                    result.get_mut(&addr).unwrap().defs.insert(bb);
+                    killed.entry(addr).or_default().insert(bb);
+                }
+                _ => (),
+            }
+        }
+    }
+    // the value is live in the predecessors which do not store it
+    for (reg, slot) in result.iter_mut() {
+        let kills = killed.remove(reg).unwrap_or_default();
+        let mut work: Vec<BBIndex> = slot.live_in.iter().copied().collect();
+        while let Some(bb) = work.pop() {
+            for pred in function.blocks[bb].pred() {
+                if !kills.contains(&pred) && slot.live_in.insert(pred) {
+                    work.push(pred);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Promotes the allocas whose address does not escape into registers,
+/// the loads are replaced by the stored values and the phi nodes are
+/// placed into the dominance frontiers where the value is live. The
+/// phi has a value for every predecessor in their order.
+pub fn create_phinodes(function: &mut Function, store: &mut InstStore) -> bool {
+    // the entry has no predecessor which could give the phi its value
+    if function.blocks.is_empty() || !function.blocks[0].pred().is_empty() {
+        return false;
+    }
+    let slots = promotable(function, store);
+    if slots.is_empty() {
+        return false;
+    }
+    let dom = DominatorTree::new(function, store);
+    let frontiers = dom.frontiers();
+
+    // the value of the slot which was not stored yet
+    let mut undef: HashMap<RegType, Register> = HashMap::new();
+    let first = function.blocks[0]
+        .iter()
+        .take_while(|x| matches!(store.get(**x).data, I::Arg(_)))
+        .count();
+    let mut undef_of = |t: RegType, function: &mut Function, store: &mut InstStore| {
+        *undef.entry(t).or_insert_with(|| {
+            let inst = match t {
+                RegType::Char => I::Ldc(ImmC('\0')),
+                _ => I::Ldi(ImmI(0)),
+            };
+            let reg = store.add_inst(inst, t);
+            function.blocks[0].insert(first, reg);
+            reg
+        })
+    };
+
+    // phi nodes of the slots in the blocks
+    let mut phis: HashMap<(Register, BBIndex), Register> = HashMap::new();
+    let mut slot_regs: Vec<&Register> = slots.keys().collect();
+    slot_regs.sort_by_key(|x| x.val());
+    for slot_reg in slot_regs {
+        let slot = &slots[slot_reg];
+        let undef = undef_of(slot.reg_type, function, store);
+        let mut work: Vec<BBIndex> = slot.defs.iter().copied().collect();
+        while let Some(bb) = work.pop() {
+            for frontier in frontiers[bb].iter() {
+                if phis.contains_key(&(*slot_reg, *frontier)) || !slot.live_in.contains(frontier) {
+                    continue;
+                }
+                let preds = function.blocks[*frontier].pred().len();
+                let phi = I::Phi(RegRegs(undef, vec![undef; preds - 1]));
+                let reg = store.add_inst(phi, slot.reg_type);
+                function.blocks[*frontier].insert(0, reg);
+                phis.insert((*slot_reg, *frontier), reg);
+                if !slot.defs.contains(frontier) {
+                    work.push(*frontier);
+                }
+            }
+        }
+    }
+
+    // values of the slots at the end of every block, the blocks are
+    // visited after their dominators, the unreachable ones at last
+    let mut order = dom.preorder();
+    order.extend((0..function.blocks.len()).filter(|x| !dom.is_reachable(*x)));
+    let mut at_end: Vec<HashMap<Register, Register>> = vec![HashMap::new(); function.blocks.len()];
+    let mut renames: HashMap<Register, Register> = HashMap::new();
+    let mut removed: HashSet<Register> = slots.keys().copied().collect();
+    for bb in order {
+        let mut values: HashMap<Register, Register> = match dom.idom(bb) {
+            Some(idom) => at_end[idom].clone(),
+            None => HashMap::new(),
+        };
+        for slot_reg in slots.keys() {
+            if let Some(phi) = phis.get(&(*slot_reg, bb)) {
+                values.insert(*slot_reg, *phi);
+            }
+        }
+        for inst in function.blocks[bb].to_vec().iter() {
+            match store.get(*inst).data {
+                I::Ld(Reg(addr)) if slots.contains_key(&addr) => {
+                    let value = match values.get(&addr) {
+                        Some(value) => *value,
+                        None => undef_of(slots[&addr].reg_type, function, store),
+                    };
+                    renames.insert(*inst, value);
+                    removed.insert(*inst);
+                }
+                I::St(RegReg(addr, value)) if slots.contains_key(&addr) => {
+                    let value = *renames.get(&value).unwrap_or(&value);
+                    values.insert(addr, value);
+                    removed.insert(*inst);
+                }
+                _ => (),
+            }
+        }
+        at_end[bb] = values;
+    }
+
+    // the values coming from the predecessors
+    for ((slot_reg, bb), phi) in phis.iter() {
+        let undef = undef_of(slots[slot_reg].reg_type, function, store);
+        let values: Vec<Register> = function.blocks[*bb]
+            .pred()
+            .into_iter()
+            .map(|pred| *at_end[pred].get(slot_reg).unwrap_or(&undef))
+            .collect();
+        let reg_type = store.get(*phi).reg_type;
+        let data = I::Phi(RegRegs(values[0], values[1..].to_vec()));
+        store.replace_inst(*phi, data, reg_type);
+    }
+
+    for bb in function.blocks.iter_mut() {
+        bb.retain(|x| !removed.contains(x));
+        for inst in bb.iter() {
+            store.get_mut(*inst).data.rename_regs(&renames);
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ir_parse::parse_ir,
+        test_utils::{count, reg, run_pass},
+    };
+
+    #[test]
+    fn loop_counter_promoted() {
+        // i = 0; t = 7; while (i < n) i = i + 1; return i;
+        // t is stored in the loop but never read after it
+        let text = "function f(1) : int {
+BB0:
+%0 : int = arg 0
+%1 : int = alloca 8
+%2 : int = alloca 8
+%3 : int = ldi 0
+store [%1] %3
+store [%2] %3
+jmp BB1
+BB1:
+%4 : int = ld [%1]
+%5 : int = lt %4 %0
+branch %5 BB2 BB3
+BB2:
+%6 : int = ld [%1]
+%7 : int = ldi 1
+%8 : int = add %6 %7
+store [%1] %8
+store [%2] %7
+jmp BB1
+BB3:
+%9 : int = ld [%1]
+retr %9
+}";
+        let (f, store) = run_pass(text, create_phinodes);
+        let memory = count(&f, &store, |x| {
+            matches!(x, I::Ld(_) | I::St(_) | I::Alloca(_))
+        });
+        assert_eq!(memory, 0);
+        // only the counter needs the phi, the pruned t does not
+        let phis: Vec<_> = f.blocks[1]
+            .iter()
+            .filter(|x| matches!(store.get(**x).data, I::Phi(_)))
+            .collect();
+        assert_eq!(phis.len(), 1);
+        assert_eq!(
+            store.get(*phis[0]).data,
+            I::Phi(RegRegs(reg(3), vec![reg(8)]))
+        );
+        assert_eq!(
+            store.get(*f.blocks[3].last().unwrap()).data,
+            I::Retr(crate::inst::TerminatorReg(*phis[0]))
+        );
+    }
+
+    #[test]
+    fn escaping_slot_kept() {
+        let text = "function f(0) : int {
+BB0:
+%0 : int = alloca 8
+%1 : int = alloca 8
+%2 : int = ldi 5
+store [%0] %2
+store [%1] %0
+%3 : int = ld [%0]
+%4 : int = calldirect g [%1]
+retr %3
+}";
+        let program = parse_ir(text).unwrap();
+        let mut store = program.store;
+        let mut f = program.funcs.into_values().next().unwrap();
+        // %0 is stored as the value and %1 is passed to the call
+        assert!(!create_phinodes(&mut f, &mut store));
+    }
+
+    #[test]
+    fn undefined_value_on_one_path() {
+        // the variable is set only in one branch of the if
+        let text = "function f(1) : char {
+BB0:
+%0 : int = arg 0
+%1 : int = alloca 1
+branch %0 BB1 BB2
+BB1:
+%2 : char = ldc 'x'
+store [%1] %2
+jmp BB2
+BB2:
+%3 : char = ld [%1]
+retr %3
+}";
+        let (f, store) = run_pass(text, create_phinodes);
+        let phi = f.blocks[2][0];
+        let undef = f.blocks[0][1];
+        assert_eq!(store.get(undef).data, I::Ldc(ImmC('\0')));
+        assert_eq!(store.get(phi).data, I::Phi(RegRegs(undef, vec![reg(2)])));
+    }
 }
@@ -0,0 +1,386 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    analysis::lattice::{FlatElem, FlatLattice, Lattice},
+    inst::{
+        ImmC, ImmI, InstructionType, Reg, RegReg, RegRegs, Terminator, TerminatorBranch,
+        TerminatorJump,
+    },
+    ir::{BBIndex, Function, InstStore, RegType, Register},
+};
+
+type I = InstructionType;
+type Value = FlatElem<i64>;
+
+/// value of the integer operation, the cases where the interpreter
+/// and the processor differ are not evaluated
+fn evaluate_binary(data: &I, l: i64, r: i64) -> Option<i64> {
+    let value = match data {
+        I::Add(_) => l.wrapping_add(r),
+        I::Sub(_) => l.wrapping_sub(r),
+        I::Mul(_) => l.wrapping_mul(r),
+        I::Div(_) => l.checked_div(r)?,
+        I::Mod(_) => l.checked_rem(r)?,
+        I::Shr(_) if (0..64).contains(&r) => l >> r,
+        I::Shl(_) if (0..64).contains(&r) => l << r,
+        I::And(_) => l & r,
+        I::Or(_) => l | r,
+        I::Xor(_) => l ^ r,
+        I::Lt(_) => (l < r) as i64,
+        I::Le(_) => (l <= r) as i64,
+        I::Gt(_) => (l > r) as i64,
+        I::Ge(_) => (l >= r) as i64,
+        I::Eql(_) => (l == r) as i64,
+        _ => return None,
+    };
+    Some(value)
+}
+
+struct Propagation<'a> {
+    function: &'a Function,
+    store: &'a InstStore,
+    lattice: FlatLattice<i64>,
+    values: HashMap<Register, Value>,
+    reachable: Vec<bool>,
+    edges: HashSet<(BBIndex, BBIndex)>,
+    // instructions using the register with their blocks
+    users: HashMap<Register, Vec<(BBIndex, Register)>>,
+    flow_work: Vec<(BBIndex, BBIndex)>,
+    ssa_work: Vec<Register>,
+}
+
+impl<'a> Propagation<'a> {
+    fn new(function: &'a Function, store: &'a InstStore) -> Self {
+        let mut users: HashMap<Register, Vec<(BBIndex, Register)>> = HashMap::new();
+        for (bb, block) in function.blocks.iter().enumerate() {
+            for inst in block.iter() {
+                for reg in store.get(*inst).data.get_regs() {
+                    users.entry(reg).or_default().push((bb, *inst));
+                }
+            }
+        }
+        Self {
+            function,
+            store,
+            lattice: FlatLattice::new(),
+            values: HashMap::new(),
+            reachable: vec![false; function.blocks.len()],
+            edges: HashSet::new(),
+            users,
+            flow_work: vec![],
+            ssa_work: vec![],
+        }
+    }
+
+    fn value(&self, reg: Register) -> Value {
+        *self.values.get(&reg).unwrap_or(&FlatElem::Bot)
+    }
+
+    fn evaluate(&self, bb: BBIndex, inst: Register) -> Value {
+        let inst = self.store.get(inst);
+        let operands_int = inst
+            .data
+            .get_regs()
+            .iter()
+            .all(|x| self.store.get(*x).reg_type == RegType::Int);
+        match &inst.data {
+            I::Ldi(ImmI(value)) => FlatElem::Value(*value),
+            I::Ldc(ImmC(value)) => FlatElem::Value(*value as u8 as i64),
+            I::Mov(Reg(reg)) => self.value(*reg),
+            I::Phi(RegRegs(first, rest)) => {
+                let preds = self.function.blocks[bb].pred();
+                [first]
+                    .into_iter()
+                    .chain(rest)
+                    .zip(preds)
+                    .filter(|(_, pred)| self.edges.contains(&(*pred, bb)))
+                    .fold(FlatElem::Bot, |acc, (reg, _)| {
+                        self.lattice.lub(&acc, &self.value(*reg))
+                    })
+            }
+            I::Neg(Reg(reg)) if inst.reg_type == RegType::Int => match self.value(*reg) {
+                FlatElem::Value(value) => FlatElem::Value((value == 0) as i64),
+                other => other,
+            },
+            // the comparisons of chars give integers, the arithmetic of
+            // chars wraps differently, so only integers are computed
+            I::Lt(RegReg(l, r))
+            | I::Le(RegReg(l, r))
+            | I::Gt(RegReg(l, r))
+            | I::Ge(RegReg(l, r))
+            | I::Eql(RegReg(l, r))
+            | I::Add(RegReg(l, r))
+            | I::Sub(RegReg(l, r))
+            | I::Mul(RegReg(l, r))
+            | I::Div(RegReg(l, r))
+            | I::Mod(RegReg(l, r))
+            | I::Shr(RegReg(l, r))
+            | I::Shl(RegReg(l, r))
+            | I::And(RegReg(l, r))
+            | I::Or(RegReg(l, r))
+            | I::Xor(RegReg(l, r)) => {
+                let compare = matches!(
+                    inst.data,
+                    I::Lt(_) | I::Le(_) | I::Gt(_) | I::Ge(_) | I::Eql(_)
+                );
+                if !compare && !operands_int {
+                    return FlatElem::Top;
+                }
+                match (self.value(*l), self.value(*r)) {
+                    (FlatElem::Value(l), FlatElem::Value(r)) => {
+                        match evaluate_binary(&inst.data, l, r) {
+                            Some(value) => FlatElem::Value(value),
+                            None => FlatElem::Top,
+                        }
+                    }
+                    (FlatElem::Bot, _) | (_, FlatElem::Bot) => FlatElem::Bot,
+                    _ => FlatElem::Top,
+                }
+            }
+            _ => FlatElem::Top,
+        }
+    }
+
+    fn visit(&mut self, bb: BBIndex, inst: Register) {
+        let data = &self.store.get(inst).data;
+        match data {
+            I::Jmp(TerminatorJump(to)) => self.flow_work.push((bb, *to)),
+            I::Branch(TerminatorBranch(cond, yes, no)) => match self.value(*cond) {
+                FlatElem::Bot => (),
+                FlatElem::Value(0) => self.flow_work.push((bb, *no)),
+                FlatElem::Value(_) => self.flow_work.push((bb, *yes)),
+                FlatElem::Top => {
+                    self.flow_work.push((bb, *yes));
+                    self.flow_work.push((bb, *no));
+                }
+            },
+            _ if self.store.get(inst).reg_type != RegType::Void => {
+                let value = self.evaluate(bb, inst);
+                if value != self.value(inst) {
+                    self.values.insert(inst, value);
+                    self.ssa_work.push(inst);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// the values of the registers in the blocks reachable
+    /// by the branches which can be taken
+    fn run(&mut self) {
+        if self.function.blocks.is_empty() {
+            return;
+        }
+        self.reachable[0] = true;
+        for inst in self.function.blocks[0].iter() {
+            self.visit(0, *inst);
+        }
+        loop {
+            if let Some((from, to)) = self.flow_work.pop() {
+                if to >= self.reachable.len() || !self.edges.insert((from, to)) {
+                    continue;
+                }
+                let block = &self.function.blocks[to];
+                if self.reachable[to] {
+                    // only the phis see the new edge
+                    for inst in block.iter() {
+                        if matches!(self.store.get(*inst).data, I::Phi(_)) {
+                            self.visit(to, *inst);
+                        }
+                    }
+                } else {
+                    self.reachable[to] = true;
+                    for inst in block.iter() {
+                        self.visit(to, *inst);
+                    }
+                }
+            } else if let Some(reg) = self.ssa_work.pop() {
+                for (bb, user) in self.users.get(&reg).cloned().unwrap_or_default() {
+                    if self.reachable[bb] {
+                        self.visit(bb, user);
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// removes the edge and the values of the phis coming by it
+fn remove_edge(function: &mut Function, store: &mut InstStore, from: BBIndex, to: BBIndex) {
+    let preds = function.blocks[to].pred();
+    for inst in function.blocks[to].iter() {
+        if let I::Phi(RegRegs(first, rest)) = &store.get(*inst).data {
+            let values: Vec<Register> = [*first]
+                .into_iter()
+                .chain(rest.iter().copied())
+                .zip(preds.iter())
+                .filter(|(_, pred)| **pred != from)
+                .map(|(value, _)| value)
+                .collect();
+            let data = I::Phi(RegRegs(values[0], values[1..].to_vec()));
+            let reg_type = store.get(*inst).reg_type;
+            store.replace_inst(*inst, data, reg_type);
+        }
+    }
+    function.blocks[to].remove_predecesor(from);
+}
+
+/// Sparse conditional constant propagation, the registers with the
+/// same constant value on every reachable path become `ldi` or `ldc`
+/// and the branches which always go one way become jumps. The blocks
+/// which are never run lose their code and their edges.
+pub fn propagate_constants(function: &mut Function, store: &mut InstStore) -> bool {
+    let mut propagation = Propagation::new(function, store);
+    propagation.run();
+    let Propagation {
+        values, reachable, ..
+    } = propagation;
+
+    let mut change = false;
+    for (bb, _) in reachable.iter().enumerate().filter(|(_, x)| !**x) {
+        let block = &function.blocks[bb];
+        let cleared = block.pred().is_empty()
+            && block.len() == 1
+            && matches!(store.get(block[0]).data, I::Ret(_));
+        if cleared {
+            continue;
+        }
+        // the phis of the unreachable blocks are dropped with them
+        for succ in block.succ(store) {
+            match reachable[succ] {
+                true => remove_edge(function, store, bb, succ),
+                false => function.blocks[succ].remove_predecesor(bb),
+            }
+        }
+        for pred in function.blocks[bb].pred() {
+            function.blocks[bb].remove_predecesor(pred);
+        }
+        let ret = store.add_inst(I::Ret(Terminator), RegType::Void);
+        function.blocks[bb].instruction = vec![ret];
+        change = true;
+    }
+
+    for (bb, _) in reachable.iter().enumerate().filter(|(_, x)| **x) {
+        let mut constant_phis = vec![];
+        let insts: Vec<Register> = function.blocks[bb].iter().copied().collect();
+        for inst in insts {
+            let value = match values.get(&inst) {
+                Some(FlatElem::Value(value)) => *value,
+                _ => continue,
+            };
+            let reg_type = store.get(inst).reg_type;
+            let data = match reg_type {
+                RegType::Char => I::Ldc(ImmC(value as u8 as char)),
+                _ => I::Ldi(ImmI(value)),
+            };
+            if store.get(inst).data == data {
+                continue;
+            }
+            if matches!(store.get(inst).data, I::Phi(_)) {
+                constant_phis.push(inst);
+            }
+            store.replace_inst(inst, data, reg_type);
+            change = true;
+        }
+        // the phis which became constants go after the rest of the phis
+        let block = &mut function.blocks[bb];
+        block.retain(|x| !constant_phis.contains(x));
+        let at = block
+            .iter()
+            .take_while(|x| matches!(store.get(**x).data, I::Phi(_)))
+            .count();
+        block.splice(at..at, constant_phis);
+
+        let last = match block.last() {
+            Some(last) => *last,
+            None => continue,
+        };
+        if let I::Branch(TerminatorBranch(cond, yes, no)) = store.get(last).data {
+            let (taken, other) = match values.get(&cond) {
+                Some(FlatElem::Value(0)) => (no, yes),
+                Some(FlatElem::Value(_)) => (yes, no),
+                _ => continue,
+            };
+            if taken != other {
+                store.replace_inst(last, I::Jmp(TerminatorJump(taken)), RegType::Void);
+                remove_edge(function, store, bb, other);
+                change = true;
+            }
+        }
+    }
+    change
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{reg, run_pass};
+
+    #[test]
+    fn branch_on_constant_folded() {
+        let text = "function f(1) : int {
+BB0:
+%0 : int = arg 0
+%1 : int = ldi 2
+%2 : int = ldi 3
+%3 : int = mul %1 %2
+%4 : int = lt %3 %1
+branch %4 BB1 BB2
+BB1:
+%5 : int = add %0 %1
+jmp BB3
+BB2:
+%6 : int = ldi 6
+jmp BB3
+BB3:
+%7 : int = phi %5 [%6]
+%8 : int = eql %7 %3
+retr %8
+}";
+        let (f, store) = run_pass(text, propagate_constants);
+        assert_eq!(store.get(reg(3)).data, I::Ldi(ImmI(6)));
+        assert_eq!(store.get(reg(4)).data, I::Ldi(ImmI(0)));
+        assert_eq!(
+            store.get(*f.blocks[0].last().unwrap()).data,
+            I::Jmp(TerminatorJump(2))
+        );
+        // only the value from BB2 can come, BB1 is never run
+        assert_eq!(store.get(reg(7)).data, I::Ldi(ImmI(6)));
+        assert_eq!(store.get(reg(8)).data, I::Ldi(ImmI(1)));
+        assert!(f.blocks[1].pred().is_empty());
+        assert_eq!(f.blocks[1].len(), 1);
+        assert_eq!(store.get(f.blocks[1][0]).data, I::Ret(Terminator));
+        assert_eq!(f.blocks[3].pred(), vec![2]);
+    }
+
+    #[test]
+    fn constant_through_loop() {
+        // x = 1; while (i < n) { x = x * 1; i = i + 1; } return x;
+        let text = "function f(1) : int {
+BB0:
+%0 : int = arg 0
+%1 : int = ldi 1
+%2 : int = ldi 0
+jmp BB1
+BB1:
+%3 : int = phi %1 [%6]
+%4 : int = phi %2 [%7]
+%5 : int = lt %4 %0
+branch %5 BB2 BB3
+BB2:
+%6 : int = mul %3 %1
+%7 : int = add %4 %1
+jmp BB1
+BB3:
+retr %3
+}";
+        let (f, store) = run_pass(text, propagate_constants);
+        assert_eq!(store.get(reg(3)).data, I::Ldi(ImmI(1)));
+        assert_eq!(store.get(reg(6)).data, I::Ldi(ImmI(1)));
+        // the counter changes, the loop stays
+        assert!(matches!(store.get(reg(4)).data, I::Phi(_)));
+        assert_eq!(f.blocks[1][..2], [reg(4), reg(3)]);
+    }
+}
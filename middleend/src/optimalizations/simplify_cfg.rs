@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use crate::{
+    inst::{ImmI, InstructionType, RegRegs, TerminatorBranch, TerminatorJump},
+    ir::{BBIndex, Function, InstStore, RegType, Register},
+};
+
+type I = InstructionType;
+
+/// Rebuilds the predecessors of the block, every one is replaced by
+/// the blocks the map gives for it and its phi values go to all of them
+fn map_edges(
+    function: &mut Function,
+    store: &mut InstStore,
+    to: BBIndex,
+    map: impl Fn(usize, BBIndex) -> Vec<BBIndex>,
+) {
+    let preds = function.blocks[to].pred();
+    let new: Vec<Vec<BBIndex>> = preds.iter().enumerate().map(|(i, x)| map(i, *x)).collect();
+    for inst in function.blocks[to].iter() {
+        if let I::Phi(RegRegs(first, rest)) = &store.get(*inst).data {
+            let values: Vec<Register> = [*first]
+                .into_iter()
+                .chain(rest.iter().copied())
+                .zip(new.iter())
+                .flat_map(|(value, preds)| vec![value; preds.len()])
+                .collect();
+            // the block without predecessors is going to be removed
+            if values.is_empty() {
+                continue;
+            }
+            let reg_type = store.get(*inst).reg_type;
+            let data = I::Phi(RegRegs(values[0], values[1..].to_vec()));
+            store.replace_inst(*inst, data, reg_type);
+        }
+    }
+    let block = &mut function.blocks[to];
+    for pred in preds {
+        block.remove_predecesor(pred);
+    }
+    for pred in new.into_iter().flatten() {
+        block.add_predecesor(pred);
+    }
+}
+
+/// changes the targets of the terminator ending the block
+fn map_targets(
+    function: &Function,
+    store: &mut InstStore,
+    bb: BBIndex,
+    map: impl Fn(BBIndex) -> BBIndex,
+) {
+    let last = match function.blocks[bb].last() {
+        Some(last) => *last,
+        None => return,
+    };
+    match &mut store.get_mut(last).data {
+        I::Jmp(TerminatorJump(target)) => *target = map(*target),
+        I::Branch(TerminatorBranch(_, yes, no)) => {
+            *yes = map(*yes);
+            *no = map(*no);
+        }
+        _ => (),
+    }
+}
+
+/// removes the blocks which cannot be reached from the start, the
+/// rest is numbered again in the same order
+pub(crate) fn remove_unreachable(function: &mut Function, store: &mut InstStore) -> bool {
+    let mut reachable = vec![false; function.blocks.len()];
+    let mut work = vec![0];
+    reachable[0] = true;
+    while let Some(bb) = work.pop() {
+        for succ in function.blocks[bb].succ(store) {
+            if !reachable[succ] {
+                reachable[succ] = true;
+                work.push(succ);
+            }
+        }
+    }
+    if reachable.iter().all(|x| *x) {
+        return false;
+    }
+
+    let mut index = vec![0; function.blocks.len()];
+    let kept = reachable.iter().enumerate().filter(|(_, x)| **x);
+    for (new, (bb, _)) in kept.enumerate() {
+        index[bb] = new;
+    }
+    for (bb, _) in reachable.iter().enumerate().filter(|(_, x)| **x) {
+        map_edges(function, store, bb, |_, pred| match reachable[pred] {
+            true => vec![index[pred]],
+            false => vec![],
+        });
+        map_targets(function, store, bb, |target| index[target]);
+    }
+    let mut bb = 0;
+    function.blocks.retain(|_| {
+        bb += 1;
+        reachable[bb - 1]
+    });
+    true
+}
+
+/// the branches with the same targets or a constant condition become jumps
+fn fold_branches(function: &mut Function, store: &mut InstStore) -> bool {
+    let mut change = false;
+    for bb in 0..function.blocks.len() {
+        let last = match function.blocks[bb].last() {
+            Some(last) => *last,
+            None => continue,
+        };
+        let (cond, yes, no) = match store.get(last).data {
+            I::Branch(TerminatorBranch(cond, yes, no)) => (cond, yes, no),
+            _ => continue,
+        };
+        let taken = if yes == no {
+            // the target has the block twice in its predecessors
+            let at = function.blocks[yes].pred().iter().rposition(|x| *x == bb);
+            map_edges(function, store, yes, |i, pred| match Some(i) == at {
+                true => vec![],
+                false => vec![pred],
+            });
+            yes
+        } else if let I::Ldi(ImmI(value)) = store.get(cond).data {
+            let (taken, other) = match value {
+                0 => (no, yes),
+                _ => (yes, no),
+            };
+            map_edges(function, store, other, |_, pred| match pred == bb {
+                true => vec![],
+                false => vec![pred],
+            });
+            taken
+        } else {
+            continue;
+        };
+        store.replace_inst(last, I::Jmp(TerminatorJump(taken)), RegType::Void);
+        change = true;
+    }
+    change
+}
+
+/// the predecessors of the block with just a jump go directly to its
+/// target, the block is left without predecessors
+fn thread_jumps(function: &mut Function, store: &mut InstStore) -> bool {
+    let mut change = false;
+    for bb in 1..function.blocks.len() {
+        let target = match function.blocks[bb][..] {
+            [last] => match store.get(last).data {
+                I::Jmp(TerminatorJump(target)) if target != bb => target,
+                _ => continue,
+            },
+            _ => continue,
+        };
+        let mut preds = function.blocks[bb].pred();
+        // the phis of the target could not tell the two edges apart
+        let target_preds = function.blocks[target].pred();
+        if preds.is_empty() || preds.iter().any(|x| target_preds.contains(x)) {
+            continue;
+        }
+        map_edges(function, store, target, |_, pred| match pred == bb {
+            true => preds.clone(),
+            false => vec![pred],
+        });
+        preds.sort_unstable();
+        preds.dedup();
+        for pred in preds {
+            map_targets(function, store, pred, |x| if x == bb { target } else { x });
+            function.blocks[bb].remove_predecesor(pred);
+        }
+        change = true;
+    }
+    change
+}
+
+/// the block which is the only successor of its only predecessor is
+/// appended to it, its phis have just one value which replaces them
+fn merge_blocks(function: &mut Function, store: &mut InstStore) -> bool {
+    let mut change = false;
+    for bb in 0..function.blocks.len() {
+        let next = match function.blocks[bb].last() {
+            Some(last) => match store.get(*last).data {
+                I::Jmp(TerminatorJump(next)) => next,
+                _ => continue,
+            },
+            None => continue,
+        };
+        if next == bb || next == 0 || function.blocks[next].pred() != [bb] {
+            continue;
+        }
+        let mut renames: HashMap<Register, Register> = HashMap::new();
+        let insts: Vec<Register> = function.blocks[next].drain(..).collect();
+        function.blocks[next].remove_predecesor(bb);
+        function.blocks[bb].pop();
+        for inst in insts {
+            match store.get(inst).data {
+                I::Phi(RegRegs(value, _)) => _ = renames.insert(inst, value),
+                _ => function.blocks[bb].push(inst),
+            }
+        }
+        for succ in function.blocks[bb].succ(store) {
+            function.blocks[succ].replace_predecesor(next, bb);
+        }
+        if !renames.is_empty() {
+            for inst in function.blocks.iter().flat_map(|x| x.iter()) {
+                store.get_mut(*inst).data.rename_regs(&renames);
+            }
+        }
+        change = true;
+    }
+    change
+}
+
+/// Simplifies the control flow graph, removes the unreachable blocks,
+/// folds the branches which go one way, jumps over the blocks which
+/// only jump and merges the chains of blocks into one. The blocks are
+/// numbered again and the predecessors keep the order of the phi values.
+pub fn simplify_cfg(function: &mut Function, store: &mut InstStore) -> bool {
+    if function.blocks.is_empty() {
+        return false;
+    }
+    let mut change = false;
+    loop {
+        let step = remove_unreachable(function, store)
+            | fold_branches(function, store)
+            | thread_jumps(function, store)
+            | merge_blocks(function, store);
+        if !step {
+            return change;
+        }
+        change = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        inst::TerminatorReg,
+        test_utils::{reg, run_pass},
+    };
+
+    #[test]
+    fn threaded_merged_and_numbered_again() {
+        // BB1 only jumps, BB4 is never reached and BB5 follows BB3
+        let text = "function f(1) : int {
+BB0:
+%0 : int = arg 0
+branch %0 BB1 BB2
+BB1:
+jmp BB3
+BB2:
+%1 : int = ldi 1
+jmp BB3
+BB3:
+%2 : int = phi %0 [%1 %0]
+jmp BB5
+BB4:
+jmp BB3
+BB5:
+retr %2
+}";
+        let (f, store) = run_pass(text, simplify_cfg);
+        assert_eq!(f.blocks.len(), 3);
+        assert_eq!(
+            store.get(f.blocks[0][1]).data,
+            I::Branch(TerminatorBranch(reg(0), 2, 1))
+        );
+        assert_eq!(f.blocks[2].pred(), vec![0, 1]);
+        assert_eq!(
+            store.get(f.blocks[2][0]).data,
+            I::Phi(RegRegs(reg(0), vec![reg(1)]))
+        );
+        assert_eq!(
+            store.get(f.blocks[2][1]).data,
+            I::Retr(TerminatorReg(reg(2)))
+        );
+    }
+
+    #[test]
+    fn branches_folded_into_one_block() {
+        let text = "function f(1) : int {
+BB0:
+%0 : int = arg 0
+%1 : int = ldi 0
+branch %0 BB1 BB1
+BB1:
+%2 : int = phi %0 [%0]
+branch %1 BB2 BB3
+BB2:
+retr %1
+BB3:
+retr %2
+}";
+        let (f, store) = run_pass(text, simplify_cfg);
+        assert_eq!(f.blocks.len(), 1);
+        assert_eq!(f.blocks[0].len(), 3);
+        assert_eq!(
+            store.get(f.blocks[0][2]).data,
+            I::Retr(TerminatorReg(reg(0)))
+        );
+    }
+}
@@ -0,0 +1,80 @@
+/// Syscalls reachable from the source through the named intrinsics,
+/// the number of the syscall depends on the target
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Syscall {
+    Read,
+    Write,
+    Exit,
+    Brk,
+    Mmap,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Target {
+    #[default]
+    Riscv64Linux,
+    X86_64Linux,
+}
+
+impl Syscall {
+    pub const ALL: [Syscall; 5] = [
+        Syscall::Read,
+        Syscall::Write,
+        Syscall::Exit,
+        Syscall::Brk,
+        Syscall::Mmap,
+    ];
+
+    /// name of the function which is compiled into the syscall
+    pub fn intrinsic(&self) -> &'static str {
+        match self {
+            Syscall::Read => "__read",
+            Syscall::Write => "__write",
+            Syscall::Exit => "__exit",
+            Syscall::Brk => "__brk",
+            Syscall::Mmap => "__mmap",
+        }
+    }
+
+    pub fn from_intrinsic(name: &str) -> Option<Syscall> {
+        Self::ALL.into_iter().find(|x| x.intrinsic() == name)
+    }
+
+    pub fn number(&self, target: Target) -> i64 {
+        match (target, self) {
+            (Target::Riscv64Linux, Syscall::Read) => 63,
+            (Target::Riscv64Linux, Syscall::Write) => 64,
+            (Target::Riscv64Linux, Syscall::Exit) => 93,
+            (Target::Riscv64Linux, Syscall::Brk) => 214,
+            (Target::Riscv64Linux, Syscall::Mmap) => 222,
+            (Target::X86_64Linux, Syscall::Read) => 0,
+            (Target::X86_64Linux, Syscall::Write) => 1,
+            (Target::X86_64Linux, Syscall::Exit) => 60,
+            (Target::X86_64Linux, Syscall::Brk) => 12,
+            (Target::X86_64Linux, Syscall::Mmap) => 9,
+        }
+    }
+
+    pub fn from_number(number: i64, target: Target) -> Option<Syscall> {
+        Self::ALL.into_iter().find(|x| x.number(target) == number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syscall_table_roundtrip() {
+        for target in [Target::Riscv64Linux, Target::X86_64Linux] {
+            for syscall in Syscall::ALL {
+                assert_eq!(
+                    Syscall::from_number(syscall.number(target), target),
+                    Some(syscall)
+                );
+                assert_eq!(Syscall::from_intrinsic(syscall.intrinsic()), Some(syscall));
+            }
+        }
+        assert_eq!(Syscall::from_intrinsic("write"), None);
+    }
+}
@@ -0,0 +1,36 @@
+use crate::{
+    builder::Pass,
+    inst::InstructionType,
+    ir::{Function, InstStore, Register},
+    ir_parse::parse_ir,
+    verify::verify_function,
+};
+
+/// first function of the parsed text after the pass, the pass has to
+/// change it, keep it verified and have nothing to do when run again
+pub(crate) fn run_pass(text: &str, pass: Pass) -> (Function, InstStore) {
+    let program = parse_ir(text).unwrap();
+    let mut store = program.store;
+    let mut f = program.funcs.into_values().next().unwrap();
+    assert!(pass(&mut f, &mut store));
+    assert_eq!(verify_function(&f, &store), vec![]);
+    assert!(!pass(&mut f, &mut store));
+    (f, store)
+}
+
+pub(crate) fn reg(id: usize) -> Register {
+    Register::from_val(id)
+}
+
+/// number of the instructions of the function matching the predicate
+pub(crate) fn count(
+    f: &Function,
+    store: &InstStore,
+    pred: impl Fn(&InstructionType) -> bool,
+) -> usize {
+    f.blocks
+        .iter()
+        .flat_map(|bb| bb.iter())
+        .filter(|x| pred(&store.get(**x).data))
+        .count()
+}
@@ -0,0 +1,357 @@
+use std::collections::HashMap;
+
+use crate::{
+    analysis::dominators::DominatorTree,
+    inst::{InstructionType, Reg, RegReg, RegRegImm, RegRegs, TerminatorReg},
+    ir::{BBIndex, Function, InstStore, IrProgram, RegType, Register},
+};
+
+/// Problem found in the function, the block is where it was found
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum VerifyError {
+    EmptyBlock(BBIndex),
+    MissingTerminator(BBIndex),
+    TerminatorInside(BBIndex, Register),
+    UnknownBlock(BBIndex, BBIndex),
+    // the edge from the first block to the second one is in
+    // the predecessors or in the successors only
+    PredecessorMismatch(BBIndex, BBIndex),
+    UndefinedRegister(BBIndex, Register),
+    DefinedTwice(BBIndex, Register),
+    NotDominated(BBIndex, Register),
+    PhiArity(BBIndex, Register),
+    PhiNotFirst(BBIndex, Register),
+    WrongType(BBIndex, Register),
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::EmptyBlock(bb) => write!(f, "BB{} is empty", bb),
+            VerifyError::MissingTerminator(bb) => {
+                write!(f, "BB{} does not end with a terminator", bb)
+            }
+            VerifyError::TerminatorInside(bb, reg) => {
+                write!(f, "BB{}: terminator %{} is not the last", bb, reg.val())
+            }
+            VerifyError::UnknownBlock(bb, to) => write!(f, "BB{} jumps to unknown BB{}", bb, to),
+            VerifyError::PredecessorMismatch(from, to) => {
+                write!(
+                    f,
+                    "edge BB{} -> BB{} does not match the predecessors",
+                    from, to
+                )
+            }
+            VerifyError::UndefinedRegister(bb, reg) => {
+                write!(f, "BB{}: %{} is not defined in the function", bb, reg.val())
+            }
+            VerifyError::DefinedTwice(bb, reg) => {
+                write!(f, "BB{}: %{} is defined twice", bb, reg.val())
+            }
+            VerifyError::NotDominated(bb, reg) => {
+                write!(
+                    f,
+                    "BB{}: definition of %{} does not dominate the use",
+                    bb,
+                    reg.val()
+                )
+            }
+            VerifyError::PhiArity(bb, reg) => {
+                write!(
+                    f,
+                    "BB{}: phi %{} does not match the predecessors",
+                    bb,
+                    reg.val()
+                )
+            }
+            VerifyError::PhiNotFirst(bb, reg) => {
+                write!(
+                    f,
+                    "BB{}: phi %{} is after other instructions",
+                    bb,
+                    reg.val()
+                )
+            }
+            VerifyError::WrongType(bb, reg) => {
+                write!(f, "BB{}: %{} has wrong types", bb, reg.val())
+            }
+        }
+    }
+}
+
+/// the types of the operands and of the result fit the instruction
+fn types_fit(function: &Function, store: &InstStore, id: Register) -> bool {
+    use InstructionType::*;
+    let inst = store.get(id);
+    let ty = |reg: &Register| store.get(*reg).reg_type;
+    let result = inst.reg_type;
+    let operands_void = inst.data.get_regs().iter().any(|x| ty(x) == RegType::Void);
+    if operands_void {
+        return false;
+    }
+    match &inst.data {
+        Ldi(_) | Alloca(_) | Ldg(_) | VaStart(_) => result == RegType::Int,
+        Ldc(_) => result == RegType::Char,
+        Ld(Reg(addr)) => ty(addr) == RegType::Int && result != RegType::Void,
+        St(RegReg(addr, _)) => ty(addr) == RegType::Int && result == RegType::Void,
+        Gep(_, RegRegImm(start, _, _)) => ty(start) == RegType::Int && result == RegType::Int,
+        Mov(Reg(reg)) | Neg(Reg(reg)) => ty(reg) == result,
+        Conv(_) => result != RegType::Void,
+        Add(RegReg(l, r)) | Sub(RegReg(l, r)) | Mul(RegReg(l, r)) | Div(RegReg(l, r))
+        | Mod(RegReg(l, r)) | Shr(RegReg(l, r)) | Shl(RegReg(l, r)) | And(RegReg(l, r))
+        | Or(RegReg(l, r)) | Xor(RegReg(l, r)) => ty(l) == ty(r) && ty(l) == result,
+        Lt(RegReg(l, r)) | Le(RegReg(l, r)) | Gt(RegReg(l, r)) | Ge(RegReg(l, r))
+        | Eql(RegReg(l, r)) => ty(l) == ty(r) && result == RegType::Int,
+        Call(RegRegs(target, _)) => ty(target) == RegType::Int,
+        CallDirect(_) | SysCall(_) => true,
+        Arg(_) => result != RegType::Void,
+        Phi(RegRegs(first, rest)) => rest.iter().chain([first]).all(|x| ty(x) == result),
+        Copy(RegReg(to, from)) => ty(to) == ty(from) && result == RegType::Void,
+        // falling off the end of the function is allowed like in c
+        Ret(_) => result == RegType::Void,
+        Retr(TerminatorReg(reg)) => ty(reg) == function.ret_type && result == RegType::Void,
+        Jmp(_) | Branch(_) | Exit(_) | Print(_) => result == RegType::Void,
+    }
+}
+
+/// Checks that the function is well formed, its blocks end with
+/// exactly one terminator, the predecessors match the jumps, every
+/// use is dominated by the definition and the types fit
+pub fn verify_function(function: &Function, store: &InstStore) -> Vec<VerifyError> {
+    let mut errors = vec![];
+    let blocks = &function.blocks;
+    // the global initialization of the empty program
+    if blocks.is_empty() {
+        return errors;
+    }
+
+    // terminators and the edges
+    for (bb, block) in blocks.iter().enumerate() {
+        let last = match block.last() {
+            Some(last) => *last,
+            None => {
+                errors.push(VerifyError::EmptyBlock(bb));
+                continue;
+            }
+        };
+        if !store.get(last).data.terminator() {
+            errors.push(VerifyError::MissingTerminator(bb));
+        }
+        for inst in &block[..block.len() - 1] {
+            if store.get(*inst).data.terminator() {
+                errors.push(VerifyError::TerminatorInside(bb, *inst));
+            }
+        }
+        for succ in block.succ(store) {
+            if succ >= blocks.len() {
+                errors.push(VerifyError::UnknownBlock(bb, succ));
+            } else if !blocks[succ].pred().contains(&bb) {
+                errors.push(VerifyError::PredecessorMismatch(bb, succ));
+            }
+        }
+        for pred in block.pred() {
+            if pred >= blocks.len() {
+                errors.push(VerifyError::UnknownBlock(bb, pred));
+            } else if !blocks[pred].succ(store).contains(&bb) {
+                errors.push(VerifyError::PredecessorMismatch(pred, bb));
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return errors;
+    }
+
+    // place of the definition of every register
+    let mut defs: HashMap<Register, (BBIndex, usize)> = HashMap::new();
+    for (bb, block) in blocks.iter().enumerate() {
+        for (index, inst) in block.iter().enumerate() {
+            if defs.insert(*inst, (bb, index)).is_some() {
+                errors.push(VerifyError::DefinedTwice(bb, *inst));
+            }
+        }
+    }
+
+    let dom = DominatorTree::new(function, store);
+    // the definition is available at the end of the block, the code
+    // in the unreachable blocks is never run
+    let available_at_end = |reg: &Register, bb: BBIndex| match defs.get(reg) {
+        Some((def_bb, _)) => !dom.is_reachable(bb) || dom.dominates(*def_bb, bb),
+        None => false,
+    };
+
+    for (bb, block) in blocks.iter().enumerate() {
+        let mut phis_end = false;
+        for (index, inst) in block.iter().enumerate() {
+            let data = &store.get(*inst).data;
+            if let InstructionType::Phi(RegRegs(first, rest)) = data {
+                if phis_end {
+                    errors.push(VerifyError::PhiNotFirst(bb, *inst));
+                }
+                // the values are in the same order as the predecessors
+                let preds = block.pred();
+                if preds.len() != rest.len() + 1 {
+                    errors.push(VerifyError::PhiArity(bb, *inst));
+                } else {
+                    for (value, pred) in [first].into_iter().chain(rest).zip(preds) {
+                        if !defs.contains_key(value) {
+                            errors.push(VerifyError::UndefinedRegister(bb, *value));
+                        } else if !available_at_end(value, pred) {
+                            errors.push(VerifyError::NotDominated(bb, *value));
+                        }
+                    }
+                }
+            } else {
+                phis_end = true;
+                for reg in data.get_regs() {
+                    let dominated = match defs.get(&reg) {
+                        None => {
+                            errors.push(VerifyError::UndefinedRegister(bb, reg));
+                            continue;
+                        }
+                        Some((def_bb, def_index)) if *def_bb == bb => *def_index < index,
+                        Some(_) => available_at_end(&reg, bb),
+                    };
+                    if !dominated {
+                        errors.push(VerifyError::NotDominated(bb, reg));
+                    }
+                }
+            }
+            if !types_fit(function, store, *inst) {
+                errors.push(VerifyError::WrongType(bb, *inst));
+            }
+        }
+    }
+    errors
+}
+
+/// Verifies every function of the program, the errors are
+/// prefixed by the name of the function
+pub fn verify_program(program: &IrProgram) -> Vec<(String, VerifyError)> {
+    let mut funcs: Vec<&Function> = program.funcs.values().collect();
+    funcs.sort_by(|a, b| a.name.cmp(&b.name));
+    [&program.glob]
+        .into_iter()
+        .chain(funcs)
+        .flat_map(|f| {
+            verify_function(f, &program.store)
+                .into_iter()
+                .map(|e| (f.name.clone(), e))
+        })
+        .collect()
+}
+
+/// In the debug builds the function is verified after every
+/// transformation, `pass` names the one which broke it
+pub(crate) fn debug_verify(function: &Function, store: &InstStore, pass: &str) {
+    if cfg!(debug_assertions) {
+        let errors = verify_function(function, store);
+        if let Some(error) = errors.first() {
+            panic!("{} broke {}: {}", pass, function.name, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ir_parse::parse_ir, test_utils::reg};
+
+    fn errors(text: &str) -> Vec<VerifyError> {
+        let program = parse_ir(text).unwrap();
+        verify_program(&program)
+            .into_iter()
+            .map(|(_, e)| e)
+            .collect()
+    }
+
+    #[test]
+    fn well_formed_function() {
+        let text = "function f(1) : int {
+BB0:
+%0 : int = arg 0
+branch %0 BB1 BB2
+BB1:
+%1 : int = ldi 1
+jmp BB3
+BB2:
+%2 : int = ldi 2
+jmp BB3
+BB3:
+%3 : int = phi %1 [%2]
+%4 : int = add %3 %0
+retr %4
+}";
+        assert_eq!(errors(text), vec![]);
+    }
+
+    #[test]
+    fn malformed_functions() {
+        let not_dominated = "function f(1) : int {
+BB0:
+%0 : int = arg 0
+branch %0 BB1 BB2
+BB1:
+%1 : int = ldi 1
+jmp BB2
+BB2:
+retr %1
+}";
+        assert_eq!(
+            errors(not_dominated),
+            vec![VerifyError::NotDominated(2, reg(1))]
+        );
+
+        let types = "function f(0) : char {
+BB0:
+%0 : int = ldi 1
+%1 : char = ldc 'a'
+%2 : int = add %0 %1
+retr %0
+}";
+        assert_eq!(
+            errors(types),
+            vec![
+                VerifyError::WrongType(0, reg(2)),
+                VerifyError::WrongType(0, reg(3))
+            ]
+        );
+
+        let phi = "function f(0) : int {
+BB0:
+%0 : int = ldi 1
+jmp BB1
+BB1:
+%1 : int = phi %0 [%0]
+retr %1
+}";
+        assert_eq!(errors(phi), vec![VerifyError::PhiArity(1, reg(1))]);
+
+        let terminator = "function f(0) : void {
+BB0:
+ret
+%0 : int = ldi 1
+}";
+        assert_eq!(
+            errors(terminator),
+            vec![
+                VerifyError::MissingTerminator(0),
+                VerifyError::TerminatorInside(0, reg(1))
+            ]
+        );
+    }
+
+    #[test]
+    fn predecessors_checked() {
+        let mut program = parse_ir("function f(0) : void {\nBB0:\njmp BB1\nBB1:\nret\n}").unwrap();
+        let func = program.funcs.get_mut("f").unwrap();
+        func.blocks[1].remove_predecesor(0);
+        func.blocks[0].add_predecesor(1);
+        assert_eq!(
+            verify_function(func, &program.store),
+            vec![
+                VerifyError::PredecessorMismatch(0, 1),
+                VerifyError::PredecessorMismatch(1, 0)
+            ]
+        );
+    }
+}